@@ -6,16 +6,28 @@ All subcommands output JSON to stdout. Errors go to stderr with exit code 1.
 from __future__ import annotations
 
 import argparse
+import base64
+import dataclasses
+import gzip
 import hashlib
+import http.server
 import json
+import logging
 import os
+import queue
+import random
+import secrets
+import sqlite3
 import sys
+import threading
 import time
 from typing import Optional
 
 from .models import RawMessage, Session, Contact
 from .db import (
+    SCHEMA_VERSION,
     init_db,
+    get_schema_version,
     get_talkers_with_stats,
     get_build_status,
     get_build_progress,
@@ -24,6 +36,7 @@ from .db import (
     get_all_messages,
     get_messages_by_ids,
     upsert_messages,
+    get_nodes_count,
     delete_session,
 )
 from .workflow import run_workflow, run_workflow_stream_values
@@ -31,6 +44,18 @@ from .workflow import run_workflow, run_workflow_stream_values
 # Stdio daemon mode: when True, _die() prints JSON error and raises StdioModeError instead of exiting
 _stdio_mode = False
 
+# Stdio daemon mode: req_ids the client cancelled with {"cmd":"cancel","req_id":...}; filled by the stdin reader thread
+_cancelled_req_ids: set = set()
+
+# Stdio daemon mode: answered questions of this session, oldest first, for {"cmd":"export"}
+_transcript: list = []
+
+# Stdio daemon mode: talker chosen with {"cmd":"select_talker"}; used by queries that name none
+_active_talker: Optional[str] = None
+
+# --log-level choices (the client's names) and the logging levels they map to; trace is below DEBUG
+_LOG_LEVELS = {"error": logging.ERROR, "warn": logging.WARNING, "info": logging.INFO, "debug": logging.DEBUG, "trace": 5}
+
 
 class StdioModeError(Exception):
     """Raised by _die() in stdio mode so the daemon loop can continue."""
@@ -70,15 +95,56 @@ def msg_to_client(msg: RawMessage, phase_index: Optional[int] = None) -> dict:
     return d
 
 
-def _die(msg: str) -> None:
-    """Write error to stderr and exit with code 1. In stdio mode, print JSON to stdout and raise instead."""
+def _die(msg: str, code: Optional[str] = None) -> None:
+    """Write error to stderr and exit with code 1. In stdio mode, print JSON to stdout and raise instead.
+
+    `code` is a machine-readable error kind, added to the JSON error for clients to branch on.
+    """
     print(msg, file=sys.stderr)
     if _stdio_mode:
-        print(json.dumps({"type": "error", "message": msg}, ensure_ascii=False), flush=True)
+        err = {"type": "error", "message": msg}
+        if code:
+            err["code"] = code
+        print(json.dumps(err, ensure_ascii=False), flush=True)
         raise StdioModeError(msg)
     sys.exit(1)
 
 
+def _seed_arg(value) -> int:
+    """Parse a --seed / "seed" value: an integer in u64 range."""
+    try:
+        seed = int(value)
+    except (TypeError, ValueError):
+        raise ValueError(f"seed must be an integer, got {value!r}")
+    if isinstance(value, (bool, float)) or not 0 <= seed < 2 ** 64:
+        raise ValueError(f"seed must be an integer between 0 and {2 ** 64 - 1}, got {value!r}")
+    return seed
+
+
+def _apply_seed(config, seed):
+    """Pin randomness for reproducible runs: seed ``random`` (stub embeddings) and the LLM's sampling.
+
+    Returns config with llm.seed set; config and seed None are passed through.
+    """
+    if seed is None:
+        return config
+    try:
+        seed = _seed_arg(seed)
+    except ValueError as e:
+        _die(str(e))
+    random.seed(seed)
+    if config is None:
+        return None
+    from .config import apply_overrides
+    return apply_overrides(config, {"llm": {"seed": seed}})
+
+
+def _cancel_requested() -> bool:
+    """True if the client cancelled the request currently being served (stdio mode only)."""
+    req_id = getattr(sys.stdout, "req_id", None)
+    return req_id is not None and req_id in _cancelled_req_ids
+
+
 def _ensure_db(path: str):
     """Ensure db exists and return connection. Die on failure."""
     if not os.path.exists(path):
@@ -176,6 +242,188 @@ def _cmd_list_sessions(args) -> None:
         conn.close()
 
 
+# ---------------------------------------------------------------------------
+# list_talkers
+# ---------------------------------------------------------------------------
+
+
+def _cmd_list_talkers(args) -> None:
+    """Talkers for a picker: id, name and message stats only (no build status lookups)."""
+    conn = _ensure_db(args.db)
+    try:
+        result = [
+            {
+                "id": s["talker_id"],
+                "name": s["display_name"],
+                "message_count": s["message_count"],
+                "last_timestamp": s["last_timestamp"],
+            }
+            for s in get_talkers_with_stats(conn)
+        ]
+        print(json.dumps(result, ensure_ascii=False), flush=True)
+    finally:
+        conn.close()
+
+
+def _talker_info(db_path: str, talker_id: str) -> Optional[dict]:
+    """list_talkers entry of `talker_id`, or None if it has no messages."""
+    conn = _ensure_db(db_path)
+    try:
+        for s in get_talkers_with_stats(conn):
+            if s["talker_id"] == talker_id:
+                return {
+                    "id": s["talker_id"],
+                    "name": s["display_name"],
+                    "message_count": s["message_count"],
+                    "last_timestamp": s["last_timestamp"],
+                }
+        return None
+    finally:
+        conn.close()
+
+
+# ---------------------------------------------------------------------------
+# warmup
+# ---------------------------------------------------------------------------
+
+
+def _cmd_warmup(args) -> None:
+    """Pay the lazy import/client setup cost of the first query up front (stdio only).
+
+    Each stage is best-effort: a missing config or vector store is reported in "skipped", not as an error.
+    """
+    start_ms = int(time.time() * 1000)
+    loaded, skipped = [], []
+    from .tools import get_all_tools  # noqa: F401
+    loaded.append("tools")
+    try:
+        import chromadb  # noqa: F401
+        loaded.append("chromadb")
+    except Exception as e:
+        skipped.append({"stage": "chromadb", "reason": str(e)})
+    try:
+        from .config import load_config
+        from .llm import from_config
+        from_config(load_config(args.config))
+        loaded.append("llm")
+    except Exception as e:
+        skipped.append({"stage": "llm", "reason": str(e)})
+    out = {
+        "type": "warm",
+        "loaded": loaded,
+        "skipped": skipped,
+        "duration_ms": int(time.time() * 1000) - start_ms,
+    }
+    print(json.dumps(out, ensure_ascii=False), flush=True)
+
+
+# ---------------------------------------------------------------------------
+# db_status
+# ---------------------------------------------------------------------------
+
+
+def _cmd_db_status(args) -> None:
+    """Open the database, creating or migrating it as needed, and report what that took (stdio only).
+
+    status is "created" for a new (missing or empty) file, "migrated" when an older schema was brought up
+    to date, "current" otherwise, and "newer" for a database from a newer app, which is left untouched.
+    """
+    path = args.db
+    created = not os.path.exists(path) or os.path.getsize(path) == 0
+    previous = None
+    try:
+        if not created:
+            conn = sqlite3.connect(path)
+            try:
+                previous = get_schema_version(conn)
+            finally:
+                conn.close()
+        if previous is not None and previous > SCHEMA_VERSION:
+            status = "newer"
+            talkers = None
+        else:
+            conn = init_db(path)
+            try:
+                talkers = len(get_talkers_with_stats(conn))
+            finally:
+                conn.close()
+            if created:
+                status = "created"
+            elif previous < SCHEMA_VERSION:
+                status = "migrated"
+            else:
+                status = "current"
+    except sqlite3.Error as e:
+        _die(f"Cannot open database {path}: {e}", code="bad_database")
+    out = {
+        "type": "db_status",
+        "path": path,
+        "status": status,
+        "created": created,
+        "migrated": status in ("created", "migrated"),
+        "schema_version": SCHEMA_VERSION,
+        "previous_version": previous,
+        "talker_count": talkers,
+        "empty": talkers == 0,
+    }
+    print(json.dumps(out, ensure_ascii=False), flush=True)
+
+
+# ---------------------------------------------------------------------------
+# check_credentials
+# ---------------------------------------------------------------------------
+
+
+def _credential_error_code(exc: BaseException) -> str:
+    """Error code for a failed credential check: auth_failed, no_network or check_failed.
+
+    Matches on class names so the openai SDK isn't needed to classify (and tests can raise look-alikes).
+    """
+    names = {cls.__name__ for cls in type(exc).__mro__}
+    if names & {"AuthenticationError", "PermissionDeniedError"}:
+        return "auth_failed"
+    if names & {"APIConnectionError", "APITimeoutError", "ConnectionError", "TimeoutError"}:
+        return "no_network"
+    return "check_failed"
+
+
+def _cmd_check_credentials(args) -> None:
+    """Send a one-token chat completion with the configured LLM, to confirm the key works before a query."""
+    from .config import load_config, apply_overrides
+    try:
+        config = load_config(args.config)
+        if args.config_overrides:
+            config = apply_overrides(config, args.config_overrides)
+    except FileNotFoundError as e:
+        _die(str(e))
+    except Exception as e:
+        _die(f"Failed to load config: {e}")
+    start_ms = int(time.time() * 1000)
+    try:
+        from openai import OpenAI
+        from .llm import _normalize_chat_base_url
+        client = OpenAI(
+            api_key=config.llm.api_key,
+            base_url=_normalize_chat_base_url(config.llm.base_url),
+            max_retries=0,
+        )
+        client.chat.completions.create(
+            model=config.llm.model,
+            messages=[{"role": "user", "content": "ping"}],
+            max_tokens=1,
+        )
+    except Exception as e:
+        _die(f"Credential check failed: {e}", code=_credential_error_code(e))
+    out = {
+        "type": "credentials",
+        "ok": True,
+        "provider": config.llm.provider,
+        "model": config.llm.model,
+        "latency_ms": int(time.time() * 1000) - start_ms,
+    }
+    print(json.dumps(out, ensure_ascii=False), flush=True)
+
+
 # ---------------------------------------------------------------------------
 # get_messages
 # ---------------------------------------------------------------------------
@@ -311,13 +559,32 @@ def _serialize_trace_steps_for_progress(steps: list, start_ms: int, end_ms: int)
     return out
 
 
+def _verbose_state(state: dict) -> dict:
+    """Workflow state behind a verbose progress line: the parsed intent, searches, what was collected and
+    how the evaluator judged it so far. Dependencies (llm, conn, tools) are left out."""
+    intent = state.get("intent")
+    return {
+        "intent": dataclasses.asdict(intent) if dataclasses.is_dataclass(intent) else intent,
+        "search_queries": list(state.get("search_queries") or []),
+        "collected_node_ids": [node.node_id for node in state.get("collected_nodes") or []],
+        "collected_message_counts": {
+            node_id: len(messages) for node_id, messages in (state.get("collected_messages") or {}).items()
+        },
+        "evaluation": state.get("evaluation"),
+        "iterations": state.get("iterations"),
+        "answer_mode": state.get("answer_mode"),
+    }
+
+
 def _cmd_query(args) -> None:
     conn = _ensure_db(args.db)
     try:
         from .tools import get_all_tools
 
+        seed = getattr(args, "seed", None)
         if args.stub:
             from .llm import StubCoTLLM, StubNonCoTLLM
+            _apply_seed(None, seed)
             llm_cot = StubCoTLLM()
             llm_noncot = StubNonCoTLLM()
         else:
@@ -329,12 +596,14 @@ def _cmd_query(args) -> None:
             overrides = getattr(args, "config_overrides", None)
             if overrides:
                 config = apply_overrides(config, overrides)
+            config = _apply_seed(config, seed)
             llm_noncot, llm_cot, reranker = from_config(config)
 
         chroma_dir = args.chroma_dir or os.path.join(os.path.dirname(args.db), "chroma")
         tools = get_all_tools(conn, args.talker, chroma_dir, llm_noncot)
 
         start_ms = int(time.time() * 1000)
+        question = _with_attachments(args.question, getattr(args, "attachments", None))
 
         if args.stream:
             # Stream mode: output NDJSON progress lines, then final result
@@ -345,7 +614,7 @@ def _cmd_query(args) -> None:
             print(json.dumps({"type": "progress", "trace_steps": []}, ensure_ascii=False), flush=True)
 
             for steps, full_state in run_workflow_stream_values(
-                question=args.question,
+                question=question,
                 talker_id=args.talker,
                 llm=llm_cot,
                 conn=conn,
@@ -354,11 +623,16 @@ def _cmd_query(args) -> None:
                 max_iterations=3,
                 debug=False,
             ):
+                if _cancel_requested():
+                    print(json.dumps({"type": "error", "message": "cancelled"}, ensure_ascii=False), flush=True)
+                    return
                 if steps:
                     end_ms = int(time.time() * 1000)
                     steps_out = _serialize_trace_steps_for_progress(steps, start_ms, end_ms)
                     progress = {"type": "progress", "trace_steps": steps_out}
-                    print(json.dumps(progress, ensure_ascii=False), flush=True)
+                    if getattr(args, "verbose", False):
+                        progress["state"] = _verbose_state(full_state)
+                    print(json.dumps(progress, ensure_ascii=False, default=str), flush=True)
 
             end_ms = int(time.time() * 1000)
             # Build final trace from last full_state
@@ -383,10 +657,11 @@ def _cmd_query(args) -> None:
                 factual_answer=factual_answer,
             )
             resp = _build_query_response(trace, args.talker, start_ms, end_ms, conn)
+            _record_transcript(resp, end_ms)
             print(json.dumps({"type": "result", **resp}, ensure_ascii=False), flush=True)
         else:
             trace = run_workflow(
-                question=args.question,
+                question=question,
                 talker_id=args.talker,
                 llm=llm_cot,
                 conn=conn,
@@ -398,6 +673,7 @@ def _cmd_query(args) -> None:
             )
             end_ms = int(time.time() * 1000)
             resp = _build_query_response(trace, args.talker, start_ms, end_ms, conn)
+            _record_transcript(resp, end_ms)
             print(json.dumps(resp, ensure_ascii=False), flush=True)
     except Exception as e:
         _die(f"query failed: {e}")
@@ -405,6 +681,61 @@ def _cmd_query(args) -> None:
         conn.close()
 
 
+def _with_attachments(question: str, attachments) -> str:
+    """The question followed by the text of attached files ({"name", "content"} as sent by the app).
+
+    Binary attachments only carry their path and are mentioned by name.
+    """
+    if not attachments:
+        return question
+    parts = [question]
+    for attachment in attachments:
+        name = attachment.get("name") or attachment.get("path") or "attachment"
+        if "content" in attachment:
+            parts.append(f"[Attached file: {name}]\n{attachment['content']}")
+        else:
+            parts.append(f"[Attached binary file: {name}, {attachment.get('size', '?')} bytes]")
+    return "\n\n".join(parts)
+
+
+def _record_transcript(resp: dict, timestamp_ms: int) -> None:
+    """Keep a query's answer for export (daemon only); the message list and agent trace are left out."""
+    if not _stdio_mode:
+        return
+    _transcript.append({
+        "talker": resp.get("conversation_id"),
+        "question": resp.get("question"),
+        "timestamp_ms": timestamp_ms,
+        "answer_mode": resp.get("answer_mode"),
+        "factual_answer": resp.get("factual_answer"),
+        "phases": resp.get("phases", []),
+    })
+
+
+# Formats accepted by {"cmd":"export"}
+_EXPORT_FORMATS = ["md", "json"]
+
+
+def _render_transcript(entries: list, fmt: str) -> str:
+    """Session transcript as Markdown (questions as headings, answers as text) or as JSON."""
+    if fmt == "json":
+        return json.dumps(entries, ensure_ascii=False, indent=2)
+    lines = ["# Narrative Mirror transcript", ""]
+    for entry in entries:
+        lines += [f"## {entry.get('question') or ''}", "", f"*{entry.get('talker') or ''}*", ""]
+        factual = entry.get("factual_answer")
+        if factual:
+            lines += [factual.get("answer", ""), ""]
+        for phase in entry.get("phases") or []:
+            title = phase.get("phase_title", "")
+            time_range = phase.get("time_range")
+            lines += [f"### {title} ({time_range})" if time_range else f"### {title}", ""]
+            lines += [phase.get("core_conclusion", ""), ""]
+            if phase.get("uncertainty_note"):
+                lines += [f"> {phase['uncertainty_note']}", ""]
+    return "\n".join(lines)
+
+
 # ---------------------------------------------------------------------------
 # import
 # ---------------------------------------------------------------------------
@@ -500,12 +831,15 @@ def _cmd_import(args) -> None:
 
 
 def _cmd_delete_session(args) -> None:
+    global _active_talker
     conn = _ensure_db(args.db)
     try:
         stats = get_talkers_with_stats(conn)
         if not any(s["talker_id"] == args.talker for s in stats):
             _die(f"Session not found: {args.talker}")
-        delete_session(conn, args.talker)
+        rows_deleted = delete_session(conn, args.talker)
+        if _active_talker == args.talker:
+            _active_talker = None
         chroma_dir = args.chroma_dir or os.path.join(os.path.dirname(args.db), "chroma")
         chroma_path = os.path.join(chroma_dir, "chroma")
         if os.path.isdir(chroma_path):
@@ -519,7 +853,7 @@ def _cmd_delete_session(args) -> None:
                     pass
             except Exception:
                 pass
-        out = {"status": "deleted", "talker_id": args.talker}
+        out = {"status": "deleted", "talker_id": args.talker, "rows_deleted": rows_deleted}
         print(json.dumps(out, ensure_ascii=False), flush=True)
     finally:
         conn.close()
@@ -530,7 +864,50 @@ def _cmd_delete_session(args) -> None:
 # ---------------------------------------------------------------------------
 
 
+def _validate_build(args) -> None:
+    """build --dry-run: check talker, config and overrides without touching the db (opened read-only)."""
+    errors = []
+    message_count = 0
+    if not os.path.exists(args.db):
+        errors.append(f"Database file not found: {args.db}")
+    else:
+        stats = {}
+        try:
+            conn = sqlite3.connect(f"file:{args.db}?mode=ro", uri=True)
+            try:
+                stats = {s["talker_id"]: s for s in get_talkers_with_stats(conn)}
+            finally:
+                conn.close()
+        except sqlite3.Error as e:
+            errors.append(f"Cannot read database: {e}")
+        if args.talker in stats:
+            message_count = stats[args.talker]["message_count"]
+        elif not errors:
+            errors.append(f"Unknown talker: {args.talker}")
+    try:
+        from .config import load_config, apply_overrides
+        from .llm import from_config
+        config = load_config(args.config)
+        overrides_raw = getattr(args, "config_overrides", None)
+        if overrides_raw:
+            config = apply_overrides(config, json.loads(overrides_raw) if isinstance(overrides_raw, str) else overrides_raw)
+        from_config(config)
+    except Exception as e:
+        errors.append(f"Invalid config: {e}")
+    out = {
+        "type": "validation",
+        "ok": not errors,
+        "talker_id": args.talker,
+        "message_count": message_count,
+        "errors": errors,
+    }
+    print(json.dumps(out, ensure_ascii=False), flush=True)
+
+
 def _cmd_build(args) -> None:
+    if getattr(args, "dry_run", False):
+        _validate_build(args)
+        return
     conn = _ensure_db(args.db)
     try:
         from .config import load_config
@@ -540,10 +917,13 @@ def _cmd_build(args) -> None:
         from .layer2 import build_layer2
 
         talker_id = args.talker
-        debug = getattr(args, "debug", False)
+        debug = getattr(args, "debug", False) or getattr(args, "log_level", "info") in ("debug", "trace")
 
         def on_progress(stage: str, step: str, detail: str) -> None:
             set_build_progress(conn, talker_id, stage, step, detail)
+            # Also stream to stdout so the client can show live build progress
+            progress = {"type": "progress", "stage": stage, "step": step, "detail": detail}
+            print(json.dumps(progress, ensure_ascii=False), flush=True)
 
         config = load_config(args.config)
         overrides_raw = getattr(args, "config_overrides", None)
@@ -551,11 +931,16 @@ def _cmd_build(args) -> None:
             from .config import apply_overrides
             overrides = json.loads(overrides_raw) if isinstance(overrides_raw, str) else overrides_raw
             config = apply_overrides(config, overrides)
+        jobs = getattr(args, "jobs", None)
+        if jobs:
+            from .config import apply_overrides
+            config = apply_overrides(config, {"llm": {"max_workers": jobs}})
+        config = _apply_seed(config, getattr(args, "seed", None))
         llm_noncot, llm_cot, reranker = from_config(config)
         source = _SqliteDataSource(conn, talker_id)
 
         try:
-            set_build_progress(conn, talker_id, "layer1", "start", "开始构建 Layer 1：消息聚合与话题分类")
+            on_progress("layer1", "start", "开始构建 Layer 1：消息聚合与话题分类")
             build_layer1(
                 talker_id=talker_id,
                 source=source,
@@ -570,7 +955,7 @@ def _cmd_build(args) -> None:
             _die(str(e))
 
         try:
-            set_build_progress(conn, talker_id, "layer1.5", "metadata", "计算元数据与异常锚点")
+            on_progress("layer1.5", "metadata", "计算元数据与异常锚点")
             build_layer15(
                 talker_id=talker_id,
                 llm=llm_noncot,
@@ -583,7 +968,7 @@ def _cmd_build(args) -> None:
 
         chroma_dir = args.chroma_dir or os.path.join(os.path.dirname(args.db), "chroma")
         try:
-            set_build_progress(conn, talker_id, "layer2", "start", "开始构建 Layer 2：语义链路")
+            on_progress("layer2", "start", "开始构建 Layer 2：语义链路")
             build_layer2(
                 talker_id=talker_id,
                 llm_noncot=llm_noncot,
@@ -607,6 +992,45 @@ def _cmd_build(args) -> None:
         conn.close()
 
 
+def _cmd_reindex(args) -> None:
+    """Re-embed a built conversation's nodes with the current embedding settings, without a full build."""
+    conn = _ensure_db(args.db)
+    try:
+        talker_id = args.talker
+        if get_nodes_count(conn, talker_id) == 0:
+            _die(f"No nodes to reindex for {talker_id}; run a full build first")
+
+        from .config import load_config
+        from .llm import from_config
+        from .layer2 import reindex_embeddings
+
+        def on_progress(stage: str, step: str, detail: str) -> None:
+            progress = {"type": "progress", "stage": stage, "step": step, "detail": detail}
+            print(json.dumps(progress, ensure_ascii=False), flush=True)
+
+        config = load_config(args.config)
+        overrides_raw = getattr(args, "config_overrides", None)
+        if overrides_raw:
+            from .config import apply_overrides
+            overrides = json.loads(overrides_raw) if isinstance(overrides_raw, str) else overrides_raw
+            config = apply_overrides(config, overrides)
+        llm_noncot, _, _ = from_config(config)
+
+        chroma_dir = args.chroma_dir or os.path.join(os.path.dirname(args.db), "chroma")
+        try:
+            embedded = reindex_embeddings(talker_id, llm_noncot, conn, chroma_dir, progress_callback=on_progress)
+        except Exception as e:
+            print("reindex", file=sys.stderr)
+            _die(str(e))
+
+        out = {"status": "complete", "talker_id": talker_id, "embedded": embedded}
+        print(json.dumps(out, ensure_ascii=False), flush=True)
+    except Exception as e:
+        _die(str(e))
+    finally:
+        conn.close()
+
+
 # ---------------------------------------------------------------------------
 # stdio daemon (one process per client lifecycle)
 # ---------------------------------------------------------------------------
@@ -619,76 +1043,602 @@ class _Namespace:
         self.__dict__.update(d)
 
 
+# Framing modes the stdio daemon can switch to with {"cmd":"set_framing"}; advertised in the pong
+_FRAMING_MODES = ["length"]
+
+# Encodings for large response lines a client can accept with {"cmd":"ping","accept_encoding":[...]}
+_ENCODINGS = ["gzip"]
+
+# Response lines shorter than this go out as plain JSON even when the client accepts gzip
+_COMPRESS_MIN_BYTES = 64 * 1024
+
+# Version of the stdio protocol; bump on incompatible changes. Reported in the pong and by capabilities
+PROTOCOL_VERSION = 1
+
+# Commands dispatched by _stdio_command
+_STDIO_COMMANDS = [
+    "get_config", "list_sessions", "list_talkers", "warmup", "get_messages", "query", "import", "delete_session",
+    "delete_talker", "check_credentials", "db_status",
+]
+
+# Commands handled by the stdio loop itself
+_CONTROL_COMMANDS = [
+    "ping", "shutdown", "cancel", "capabilities", "set_framing", "reload_config", "batch", "export", "select_talker",
+    "history", "reset", "validate_config",
+]
+
+# Optional behaviours a client can feature-detect
+_FEATURES = [
+    "streaming", "cancel", "batch", "framing:length", "reload_config", "warmup", "export", "select_talker", "history",
+    "multiplex", "reset", "http", "validate_config", "encoding:gzip",
+]
+
+# Streaming queries served at once, each on its own thread; advertised in the pong as max_concurrent
+_MAX_CONCURRENT_STREAMS = 4
+
+# Header carrying the token from the endpoint line; requests to the --http server without it are refused
+_HTTP_TOKEN_HEADER = "X-Narrarc-Token"
+
+# Response lines of these types are followed by more; any other ends the request's HTTP response
+_INTERIM_TYPES = ("progress", "partial")
+
+
+def _read_frame(stream) -> Optional[bytes]:
+    """Read one length-prefixed frame (4-byte big-endian length, then payload); None at EOF."""
+    header = stream.read(4)
+    if len(header) < 4:
+        return None
+    payload = stream.read(int.from_bytes(header, "big"))
+    return payload
+
+
+def _encode(obj, line: str, encoding: str) -> dict:
+    """A response line as {"type": ..., "encoding": "gzip", "data": <base64 of the gzipped line>}.
+
+    `type` is the line's own type (or "result" for lines without one), so a client that routes on it
+    before decoding still sees whether the request is done.
+    """
+    kind = obj.get("type") if isinstance(obj, dict) else None
+    packed = base64.b64encode(gzip.compress(line.encode("utf-8"))).decode("ascii")
+    return {"type": kind or "result", "encoding": encoding, "data": packed}
+
+
+class _ReqIdWriter:
+    """Stdout wrapper for stdio mode: wraps every JSON line as {"req_id": ..., "data": <line>}.
+
+    The client routes response lines by req_id, so _cmd_* functions can keep printing plain JSON.
+    Non-JSON output (stray prints) passes through untagged and is ignored by the client. Once `framed` is
+    set, each JSON line goes out as a length-prefixed frame instead and stray prints go to stderr.
+
+    req_id, the partial-line buffer and `capture` are per thread, so streaming queries served concurrently
+    tag their own lines; whole lines are written under a lock so they never interleave mid-line.
+
+    With `encoding` set (negotiated in the ping), lines of at least _COMPRESS_MIN_BYTES are sent compressed;
+    see _encode.
+    """
+
+    def __init__(self, inner) -> None:
+        self._inner = inner
+        self._local = threading.local()
+        self._lock = threading.Lock()
+        self.framed = False
+        self.encoding = None
+
+    @property
+    def req_id(self):
+        return getattr(self._local, "req_id", None)
+
+    @req_id.setter
+    def req_id(self, value) -> None:
+        self._local.req_id = value
+
+    @property
+    def capture(self):
+        """Writer this thread's output goes to instead (a batch item's _CaptureWriter), or None."""
+        return getattr(self._local, "capture", None)
+
+    @capture.setter
+    def capture(self, value) -> None:
+        self._local.capture = value
+
+    def write(self, s: str) -> int:
+        if self.capture is not None:
+            return self.capture.write(s)
+        buf = getattr(self._local, "buf", "") + s
+        while "\n" in buf:
+            line, buf = buf.split("\n", 1)
+            with self._lock:
+                self._emit(line)
+        self._local.buf = buf
+        return len(s)
+
+    def _emit(self, line: str) -> None:
+        if not self.framed:
+            self._inner.write(self._tag(line) + "\n")
+            self._inner.flush()
+            return
+        try:
+            json.loads(line)
+        except ValueError:
+            # A stray print would corrupt the frame stream
+            if line.strip():
+                sys.stderr.write(line + "\n")
+            return
+        payload = self._tag(line).encode("utf-8")
+        self._inner.flush()
+        self._inner.buffer.write(len(payload).to_bytes(4, "big") + payload)
+        self._inner.buffer.flush()
+
+    def _tag(self, line: str) -> str:
+        if self.req_id is None:
+            return line
+        try:
+            obj = json.loads(line)
+        except ValueError:
+            return line
+        if self.encoding and len(line) >= _COMPRESS_MIN_BYTES:
+            obj = _encode(obj, line, self.encoding)
+        return json.dumps({"req_id": self.req_id, "data": obj}, ensure_ascii=False)
+
+    def flush(self) -> None:
+        self._inner.flush()
+
+    def __getattr__(self, name):
+        return getattr(self._inner, name)
+
+
+class _HttpResponses:
+    """Inner stream of the _ReqIdWriter in --http mode: a tagged line goes to the HTTP response waiting for
+    its req_id, anything else (untagged lines, requests nobody waits for) to real stdout."""
+
+    def __init__(self, inner) -> None:
+        self._inner = inner
+        self._lock = threading.Lock()
+        self._waiting: dict = {}
+
+    def open(self, req_id) -> queue.Queue:
+        replies: queue.Queue = queue.Queue()
+        with self._lock:
+            self._waiting[req_id] = replies
+        return replies
+
+    def close(self, req_id) -> None:
+        with self._lock:
+            self._waiting.pop(req_id, None)
+
+    def write(self, s: str) -> int:
+        # _ReqIdWriter writes whole lines
+        for line in s.splitlines():
+            try:
+                msg = json.loads(line)
+            except ValueError:
+                msg = None
+            with self._lock:
+                replies = self._waiting.get(msg.get("req_id")) if isinstance(msg, dict) else None
+            if replies is None:
+                self._inner.write(line + "\n")
+            else:
+                replies.put(line)
+        return len(s)
+
+    def flush(self) -> None:
+        self._inner.flush()
+
+    def __getattr__(self, name):
+        return getattr(self._inner, name)
+
+
+def _http_handler(lines: queue.Queue, responses: _HttpResponses, token: str):
+    """Request handler for `stdio --http`: each POST /rpc body is one message, queued like a stdin line; the
+    response streams that request's tagged lines as NDJSON until its final line."""
+
+    class Handler(http.server.BaseHTTPRequestHandler):
+        def do_POST(self) -> None:
+            if self.path != "/rpc":
+                self.send_error(404)
+                return
+            if not secrets.compare_digest(self.headers.get(_HTTP_TOKEN_HEADER, ""), token):
+                self.send_error(403)
+                return
+            body = self.rfile.read(int(self.headers.get("Content-Length") or 0)).decode("utf-8", errors="replace")
+            try:
+                msg = json.loads(body)
+            except json.JSONDecodeError:
+                msg = None
+            self.send_response(200)
+            self.send_header("Content-Type", "application/x-ndjson")
+            self.end_headers()
+            if isinstance(msg, dict) and msg.get("cmd") == "cancel":
+                _cancelled_req_ids.add(msg.get("req_id"))
+                return
+            req_id = msg.get("req_id") if isinstance(msg, dict) else None
+            if req_id is None:
+                lines.put(body)
+                return
+            replies = responses.open(req_id)
+            lines.put(body)
+            try:
+                while True:
+                    line = replies.get()
+                    self.wfile.write(line.encode("utf-8") + b"\n")
+                    self.wfile.flush()
+                    data = json.loads(line).get("data")
+                    if not (isinstance(data, dict) and data.get("type") in _INTERIM_TYPES):
+                        break
+            except OSError:
+                pass  # The client went away; the request still runs to completion
+            finally:
+                responses.close(req_id)
+
+        def log_message(self, format, *args) -> None:
+            logging.getLogger(__name__).debug("http: " + format, *args)
+
+    return Handler
+
+
+def _stdio_command(cmd: str, data: dict, default_db: str, default_config: str):
+    """(func, namespace) serving stdio `cmd`, or None for an unknown cmd.
+
+    Payload keys match CLI option names (e.g. talker, limit, offset); missing ones get the daemon defaults.
+    """
+    base = {"db": default_db}
+    if cmd == "get_config":
+        ns = _Namespace({"config": data.get("config") or default_config})
+        func = _cmd_get_config
+    elif cmd == "list_sessions":
+        ns = _Namespace(base)
+        func = _cmd_list_sessions
+    elif cmd == "list_talkers":
+        ns = _Namespace(base)
+        func = _cmd_list_talkers
+    elif cmd == "warmup":
+        ns = _Namespace({"config": data.get("config") or default_config})
+        func = _cmd_warmup
+    elif cmd == "db_status":
+        ns = _Namespace(base)
+        func = _cmd_db_status
+    elif cmd == "check_credentials":
+        ns = _Namespace({
+            "config": data.get("config") or default_config,
+            "config_overrides": data.get("config_overrides"),
+        })
+        func = _cmd_check_credentials
+    elif cmd == "get_messages":
+        ns = _Namespace({
+            **base,
+            "talker": data.get("talker"),
+            "limit": data.get("limit"),
+            "offset": data.get("offset", 0),
+        })
+        func = _cmd_get_messages
+    elif cmd == "query":
+        ns = _Namespace({
+            **base,
+            "talker": data.get("talker") or _active_talker,
+            "question": data.get("question"),
+            "config": data.get("config") or default_config,
+            "config_overrides": data.get("config_overrides"),
+            "chroma_dir": data.get("chroma_dir"),
+            "stub": data.get("stub", False),
+            "stream": data.get("stream", False),
+            "seed": data.get("seed"),
+            "attachments": data.get("attachments"),
+            "verbose": data.get("verbose", False),
+        })
+        func = _cmd_query
+    elif cmd == "import":
+        ns = _Namespace({**base, "file": data.get("file")})
+        func = _cmd_import
+    elif cmd in ("delete_session", "delete_talker"):
+        ns = _Namespace({
+            **base,
+            "talker": data.get("talker"),
+            "chroma_dir": data.get("chroma_dir"),
+        })
+        func = _cmd_delete_session
+    else:
+        return None
+    return func, ns
+
+
+class _CaptureWriter:
+    """Stdout stand-in for one batch item: collects its JSON output lines instead of writing them."""
+
+    def __init__(self, req_id) -> None:
+        self.req_id = req_id
+        self.lines: list = []
+        self._buf = ""
+
+    def write(self, s: str) -> int:
+        self._buf += s
+        while "\n" in self._buf:
+            line, self._buf = self._buf.split("\n", 1)
+            if line.strip():
+                self.lines.append(line)
+        return len(s)
+
+    def flush(self) -> None:
+        pass
+
+
+def _run_batch(items, default_db: str, default_config: str) -> list:
+    """Run each batch item in order and return its response (the last JSON line it printed).
+
+    A failing item yields {"type": "error", ...} in its slot; the other items still run.
+    Streaming queries and control commands are not allowed in a batch.
+    """
+    results = []
+    outer = sys.stdout
+    for item in items:
+        cmd = item.get("cmd") if isinstance(item, dict) else None
+        if cmd in ("batch", "shutdown", "ping") or (cmd == "query" and item.get("stream")):
+            results.append({"type": "error", "message": f"cmd not allowed in batch: {cmd}"})
+            continue
+        resolved = _stdio_command(cmd, item, default_db, default_config) if cmd else None
+        if resolved is None:
+            if cmd:
+                results.append({"type": "error", "message": f"Unknown cmd: {cmd}", "code": "unknown_cmd"})
+            else:
+                results.append({"type": "error", "message": "Missing 'cmd' field"})
+            continue
+        func, ns = resolved
+        capture = _CaptureWriter(getattr(outer, "req_id", None))
+        outer.capture = capture
+        try:
+            func(ns)
+        except StdioModeError:
+            pass
+        except Exception as e:
+            print(json.dumps({"type": "error", "message": str(e)}, ensure_ascii=False))
+        finally:
+            outer.capture = None
+        result = None
+        for line in reversed(capture.lines):
+            try:
+                result = json.loads(line)
+                break
+            except ValueError:
+                continue
+        results.append(result)
+    return results
+
 def _cmd_stdio(args) -> None:
-    """Read JSON lines from stdin, dispatch to existing _cmd_* by cmd, write responses to stdout."""
-    global _stdio_mode
+    """Read JSON lines from stdin, dispatch to existing _cmd_* by cmd, write responses to stdout.
+
+    With --http, requests can also be POSTed to a local HTTP server, announced on stdout as
+    {"type":"http","port":...,"token":...} before anything else; see _http_handler. Framing is not offered
+    then. The daemon still exits when stdin closes.
+    """
+    global _stdio_mode, _active_talker
     _stdio_mode = True
     default_db = args.db
     default_config = getattr(args, "config", None) or "config.yml"
-
-    for line in sys.stdin:
+    http_mode = getattr(args, "http", False)
+    framing_modes = [] if http_mode else _FRAMING_MODES
+    lines: queue.Queue = queue.Queue()
+    server = None
+    if http_mode:
+        responses = _HttpResponses(sys.stdout)
+        token = secrets.token_hex(16)
+        server = http.server.ThreadingHTTPServer(("127.0.0.1", 0), _http_handler(lines, responses, token))
+        server.daemon_threads = True
+        threading.Thread(target=server.serve_forever, daemon=True).start()
+        endpoint = {"type": "http", "port": server.server_address[1], "token": token}
+        print(json.dumps(endpoint), flush=True)
+        out = _ReqIdWriter(responses)
+    else:
+        out = _ReqIdWriter(sys.stdout)
+    sys.stdout = out
+
+    def _read_stdin() -> None:
+        # Runs beside the dispatch loop so a cancel is seen while a streaming query is still running.
+        # Reads bytes so it can switch to frames right after set_framing (the client sends nothing else
+        # until it gets the acknowledgement).
+        stream = sys.stdin.buffer
+        framed = False
+        while True:
+            raw = _read_frame(stream) if framed else stream.readline()
+            if not raw:
+                break
+            raw = raw.decode("utf-8", errors="replace")
+            try:
+                msg = json.loads(raw)
+            except json.JSONDecodeError:
+                msg = None
+            if isinstance(msg, dict) and msg.get("cmd") == "cancel":
+                _cancelled_req_ids.add(msg.get("req_id"))
+                continue
+            if isinstance(msg, dict) and msg.get("cmd") == "set_framing" and msg.get("mode") in framing_modes:
+                framed = True
+            lines.put(raw)
+        lines.put(None)
+
+    threading.Thread(target=_read_stdin, daemon=True).start()
+    streams = threading.Semaphore(_MAX_CONCURRENT_STREAMS)
+    stream_threads: list = []
+
+    while True:
+        line = lines.get()
+        if line is None:
+            break
         line = line.strip()
         if not line:
             continue
+        out.req_id = None
         try:
             data = json.loads(line)
         except json.JSONDecodeError as e:
             print(json.dumps({"type": "error", "message": f"Invalid JSON: {e}"}, ensure_ascii=False), flush=True)
             continue
+        if isinstance(data, dict):
+            out.req_id = data.get("req_id")
 
         cmd = data.get("cmd")
         if not cmd:
             print(json.dumps({"type": "error", "message": "Missing 'cmd' field"}, ensure_ascii=False), flush=True)
             continue
+        if cmd == "shutdown":
+            # Graceful exit requested by the client: return so open connections are closed normally
+            break
+        if cmd == "ping":
+            # Readiness/health check: answered once imports are done and the loop is serving. A ping with
+            # accept_encoding turns on compression of large lines; the pong reports the encoding chosen.
+            accepted = data.get("accept_encoding")
+            if isinstance(accepted, list):
+                out.encoding = next((e for e in _ENCODINGS if e in accepted), None)
+            pong = {
+                "type": "pong",
+                "framing": framing_modes,
+                "protocol_version": PROTOCOL_VERSION,
+                "max_concurrent": _MAX_CONCURRENT_STREAMS,
+                "encoding": out.encoding,
+            }
+            print(json.dumps(pong), flush=True)
+            continue
+        if cmd == "capabilities":
+            caps = {
+                "type": "capabilities",
+                "protocol_version": PROTOCOL_VERSION,
+                "commands": _CONTROL_COMMANDS + _STDIO_COMMANDS,
+                "features": _FEATURES,
+            }
+            print(json.dumps(caps), flush=True)
+            continue
+        if cmd == "reload_config":
+            # Make `path` the default config of later requests once it loads; answer with its summary
+            path = data.get("path") or default_config
+            try:
+                from .config import load_config, config_to_dict
+                summary = config_to_dict(load_config(path))
+            except Exception as e:
+                print(json.dumps({"type": "error", "message": f"Failed to load config: {e}"}, ensure_ascii=False), flush=True)
+                continue
+            default_config = path
+            print(json.dumps({"type": "config_reloaded", "path": path, "config": summary}, ensure_ascii=False), flush=True)
+            continue
+        if cmd == "validate_config":
+            # Check a config file without loading it: {"cmd":"validate_config","path":...}
+            path = data.get("path") or default_config
+            try:
+                with open(path, "r", encoding="utf-8") as f:
+                    text = f.read()
+            except OSError as e:
+                print(json.dumps({"type": "error", "message": f"Cannot read {path}: {e}"}, ensure_ascii=False), flush=True)
+                continue
+            from .config import validate_config
+            issues = validate_config(text)
+            valid = not any(i["severity"] == "error" for i in issues)
+            print(json.dumps({"type": "config_validation", "path": path, "valid": valid, "issues": issues}, ensure_ascii=False), flush=True)
+            continue
+        if cmd == "set_framing":
+            # Acknowledge in the current framing, then switch; the stdin reader has already switched
+            mode = data.get("mode")
+            if mode not in framing_modes:
+                print(json.dumps({"type": "error", "message": f"Unsupported framing: {mode}"}), flush=True)
+                continue
+            print(json.dumps({"type": "framing", "mode": mode}), flush=True)
+            out.framed = True
+            continue
 
-        # Build namespace with defaults; payload keys match CLI option names (e.g. talker, limit, offset)
-        base = {"db": default_db}
-        if cmd == "get_config":
-            ns = _Namespace({"config": data.get("config") or default_config})
-            func = _cmd_get_config
-        elif cmd == "list_sessions":
-            ns = _Namespace(base)
-            func = _cmd_list_sessions
-        elif cmd == "get_messages":
-            ns = _Namespace({
-                **base,
-                "talker": data.get("talker"),
-                "limit": data.get("limit"),
-                "offset": data.get("offset", 0),
-            })
-            func = _cmd_get_messages
-        elif cmd == "query":
-            ns = _Namespace({
-                **base,
-                "talker": data.get("talker"),
-                "question": data.get("question"),
-                "config": data.get("config") or default_config,
-                "config_overrides": data.get("config_overrides"),
-                "chroma_dir": data.get("chroma_dir"),
-                "stub": data.get("stub", False),
-                "stream": data.get("stream", False),
-            })
-            func = _cmd_query
-        elif cmd == "import":
-            ns = _Namespace({**base, "file": data.get("file")})
-            func = _cmd_import
-        elif cmd == "delete_session":
-            ns = _Namespace({
-                **base,
-                "talker": data.get("talker"),
-                "chroma_dir": data.get("chroma_dir"),
-            })
-            func = _cmd_delete_session
-        else:
-            print(json.dumps({"type": "error", "message": f"Unknown cmd: {cmd}"}, ensure_ascii=False), flush=True)
+        if cmd == "export":
+            # Transcript of the questions answered in this session: {"cmd":"export","format":"md"|"json"}
+            fmt = data.get("format", "md")
+            if fmt not in _EXPORT_FORMATS:
+                msg = f"Unsupported export format: {fmt} (expected one of {', '.join(_EXPORT_FORMATS)})"
+                print(json.dumps({"type": "error", "message": msg}, ensure_ascii=False), flush=True)
+                continue
+            content = _render_transcript(_transcript, fmt)
+            print(json.dumps({"type": "export", "format": fmt, "count": len(_transcript), "content": content}, ensure_ascii=False), flush=True)
             continue
 
-        try:
-            func(ns)
-        except StdioModeError:
-            pass
-        except Exception as e:
-            print(json.dumps({"type": "error", "message": str(e)}, ensure_ascii=False), flush=True)
+        if cmd == "history":
+            # Questions answered in this session, newest first: {"cmd":"history","limit":n}
+            limit = data.get("limit")
+            items = [
+                {"id": i + 1, "talker": e["talker"], "question": e["question"], "timestamp": e["timestamp_ms"]}
+                for i, e in enumerate(_transcript)
+            ][::-1]
+            if isinstance(limit, int) and limit >= 0:
+                items = items[:limit]
+            print(json.dumps({"type": "history", "items": items}, ensure_ascii=False), flush=True)
+            continue
+
+        if cmd == "reset":
+            # Start over without a restart: forget the questions answered for `talker` (all without one)
+            talker = data.get("talker")
+            kept = [e for e in _transcript if talker is not None and e["talker"] != talker]
+            cleared = len(_transcript) - len(kept)
+            _transcript[:] = kept
+            print(json.dumps({"type": "reset", "talker": talker, "cleared": cleared}, ensure_ascii=False), flush=True)
+            continue
+
+        if cmd == "select_talker":
+            # Current conversation: {"cmd":"select_talker","talker":...}; queries without a talker use it
+            talker = data.get("talker")
+            try:
+                info = _talker_info(default_db, talker) if talker else None
+            except StdioModeError:
+                continue
+            except Exception as e:
+                print(json.dumps({"type": "error", "message": str(e)}, ensure_ascii=False), flush=True)
+                continue
+            if info is None:
+                print(json.dumps({"type": "error", "message": f"Unknown talker: {talker}"}, ensure_ascii=False), flush=True)
+                continue
+            _active_talker = talker
+            print(json.dumps({"type": "talker_selected", "talker": info}, ensure_ascii=False), flush=True)
+            continue
+
+        if cmd == "batch":
+            # Several independent requests in one round trip: {"cmd":"batch","items":[{...}, ...]}
+            items = data.get("items")
+            if not isinstance(items, list):
+                print(json.dumps({"type": "error", "message": "'items' must be a list"}, ensure_ascii=False), flush=True)
+                continue
+            results = _run_batch(items, default_db, default_config)
+            print(json.dumps({"type": "batch", "results": results}, ensure_ascii=False), flush=True)
+            continue
+
+        resolved = _stdio_command(cmd, data, default_db, default_config)
+        if resolved is None:
+            # Typed, so clients can tell an older backend from a failing command
+            unknown = {"type": "error", "message": f"Unknown cmd: {cmd}", "code": "unknown_cmd"}
+            print(json.dumps(unknown, ensure_ascii=False), flush=True)
+            continue
+        func, ns = resolved
+
+        if cmd == "query" and data.get("stream"):
+            # Streams run on their own thread so several can interleave; each tags its lines with its req_id
+            thread = threading.Thread(target=_serve_stream, args=(func, ns, out.req_id, streams), daemon=True)
+            thread.start()
+            stream_threads = [t for t in stream_threads if t.is_alive()] + [thread]
+            continue
+        _serve(func, ns)
+
+    # Let streams still running finish before the daemon exits
+    for thread in stream_threads:
+        thread.join()
+    if server is not None:
+        server.shutdown()
+
+
+def _serve(func, ns) -> None:
+    """Run one stdio command on this thread; its errors become {"type":"error"} lines."""
+    try:
+        func(ns)
+    except StdioModeError:
+        pass
+    except Exception as e:
+        print(json.dumps({"type": "error", "message": str(e)}, ensure_ascii=False), flush=True)
+    finally:
+        _cancelled_req_ids.discard(sys.stdout.req_id)
+
+
+def _serve_stream(func, ns, req_id, streams: threading.Semaphore) -> None:
+    """Thread body of a streaming query: waits for one of the _MAX_CONCURRENT_STREAMS slots, then serves it."""
+    sys.stdout.req_id = req_id
+    with streams:
+        _serve(func, ns)
 
 
 # ---------------------------------------------------------------------------
@@ -707,12 +1657,20 @@ def main() -> None:
 
     parser = argparse.ArgumentParser(description="Narrative Mirror JSON CLI")
     parser.add_argument("--db", default="data/mirror.db", help="SQLite database path")
+    parser.add_argument(
+        "--log-level", dest="log_level", choices=list(_LOG_LEVELS), default="info",
+        help="stderr verbosity; debug and trace also turn on build progress logs",
+    )
     subparsers = parser.add_subparsers(dest="cmd", required=True)
 
     # list_sessions
     p_list = subparsers.add_parser("list_sessions")
     p_list.set_defaults(func=_cmd_list_sessions)
 
+    # list_talkers
+    p_talkers = subparsers.add_parser("list_talkers")
+    p_talkers.set_defaults(func=_cmd_list_talkers)
+
     # get_messages
     p_get = subparsers.add_parser("get_messages")
     p_get.add_argument("--talker", required=True, help="Talker ID")
@@ -728,6 +1686,7 @@ def main() -> None:
     p_query.add_argument("--chroma-dir", dest="chroma_dir", default=None, help="ChromaDB directory")
     p_query.add_argument("--stub", action="store_true", help="Use stub LLM for testing")
     p_query.add_argument("--stream", action="store_true", help="Stream progress as NDJSON to stdout")
+    p_query.add_argument("--seed", type=_seed_arg, default=None, help="Pin randomness so repeated runs are comparable")
     p_query.set_defaults(func=_cmd_query)
 
     # import
@@ -747,15 +1706,32 @@ def main() -> None:
     p_build.add_argument("--config", required=True, help="Path to config.yml")
     p_build.add_argument("--config-overrides", dest="config_overrides", default=None, help="JSON string of config overrides")
     p_build.add_argument("--chroma-dir", dest="chroma_dir", default=None, help="ChromaDB directory (optional)")
+    p_build.add_argument("--jobs", type=int, default=None, help="Parallel LLM/embedding workers (overrides llm.max_workers)")
+    p_build.add_argument("--seed", type=_seed_arg, default=None, help="Pin randomness so repeated runs are comparable")
     p_build.add_argument("--debug", action="store_true", help="Print progress logs to stderr")
+    p_build.add_argument("--dry-run", dest="dry_run", action="store_true", help="Only validate the inputs and print the result")
     p_build.set_defaults(func=_cmd_build)
 
+    # reindex (re-embed nodes after embedding settings changed)
+    p_reindex = subparsers.add_parser("reindex")
+    p_reindex.add_argument("--talker", required=True, help="Talker ID")
+    p_reindex.add_argument("--config", required=True, help="Path to config.yml")
+    p_reindex.add_argument("--config-overrides", dest="config_overrides", default=None, help="JSON string of config overrides")
+    p_reindex.add_argument("--chroma-dir", dest="chroma_dir", default=None, help="ChromaDB directory (optional)")
+    p_reindex.set_defaults(func=_cmd_reindex)
+
     # stdio daemon (one process per client; requests as JSON lines on stdin)
     p_stdio = subparsers.add_parser("stdio")
     p_stdio.add_argument("--config", default="config.yml", help="Default config path for query")
+    p_stdio.add_argument("--http", action="store_true", help="Also serve requests over HTTP on 127.0.0.1 (port printed first)")
     p_stdio.set_defaults(func=_cmd_stdio)
 
     args = parser.parse_args()
+    logging.basicConfig(
+        level=_LOG_LEVELS[args.log_level],
+        stream=sys.stderr,
+        format="%(asctime)s %(levelname)s %(name)s: %(message)s",
+    )
     try:
         args.func(args)
     except Exception as e:
@@ -20,6 +20,8 @@ class LLMConfig:
     api_key: str = ""
     base_url: str = "https://api.anthropic.com/v1"
     max_workers: int = 8
+    # Sent as the API's ``seed`` so repeated runs are comparable; None leaves sampling unpinned.
+    seed: int | None = None
 
 
 @dataclass
@@ -67,6 +69,7 @@ def apply_overrides(config: Config, overrides: dict) -> Config:
         "api_key": config.llm.api_key,
         "base_url": config.llm.base_url,
         "max_workers": config.llm.max_workers,
+        "seed": config.llm.seed,
     }
     if overrides.get("llm"):
         llm_data.update({k: v for k, v in overrides["llm"].items() if v is not None})
@@ -95,6 +98,7 @@ def apply_overrides(config: Config, overrides: dict) -> Config:
             api_key=llm_data.get("api_key", ""),
             base_url=llm_data.get("base_url", "https://api.anthropic.com/v1"),
             max_workers=int(llm_data.get("max_workers", 8)),
+            seed=llm_data.get("seed"),
         ),
         embedding=EmbeddingConfig(
             provider=embedding_data.get("provider", "openai"),
@@ -119,6 +123,7 @@ def config_to_dict(config: Config) -> dict:
             "api_key": config.llm.api_key,
             "base_url": config.llm.base_url,
             "max_workers": config.llm.max_workers,
+            "seed": config.llm.seed,
         },
         "embedding": {
             "provider": config.embedding.provider,
@@ -175,6 +180,7 @@ def load_config(path: str = "config.yml") -> Config:
             api_key=llm_data.get("api_key", ""),
             base_url=llm_data.get("base_url", "https://api.anthropic.com/v1"),
             max_workers=int(llm_data.get("max_workers", 8)),
+            seed=llm_data.get("seed"),
         ),
         embedding=EmbeddingConfig(
             provider=embedding_data.get("provider", "openai"),
@@ -188,3 +194,85 @@ def load_config(path: str = "config.yml") -> Config:
             base_url=reranker_data.get("base_url", ""),
         ),
     )
+
+
+# Keys each section accepts and the type of their values; checked by validate_config
+_SCHEMA = {
+    "llm": {"provider": str, "model": str, "api_key": str, "base_url": str, "max_workers": int, "seed": int},
+    "embedding": {"provider": str, "model": str, "api_key": str, "base_url": str},
+    "reranker": {"model": str, "api_key": str, "base_url": str},
+}
+
+# config.yml.example placeholders start with this; a value still holding one was never filled in
+_PLACEHOLDER_PREFIX = "YOUR_"
+
+
+def validate_config(text: str) -> list[dict]:
+    """Check config file contents against _SCHEMA without loading it.
+
+    Returns the problems found, each {"severity": "error" | "warning", "message", "key", "line"}, where key
+    is e.g. "llm.max_workers" (None for the whole file) and line is 1-based (None if not tied to a line).
+    Errors make load_config fail or the backend misbehave; warnings are values left empty or unfilled.
+    """
+    issues: list[dict] = []
+
+    def issue(severity: str, message: str, key=None, node=None) -> None:
+        line = node.start_mark.line + 1 if node is not None else None
+        issues.append({"severity": severity, "message": message, "key": key, "line": line})
+
+    try:
+        root = yaml.compose(text, Loader=yaml.SafeLoader)
+        data = yaml.safe_load(text)
+    except yaml.YAMLError as e:
+        mark = getattr(e, "problem_mark", None)
+        issues.append({
+            "severity": "error",
+            "message": f"Not valid YAML: {getattr(e, 'problem', None) or e}",
+            "key": None,
+            "line": mark.line + 1 if mark is not None else None,
+        })
+        return issues
+    if not isinstance(data, dict):
+        issue("error", "The config must be a mapping of sections (llm, embedding, reranker)", node=root)
+        return issues
+
+    nodes = {k.value: (k, v) for k, v in root.value}
+    for section, fields in _SCHEMA.items():
+        if section not in data:
+            if section == "reranker":
+                issue("error", "Missing 'reranker' section", key=section)
+            else:
+                issue("warning", f"Missing '{section}' section; defaults are used", key=section)
+            continue
+        key_node, value_node = nodes[section]
+        values = data[section]
+        if not isinstance(values, dict):
+            issue("error", f"'{section}' must be a mapping of settings", key=section, node=key_node)
+            continue
+        field_nodes = {k.value: k for k, _ in value_node.value}
+        for name, value in values.items():
+            key = f"{section}.{name}"
+            node = field_nodes.get(name)
+            expected = fields.get(name)
+            if expected is None:
+                issue("warning", f"Unknown setting '{key}' is ignored", key=key, node=node)
+            elif value is None:
+                continue
+            elif expected is int and (isinstance(value, bool) or not isinstance(value, int)):
+                issue("error", f"'{key}' must be an integer", key=key, node=node)
+            elif expected is str and not isinstance(value, str):
+                issue("error", f"'{key}' must be a string", key=key, node=node)
+            elif name == "max_workers" and value < 1:
+                issue("error", f"'{key}' must be at least 1", key=key, node=node)
+            elif name == "seed" and value < 0:
+                issue("error", f"'{key}' must not be negative", key=key, node=node)
+            elif isinstance(value, str) and value.startswith(_PLACEHOLDER_PREFIX):
+                issue("warning", f"'{key}' still holds the placeholder {value}", key=key, node=node)
+        # llm.base_url has a usable default; the other endpoints don't
+        for name in ("api_key",) if section == "llm" else ("api_key", "base_url"):
+            if not values.get(name):
+                issue("warning", f"'{section}.{name}' is not set", key=f"{section}.{name}", node=key_node)
+    for name, (key_node, _) in nodes.items():
+        if name not in _SCHEMA:
+            issue("warning", f"Unknown section '{name}' is ignored", key=name, node=key_node)
+    return issues
@@ -14,6 +14,16 @@ from .models import (
 )
 
 
+# Version of the schema init_db creates, stamped in PRAGMA user_version. Databases from before versioning
+# read 0; init_db brings any older one up to date (its CREATE ... IF NOT EXISTS add what is missing).
+SCHEMA_VERSION = 1
+
+
+def get_schema_version(conn: sqlite3.Connection) -> int:
+    """Schema version stamped in the database (0 when it predates versioning or is new)."""
+    return conn.execute("PRAGMA user_version").fetchone()[0]
+
+
 def init_db(path: str) -> sqlite3.Connection:
     """Initialize the SQLite database with all required tables.
 
@@ -202,6 +212,10 @@ def init_db(path: str) -> sqlite3.Connection:
         )
     """)
 
+    # Never lower the stamp: a newer app may have written this database
+    if get_schema_version(conn) < SCHEMA_VERSION:
+        cursor.execute(f"PRAGMA user_version = {SCHEMA_VERSION}")
+
     conn.commit()
     return conn
 
@@ -907,21 +921,25 @@ def clear_build_progress(conn: sqlite3.Connection, talker_id: str) -> None:
     conn.commit()
 
 
-def delete_session(conn: sqlite3.Connection, talker_id: str) -> None:
+def delete_session(conn: sqlite3.Connection, talker_id: str) -> int:
     """Delete all data for a session (talker_id) from SQLite.
 
     Removes from: raw_messages, bursts, topic_nodes, node_metadata,
     anomaly_anchors, semantic_thread_pointers, build_progress.
+
+    Returns:
+        Number of rows deleted across those tables.
     """
     cursor = conn.cursor()
-    cursor.execute("DELETE FROM raw_messages WHERE talker_id = ?", (talker_id,))
-    cursor.execute("DELETE FROM bursts WHERE talker_id = ?", (talker_id,))
-    cursor.execute("DELETE FROM topic_nodes WHERE talker_id = ?", (talker_id,))
-    cursor.execute("DELETE FROM node_metadata WHERE talker_id = ?", (talker_id,))
-    cursor.execute("DELETE FROM anomaly_anchors WHERE talker_id = ?", (talker_id,))
-    cursor.execute("DELETE FROM semantic_thread_pointers WHERE talker_id = ?", (talker_id,))
-    cursor.execute("DELETE FROM build_progress WHERE talker_id = ?", (talker_id,))
+    deleted = 0
+    for table in (
+        "raw_messages", "bursts", "topic_nodes", "node_metadata",
+        "anomaly_anchors", "semantic_thread_pointers", "build_progress",
+    ):
+        cursor.execute(f"DELETE FROM {table} WHERE talker_id = ?", (talker_id,))
+        deleted += cursor.rowcount
     conn.commit()
+    return deleted
 
 
 def get_messages_for_node(
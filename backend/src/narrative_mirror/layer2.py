@@ -423,6 +423,62 @@ def get_thread(node_id: str, talker_id: str, conn: sqlite3.Connection) -> list[s
     return list(thread_nodes)
 
 
+def _messages_by_node(conn: sqlite3.Connection, talker_id: str, nodes: list[TopicNode]) -> dict[str, list]:
+    """Map each node_id to the messages in its local_id range."""
+    msg_by_id = {m.local_id: m for m in get_all_messages(conn, talker_id)}
+    return {
+        node.node_id: [
+            msg_by_id[lid]
+            for lid in range(node.start_local_id, node.end_local_id + 1)
+            if lid in msg_by_id
+        ]
+        for node in nodes
+    }
+
+
+def reindex_embeddings(
+    talker_id: str,
+    llm_noncot: "NonCoTLLM",
+    conn: sqlite3.Connection,
+    data_dir: str,
+    progress_callback: Optional[Callable[[str, str, str], None]] = None,
+) -> int:
+    """Drop a conversation's ChromaDB collection and embed all of its nodes again.
+
+    For when only the embedding settings changed: Layer 1 nodes and Layer 2 thread pointers are kept.
+
+    Args:
+        talker_id: The conversation's talker ID.
+        llm_noncot: The NonCoTLLM for embedding.
+        conn: SQLite connection.
+        data_dir: Path to data directory.
+        progress_callback: Optional (stage, step, detail) callback for UI progress.
+
+    Returns:
+        Number of nodes embedded.
+    """
+    def _progress(step: str, detail: str) -> None:
+        if progress_callback:
+            progress_callback("reindex", step, detail)
+
+    nodes = get_nodes(conn, talker_id)
+    if not nodes:
+        return 0
+
+    client = chromadb.PersistentClient(path=f"{data_dir}/chroma")
+    try:
+        client.delete_collection(name=f"narrative_mirror_{talker_id}".replace("-", "_"))
+    except Exception:
+        pass
+    _progress("clear", "已清除旧的向量索引")
+
+    collection = init_chroma(data_dir, talker_id)
+    _progress("embed", f"正在嵌入 {len(nodes)} 个节点")
+    embedded = embed_nodes(nodes, _messages_by_node(conn, talker_id, nodes), llm_noncot, collection)
+    _progress("embed", f"已嵌入 {embedded} 个节点")
+    return embedded
+
+
 def build_layer2(
     talker_id: str,
     llm_noncot: "NonCoTLLM",
@@ -476,19 +532,7 @@ def build_layer2(
     if debug:
         print(f"Found {len(nodes)} nodes", file=sys.stderr)
 
-    # Get all messages
-    all_messages = get_all_messages(conn, talker_id)
-    msg_by_id = {m.local_id: m for m in all_messages}
-
-    # Build messages_by_node mapping
-    messages_by_node = {}
-    for node in nodes:
-        node_messages = [
-            msg_by_id[lid]
-            for lid in range(node.start_local_id, node.end_local_id + 1)
-            if lid in msg_by_id
-        ]
-        messages_by_node[node.node_id] = node_messages
+    messages_by_node = _messages_by_node(conn, talker_id, nodes)
 
     # Initialize ChromaDB
     collection = init_chroma(data_dir, talker_id)
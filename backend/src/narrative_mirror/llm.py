@@ -269,6 +269,7 @@ class OpenAICompatibleNonCoTLLM:
         )
         self.llm_model = llm_cfg.model
         self.max_workers: int = getattr(llm_cfg, "max_workers", 8)
+        self.seed: int | None = getattr(llm_cfg, "seed", None)
 
         self.embed_client = OpenAI(
             api_key=embed_cfg.api_key,
@@ -289,6 +290,8 @@ class OpenAICompatibleNonCoTLLM:
         }
         if response_format == "json_object":
             request_params["response_format"] = {"type": "json_object"}
+        if self.seed is not None:
+            request_params["seed"] = self.seed
 
         for attempt in range(4):  # up to 3 retries
             try:
@@ -339,6 +342,7 @@ class OpenAICompatibleCoTLLM:
         )
         self.model = llm_cfg.model
         self.max_workers: int = getattr(llm_cfg, "max_workers", 8)
+        self.seed: int | None = getattr(llm_cfg, "seed", None)
 
     def think_and_complete(self, system: str, prompt: str, max_tokens: int = 4096, response_format: str | None = None) -> str:
         """Generate a completion with chain-of-thought reasoning, retrying on rate-limit errors."""
@@ -359,6 +363,8 @@ class OpenAICompatibleCoTLLM:
         }
         if response_format == "json_object":
             request_params["response_format"] = {"type": "json_object"}
+        if self.seed is not None:
+            request_params["seed"] = self.seed
 
         for attempt in range(4):
             try:
@@ -286,12 +286,32 @@ def test_list_sessions_empty_db(tmp_path):
     assert json.loads(out) == []
 
 
+def test_list_talkers_output_format(tmp_db):
+    """list_talkers outputs id/name/message_count/last_timestamp per talker."""
+    code, out, err = _run_cli(["--db", tmp_db, "list_talkers"])
+    assert code == 0
+    data = json.loads(out)
+    talker = next(t for t in data if t["id"] == TALKER)
+    assert set(talker.keys()) == {"id", "name", "message_count", "last_timestamp"}
+    assert isinstance(talker["name"], str)
+    assert isinstance(talker["message_count"], int)
+
+
+def test_list_talkers_empty_db(tmp_path):
+    """list_talkers returns [] for a db without messages."""
+    db_path = tmp_path / "empty.db"
+    init_db(str(db_path))
+    code, out, err = _run_cli(["--db", str(db_path), "list_talkers"])
+    assert code == 0
+    assert json.loads(out) == []
+
+
 def test_delete_session(tmp_db):
     """delete_session removes talker data and returns JSON."""
     code, out, err = _run_cli(["--db", tmp_db, "delete_session", "--talker", TALKER])
     assert code == 0
     data = json.loads(out)
-    assert data == {"status": "deleted", "talker_id": TALKER}
+    assert data == {"status": "deleted", "talker_id": TALKER, "rows_deleted": 13}
 
     code2, out2, _ = _run_cli(["--db", tmp_db, "list_sessions"])
     assert code2 == 0
@@ -311,3 +331,563 @@ def test_list_sessions_db_not_found():
     code, out, err = _run_cli(["--db", "/nonexistent/path/db.sqlite", "list_sessions"])
     assert code != 0
     assert "not found" in err.lower() or "error" in err.lower()
+
+
+def test_stdio_tags_responses_with_req_id(tmp_db):
+    """stdio mode wraps each response line as {"req_id", "data"} so the client can route it."""
+    requests = [
+        {"cmd": "list_sessions", "req_id": 7},
+        {"cmd": "no_such_cmd", "req_id": 8},
+        {"cmd": "ping", "req_id": 9},
+    ]
+    stdin = "".join(json.dumps(r) + "\n" for r in requests)
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=stdin)
+    assert code == 0
+    lines = [json.loads(l) for l in out.splitlines() if l.strip()]
+    assert [l["req_id"] for l in lines] == [7, 8, 9]
+    assert any(s["talker_id"] == TALKER for s in lines[0]["data"])
+    assert lines[1]["data"]["type"] == "error"
+    assert lines[1]["data"]["code"] == "unknown_cmd"
+    assert lines[2]["data"] == {
+        "type": "pong",
+        "framing": ["length"],
+        "protocol_version": 1,
+        "max_concurrent": 4,
+        "encoding": None,
+    }
+
+
+def test_stdio_warmup_reports_stages(tmp_db, tmp_path):
+    """warmup never fails the request: stages that can't load (here: no config) are listed as skipped."""
+    req = {"cmd": "warmup", "req_id": 1, "config": str(tmp_path / "missing.yml")}
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=json.dumps(req) + "\n")
+    assert code == 0
+    data = json.loads(out.splitlines()[0])["data"]
+    assert data["type"] == "warm"
+    assert "tools" in data["loaded"]
+    assert any(s["stage"] == "llm" for s in data["skipped"])
+    assert isinstance(data["duration_ms"], int)
+
+
+def test_stdio_batch_returns_per_item_results(tmp_db):
+    """batch answers every item in order; a failing item doesn't fail the others."""
+    req = {
+        "cmd": "batch",
+        "req_id": 4,
+        "items": [
+            {"cmd": "list_talkers"},
+            {"cmd": "no_such_cmd"},
+            {"cmd": "query", "stream": True},
+            {"cmd": "list_sessions"},
+        ],
+    }
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=json.dumps(req) + "\n")
+    assert code == 0
+    lines = [json.loads(l) for l in out.splitlines() if l.strip()]
+    assert len(lines) == 1 and lines[0]["req_id"] == 4
+    results = lines[0]["data"]["results"]
+    assert lines[0]["data"]["type"] == "batch"
+    assert len(results) == 4
+    assert any(t["id"] == TALKER for t in results[0])
+    assert results[1]["type"] == "error"
+    assert results[2]["type"] == "error"
+    assert any(s["talker_id"] == TALKER for s in results[3])
+
+
+def _frame(obj) -> bytes:
+    payload = json.dumps(obj).encode("utf-8")
+    return len(payload).to_bytes(4, "big") + payload
+
+
+def test_stdio_switches_to_length_prefixed_frames(tmp_db):
+    """After set_framing the daemon reads and writes 4-byte big-endian length-prefixed frames."""
+    stdin = (
+        json.dumps({"cmd": "set_framing", "mode": "length", "req_id": 1}).encode() + b"\n"
+        + _frame({"cmd": "ping", "req_id": 2})
+        + _frame({"cmd": "list_talkers", "req_id": 3})
+    )
+    result = subprocess.run(
+        ["uv", "run", "python", "-m", "narrative_mirror.cli_json", "--db", tmp_db, "stdio"],
+        capture_output=True,
+        input=stdin,
+        cwd=os.path.dirname(os.path.dirname(os.path.abspath(__file__))),
+    )
+    assert result.returncode == 0
+    out = result.stdout
+    ack_line, rest = out.split(b"\n", 1)
+    assert json.loads(ack_line) == {"req_id": 1, "data": {"type": "framing", "mode": "length"}}
+    frames = []
+    while rest:
+        n = int.from_bytes(rest[:4], "big")
+        frames.append(json.loads(rest[4:4 + n]))
+        rest = rest[4 + n:]
+    assert frames[0]["data"]["type"] == "pong"
+    assert frames[1]["req_id"] == 3
+    assert any(t["id"] == TALKER for t in frames[1]["data"])
+
+
+def test_stdio_reload_config_switches_default_config(tmp_db, tmp_path):
+    """reload_config answers with the config summary and makes it the default for get_config."""
+    config = tmp_path / "other.yml"
+    example = os.path.join(os.path.dirname(os.path.dirname(os.path.abspath(__file__))), "config.yml.example")
+    config.write_text(open(example, encoding="utf-8").read(), encoding="utf-8")
+    requests = [
+        {"cmd": "reload_config", "path": str(config), "req_id": 1},
+        {"cmd": "reload_config", "path": str(tmp_path / "missing.yml"), "req_id": 2},
+        {"cmd": "get_config", "req_id": 3},
+    ]
+    stdin = "".join(json.dumps(r) + "\n" for r in requests)
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=stdin)
+    assert code == 0
+    lines = [json.loads(l) for l in out.splitlines() if l.strip()]
+    assert lines[0]["data"]["type"] == "config_reloaded"
+    assert lines[0]["data"]["path"] == str(config)
+    assert lines[1]["data"]["type"] == "error"
+    assert lines[2]["data"] == lines[0]["data"]["config"]
+
+
+
+def test_config_summary_includes_llm_seed():
+    """config_to_dict reports llm.seed, so get_config and reload_config show the seed queries are pinned to."""
+    from narrative_mirror.config import apply_overrides, config_to_dict, load_config
+
+    example = os.path.join(os.path.dirname(os.path.dirname(os.path.abspath(__file__))), "config.yml.example")
+    config = load_config(example)
+    assert config_to_dict(config)["llm"]["seed"] == config.llm.seed
+    assert config_to_dict(apply_overrides(config, {"llm": {"seed": 7}}))["llm"]["seed"] == 7
+
+def test_stdio_capabilities_lists_served_commands(tmp_db):
+    """capabilities reports the protocol version and only commands the daemon actually serves."""
+    from narrative_mirror.cli_json import _STDIO_COMMANDS, _stdio_command
+
+    for cmd in _STDIO_COMMANDS:
+        assert _stdio_command(cmd, {}, tmp_db, "config.yml") is not None, cmd
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=json.dumps({"cmd": "capabilities", "req_id": 1}) + "\n")
+    assert code == 0
+    caps = json.loads(out.splitlines()[0])["data"]
+    assert caps["type"] == "capabilities"
+    assert caps["protocol_version"] == 1
+    assert {"ping", "query", "batch", "list_talkers"} <= set(caps["commands"])
+    assert "framing:length" in caps["features"]
+
+
+def test_query_attachments_are_appended_to_the_question():
+    """Attached text reaches the workflow after the question; binary attachments are named only."""
+    from narrative_mirror.cli_json import _stdio_command, _with_attachments
+
+    attachments = [
+        {"name": "notes.txt", "path": "/home/u/notes.txt", "content": "她说周五见"},
+        {"name": "photo.png", "path": "/home/u/photo.png", "binary": True, "size": 6},
+    ]
+    _, ns = _stdio_command("query", {"question": "q", "attachments": attachments}, "db", "config.yml")
+    assert ns.attachments == attachments
+    assert _with_attachments("q", None) == "q"
+    assert _with_attachments("q", attachments) == (
+        "q\n\n[Attached file: notes.txt]\n她说周五见\n\n[Attached binary file: photo.png, 6 bytes]"
+    )
+
+
+def test_build_dry_run_validates_without_writing(tmp_db, tmp_path):
+    """build --dry-run reports talker/config problems as a validation line and leaves the db untouched."""
+    example = os.path.join(os.path.dirname(os.path.dirname(os.path.abspath(__file__))), "config.yml.example")
+    before = os.path.getmtime(tmp_db)
+    code, out, err = _run_cli(["--db", tmp_db, "build", "--talker", TALKER, "--config", example, "--dry-run"])
+    assert code == 0
+    result = json.loads(out.splitlines()[-1])
+    assert result["type"] == "validation"
+    assert result["message_count"] == 10
+    assert not any("talker" in e.lower() for e in result["errors"])
+
+    code, out, err = _run_cli(
+        ["--db", tmp_db, "build", "--talker", "nobody", "--config", str(tmp_path / "missing.yml"), "--dry-run"]
+    )
+    assert code == 0
+    result = json.loads(out.splitlines()[-1])
+    assert result["ok"] is False
+    assert any("Unknown talker" in e for e in result["errors"])
+    assert any("Invalid config" in e for e in result["errors"])
+    assert os.path.getmtime(tmp_db) == before
+
+
+def test_reindex_refuses_a_talker_without_nodes(tmp_db_pending, tmp_path):
+    """reindex only re-embeds existing nodes; an unbuilt talker fails before any config or model is loaded."""
+    code, out, err = _run_cli(
+        ["--db", tmp_db_pending, "reindex", "--talker", "p1", "--config", str(tmp_path / "missing.yml")]
+    )
+    assert code == 1
+    assert "run a full build first" in err
+
+
+def test_stdio_export_transcript(tmp_db, tmp_path):
+    """export renders the questions answered in this session as Markdown or JSON and rejects other formats."""
+    chroma_dir = str(tmp_path / "chroma")
+    os.makedirs(chroma_dir, exist_ok=True)
+    query = {"cmd": "query", "talker": TALKER, "question": "测试问题", "stub": True, "chroma_dir": chroma_dir, "req_id": 1}
+    stdin = "\n".join(json.dumps(m, ensure_ascii=False) for m in [
+        query,
+        {"cmd": "export", "format": "md", "req_id": 2},
+        {"cmd": "export", "format": "json", "req_id": 3},
+        {"cmd": "export", "format": "pdf", "req_id": 4},
+    ]) + "\n"
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=stdin)
+    assert code == 0, err
+    by_id = {}
+    for line in out.splitlines():
+        msg = json.loads(line)
+        by_id[msg["req_id"]] = msg["data"]
+    md = by_id[2]
+    assert md["type"] == "export" and md["count"] == 1
+    assert "## 测试问题" in md["content"]
+    entries = json.loads(by_id[3]["content"])
+    assert entries[0]["question"] == "测试问题"
+    assert entries[0]["talker"] == TALKER
+    assert "all_messages" not in entries[0]
+    assert by_id[4]["type"] == "error"
+    assert "Unsupported export format" in by_id[4]["message"]
+
+
+def test_stdio_select_talker_is_used_by_queries_without_one(tmp_db, tmp_path):
+    """select_talker returns the talker's metadata and becomes the talker of queries that omit it."""
+    chroma_dir = str(tmp_path / "chroma")
+    os.makedirs(chroma_dir, exist_ok=True)
+    stdin = "\n".join(json.dumps(m, ensure_ascii=False) for m in [
+        {"cmd": "select_talker", "talker": "nobody", "req_id": 1},
+        {"cmd": "select_talker", "talker": TALKER, "req_id": 2},
+        {"cmd": "query", "question": "测试问题", "stub": True, "chroma_dir": chroma_dir, "req_id": 3},
+    ]) + "\n"
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=stdin)
+    assert code == 0, err
+    by_id = {}
+    for line in out.splitlines():
+        msg = json.loads(line)
+        by_id[msg["req_id"]] = msg["data"]
+    assert by_id[1]["type"] == "error"
+    assert "Unknown talker" in by_id[1]["message"]
+    assert by_id[2]["type"] == "talker_selected"
+    assert by_id[2]["talker"]["id"] == TALKER
+    assert by_id[2]["talker"]["message_count"] > 0
+    assert by_id[3]["type"] == "result"
+    assert by_id[3]["conversation_id"] == TALKER
+
+
+def test_log_level_is_validated(tmp_db):
+    """--log-level accepts the client's level names and rejects anything else."""
+    code, out, err = _run_cli(["--db", tmp_db, "--log-level", "debug", "list_talkers"])
+    assert code == 0, err
+    assert json.loads(out)[0]["id"] == TALKER
+    code, _, err = _run_cli(["--db", tmp_db, "--log-level", "loud", "list_talkers"])
+    assert code != 0
+    assert "--log-level" in err
+
+
+def test_stdio_query_seed_is_validated(tmp_db, tmp_path):
+    """A query's seed must be a u64; a valid one is accepted and the query answers as usual."""
+    chroma_dir = str(tmp_path / "chroma")
+    os.makedirs(chroma_dir, exist_ok=True)
+    query = {"cmd": "query", "talker": TALKER, "question": "测试问题", "stub": True, "chroma_dir": chroma_dir}
+    stdin = "\n".join(json.dumps(m, ensure_ascii=False) for m in [
+        {**query, "seed": 42, "req_id": 1},
+        {**query, "seed": -1, "req_id": 2},
+        {**query, "seed": "abc", "req_id": 3},
+    ]) + "\n"
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=stdin)
+    assert code == 0, err
+    by_id = {}
+    for line in out.splitlines():
+        msg = json.loads(line)
+        by_id[msg["req_id"]] = msg["data"]
+    assert by_id[1]["type"] == "result"
+    assert by_id[2]["type"] == "error"
+    assert "seed must be an integer between" in by_id[2]["message"]
+    assert by_id[3]["type"] == "error"
+    assert "seed must be an integer" in by_id[3]["message"]
+
+
+def test_stdio_history_lists_recent_questions(tmp_db, tmp_path):
+    """history returns this session's questions newest first, capped at limit; empty before any query."""
+    chroma_dir = str(tmp_path / "chroma")
+    os.makedirs(chroma_dir, exist_ok=True)
+    query = {"cmd": "query", "talker": TALKER, "stub": True, "chroma_dir": chroma_dir}
+    stdin = "\n".join(json.dumps(m, ensure_ascii=False) for m in [
+        {"cmd": "history", "req_id": 1},
+        {**query, "question": "第一个问题", "req_id": 2},
+        {**query, "question": "第二个问题", "req_id": 3},
+        {"cmd": "history", "req_id": 4},
+        {"cmd": "history", "limit": 1, "req_id": 5},
+    ]) + "\n"
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=stdin)
+    assert code == 0, err
+    by_id = {}
+    for line in out.splitlines():
+        msg = json.loads(line)
+        by_id[msg["req_id"]] = msg["data"]
+    assert by_id[1] == {"type": "history", "items": []}
+    items = by_id[4]["items"]
+    assert [i["question"] for i in items] == ["第二个问题", "第一个问题"]
+    assert items[0]["id"] == 2 and items[0]["talker"] == TALKER and items[0]["timestamp"] > 0
+    assert [i["id"] for i in by_id[5]["items"]] == [2]
+
+
+def test_stdio_delete_talker_reports_rows_and_forgets_the_active_talker(tmp_db):
+    """delete_talker removes the talker, reports the rows it deleted and clears it as the active talker."""
+    stdin = "\n".join(json.dumps(m) for m in [
+        {"cmd": "select_talker", "talker": TALKER, "req_id": 1},
+        {"cmd": "delete_talker", "talker": TALKER, "req_id": 2},
+        {"cmd": "list_talkers", "req_id": 3},
+        {"cmd": "delete_talker", "talker": TALKER, "req_id": 4},
+    ]) + "\n"
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=stdin)
+    assert code == 0, err
+    by_id = {json.loads(line)["req_id"]: json.loads(line)["data"] for line in out.splitlines()}
+    assert by_id[2] == {"status": "deleted", "talker_id": TALKER, "rows_deleted": 13}
+    assert by_id[3] == []
+    assert by_id[4]["type"] == "error" and "not found" in by_id[4]["message"].lower()
+
+
+def test_stdio_streaming_queries_are_multiplexed(tmp_db, tmp_path):
+    """Two streaming queries sent back to back both stream and finish, each line tagged with its own req_id."""
+    chroma_dir = str(tmp_path / "chroma")
+    os.makedirs(chroma_dir, exist_ok=True)
+    query = {"cmd": "query", "talker": TALKER, "stub": True, "stream": True, "chroma_dir": chroma_dir}
+    stdin = "\n".join(json.dumps(m, ensure_ascii=False) for m in [
+        {"cmd": "ping", "req_id": 1},
+        {**query, "question": "第一个问题", "req_id": 2},
+        {**query, "question": "第二个问题", "req_id": 3},
+    ]) + "\n"
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=stdin)
+    assert code == 0, err
+    by_id: dict = {}
+    for line in out.splitlines():
+        msg = json.loads(line)
+        by_id.setdefault(msg["req_id"], []).append(msg["data"])
+    assert by_id[1][0]["max_concurrent"] > 1
+    for req_id, question in [(2, "第一个问题"), (3, "第二个问题")]:
+        lines = by_id[req_id]
+        assert lines[0]["type"] == "progress"
+        assert lines[-1]["type"] == "result"
+        assert lines[-1]["question"] == question
+
+
+def test_verbose_query_state_is_json_ready():
+    """verbose reaches the query; its progress state summarizes the workflow state without the dependencies."""
+    from narrative_mirror.cli_json import _stdio_command, _verbose_state
+    from narrative_mirror.models import QueryIntent
+
+    _, ns = _stdio_command("query", {"question": "q", "verbose": True}, "db", "config.yml")
+    assert ns.verbose is True
+    assert _stdio_command("query", {"question": "q"}, "db", "config.yml")[1].verbose is False
+    node = TopicNode(node_id="n1", talker_id=TALKER, burst_id="b1", topic_name="Test",
+                     start_local_id=1, end_local_id=10, start_time=0, end_time=1)
+    state = {
+        "question": "q",
+        "intent": QueryIntent(query_type="arc_narrative"),
+        "search_queries": ["trip"],
+        "collected_nodes": [node],
+        "collected_messages": {"n1": [object(), object()]},
+        "evaluation": "sufficient",
+        "iterations": 1,
+        "answer_mode": "full_narrative",
+        "conn": sqlite3.connect(":memory:"),
+    }
+    out = _verbose_state(state)
+    assert json.loads(json.dumps(out)) == out
+    assert out["intent"]["query_type"] == "arc_narrative"
+    assert out["collected_node_ids"] == ["n1"]
+    assert out["collected_message_counts"] == {"n1": 2}
+    assert "conn" not in out
+
+
+def test_stdio_reset_forgets_the_conversation(tmp_db, tmp_path):
+    """reset clears this session's answered questions (of one talker, or all) while the daemon keeps running."""
+    chroma_dir = str(tmp_path / "chroma")
+    os.makedirs(chroma_dir, exist_ok=True)
+    query = {"cmd": "query", "talker": TALKER, "question": "测试问题", "stub": True, "chroma_dir": chroma_dir}
+    stdin = "\n".join(json.dumps(m, ensure_ascii=False) for m in [
+        {**query, "req_id": 1},
+        {"cmd": "reset", "talker": "someone_else", "req_id": 2},
+        {"cmd": "reset", "talker": TALKER, "req_id": 3},
+        {"cmd": "history", "req_id": 4},
+        {"cmd": "ping", "req_id": 5},
+    ]) + "\n"
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=stdin)
+    assert code == 0, err
+    by_id = {}
+    for line in out.splitlines():
+        msg = json.loads(line)
+        by_id[msg["req_id"]] = msg["data"]
+    assert by_id[2] == {"type": "reset", "talker": "someone_else", "cleared": 0}
+    assert by_id[3] == {"type": "reset", "talker": TALKER, "cleared": 1}
+    assert by_id[4]["items"] == []
+    assert by_id[5]["type"] == "pong"
+
+
+def test_stdio_http_mode_serves_requests_over_http(tmp_db):
+    """stdio --http announces its port and token first, then answers POST /rpc with the request's tagged lines."""
+    import urllib.error
+    import urllib.request
+
+    proc = subprocess.Popen(
+        ["uv", "run", "python", "-m", "narrative_mirror.cli_json", "--db", tmp_db, "stdio", "--http"],
+        stdin=subprocess.PIPE,
+        stdout=subprocess.PIPE,
+        stderr=subprocess.PIPE,
+        text=True,
+        cwd=os.path.dirname(os.path.dirname(os.path.abspath(__file__))),
+    )
+    try:
+        endpoint = json.loads(proc.stdout.readline())
+        assert endpoint["type"] == "http"
+
+        def post(msg: dict, token: str = endpoint["token"]) -> list:
+            request = urllib.request.Request(
+                f"http://127.0.0.1:{endpoint['port']}/rpc",
+                data=json.dumps(msg).encode("utf-8"),
+                headers={"Content-Type": "application/json", "X-Narrarc-Token": token},
+            )
+            with urllib.request.urlopen(request, timeout=30) as response:
+                return [json.loads(line) for line in response.read().decode("utf-8").splitlines()]
+
+        [pong] = post({"cmd": "ping", "req_id": 1})
+        assert pong["req_id"] == 1
+        assert pong["data"]["type"] == "pong"
+        assert pong["data"]["framing"] == []
+        [messages] = post({"cmd": "get_messages", "talker": TALKER, "limit": 2, "req_id": 2})
+        assert len(messages["data"]) == 2
+        with pytest.raises(urllib.error.HTTPError) as refused:
+            post({"cmd": "ping", "req_id": 3}, token="wrong")
+        assert refused.value.code == 403
+    finally:
+        proc.stdin.close()
+        assert proc.wait(timeout=30) == 0
+        proc.stdout.close()
+        proc.stderr.close()
+
+
+def test_stdio_validate_config_reports_issues_with_lines(tmp_db, tmp_path):
+    """validate_config lists schema errors and unfilled values with the line they are on."""
+    config = tmp_path / "config.yml"
+    config.write_text(
+        "llm:\n"
+        "  model: m\n"
+        "  api_key: sk-test\n"
+        "  max_workers: zero\n"
+        "  temperature: 0.2\n"
+        "embedding:\n"
+        "  api_key: YOUR_API_KEY_HERE\n"
+        "  base_url: http://localhost\n",
+        encoding="utf-8",
+    )
+    broken = tmp_path / "broken.yml"
+    broken.write_text("llm:\n  model: [unclosed\n", encoding="utf-8")
+    stdin = "\n".join(json.dumps(m) for m in [
+        {"cmd": "validate_config", "path": str(config), "req_id": 1},
+        {"cmd": "validate_config", "path": str(broken), "req_id": 2},
+        {"cmd": "validate_config", "path": str(tmp_path / "missing.yml"), "req_id": 3},
+    ]) + "\n"
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=stdin)
+    assert code == 0, err
+    by_id = {msg["req_id"]: msg["data"] for msg in map(json.loads, out.splitlines())}
+
+    report = by_id[1]
+    assert report["type"] == "config_validation"
+    assert report["valid"] is False
+    issues = {i["key"]: i for i in report["issues"]}
+    assert issues["llm.max_workers"]["severity"] == "error"
+    assert issues["llm.max_workers"]["line"] == 4
+    assert issues["llm.temperature"]["severity"] == "warning"
+    assert issues["embedding.api_key"]["severity"] == "warning"
+    assert issues["embedding.api_key"]["line"] == 7
+    assert issues["reranker"]["severity"] == "error"
+
+    assert by_id[2]["valid"] is False
+    assert by_id[2]["issues"][0]["line"] is not None
+    assert by_id[3]["type"] == "error"
+
+
+def test_stdio_check_credentials_reports_a_typed_error(tmp_db, tmp_path):
+    """check_credentials fails with no_network when the provider can't be reached; errors are classified by kind."""
+    from narrative_mirror.cli_json import _credential_error_code
+
+    class AuthenticationError(Exception):
+        pass
+
+    assert _credential_error_code(AuthenticationError("401")) == "auth_failed"
+    assert _credential_error_code(ConnectionRefusedError()) == "no_network"
+    assert _credential_error_code(ValueError("bad model")) == "check_failed"
+
+    config = tmp_path / "config.yml"
+    example = os.path.join(os.path.dirname(os.path.dirname(os.path.abspath(__file__))), "config.yml.example")
+    config.write_text(open(example, encoding="utf-8").read(), encoding="utf-8")
+    req = {
+        "cmd": "check_credentials",
+        "req_id": 1,
+        "config": str(config),
+        "config_overrides": {"llm": {"base_url": "http://127.0.0.1:9/v1"}},
+    }
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=json.dumps(req) + "\n")
+    assert code == 0, err
+    data = json.loads(out.splitlines()[0])["data"]
+    assert data["type"] == "error"
+    assert data["code"] == "no_network"
+
+
+def test_stdio_large_lines_are_gzipped_once_negotiated(tmp_db):
+    """A ping accepting gzip turns it on; lines past the threshold then go out compressed, small ones plain."""
+    import base64
+    import gzip
+    import io
+    from narrative_mirror.cli_json import _COMPRESS_MIN_BYTES, _ReqIdWriter
+
+    stdin = json.dumps({"cmd": "ping", "req_id": 1, "accept_encoding": ["br", "gzip"]}) + "\n"
+    code, out, err = _run_cli(["--db", tmp_db, "stdio"], stdin=stdin)
+    assert code == 0, err
+    assert json.loads(out.splitlines()[0])["data"]["encoding"] == "gzip"
+
+    sink = io.StringIO()
+    writer = _ReqIdWriter(sink)
+    writer.req_id = 3
+    writer.encoding = "gzip"
+    result = {"type": "result", "answer": "x" * _COMPRESS_MIN_BYTES}
+    writer.write(json.dumps({"type": "progress"}) + "\n")
+    writer.write(json.dumps(result) + "\n")
+    small, large = [json.loads(line) for line in sink.getvalue().splitlines()]
+    assert small == {"req_id": 3, "data": {"type": "progress"}}
+    assert large["data"]["type"] == "result" and large["data"]["encoding"] == "gzip"
+    assert len(large["data"]["data"]) < _COMPRESS_MIN_BYTES // 10
+    assert json.loads(gzip.decompress(base64.b64decode(large["data"]["data"]))) == result
+
+
+def test_stdio_db_status_creates_migrates_and_leaves_newer_databases_alone(tmp_db, tmp_path):
+    """db_status reports a new database as created, stamps an unversioned one as migrated and keeps its data."""
+    from narrative_mirror.db import SCHEMA_VERSION
+
+    def db_status(db):
+        stdin = json.dumps({"cmd": "db_status", "req_id": 1}) + "\n"
+        code, out, err = _run_cli(["--db", str(db), "stdio"], stdin=stdin)
+        assert code == 0, err
+        return json.loads(out.splitlines()[0])["data"]
+
+    fresh = tmp_path / "fresh.db"
+    status = db_status(fresh)
+    assert status["status"] == "created" and status["created"] and status["migrated"]
+    assert status["empty"] is True and status["previous_version"] is None
+    assert db_status(fresh)["status"] == "current"
+
+    conn = sqlite3.connect(tmp_db)
+    conn.execute("PRAGMA user_version = 0")
+    conn.commit()
+    conn.close()
+    status = db_status(tmp_db)
+    assert status["status"] == "migrated" and not status["created"]
+    assert status["previous_version"] == 0 and status["schema_version"] == SCHEMA_VERSION
+    assert status["empty"] is False and status["talker_count"] >= 1
+
+    conn = sqlite3.connect(tmp_db)
+    conn.execute(f"PRAGMA user_version = {SCHEMA_VERSION + 1}")
+    conn.commit()
+    conn.close()
+    status = db_status(tmp_db)
+    assert status["status"] == "newer" and not status["migrated"]
+    conn = sqlite3.connect(tmp_db)
+    assert conn.execute("PRAGMA user_version").fetchone()[0] == SCHEMA_VERSION + 1
+    conn.close()
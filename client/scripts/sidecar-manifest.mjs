@@ -0,0 +1,31 @@
+// Write manifest.json next to the sidecars for the verify-sidecar feature: {"sha256": {"<file name>": "<hex>"}}.
+// Usage: node scripts/sidecar-manifest.mjs [dir]   (dir defaults to src-tauri/bin/backend)
+//
+// The hashes must be of the bytes that ship. This runs before `tauri build` bundles the app, and the bundler
+// code-signs the sidecar when a macOS signing identity is configured, which rewrites it; the manifest would
+// then reject every launch. So signed builds are refused here rather than producing an app that cannot start.
+import crypto from 'node:crypto';
+import fs from 'node:fs';
+import path from 'node:path';
+
+const SIGNING_ENV = ['APPLE_SIGNING_IDENTITY', 'APPLE_CERTIFICATE'];
+
+const signing = SIGNING_ENV.filter((name) => process.env[name]);
+if (signing.length > 0) {
+  console.error(
+    `sidecar-manifest: ${signing.join(', ')} is set, so the bundler will re-sign the sidecar after it is hashed ` +
+      'and the manifest would not match. Build without verify-sidecar, or unset the signing variables.',
+  );
+  process.exit(1);
+}
+
+const dir = process.argv[2] ?? path.join('src-tauri', 'bin', 'backend');
+const sha256 = {};
+for (const file of fs.readdirSync(dir).filter((f) => f.startsWith('backend-')).sort()) {
+  sha256[file] = crypto.createHash('sha256').update(fs.readFileSync(path.join(dir, file))).digest('hex');
+}
+if (Object.keys(sha256).length === 0) {
+  console.error(`sidecar-manifest: no backend-* sidecar in ${dir}; run npm run build:sidecar first`);
+  process.exit(1);
+}
+fs.writeFileSync(path.join(dir, 'manifest.json'), JSON.stringify({ sha256 }, null, 2) + '\n');
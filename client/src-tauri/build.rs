@@ -1,5 +1,9 @@
 fn main() {
-  // Pass TARGET to lib.rs - Cargo sets TARGET in build scripts but not always at compile time
-  println!("cargo:rustc-env=APP_TARGET={}", std::env::var("TARGET").unwrap_or_else(|_| "unknown".into()));
+  // Pass TARGET to lib.rs - Cargo sets TARGET in build scripts but not always at compile time. HOST is the
+  // fallback (right for native builds); lib.rs asserts at compile time that the result is a target triple.
+  let target = std::env::var("TARGET")
+    .or_else(|_| std::env::var("HOST"))
+    .unwrap_or_else(|_| "unknown".into());
+  println!("cargo:rustc-env=APP_TARGET={}", target);
   tauri_build::build()
 }
@@ -0,0 +1,70 @@
+//! In-memory sink for the app's own (Rust-side) log records, so spawn errors and restarts can be shown in
+//! a diagnostics view next to the backend's stderr. Release builds have no other log target.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// How many recent log lines get_app_log can return.
+const APP_LOG_LINES: usize = 1000;
+
+/// Ring buffer of recent log lines, oldest first. Cloning shares the buffer: one clone is the logger, one
+/// is managed state read by get_app_log.
+#[derive(Clone, Default)]
+pub(crate) struct AppLog(Arc<Mutex<VecDeque<String>>>);
+
+impl AppLog {
+  /// A tauri_plugin_log target that appends every record (already formatted by the plugin) to this buffer.
+  pub(crate) fn target(&self) -> tauri_plugin_log::Target {
+    let sink: Box<dyn log::Log> = Box::new(self.clone());
+    tauri_plugin_log::Target::new(tauri_plugin_log::TargetKind::Dispatch(
+      tauri_plugin_log::fern::Dispatch::new().chain(sink),
+    ))
+  }
+
+  pub(crate) fn lines(&self) -> Vec<String> {
+    self
+      .0
+      .lock()
+      .map(|lines| lines.iter().cloned().collect())
+      .unwrap_or_default()
+  }
+
+  fn push(&self, line: String) {
+    if let Ok(mut lines) = self.0.lock() {
+      if lines.len() == APP_LOG_LINES {
+        lines.pop_front();
+      }
+      lines.push_back(line);
+    }
+  }
+}
+
+impl log::Log for AppLog {
+  fn enabled(&self, _metadata: &log::Metadata) -> bool {
+    true
+  }
+
+  fn log(&self, record: &log::Record) {
+    self.push(record.args().to_string());
+  }
+
+  fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use log::Log;
+
+  #[test]
+  fn app_log_keeps_the_most_recent_lines() {
+    let app_log = AppLog::default();
+    for i in 0..APP_LOG_LINES + 2 {
+      app_log.log(&log::Record::builder().args(format_args!("line {}", i)).build());
+    }
+    let lines = app_log.lines();
+    assert_eq!(lines.len(), APP_LOG_LINES);
+    assert_eq!(lines[0], "line 2");
+    assert_eq!(lines.last(), Some(&format!("line {}", APP_LOG_LINES + 1)));
+  }
+}
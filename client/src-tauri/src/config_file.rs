@@ -0,0 +1,310 @@
+//! Editing config.yml from the settings form. A patch only touches CONFIG_KEYS, and is applied line by line
+//! so the user's comments, key order and blank lines survive; when the file is laid out in a way the line
+//! editor can't follow (flow mappings, multi-line values), it falls back to a YAML round-trip that keeps key
+//! order but drops comments.
+
+use serde_json::Value;
+
+use crate::ipc_log::REDACTED;
+
+/// Keys set_config may change, per section. Mirrors LLMConfig / EmbeddingConfig / RerankerConfig.
+pub(crate) const CONFIG_KEYS: &[(&str, &[&str])] = &[
+  ("llm", &["provider", "model", "api_key", "base_url", "max_workers", "seed"]),
+  ("embedding", &["provider", "model", "api_key", "base_url"]),
+  ("reranker", &["model", "api_key", "base_url"]),
+];
+
+/// Check a set_config patch: {section: {key: value}} with sections and keys from CONFIG_KEYS. Values are
+/// strings, except max_workers (a positive integer) and seed (a non-negative integer); null removes the key
+/// so the backend default applies again.
+pub(crate) fn validate_patch(patch: &Value) -> Result<(), String> {
+  let sections = patch
+    .as_object()
+    .ok_or_else(|| format!("config patch must be a JSON object, got {}", crate::json_kind(patch)))?;
+  for (section, fields) in sections {
+    let Some((_, keys)) = CONFIG_KEYS.iter().find(|(name, _)| name == section) else {
+      return Err(format!(
+        "config patch has unknown section \"{}\" (expected one of: {})",
+        section,
+        CONFIG_KEYS.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ")
+      ));
+    };
+    let fields = fields.as_object().ok_or_else(|| {
+      format!(
+        "config patch {} must be a JSON object, got {}",
+        section,
+        crate::json_kind(fields)
+      )
+    })?;
+    for (key, value) in fields {
+      if !keys.contains(&key.as_str()) {
+        return Err(format!(
+          "config patch has unknown key \"{}.{}\" (expected one of: {})",
+          section,
+          key,
+          keys.join(", ")
+        ));
+      }
+      let ok = match (key.as_str(), value) {
+        (_, Value::Null) => true,
+        ("max_workers", v) => v.as_u64().is_some_and(|n| n > 0),
+        ("seed", v) => v.as_u64().is_some(),
+        (_, v) => v.is_string(),
+      };
+      if !ok {
+        let expected = match key.as_str() {
+          "max_workers" => "a positive integer",
+          "seed" => "a non-negative integer",
+          _ => "a string",
+        };
+        return Err(format!(
+          "config patch {}.{} must be {} or null, got {}",
+          section,
+          key,
+          expected,
+          crate::json_kind(value)
+        ));
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Apply a validated patch to the YAML `text` of a config file and return the new text.
+pub(crate) fn apply_patch(text: &str, patch: &Value) -> Result<String, String> {
+  let original: serde_yaml::Value =
+    serde_yaml::from_str(text).map_err(|e| format!("config is not valid YAML: {}", e))?;
+  let edited = edit_lines(text, patch);
+  if let Ok(parsed) = serde_yaml::from_str::<serde_yaml::Value>(&edited) {
+    if parsed == round_trip(original.clone(), patch)? {
+      return Ok(edited);
+    }
+  }
+  log::warn!("Could not edit config in place; rewriting it without comments");
+  serde_yaml::to_string(&round_trip(original, patch)?).map_err(|e| e.to_string())
+}
+
+/// The patch applied to the parsed document; what the edited text must parse to.
+fn round_trip(mut doc: serde_yaml::Value, patch: &Value) -> Result<serde_yaml::Value, String> {
+  if doc.is_null() {
+    doc = serde_yaml::Value::Mapping(Default::default());
+  }
+  let root = doc
+    .as_mapping_mut()
+    .ok_or("config must be a YAML mapping")?;
+  for (section, fields) in patch.as_object().into_iter().flatten() {
+    let entry = root
+      .entry(section.as_str().into())
+      .or_insert_with(|| serde_yaml::Value::Mapping(Default::default()));
+    if entry.is_null() {
+      *entry = serde_yaml::Value::Mapping(Default::default());
+    }
+    let map = entry
+      .as_mapping_mut()
+      .ok_or_else(|| format!("config section {} must be a YAML mapping", section))?;
+    for (key, value) in fields.as_object().into_iter().flatten() {
+      if value.is_null() {
+        map.remove(key.as_str());
+      } else {
+        let value = serde_yaml::to_value(value).map_err(|e| e.to_string())?;
+        map.insert(key.as_str().into(), value);
+      }
+    }
+  }
+  Ok(doc)
+}
+
+/// Replace, insert or remove `key: value` lines inside each top-level `section:` block, keeping indentation
+/// and trailing comments. Sections missing from the file are appended.
+fn edit_lines(text: &str, patch: &Value) -> String {
+  let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+  for (section, fields) in patch.as_object().into_iter().flatten() {
+    let header = lines.iter().position(|line| {
+      line
+        .strip_prefix(section.as_str())
+        .and_then(|rest| rest.strip_prefix(':'))
+        .is_some_and(|rest| rest.trim().is_empty() || rest.trim_start().starts_with('#'))
+    });
+    let Some(header) = header else {
+      let set: Vec<String> = fields
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(_, v)| !v.is_null())
+        .map(|(key, value)| format!("  {}: {}", key, scalar(value)))
+        .collect();
+      if !set.is_empty() {
+        if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+          lines.push(String::new());
+        }
+        lines.push(format!("{}:", section));
+        lines.extend(set);
+      }
+      continue;
+    };
+    for (key, value) in fields.as_object().into_iter().flatten() {
+      let end = block_end(&lines, header);
+      let found = (header + 1..end).find(|&i| {
+        let line = &lines[i];
+        let body = line.trim_start();
+        body.len() < line.len() && body.strip_prefix(key.as_str()).is_some_and(|r| r.starts_with(':'))
+      });
+      match (found, value.is_null()) {
+        (Some(i), true) => {
+          lines.remove(i);
+        }
+        (Some(i), false) => {
+          let line = &lines[i];
+          let indent = &line[..line.len() - line.trim_start().len()];
+          let rest = &line[indent.len() + key.len() + 1..];
+          let comment = comment_start(rest).map(|at| {
+            let before = &rest[..at];
+            let pad = &before[before.trim_end().len()..];
+            format!("{}{}", pad, &rest[at..])
+          });
+          lines[i] = format!("{}{}: {}{}", indent, key, scalar(value), comment.unwrap_or_default());
+        }
+        (None, true) => {}
+        (None, false) => {
+          let last = (header + 1..end)
+            .rev()
+            .find(|&i| !lines[i].trim().is_empty())
+            .unwrap_or(header);
+          let indent = (header + 1..end)
+            .map(|i| &lines[i])
+            .find(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+            .map(|l| l[..l.len() - l.trim_start().len()].to_string())
+            .unwrap_or_else(|| "  ".to_string());
+          lines.insert(last + 1, format!("{}{}: {}", indent, key, scalar(value)));
+        }
+      }
+    }
+  }
+  let mut out = lines.join("\n");
+  if text.ends_with('\n') || text.is_empty() {
+    out.push('\n');
+  }
+  out
+}
+
+/// Index just past the block of `header`: the next line that starts in column 0 with anything but `#`.
+fn block_end(lines: &[String], header: usize) -> usize {
+  (header + 1..lines.len())
+    .find(|&i| {
+      let line = &lines[i];
+      !line.is_empty() && !line.starts_with([' ', '\t', '#'])
+    })
+    .unwrap_or(lines.len())
+}
+
+/// Where a `#` comment starts in the value part of a line, skipping quoted strings.
+fn comment_start(rest: &str) -> Option<usize> {
+  let mut quote = None;
+  let mut prev = ' ';
+  for (i, c) in rest.char_indices() {
+    match quote {
+      Some(q) if c == q => quote = None,
+      Some(_) => {}
+      None if c == '\'' || c == '"' => quote = Some(c),
+      None if c == '#' && prev.is_whitespace() => return Some(i),
+      None => {}
+    }
+    prev = c;
+  }
+  None
+}
+
+/// Replace the values of secret-looking keys ("key"/"token" in the name) anywhere in a parsed config, e.g. for
+/// a diagnostics report. Returns the string values replaced, so they can be scrubbed from log lines too.
+pub(crate) fn redact_secrets(config: &mut Value) -> Vec<String> {
+  let mut secrets = Vec::new();
+  redact_into(config, &mut secrets);
+  secrets
+}
+
+fn redact_into(v: &mut Value, secrets: &mut Vec<String>) {
+  match v {
+    Value::Object(map) => {
+      for (k, child) in map.iter_mut() {
+        let name = k.to_ascii_lowercase();
+        if (name.contains("key") || name.contains("token")) && !child.is_null() {
+          if let Some(secret) = child.as_str().filter(|s| !s.is_empty()) {
+            secrets.push(secret.to_string());
+          }
+          *child = Value::from(REDACTED);
+        } else {
+          redact_into(child, secrets);
+        }
+      }
+    }
+    Value::Array(items) => items.iter_mut().for_each(|item| redact_into(item, secrets)),
+    _ => {}
+  }
+}
+
+/// A JSON scalar as a one-line YAML value, quoted when needed.
+fn scalar(value: &Value) -> String {
+  serde_yaml::to_string(value)
+    .map(|s| s.trim_end().to_string())
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  const EXAMPLE: &str = "# LLM configuration\n\nllm:\n  provider: openai          # only openai\n  model: old-model\n  max_workers: 8\n\n\nembedding:\n  model: BAAI/bge-m3        # 1024-dim\n  api_key: KEY\n";
+
+  #[test]
+  fn apply_patch_keeps_comments_and_order() {
+    let patch = json!({
+      "llm": { "model": "new: model", "max_workers": 4, "seed": 7 },
+      "embedding": { "model": "m3", "api_key": null },
+      "reranker": { "base_url": "http://localhost" },
+    });
+    validate_patch(&patch).unwrap();
+    assert_eq!(
+      apply_patch(EXAMPLE, &patch).unwrap(),
+      "# LLM configuration\n\nllm:\n  provider: openai          # only openai\n  model: 'new: model'\n  max_workers: 4\n  seed: 7\n\n\nembedding:\n  model: m3        # 1024-dim\n\nreranker:\n  base_url: http://localhost\n"
+    );
+
+    let flow = "llm: {model: a}\n";
+    let rewritten = apply_patch(flow, &json!({ "llm": { "model": "b" } })).unwrap();
+    assert_eq!(serde_yaml::from_str::<Value>(&rewritten).unwrap(), json!({ "llm": { "model": "b" } }));
+  }
+
+  #[test]
+  fn redact_secrets_replaces_keys_and_tokens_and_returns_them() {
+    let mut config = json!({
+      "llm": { "model": "m", "api_key": "sk-live", "max_workers": 4 },
+      "embedding": { "api_key": null, "base_url": "http://localhost" },
+      "reranker": { "auth_token": "tok" },
+    });
+    assert_eq!(redact_secrets(&mut config), ["sk-live", "tok"]);
+    assert_eq!(
+      config,
+      json!({
+        "llm": { "model": "m", "api_key": REDACTED, "max_workers": 4 },
+        "embedding": { "api_key": null, "base_url": "http://localhost" },
+        "reranker": { "auth_token": REDACTED },
+      })
+    );
+  }
+
+  #[test]
+  fn validate_patch_rejects_unknown_keys_and_bad_values() {
+    assert!(validate_patch(&json!([])).unwrap_err().contains("must be a JSON object"));
+    assert!(validate_patch(&json!({ "db": {} })).unwrap_err().contains("unknown section \"db\""));
+    assert!(validate_patch(&json!({ "llm": { "temperature": 1 } }))
+      .unwrap_err()
+      .contains("unknown key \"llm.temperature\""));
+    assert!(validate_patch(&json!({ "llm": { "max_workers": 0 } }))
+      .unwrap_err()
+      .contains("positive integer"));
+    assert!(validate_patch(&json!({ "reranker": { "model": 3 } }))
+      .unwrap_err()
+      .contains("must be a string or null, got number"));
+    assert_eq!(validate_patch(&json!({ "llm": { "seed": null, "model": "m" } })), Ok(()));
+  }
+}
@@ -0,0 +1,141 @@
+//! Guard against two app instances running backends on the same database, which makes SQLite fail with
+//! intermittent "database is locked" errors. The lock file next to the database is held with an OS lock
+//! (flock on Unix, a handle that denies other writers on Windows) for as long as this app runs a backend on
+//! it, so the OS drops it when the app exits or crashes. The file also holds the owner's pid, which is only
+//! used to name it in `backend://db_locked`.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// Appended to the database file name to get its lock file, e.g. `mirror.db.applock`.
+const LOCK_SUFFIX: &str = ".applock";
+
+/// Lock files this app holds, open for as long as it holds them.
+static HELD: Mutex<Vec<(PathBuf, File)>> = Mutex::new(Vec::new());
+
+pub(crate) fn lock_path(db: &Path) -> PathBuf {
+  let mut name = db.file_name().unwrap_or_default().to_os_string();
+  name.push(LOCK_SUFFIX);
+  db.with_file_name(name)
+}
+
+/// Take the lock on `db` for this app, or return the pid of the app that holds it (0 if it hasn't written
+/// it yet). Taking a lock this app already holds (respawns, other instances on the same database) is fine.
+/// A lock file that can't be opened only logs a warning: the lock is a courtesy, not a requirement for
+/// spawning.
+pub(crate) fn acquire(db: &Path) -> Result<(), u32> {
+  let path = lock_path(db);
+  let mut held = HELD.lock().unwrap_or_else(PoisonError::into_inner);
+  if held.iter().any(|(p, _)| *p == path) {
+    return Ok(());
+  }
+  let mut file = match open_locked(&path) {
+    Ok(Some(file)) => file,
+    Ok(None) => return Err(read_owner(&path).unwrap_or(0)),
+    Err(e) => {
+      log::warn!("Could not take database lock {}: {}", path.display(), e);
+      return Ok(());
+    }
+  };
+  let written = file.set_len(0).and_then(|_| write!(file, "{}", std::process::id()));
+  if let Err(e) = written {
+    log::warn!("Could not write database lock {}: {}", path.display(), e);
+  }
+  held.push((path, file));
+  Ok(())
+}
+
+/// Give up this app's lock on `db`, if it holds it. The file is emptied but left in place: deleting it could
+/// let another app lock the old file while a third creates a new one.
+pub(crate) fn release(db: &Path) {
+  let path = lock_path(db);
+  let mut held = HELD.lock().unwrap_or_else(PoisonError::into_inner);
+  if let Some(i) = held.iter().position(|(p, _)| *p == path) {
+    let (_, file) = held.swap_remove(i);
+    let _ = file.set_len(0);
+  }
+}
+
+fn read_owner(path: &Path) -> Option<u32> {
+  std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Open `path` (creating it) and lock it without waiting: None if another process holds it.
+fn open_locked(path: &Path) -> std::io::Result<Option<File>> {
+  #[cfg(unix)]
+  {
+    use std::os::unix::io::AsRawFd;
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(path)?;
+    // SAFETY: flock(2) on a descriptor owned by `file`, which outlives the call.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+      return Ok(Some(file));
+    }
+    let e = std::io::Error::last_os_error();
+    if e.raw_os_error() == Some(libc::EWOULDBLOCK) {
+      Ok(None)
+    } else {
+      Err(e)
+    }
+  }
+
+  #[cfg(windows)]
+  {
+    use std::os::windows::fs::OpenOptionsExt;
+    // Others may still open the file to read the pid, but not for writing while this handle is open.
+    const FILE_SHARE_READ: u32 = 0x1;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    match OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .share_mode(FILE_SHARE_READ)
+      .open(path)
+    {
+      Ok(file) => Ok(Some(file)),
+      Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Ok(None),
+      Err(e) => Err(e),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn lock_is_refused_while_another_app_holds_it_and_free_after() {
+    let dir = std::env::temp_dir().join(format!("narrarc-lock-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let db = dir.join("mirror.db");
+    assert_eq!(lock_path(&db), dir.join("mirror.db.applock"));
+
+    assert_eq!(acquire(&db), Ok(()));
+    assert_eq!(acquire(&db), Ok(()), "this app may take its own lock again");
+    assert_eq!(read_owner(&lock_path(&db)), Some(std::process::id()));
+    release(&db);
+    assert_eq!(read_owner(&lock_path(&db)), None);
+
+    // Another app: its own lock on the file, with its pid inside.
+    let mut other = open_locked(&lock_path(&db)).unwrap().expect("lock is free after release");
+    write!(other, "4242").unwrap();
+    assert_eq!(acquire(&db), Err(4242));
+    release(&db);
+    assert_eq!(read_owner(&lock_path(&db)), Some(4242), "release leaves another app's lock alone");
+    drop(other);
+
+    // A crashed app leaves its pid behind but no OS lock, so the file is free whatever runs under that pid.
+    std::fs::write(lock_path(&db), "1").unwrap();
+    assert_eq!(acquire(&db), Ok(()));
+    assert_eq!(read_owner(&lock_path(&db)), Some(std::process::id()));
+    release(&db);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,92 @@
+//! Compressed response lines. A backend whose ping accepted an encoding (accept_encoding in the handshake)
+//! may send a large response as {"type": ..., "encoding": "gzip", "data": "<base64>"}, where `data` is the
+//! gzipped JSON of the original response. The dispatcher unpacks it before routing, so requests only ever
+//! see plain JSON.
+
+use std::io::Read;
+
+use base64::Engine;
+use flate2::read::GzDecoder;
+use serde_json::Value;
+
+/// The encoding offered in the handshake ping.
+pub(crate) const GZIP: &str = "gzip";
+
+/// Upper bound on a decompressed response, so a corrupt or hostile payload can't exhaust memory.
+const MAX_DECODED_BYTES: u64 = 256 * 1024 * 1024;
+
+/// The response `data` carries: unchanged unless it is an encoded envelope, which is replaced by the JSON
+/// it holds. An envelope that can't be decoded becomes an error response with code "bad_encoding".
+pub(crate) fn decode(data: Value) -> Value {
+  let (Some(encoding), Some(packed)) = (
+    data.get("encoding").and_then(Value::as_str),
+    data.get("data").and_then(Value::as_str),
+  ) else {
+    return data;
+  };
+  match unpack(encoding, packed) {
+    Ok(value) => value,
+    Err(e) => {
+      log::error!("Could not decode {} backend response: {}", encoding, e);
+      serde_json::json!({
+        "type": "error",
+        "message": format!("Could not decode {} backend response: {}", encoding, e),
+        "code": "bad_encoding",
+      })
+    }
+  }
+}
+
+fn unpack(encoding: &str, packed: &str) -> Result<Value, String> {
+  if encoding != GZIP {
+    return Err("unsupported encoding".to_string());
+  }
+  let compressed = base64::engine::general_purpose::STANDARD
+    .decode(packed)
+    .map_err(|e| format!("bad base64: {}", e))?;
+  let mut json = Vec::new();
+  GzDecoder::new(compressed.as_slice())
+    .take(MAX_DECODED_BYTES + 1)
+    .read_to_end(&mut json)
+    .map_err(|e| format!("bad gzip: {}", e))?;
+  if json.len() as u64 > MAX_DECODED_BYTES {
+    return Err(format!("larger than {} bytes decompressed", MAX_DECODED_BYTES));
+  }
+  serde_json::from_slice(&json).map_err(|e| format!("not JSON: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use flate2::write::GzEncoder;
+  use serde_json::json;
+  use std::io::Write;
+
+  fn gzip_envelope(value: &Value) -> Value {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(value.to_string().as_bytes()).unwrap();
+    let packed = base64::engine::general_purpose::STANDARD.encode(encoder.finish().unwrap());
+    json!({ "type": "result", "encoding": GZIP, "data": packed })
+  }
+
+  #[test]
+  fn gzip_envelopes_round_trip_and_plain_json_passes_through() {
+    let result = json!({
+      "type": "result",
+      "answer": "a".repeat(100_000),
+      "citations": [{ "node_id": "n1", "quote": "引用" }],
+    });
+    let envelope = gzip_envelope(&result);
+    assert!(envelope.to_string().len() < 10_000);
+    assert_eq!(decode(envelope), result);
+
+    assert_eq!(decode(result.clone()), result);
+    let plain = json!({ "type": "export", "data": "not encoded" });
+    assert_eq!(decode(plain.clone()), plain);
+
+    let corrupt = decode(json!({ "type": "result", "encoding": GZIP, "data": "bm90IGd6aXA=" }));
+    assert_eq!(corrupt["type"], "error");
+    assert_eq!(corrupt["code"], "bad_encoding");
+    assert_eq!(decode(json!({ "encoding": "br", "data": "" }))["code"], "bad_encoding");
+  }
+}
@@ -0,0 +1,262 @@
+//! HTTP fallback transport, for systems where writing requests into the backend's stdin is unreliable or
+//! restricted. The backend is started with `stdio --http`, announces {"type":"http","port":...,"token":...}
+//! as its first stdout line, and takes each message as a POST to http://127.0.0.1:<port>/rpc. The response
+//! streams that request's lines as NDJSON, tagged like stdout ({"req_id": ..., "data": ...}).
+//!
+//! HttpWriter is a BackendWriter, so requests are registered in the same PendingMap and awaited the same
+//! way as over stdio; only where their lines come from differs.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::ipc_log::{self, Direction};
+use crate::transport::{dispatch_lines, BackendReader, BackendWriter, PendingMap, PipeReader};
+
+/// How long the first request waits for the backend to announce its HTTP port.
+const ENDPOINT_TIMEOUT: Duration = Duration::from_secs(60);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Header the backend checks the announced token in, so other local processes can't use its server.
+const TOKEN_HEADER: &str = "X-Narrarc-Token";
+
+/// Bytes of each response buffered at a time.
+const RESPONSE_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Where the backend's HTTP server listens, from its first stdout line.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Endpoint {
+  pub(crate) port: u16,
+  pub(crate) token: String,
+}
+
+fn parse_endpoint(line: &str) -> Option<Endpoint> {
+  let v: serde_json::Value = serde_json::from_str(line.trim()).ok()?;
+  if v.get("type").and_then(|t| t.as_str()) != Some("http") {
+    return None;
+  }
+  Some(Endpoint {
+    port: u16::try_from(v.get("port")?.as_u64()?).ok()?,
+    token: v.get("token")?.as_str()?.to_string(),
+  })
+}
+
+/// Own backend stdout on a dedicated thread: read up to the endpoint line and hand it to the returned
+/// receiver, then dispatch the rest as over stdio, so its EOF when the backend exits still fails every
+/// pending request. The receiver errors if stdout ends first.
+pub(crate) fn spawn_endpoint_reader(
+  mut reader: Box<dyn BackendReader>,
+  pending: PendingMap,
+  poisoned: Arc<AtomicBool>,
+) -> Receiver<Endpoint> {
+  let (tx, rx) = std::sync::mpsc::channel();
+  std::thread::spawn(move || {
+    loop {
+      match reader.read_line() {
+        Ok(Some(line)) => {
+          if let Some(endpoint) = parse_endpoint(&line) {
+            log::debug!("Backend serves HTTP on port {}", endpoint.port);
+            let _ = tx.send(endpoint);
+            break;
+          }
+          log::warn!("Skipping backend stdout line before its HTTP endpoint: {}", line.trim());
+        }
+        Ok(None) | Err(_) => return,
+      }
+    }
+    dispatch_lines(reader.as_mut(), &pending, &poisoned);
+  });
+  rx
+}
+
+/// BackendWriter that POSTs each message to the backend's HTTP server and routes the lines of the response
+/// into `pending` from a thread of its own. A response that ends before its request's final line closes the
+/// request, which then fails like a crash.
+pub(crate) struct HttpWriter<S> {
+  endpoint: Option<Endpoint>,
+  endpoint_rx: Receiver<Endpoint>,
+  pending: PendingMap,
+  max_line_bytes: usize,
+  /// Backend stdin, held open only: the backend exits when it closes.
+  _stdin: Option<S>,
+}
+
+impl<S: Send> HttpWriter<S> {
+  pub(crate) fn new(
+    endpoint_rx: Receiver<Endpoint>,
+    pending: PendingMap,
+    max_line_bytes: usize,
+    stdin: Option<S>,
+  ) -> Self {
+    HttpWriter {
+      endpoint: None,
+      endpoint_rx,
+      pending,
+      max_line_bytes,
+      _stdin: stdin,
+    }
+  }
+
+  fn endpoint(&mut self) -> std::io::Result<Endpoint> {
+    if let Some(ref endpoint) = self.endpoint {
+      return Ok(endpoint.clone());
+    }
+    let endpoint = self.endpoint_rx.recv_timeout(ENDPOINT_TIMEOUT).map_err(|e| {
+      let kind = match e {
+        RecvTimeoutError::Timeout => std::io::ErrorKind::TimedOut,
+        RecvTimeoutError::Disconnected => std::io::ErrorKind::BrokenPipe,
+      };
+      std::io::Error::new(kind, "backend did not announce its HTTP endpoint")
+    })?;
+    self.endpoint = Some(endpoint.clone());
+    Ok(endpoint)
+  }
+}
+
+impl<S: Send> BackendWriter for HttpWriter<S> {
+  fn send_line(&mut self, line: &str) -> std::io::Result<()> {
+    let endpoint = self.endpoint()?;
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, endpoint.port));
+    let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    write!(
+      stream,
+      "POST /rpc HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}: {}\r\nConnection: close\r\n\r\n",
+      addr,
+      line.len(),
+      TOKEN_HEADER,
+      endpoint.token
+    )?;
+    stream.write_all(line.as_bytes())?;
+    stream.flush()?;
+    let req_id = serde_json::from_str::<serde_json::Value>(line)
+      .ok()
+      .and_then(|v| v.get("req_id")?.as_u64());
+    let (pending, max_line_bytes) = (self.pending.clone(), self.max_line_bytes);
+    std::thread::spawn(move || read_response(stream, req_id, &pending, max_line_bytes));
+    Ok(())
+  }
+}
+
+/// Route the NDJSON lines of one response to their requests, then close `req_id`'s route.
+fn read_response(stream: TcpStream, req_id: Option<u64>, pending: &PendingMap, max_line_bytes: usize) {
+  let mut reader = BufReader::new(stream);
+  match read_status(&mut reader) {
+    Ok(200) => {
+      let mut lines = PipeReader::new(reader, max_line_bytes, RESPONSE_BUFFER_BYTES);
+      while let Ok(Some(line)) = lines.read_line() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+          continue;
+        }
+        ipc_log::record(Direction::Received, trimmed);
+        let Ok(mut value) = serde_json::from_str::<serde_json::Value>(trimmed) else {
+          log::warn!("Skipping non-JSON backend HTTP line: {}", trimmed);
+          continue;
+        };
+        let Some(id) = value.get("req_id").and_then(|id| id.as_u64()) else {
+          continue;
+        };
+        let data = crate::encoding::decode(
+          value
+            .get_mut("data")
+            .map(serde_json::Value::take)
+            .unwrap_or(serde_json::Value::Null),
+        );
+        if let Ok(mut map) = pending.lock() {
+          if let Some(route) = map.get_mut(&id) {
+            route.send(data);
+          }
+        }
+      }
+    }
+    status => {
+      let message = match status {
+        Ok(status) => format!("backend HTTP server answered {}", status),
+        Err(e) => format!("backend HTTP response unreadable: {}", e),
+      };
+      log::error!("{}", message);
+      if let (Some(id), Ok(mut map)) = (req_id, pending.lock()) {
+        if let Some(route) = map.get_mut(&id) {
+          route.send(serde_json::json!({ "type": "error", "message": message }));
+        }
+      }
+    }
+  }
+  if let (Some(id), Ok(mut map)) = (req_id, pending.lock()) {
+    map.remove(&id);
+  }
+}
+
+/// Read the status line and headers of an HTTP response; returns the status code.
+fn read_status(reader: &mut impl BufRead) -> std::io::Result<u16> {
+  let invalid = |what: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, what.to_string());
+  let mut status_line = String::new();
+  reader.read_line(&mut status_line)?;
+  let status = status_line
+    .split_whitespace()
+    .nth(1)
+    .and_then(|code| code.parse().ok())
+    .ok_or_else(|| invalid("malformed status line"))?;
+  loop {
+    let mut header = String::new();
+    if reader.read_line(&mut header)? == 0 {
+      return Err(invalid("response ended inside its headers"));
+    }
+    if header.trim().is_empty() {
+      return Ok(status);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::transport::PendingRequest;
+  use std::io::Read;
+  use std::net::TcpListener;
+
+  #[test]
+  fn http_writer_routes_the_response_to_its_request() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+      let (mut conn, _) = listener.accept().unwrap();
+      let mut request = Vec::new();
+      let mut buf = [0u8; 1024];
+      while !String::from_utf8_lossy(&request).contains("\"req_id\":7}") {
+        let n = conn.read(&mut buf).unwrap();
+        request.extend_from_slice(&buf[..n]);
+      }
+      conn
+        .write_all(b"HTTP/1.0 200 OK\r\nContent-Type: application/x-ndjson\r\n\r\n")
+        .unwrap();
+      conn
+        .write_all(b"{\"req_id\":7,\"data\":{\"type\":\"progress\"}}\n{\"req_id\":7,\"data\":{\"type\":\"pong\"}}\n")
+        .unwrap();
+      String::from_utf8(request).unwrap()
+    });
+
+    assert_eq!(
+      parse_endpoint(&format!("{{\"type\":\"http\",\"port\":{},\"token\":\"t0k\"}}", port)),
+      Some(Endpoint { port, token: "t0k".to_string() })
+    );
+    assert_eq!(parse_endpoint("{\"type\":\"pong\"}"), None);
+    let (tx, rx) = std::sync::mpsc::channel();
+    tx.send(Endpoint { port, token: "t0k".to_string() }).unwrap();
+    let pending = PendingMap::default();
+    let mut writer = HttpWriter::<()>::new(rx, pending.clone(), 1024, None);
+    let mut request = PendingRequest::register(&pending, 7, 0).unwrap();
+    writer.send_line("{\"cmd\":\"ping\",\"req_id\":7}").unwrap();
+
+    let sent = server.join().unwrap();
+    assert!(sent.starts_with("POST /rpc HTTP/1.1\r\n"));
+    assert!(sent.contains("X-Narrarc-Token: t0k\r\n"));
+    assert_eq!(request.rx.blocking_recv().unwrap().data["type"], "progress");
+    assert_eq!(request.rx.blocking_recv().unwrap().data["type"], "pong");
+    assert!(request.rx.blocking_recv().is_none(), "the route closes with the response");
+  }
+}
@@ -0,0 +1,178 @@
+//! Opt-in transcript of the backend protocol for bug reports: every line written to and read from the
+//! backend, appended to `ipc.log` with a timestamp and direction marker. Off by default because it contains
+//! the user's questions and answers.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Size at which ipc.log is rotated to ipc.log.1 (the previous ipc.log.1 is discarded).
+const IPC_LOG_MAX_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Replacement for redacted secret values.
+pub(crate) const REDACTED: &str = "[redacted]";
+
+/// The open transcript, if logging is enabled.
+static IPC_LOG: Mutex<Option<IpcLog>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Direction {
+  Sent,
+  Received,
+}
+
+struct IpcLog {
+  path: PathBuf,
+  file: File,
+  written: u64,
+}
+
+impl IpcLog {
+  fn open(path: &Path) -> std::io::Result<Self> {
+    if let Some(dir) = path.parent() {
+      std::fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let written = file.metadata()?.len();
+    Ok(IpcLog {
+      path: path.to_path_buf(),
+      file,
+      written,
+    })
+  }
+
+  fn append(&mut self, direction: Direction, line: &str) -> std::io::Result<()> {
+    if self.written >= IPC_LOG_MAX_BYTES {
+      let mut rotated = self.path.clone().into_os_string();
+      rotated.push(".1");
+      std::fs::rename(&self.path, rotated)?;
+      *self = IpcLog::open(&self.path)?;
+    }
+    let ts_ms = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_millis())
+      .unwrap_or(0);
+    let marker = match direction {
+      Direction::Sent => ">>",
+      Direction::Received => "<<",
+    };
+    let entry = format!("{} {} {}\n", ts_ms, marker, redact(line));
+    self.file.write_all(entry.as_bytes())?;
+    self.written += entry.len() as u64;
+    Ok(())
+  }
+}
+
+/// Start appending to the transcript at `path`.
+pub(crate) fn enable(path: &Path) -> std::io::Result<()> {
+  let log = IpcLog::open(path)?;
+  if let Ok(mut slot) = IPC_LOG.lock() {
+    *slot = Some(log);
+  }
+  Ok(())
+}
+
+/// Stop logging and close the transcript.
+pub(crate) fn disable() {
+  if let Ok(mut slot) = IPC_LOG.lock() {
+    *slot = None;
+  }
+}
+
+pub(crate) fn is_enabled() -> bool {
+  IPC_LOG.lock().map(|slot| slot.is_some()).unwrap_or(false)
+}
+
+/// Append one protocol line if logging is enabled. A failed write disables logging rather than erroring
+/// the request.
+pub(crate) fn record(direction: Direction, line: &str) {
+  let Ok(mut slot) = IPC_LOG.lock() else {
+    return;
+  };
+  if let Some(log) = slot.as_mut() {
+    if let Err(e) = log.append(direction, line) {
+      log::warn!("IPC logging disabled: {}", e);
+      *slot = None;
+    }
+  }
+}
+
+/// `line` with the values of secret-looking keys ("key"/"token" in the name) under any `config_overrides`
+/// replaced. Lines that aren't JSON are logged as-is.
+fn redact(line: &str) -> String {
+  match serde_json::from_str::<serde_json::Value>(line) {
+    Ok(mut v) => {
+      redact_value(&mut v, false);
+      v.to_string()
+    }
+    Err(_) => line.to_string(),
+  }
+}
+
+fn redact_value(v: &mut serde_json::Value, in_overrides: bool) {
+  match v {
+    serde_json::Value::Object(map) => {
+      for (k, child) in map.iter_mut() {
+        let name = k.to_ascii_lowercase();
+        if in_overrides && (name.contains("key") || name.contains("token")) && !child.is_null() {
+          *child = serde_json::json!(REDACTED);
+        } else {
+          redact_value(child, in_overrides || k == "config_overrides");
+        }
+      }
+    }
+    serde_json::Value::Array(items) => {
+      for item in items {
+        redact_value(item, in_overrides);
+      }
+    }
+    _ => {}
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn redact_only_touches_secrets_under_config_overrides() {
+    let line = json!({
+      "cmd": "query",
+      "question": "what key moments?",
+      "config_overrides": {
+        "llm": { "api_key": "sk-1", "model": "m", "max_tokens": 512 },
+        "embedding": { "access_token": "t" }
+      }
+    })
+    .to_string();
+    let out: serde_json::Value = serde_json::from_str(&redact(&line)).unwrap();
+    assert_eq!(out["question"], "what key moments?");
+    assert_eq!(out["config_overrides"]["llm"]["api_key"], REDACTED);
+    assert_eq!(out["config_overrides"]["llm"]["model"], "m");
+    assert_eq!(out["config_overrides"]["llm"]["max_tokens"], REDACTED);
+    assert_eq!(out["config_overrides"]["embedding"]["access_token"], REDACTED);
+    assert_eq!(redact("not json"), "not json");
+  }
+
+  #[test]
+  fn append_marks_direction_and_rotates() {
+    let dir = std::env::temp_dir().join(format!("narrarc-ipc-log-test-{}", std::process::id()));
+    let path = dir.join("ipc.log");
+    let mut log = IpcLog::open(&path).unwrap();
+    log.append(Direction::Sent, r#"{"cmd":"ping","req_id":1}"#).unwrap();
+    log.append(Direction::Received, r#"{"req_id":1,"data":{"type":"pong"}}"#).unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0].split(' ').nth(1), Some(">>"));
+    assert_eq!(lines[1].split(' ').nth(1), Some("<<"));
+    log.written = IPC_LOG_MAX_BYTES;
+    log.append(Direction::Sent, "after rotation").unwrap();
+    assert!(dir.join("ipc.log.1").exists());
+    assert!(std::fs::read_to_string(&path).unwrap().ends_with("after rotation\n"));
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+}
@@ -1,415 +1,4839 @@
-use std::io::{BufRead, BufReader, Write};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Read};
 use std::ops::DerefMut;
-use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::Duration;
 use tauri::{Emitter, Manager};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::sync::mpsc::error::TryRecvError;
+use app_log::AppLog;
+use process_tree::{exit_signal, kill_child_tree, kill_process_tree, new_process_group};
+use progress::ProgressEvent;
+use resource_usage::resource_usage;
+use sidecar::SidecarDiagnostics;
+use http_transport::HttpWriter;
+use transport::{
+  await_response, next_stream_line, spawn_stdout_dispatcher, BackendError, BackendReader, BackendWriter,
+  FrameWriter, Framing, PendingMap, PendingRequest, PipeReader, ResponseError, StreamLine,
+};
 
-/// Long-lived backend process: stdin/stdout for JSON lines; child kept for kill on exit.
+mod app_log;
+mod config_file;
+mod db_lock;
+mod encoding;
+mod http_transport;
+mod ipc_log;
+mod process_tree;
+mod progress;
+mod resource_usage;
+#[cfg_attr(debug_assertions, allow(dead_code))]
+mod sidecar;
+mod transport;
+
+/// Config file passed to the backend when the frontend does not pick one.
+const DEFAULT_CONFIG_PATH: &str = "config.yml";
+
+/// Top-level sections the backend's apply_overrides understands.
+const OVERRIDE_SECTIONS: &[&str] = &["llm", "embedding", "reranker"];
+
+/// Environment variables the frontend may set for the backend via restart_backend: LLM endpoint/key
+/// overrides read by the OpenAI SDK, proxy settings and CA bundles. Anything else (LD_PRELOAD, PATH,
+/// PYTHONPATH, ...) is rejected.
+const ALLOWED_BACKEND_ENV: &[&str] = &[
+  "OPENAI_API_KEY",
+  "OPENAI_BASE_URL",
+  "HTTP_PROXY",
+  "HTTPS_PROXY",
+  "ALL_PROXY",
+  "NO_PROXY",
+  "http_proxy",
+  "https_proxy",
+  "all_proxy",
+  "no_proxy",
+  "SSL_CERT_FILE",
+  "REQUESTS_CA_BUNDLE",
+];
+
+/// Applied by backend_request when the frontend does not pass timeout_ms.
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 120_000;
+/// How many questions get_query_history returns when the frontend does not pass a limit.
+const DEFAULT_HISTORY_LIMIT: u32 = 50;
+
+/// Target triple the app was built for, which names the release sidecar (`backend-<triple>`). Cargo sets
+/// TARGET only while running build scripts, so build.rs passes it on as APP_TARGET.
+const APP_TARGET: &str = env!("APP_TARGET");
+const _: () = assert!(
+  looks_like_triple(APP_TARGET),
+  "APP_TARGET from build.rs is not a target triple"
+);
+
+/// Whether `s` has the shape of a target triple: at least three non-empty `-`-separated parts
+/// (arch-vendor-os[-env]) and no whitespace.
+const fn looks_like_triple(s: &str) -> bool {
+  let bytes = s.as_bytes();
+  let (mut i, mut parts, mut part_len) = (0, 1, 0);
+  while i < bytes.len() {
+    if bytes[i] == b'-' {
+      if part_len == 0 {
+        return false;
+      }
+      parts += 1;
+      part_len = 0;
+    } else if bytes[i].is_ascii_whitespace() {
+      return false;
+    } else {
+      part_len += 1;
+    }
+    i += 1;
+  }
+  part_len > 0 && parts >= 3
+}
+
+/// Read-only commands backend_request may send a second time after the pipe broke (see should_retry).
+const IDEMPOTENT_COMMANDS: &[&str] = &[
+  "ping",
+  "capabilities",
+  "get_config",
+  "list_sessions",
+  "list_talkers",
+  "get_messages",
+  "warmup",
+  "select_talker",
+];
+
+/// Commands that write to the database, refused in read-only mode by the generic request commands
+/// (backend_request, backend_batch, backend_stream) as ensure_writable refuses the dedicated ones.
+const DB_WRITE_COMMANDS: &[&str] = &["import", "delete_session", "delete_talker", "reset", "open_db"];
+
+/// How many recent backend stderr lines get_backend_stderr can return.
+const STDERR_BUFFER_LINES: usize = 500;
+/// Emitted events kept in RecentEvents for windows that subscribe late.
+const RECENT_EVENTS_CAPACITY: usize = 500;
+
+/// How long a freshly spawned backend has to answer the readiness ping (interpreter start + imports).
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often `backend://startup_progress` is emitted while waiting for the readiness handshake.
+const STARTUP_PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long backend_health waits for a pong before reporting the backend unresponsive.
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the exit monitor checks whether the backend died on its own.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many trailing stderr lines accompany a `backend://exited` event.
+const EXIT_STDERR_TAIL_LINES: usize = 20;
+
+/// How long after spawning a backend is checked for having exited already (missing dependency, bad config).
+const STARTUP_EXIT_CHECK: Duration = Duration::from_millis(150);
+
+/// How often the idle monitor checks whether a backend has been unused for the idle timeout.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Error of requests to a backend shut down with stop_backend.
+const BACKEND_STOPPED: &str = "Backend is stopped; start it again with start_backend";
+
+/// How long the backend gets to exit after {"cmd":"shutdown"} before it is force-killed (idle suspend,
+/// stop_backend, drop_named_backend, restarts while no build runs).
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+
+/// Shutdown grace when the app quits, so closing the window stays snappy.
+const APP_EXIT_GRACE: Duration = Duration::from_secs(1);
+
+/// Shutdown grace for a restart while a build is running, so the backend's database writes can commit.
+const BUILD_RESTART_GRACE: Duration = Duration::from_secs(10);
+
+/// Default cap on a single backend stdout line; larger lines fail pending requests instead of growing memory.
+const DEFAULT_MAX_LINE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Largest file backend_query_stream accepts as an attachment.
+const MAX_ATTACHMENT_BYTES: u64 = 1024 * 1024;
+
+/// Cap on the attachments of one query together; they travel inside a single stdin line.
+const MAX_ATTACHMENTS_TOTAL_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Read buffer for backend stdout. With std's default 8 KiB a 4 MiB result takes ~512 read() calls; at
+/// 64 KiB (also the default Linux pipe capacity, so one read rarely returns more) it takes ~64.
+const DEFAULT_STDOUT_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Environment variable overriding DEFAULT_STDOUT_BUFFER_BYTES (in bytes).
+const STDOUT_BUFFER_ENV: &str = "NARRARC_STDOUT_BUFFER_BYTES";
+
+/// Spawn attempts before giving up; transient failures (antivirus locking the sidecar, a volume not yet
+/// mounted at login) usually clear within a few seconds.
+const SPAWN_ATTEMPTS: u32 = 3;
+
+/// Delay after the first failed spawn attempt; each further delay is SPAWN_BACKOFF_FACTOR times longer.
+const SPAWN_RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+const SPAWN_BACKOFF_FACTOR: u32 = 4;
+
+/// File in the app config dir holding the database chosen with set_database.
+const ACTIVE_DB_FILE: &str = "active_database";
+
+/// Default silence on a streaming query after which `backend://stalled` is emitted (the query keeps running).
+const DEFAULT_STREAM_STALL_MS: u64 = 30_000;
+
+/// Default silence on a streaming query after which it is aborted and the backend marked for restart.
+const DEFAULT_STREAM_SILENCE_TIMEOUT_MS: u64 = 600_000;
+
+/// Characters of a request payload kept in the debug request log.
+const LOG_PAYLOAD_CHARS: usize = 200;
+
+/// Requests larger than this are logged with a warning when written (attachments, big batches).
+const LARGE_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// Dev only: overrides the interpreter command (`uv run python` by default), split like a shell would, so a
+/// plain venv, conda or a debugger wrapper works, e.g. `.venv/bin/python` or `python -m pdb`.
+#[cfg(debug_assertions)]
+const BACKEND_CMD_ENV: &str = "NARRARC_BACKEND_CMD";
+
+/// Dev only: the backend directory (the one holding pyproject.toml), for launches from an IDE or another
+/// working directory where discovery can't find it.
+#[cfg(debug_assertions)]
+const BACKEND_DIR_ENV: &str = "NARRARC_BACKEND_DIR";
+
+/// Dev only: how many ancestors of CARGO_MANIFEST_DIR are searched for a `backend/pyproject.toml`.
+#[cfg(debug_assertions)]
+const BACKEND_SEARCH_DEPTH: usize = 4;
+
+/// Protocol transcript in the app data dir, written while set_ipc_logging is on.
+const IPC_LOG_FILE: &str = "ipc.log";
+
+/// JSON settings file in the app data dir: the release-mode `db_dir` preference, `build_jobs`,
+/// `idle_timeout_minutes`, `log_level` and `backend_transport`.
+const SETTINGS_FILE: &str = "settings.json";
+
+/// Allowed number of parallel build workers (`build --jobs`, the backend's llm.max_workers).
+const BUILD_JOBS_RANGE: std::ops::RangeInclusive<u32> = 1..=64;
+
+/// How many stdout lines of a build get_build_log keeps; older ones are dropped and counted.
+const BUILD_LOG_LINES: usize = 5000;
+
+/// Environment variable setting the build worker count when neither the caller nor settings.json does.
+const JOBS_ENV: &str = "NARRARC_JOBS";
+
+/// Values of the `log_level` setting, passed to the backend as `--log-level` (stdio daemon and builds).
+const LOG_LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Values of the `backend_transport` setting: requests go over the backend's stdin, or, where that is
+/// restricted, to the local HTTP server it starts with `stdio --http` (see http_transport).
+const BACKEND_TRANSPORTS: [&str; 2] = ["stdio", "http"];
+const DEFAULT_BACKEND_TRANSPORT: &str = "stdio";
+
+/// Transcript formats export_transcript accepts: (extension, save dialog filter name).
+const EXPORT_FORMATS: &[(&str, &str)] = &[("md", "Markdown"), ("json", "JSON")];
+
+/// First 16 bytes of every SQLite 3 database file.
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Marker file in the app config dir; when present the startup warmup is skipped (see set_warmup_enabled).
+const WARMUP_DISABLED_FILE: &str = "warmup_disabled";
+
+/// Warmup loads the LLM/embedding clients, which can take much longer than an ordinary request.
+const WARMUP_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Operations in flight per backend unless its pong advertises `max_concurrent` (a backend with the
+/// "multiplex" feature serves that many streaming queries at once); older backends serve one at a time, so
+/// more would only queue inside the pipe.
+const MAX_IN_FLIGHT_REQUESTS: usize = 1;
+
+/// Environment variable overriding MAX_IN_FLIGHT_REQUESTS.
+const MAX_IN_FLIGHT_ENV: &str = "NARRARC_MAX_IN_FLIGHT";
+
+/// Environment variable that starts the app in read-only mode ("1", "true", "yes" or "on"), for demo and
+/// kiosk machines. Read-only mode entered this way can't be turned off from the UI.
+const READ_ONLY_ENV: &str = "NARRARC_READ_ONLY";
+
+/// Error of commands refused in read-only mode.
+const READ_ONLY_ERROR: &str = "read-only mode";
+
+/// Consecutive {"type":"error"} responses after which the watchdog restarts a backend.
+const WATCHDOG_THRESHOLD: u32 = 3;
+
+/// Environment variable overriding WATCHDOG_THRESHOLD; 0 turns the watchdog off.
+const WATCHDOG_THRESHOLD_ENV: &str = "NARRARC_WATCHDOG_THRESHOLD";
+
+/// Wait before the next auto-restart after one that did not help; doubles with each further one, up to
+/// WATCHDOG_MAX_BACKOFF.
+const WATCHDOG_BASE_BACKOFF: Duration = Duration::from_secs(30);
+const WATCHDOG_MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+/// Backend protocol versions this app can talk to (reported in the pong and by {"cmd":"capabilities"}).
+const SUPPORTED_PROTOCOL_VERSIONS: std::ops::RangeInclusive<u64> = 1..=1;
+
+/// Event streaming progress is emitted as unless backend_stream is given another.
+const PROGRESS_EVENT: &str = "backend://progress";
+
+/// Event carrying the provisional answer of a streaming query ({"type":"partial"} lines).
+const PARTIAL_EVENT: &str = "backend://partial";
+
+/// Instance id of the backend spawned at startup; commands without an instance_id target it.
+const DEFAULT_INSTANCE: &str = "default";
+
+/// Source of the `req_id` attached to every payload written to the backend.
+static NEXT_REQ_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Source of the query ids handed out by start_query.
+static NEXT_QUERY_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Everything a (re)spawn needs; remembered on BackendProcess so a respawn after a crash targets the
+/// same database with the same environment.
+#[derive(Clone)]
+struct BackendSpawnConfig {
+  /// Key of this backend in Backends; included in its events.
+  instance_id: String,
+  cwd: PathBuf,
+  /// Passed to `--db` as an OsStr (see backend_args), never through a lossy string conversion.
+  db_arg: PathBuf,
+  /// Default config path for requests that don't name one (`stdio --config`); set by reload_config.
+  config_arg: String,
+  /// Extra environment variables (keys restricted to ALLOWED_BACKEND_ENV).
+  env: HashMap<String, String>,
+  /// Longest stdout line the dispatcher will buffer.
+  max_line_bytes: usize,
+  /// `--log-level` (one of LOG_LEVELS); set_log_level updates it for the next restart.
+  log_level: String,
+  /// Start the backend with `--http` and send requests over HTTP; set_backend_transport updates it for the
+  /// next restart.
+  http: bool,
+  /// What to run; set_backend_source updates it for the next restart.
+  source: BackendSource,
+}
+
+impl BackendSpawnConfig {
+  /// Point the backend, and the builds and reindexes run for it (job_db_path), at `db` from its next
+  /// restart on. Releases this app's lock on the old database.
+  fn switch_db(&mut self, db: PathBuf) {
+    db_lock::release(&self.db_arg);
+    self.db_arg = db;
+  }
+}
+
+/// Where the backend process comes from, stored as the `backend_source` setting.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BackendSource {
+  /// The sidecar shipped with the app; in dev, `uv run python` (or NARRARC_BACKEND_CMD) in the backend dir.
+  #[default]
+  Bundled,
+  /// A local backend checkout: `cmd` (program and leading args, e.g. `uv run python -m
+  /// narrative_mirror.cli_json`) gets the usual backend args appended and runs in `cwd`.
+  External { cmd: Vec<String>, cwd: PathBuf },
+}
+
+/// Long-lived backend process: stdin for JSON lines; stdout is owned by a dispatcher thread
+/// that routes lines by req_id into `pending`. child kept for kill on exit.
 struct BackendProcess {
   child: Child,
-  stdin: Option<std::process::ChildStdin>,
-  stdout: Option<BufReader<std::process::ChildStdout>>,
+  stdin: Option<Box<dyn BackendWriter>>,
+  pending: PendingMap,
+  spawn: BackendSpawnConfig,
+  /// Set when a request timed out, a write to stdin failed or the dispatcher hit an oversized line; the next
+  /// request kills and respawns instead of reusing a wedged process.
+  poisoned: Arc<AtomicBool>,
+  /// Set once the readiness handshake ({"cmd":"ping"} -> {"type":"pong"}) succeeded.
+  ready: bool,
+  /// Set by shutdown_backend so the exit monitor does not report an intentional exit.
+  shutting_down: bool,
+  /// Set when the app force-killed the child (shutdown grace ran out, or it was poisoned), so its exit is
+  /// reported as ours rather than a crash.
+  killed: bool,
+  /// The backend's answer to {"cmd":"capabilities"}, once asked; a respawned process is asked again.
+  capabilities: Option<serde_json::Value>,
+  /// Streaming queries the backend serves at once, from `max_concurrent` in its pong; None if it did not
+  /// say (it serves one request at a time).
+  max_concurrent: Option<usize>,
+  state: BackendState,
 }
 
-/// Spawn backend: dev uses uv run python, release uses bundled sidecar via std::process::Command.
-fn spawn_backend_process(app: Option<&tauri::AppHandle>) -> Result<BackendProcess, String> {
-  let (cwd, db_arg) = get_backend_cwd_and_db(app);
+/// What a BackendProcess is meant to be doing; whether the child is actually alive is checked separately.
+#[derive(Clone, Debug, PartialEq)]
+enum BackendState {
+  /// Serving requests; ensure_alive respawns it if it died.
+  Running,
+  /// Shut down by the idle monitor; the next request respawns it and emits `backend://resumed`.
+  Suspended,
+  /// Shut down with stop_backend; requests fail with BACKEND_STOPPED until start_backend.
+  Stopped,
+  /// The last (re)spawn failed with this error; the next request tries again.
+  Failed(String),
+}
 
-  #[cfg(debug_assertions)]
-  {
-    let mut child = Command::new("uv")
-      .args([
-        "run",
-        "python",
-        "-m",
-        "narrative_mirror.cli_json",
-        "--db",
-        &db_arg,
-        "stdio",
-      ])
-      .env("PYTHONUNBUFFERED", "1")
-      .env("PYTHONIOENCODING", "utf-8")
-      .current_dir(&cwd)
-      .stdin(Stdio::piped())
-      .stdout(Stdio::piped())
-      .stderr(Stdio::inherit())
-      .spawn()
-      .map_err(|e| format!("Failed to spawn backend: {}", e))?;
-    let stdin = child.stdin.take();
-    let stdout = child.stdout.take().map(BufReader::new);
-    Ok(BackendProcess {
-      child,
-      stdin,
-      stdout,
+impl BackendState {
+  fn name(&self) -> &'static str {
+    match self {
+      BackendState::Running => "running",
+      BackendState::Suspended => "suspended",
+      BackendState::Stopped => "stopped",
+      BackendState::Failed(_) => "failed",
+    }
+  }
+}
+
+/// Why the backend could not be started. The Display text is shown to the user, so it says what to do.
+#[derive(Debug)]
+enum SpawnError {
+  /// Dev mode: `uv` is not on PATH.
+  #[cfg_attr(not(debug_assertions), allow(dead_code))]
+  UvNotFound,
+  /// Release: the bundled sidecar binary is missing or built for another target; see sidecar::diagnose.
+  #[cfg_attr(debug_assertions, allow(dead_code))]
+  SidecarUnusable(Box<SidecarDiagnostics>),
+  /// Release with `verify-sidecar`: the sidecar's SHA-256 does not match sidecar::MANIFEST_FILE.
+  #[cfg_attr(any(debug_assertions, not(feature = "verify-sidecar")), allow(dead_code))]
+  SidecarChecksum(String),
+  /// Dev mode: NARRARC_BACKEND_CMD could not be parsed or is empty; or an external backend source is no
+  /// longer valid (see validate_backend_source).
+  BadCommand(String),
+  /// Dev mode: no backend directory was found (see find_backend_dir).
+  BackendNotFound(String),
+  Io(std::io::Error),
+  /// The process started but exited within STARTUP_EXIT_CHECK; see check_started.
+  ExitedImmediately {
+    status: String,
+    exit_code: Option<i32>,
+    stderr_tail: Vec<String>,
+  },
+  /// Every one of `attempts` spawn attempts failed; `last` is the final failure.
+  GaveUp { attempts: u32, last: Box<SpawnError> },
+  /// Another running app instance (`pid`) holds the lock on the database; see db_lock.
+  DbLocked { db_path: PathBuf, pid: u32 },
+}
+
+impl std::fmt::Display for SpawnError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SpawnError::UvNotFound => write!(
+        f,
+        "uv was not found on PATH. Install uv (https://docs.astral.sh/uv/) and restart the app."
+      ),
+      SpawnError::SidecarUnusable(diag) => match &diag.mismatch {
+        Some(m) => write!(
+          f,
+          "Backend binary {} is built for {} but this app is {}. Reinstall the app for this platform.",
+          m.file,
+          m.arch.as_deref().unwrap_or("another platform"),
+          diag.target
+        ),
+        None if !diag.bin_dir_exists => write!(
+          f,
+          "Backend directory {} is missing. Reinstall the app.",
+          diag.resource_dir.join("bin").join("backend").display()
+        ),
+        None => write!(
+          f,
+          "Backend binary not found at {}. Reinstall the app.",
+          diag.expected_path.display()
+        ),
+      },
+      SpawnError::SidecarChecksum(message) => write!(
+        f,
+        "Backend binary failed its integrity check ({}). Reinstall the app.",
+        message
+      ),
+      SpawnError::BadCommand(message) | SpawnError::BackendNotFound(message) => {
+        write!(f, "{}", message)
+      }
+      SpawnError::Io(e) => write!(f, "Failed to spawn backend: {}", e),
+      SpawnError::ExitedImmediately {
+        status,
+        stderr_tail,
+        ..
+      } => {
+        write!(f, "Backend started but exited immediately ({})", status)?;
+        match stderr_tail.last() {
+          Some(last) => write!(f, ": {}", last),
+          None => Ok(()),
+        }
+      }
+      SpawnError::GaveUp { attempts, last } => {
+        write!(f, "{} (gave up after {} attempts)", last, attempts)
+      }
+      SpawnError::DbLocked { db_path, pid } => write!(
+        f,
+        "Database {} is in use by another running copy of the app (pid {}). Close it and try again.",
+        db_path.display(),
+        pid
+      ),
+    }
+  }
+}
+
+impl SpawnError {
+  /// Structured detail for get_spawn_diagnostics, for the errors that have any.
+  fn details(&self) -> Option<serde_json::Value> {
+    match self {
+      SpawnError::SidecarUnusable(diag) => serde_json::to_value(diag).ok(),
+      SpawnError::ExitedImmediately {
+        status,
+        exit_code,
+        stderr_tail,
+      } => Some(serde_json::json!({
+        "status": status,
+        "exit_code": exit_code,
+        "stderr_tail": stderr_tail,
+      })),
+      SpawnError::GaveUp { last, .. } => last.details(),
+      SpawnError::DbLocked { db_path, pid } => Some(serde_json::json!({
+        "db_path": db_path.to_string_lossy(),
+        "pid": pid,
+      })),
+      _ => None,
+    }
+  }
+}
+
+impl From<SpawnError> for String {
+  fn from(e: SpawnError) -> String {
+    e.to_string()
+  }
+}
+
+/// The most recent failed backend spawn; cleared by the next successful one.
+#[derive(Default)]
+struct LastSpawnError(Mutex<Option<SpawnFailure>>);
+
+#[derive(Clone, serde::Serialize)]
+struct SpawnFailure {
+  message: String,
+  /// E.g. the SidecarDiagnostics of a missing or mismatched sidecar.
+  details: Option<serde_json::Value>,
+}
+
+/// Running backends keyed by instance id. DEFAULT_INSTANCE is spawned at startup; more can be added with
+/// spawn_named_backend, e.g. to compare two talkers side by side.
+struct Backends(Mutex<HashMap<String, BackendInstance>>);
+
+/// One backend process, the queue of its request worker and the limit on its in-flight operations.
+#[derive(Clone)]
+struct BackendInstance {
+  process: Arc<Mutex<BackendProcess>>,
+  queue: RequestQueue,
+  limiter: RequestLimiter,
+  watchdog: Watchdog,
+}
+
+/// Restarts a backend that answers every request with an error (corrupt db, stuck model client) once
+/// `threshold` errors arrive in a row, then emits `backend://auto_recovered` {instance_id, errors, pid}.
+#[derive(Clone)]
+struct Watchdog {
+  app: tauri::AppHandle,
+  threshold: u32,
+  state: Arc<Mutex<WatchdogState>>,
+}
+
+#[derive(Debug, Default)]
+struct WatchdogState {
+  consecutive_errors: u32,
+  /// Auto-restarts in a row that failed, or were followed by `threshold` errors before any success.
+  failed_restarts: u32,
+  /// No auto-restart before this.
+  backoff_until: Option<std::time::Instant>,
+  restarting: bool,
+  /// An auto-restart succeeded and no response has succeeded since.
+  unproven: bool,
+}
+
+impl WatchdogState {
+  /// Count one response (a success, or a {"type":"error"} one); true if it should trigger an auto-restart.
+  /// A success resets everything.
+  fn observe(&mut self, ok: bool, threshold: u32, now: std::time::Instant) -> bool {
+    if ok {
+      *self = WatchdogState {
+        restarting: self.restarting,
+        ..WatchdogState::default()
+      };
+      return false;
+    }
+    self.consecutive_errors += 1;
+    if threshold == 0 || self.consecutive_errors < threshold || self.restarting {
+      return false;
+    }
+    if self.unproven {
+      // The last restart did not help; count it as failed instead of restarting straight away again.
+      self.unproven = false;
+      self.restart_failed(now);
+    }
+    self.backoff_until.map_or(true, |until| now >= until)
+  }
+
+  fn restart_finished(&mut self, ok: bool, now: std::time::Instant) {
+    self.restarting = false;
+    self.consecutive_errors = 0;
+    if ok {
+      self.unproven = true;
+    } else {
+      self.restart_failed(now);
+    }
+  }
+
+  fn restart_failed(&mut self, now: std::time::Instant) {
+    self.failed_restarts += 1;
+    let backoff = WATCHDOG_BASE_BACKOFF
+      .saturating_mul(1 << (self.failed_restarts - 1).min(16))
+      .min(WATCHDOG_MAX_BACKOFF);
+    self.backoff_until = Some(now + backoff);
+  }
+}
+
+impl Watchdog {
+  fn new(app: &tauri::AppHandle) -> Self {
+    let threshold = std::env::var(WATCHDOG_THRESHOLD_ENV)
+      .ok()
+      .and_then(|v| v.trim().parse().ok())
+      .unwrap_or(WATCHDOG_THRESHOLD);
+    Watchdog {
+      app: app.clone(),
+      threshold,
+      state: Arc::default(),
+    }
+  }
+
+  /// Record the outcome of one response from `process`; timeouts and crashes are not counted (they
+  /// already poison the process). Restarts it in the background once the threshold is reached.
+  fn observe(&self, process: &Arc<Mutex<BackendProcess>>, ok: bool) {
+    let errors = {
+      let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+      if !state.observe(ok, self.threshold, std::time::Instant::now()) {
+        return;
+      }
+      state.restarting = true;
+      state.consecutive_errors
+    };
+    let (app, state, process) = (self.app.clone(), self.state.clone(), process.clone());
+    tauri::async_runtime::spawn_blocking(move || {
+      let mut guard = lock_process(&process);
+      let reason = format!("{} consecutive errors", errors);
+      let result = guard.restart_backend_inner(&app, reason, true);
+      let (instance_id, pid) = (guard.spawn.instance_id.clone(), guard.child.id());
+      drop(guard);
+      state
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .restart_finished(result.is_ok(), std::time::Instant::now());
+      match result {
+        Ok(()) => emit_recorded(
+          &app,
+          "backend://auto_recovered",
+          serde_json::json!({ "instance_id": instance_id, "errors": errors, "pid": pid }),
+        ),
+        Err(e) => log::error!("Backend {} auto-restart failed: {}", instance_id, e),
+      }
+    });
+  }
+}
+
+/// Bounds the operations (requests, streaming queries) in flight on one backend; the rest wait in FIFO
+/// order on the semaphore instead of piling up behind the process mutex.
+#[derive(Clone)]
+struct RequestLimiter {
+  permits: Arc<Semaphore>,
+  limit: Arc<AtomicUsize>,
+  waiting: Arc<AtomicUsize>,
+  /// When an operation last started or finished; the idle monitor measures from here.
+  last_activity: Arc<Mutex<std::time::Instant>>,
+}
+
+/// A slot from RequestLimiter::acquire; dropping it frees the slot and counts as activity.
+struct LimiterPermit {
+  _permit: OwnedSemaphorePermit,
+  last_activity: Arc<Mutex<std::time::Instant>>,
+}
+
+impl Drop for LimiterPermit {
+  fn drop(&mut self) {
+    touch(&self.last_activity);
+  }
+}
+
+fn touch(last_activity: &Mutex<std::time::Instant>) {
+  *last_activity.lock().unwrap_or_else(|e| e.into_inner()) = std::time::Instant::now();
+}
+
+impl RequestLimiter {
+  fn new(limit: usize) -> Self {
+    RequestLimiter {
+      permits: Arc::new(Semaphore::new(limit)),
+      limit: Arc::new(AtomicUsize::new(limit)),
+      waiting: Arc::new(AtomicUsize::new(0)),
+      last_activity: Arc::new(Mutex::new(std::time::Instant::now())),
+    }
+  }
+
+  /// Wait for a free slot; the operation holds it until the permit is dropped.
+  async fn acquire(&self) -> Result<LimiterPermit, String> {
+    /// Counts this caller as queued until it gets a permit or its future is dropped.
+    struct Queued<'a>(&'a AtomicUsize);
+    impl Drop for Queued<'_> {
+      fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+      }
+    }
+    self.waiting.fetch_add(1, Ordering::SeqCst);
+    let _queued = Queued(&self.waiting);
+    let permit = self
+      .permits
+      .clone()
+      .acquire_owned()
+      .await
+      .map_err(|_| "backend request limiter closed".to_string())?;
+    touch(&self.last_activity);
+    Ok(LimiterPermit {
+      _permit: permit,
+      last_activity: self.last_activity.clone(),
     })
   }
 
-  #[cfg(not(debug_assertions))]
-  {
-    let app = app.ok_or("AppHandle required for sidecar")?;
-    let resource_dir = app
-      .path()
-      .resource_dir()
-      .map_err(|e| format!("resource_dir: {}", e))?;
-    let target = env!("APP_TARGET");
-    let sidecar_name = format!(
-      "backend-{}{}",
-      target,
-      if cfg!(windows) { ".exe" } else { "" }
-    );
-    let sidecar_path = resource_dir
-      .join("bin")
-      .join("backend")
-      .join(&sidecar_name);
-    if !sidecar_path.exists() {
-      return Err(format!(
-        "Sidecar not found: {}",
-        sidecar_path.display()
-      ));
+  /// Raise the limit to `limit`, e.g. once a backend that started in the background has advertised it;
+  /// never lowers it.
+  fn raise_to(&self, limit: usize) {
+    let prev = self.limit.fetch_max(limit, Ordering::SeqCst);
+    if limit > prev {
+      self.permits.add_permits(limit - prev);
     }
-    let mut child = Command::new(&sidecar_path)
-      .args(["--db", &db_arg, "stdio"])
-      .current_dir(&cwd)
-      .stdin(Stdio::piped())
-      .stdout(Stdio::piped())
-      .stderr(Stdio::inherit())
-      .spawn()
-      .map_err(|e| format!("Failed to spawn sidecar: {}", e))?;
-    let stdin = child.stdin.take();
-    let stdout = child.stdout.take().map(BufReader::new);
-    Ok(BackendProcess {
-      child,
-      stdin,
-      stdout,
+  }
+
+  /// How long nothing has been in flight or queued, or None while something is.
+  fn idle_for(&self) -> Option<Duration> {
+    let limit = self.limit.load(Ordering::SeqCst);
+    let busy = self.permits.available_permits() < limit || self.waiting.load(Ordering::SeqCst) > 0;
+    if busy {
+      return None;
+    }
+    Some(self.last_activity.lock().unwrap_or_else(|e| e.into_inner()).elapsed())
+  }
+
+  /// {limit, in_flight, queued} for backend_queue_depth.
+  fn depth(&self) -> serde_json::Value {
+    let limit = self.limit.load(Ordering::SeqCst);
+    serde_json::json!({
+      "limit": limit,
+      "in_flight": limit - self.permits.available_permits(),
+      "queued": self.waiting.load(Ordering::SeqCst),
+    })
+  }
+}
+
+/// STDOUT_BUFFER_ENV if set to a positive number, else DEFAULT_STDOUT_BUFFER_BYTES. Read by from_child, which
+/// both the dev and the release spawn go through.
+fn stdout_buffer_bytes() -> usize {
+  std::env::var(STDOUT_BUFFER_ENV)
+    .ok()
+    .and_then(|v| v.trim().parse().ok())
+    .filter(|&n| n > 0)
+    .unwrap_or(DEFAULT_STDOUT_BUFFER_BYTES)
+}
+
+/// MAX_IN_FLIGHT_ENV if set to a positive number, else what the backend advertised at the handshake.
+fn max_in_flight(advertised: Option<usize>) -> usize {
+  in_flight_limit(std::env::var(MAX_IN_FLIGHT_ENV).ok().as_deref(), advertised)
+}
+
+/// Whether a READ_ONLY_ENV value turns read-only mode on.
+fn read_only_flag(env: Option<&str>) -> bool {
+  env.is_some_and(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+}
+
+/// Err(READ_ONLY_ERROR) while read-only mode is on, for commands that write.
+fn ensure_writable(app: &tauri::AppHandle) -> Result<(), String> {
+  match app.try_state::<ReadOnly>() {
+    Some(read_only) if read_only.enabled.load(Ordering::SeqCst) => Err(READ_ONLY_ERROR.to_string()),
+    _ => Ok(()),
+  }
+}
+
+/// ensure_writable for a raw request `payload`: only if its cmd (or that of any batch item) is in
+/// DB_WRITE_COMMANDS.
+fn ensure_payload_writable(app: &tauri::AppHandle, payload: &serde_json::Value) -> Result<(), String> {
+  if writes_database(payload) {
+    ensure_writable(app)?;
+  }
+  Ok(())
+}
+
+fn writes_database(payload: &serde_json::Value) -> bool {
+  match payload.get("cmd").and_then(|c| c.as_str()) {
+    Some("batch") => payload
+      .get("items")
+      .and_then(|items| items.as_array())
+      .is_some_and(|items| items.iter().any(writes_database)),
+    Some(cmd) => DB_WRITE_COMMANDS.contains(&cmd),
+    None => false,
+  }
+}
+
+fn in_flight_limit(env: Option<&str>, advertised: Option<usize>) -> usize {
+  env
+    .and_then(|v| v.trim().parse().ok())
+    .filter(|&n| n > 0)
+    .or(advertised.filter(|&n| n > 0))
+    .unwrap_or(MAX_IN_FLIGHT_REQUESTS)
+}
+
+impl Backends {
+  /// The instance named `instance_id`, or DEFAULT_INSTANCE when None.
+  fn get(&self, instance_id: Option<&str>) -> Result<BackendInstance, String> {
+    let id = instance_id.unwrap_or(DEFAULT_INSTANCE);
+    self
+      .0
+      .lock()
+      .map_err(|e| e.to_string())?
+      .get(id)
+      .cloned()
+      .ok_or_else(|| format!("No backend instance named {}", id))
+  }
+}
+
+/// A talker as returned by list_talkers; the stable shape the talker picker relies on.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct Talker {
+  id: String,
+  name: String,
+  #[serde(default)]
+  message_count: u64,
+  /// Time of the newest message, ms since the epoch.
+  #[serde(default)]
+  last_timestamp: i64,
+}
+
+/// A question answered in this backend session, as returned by get_query_history.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct HistoryItem {
+  id: u64,
+  #[serde(default)]
+  talker: Option<String>,
+  #[serde(default)]
+  question: String,
+  /// When the answer was produced, ms since the epoch.
+  #[serde(default)]
+  timestamp: i64,
+}
+
+/// Last list_talkers result per backend instance, served when the caller asks for a cached answer.
+#[derive(Default)]
+struct TalkerCache(Mutex<HashMap<String, Vec<Talker>>>);
+
+/// Talker chosen with set_active_talker per backend instance: the current conversation, used by
+/// backend_query_stream calls that don't name one.
+#[derive(Default)]
+struct ActiveTalkers(Mutex<HashMap<String, String>>);
+
+/// Ring buffer of recent backend stderr lines, kept across respawns for "copy diagnostics".
+#[derive(Default)]
+struct BackendStderr(Mutex<VecDeque<String>>);
+
+/// Ring buffer of recently emitted backend events, each tagged with a sequence number, so a window that
+/// subscribes late can catch up via get_recent_events.
+#[derive(Default)]
+struct RecentEvents(Mutex<EventLog>);
+
+#[derive(Default)]
+struct EventLog {
+  last_seq: u64,
+  /// {"seq": ..., "event": ..., "payload": ...}, oldest first.
+  events: VecDeque<serde_json::Value>,
+}
+
+/// Minutes without requests after which a backend is shut down to free memory (0 = never); set with
+/// set_idle_timeout and kept in the `idle_timeout_minutes` setting.
+struct IdleTimeout(AtomicU64);
+
+/// Whether builds, config edits and database switches are refused (queries still work); set with
+/// set_read_only or READ_ONLY_ENV, not persisted.
+struct ReadOnly {
+  enabled: AtomicBool,
+  /// Set when READ_ONLY_ENV turned it on; set_read_only can't turn it off then.
+  locked: bool,
+}
+
+/// Token and cost totals of the queries run since the app started, for the running cost meter.
+#[derive(Default)]
+struct SessionUsage(Mutex<SessionStats>);
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize)]
+struct SessionStats {
+  total_tokens: u64,
+  total_cost: f64,
+  /// Finished queries, including those whose result reported no usage.
+  query_count: u64,
+}
+
+/// Optional settings of backend_query_stream, sent as one `options` object with camelCase keys.
+#[derive(Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct QueryOptions {
+  /// The talker to ask about; without it the instance's active talker (set_active_talker).
+  talker: Option<String>,
+  /// Makes the query cancellable (cancel_query) and gives it its own event channels (start_query).
+  query_id: Option<String>,
+  instance_id: Option<String>,
+  config_overrides: Option<serde_json::Value>,
+  config_path: Option<String>,
+  /// Emit `backend://stalled` after this long without a line.
+  stall_ms: Option<u64>,
+  /// Abort and poison the backend after this long without a line.
+  silence_timeout_ms: Option<u64>,
+  /// Coalesce progress arriving within this window into one event (progress::coalesce).
+  progress_window_ms: Option<u64>,
+  /// Passed to the backend to pin randomness for golden-output comparisons.
+  seed: Option<u64>,
+  /// "block" (default) or "latest"; see ProgressPolicy.
+  progress_policy: Option<String>,
+  /// Local files under the home or app data dir given as extra context; see read_attachments.
+  attachments: Option<Vec<String>>,
+  /// Pass binary attachments by path instead of refusing them.
+  allow_binary_attachments: Option<bool>,
+  /// Ask the backend to add its workflow state to each progress line.
+  verbose: Option<bool>,
+}
+
+/// The result of a streaming query, exactly as the backend's _build_query_response builds it: a backend that
+/// stops sending a field, or sends one this app doesn't know, fails here, naming it, instead of somewhere in
+/// the UI.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QueryResult {
+  /// The talker the question was asked about.
+  conversation_id: String,
+  question: String,
+  /// "full_narrative", or "factual_rag" for a short answer in `factual_answer`.
+  answer_mode: String,
+  /// {answer, evidence} in factual_rag mode, else null.
+  #[serde(default)]
+  factual_answer: Option<serde_json::Value>,
+  /// The answer: narrative phases, each citing its evidence messages.
+  phases: Vec<serde_json::Value>,
+  agent_trace: QueryTrace,
+  all_messages: Vec<serde_json::Value>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  usage: Option<QueryUsage>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QueryTrace {
+  steps: Vec<serde_json::Value>,
+  total_llm_calls: u64,
+  total_duration_ms: u64,
+}
+
+/// Token and cost accounting of one query, when the backend reports it (see SessionStats::record).
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+struct QueryUsage {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  prompt_tokens: Option<u64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  completion_tokens: Option<u64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  total_tokens: Option<u64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  cost: Option<f64>,
+}
+
+impl QueryResult {
+  /// Type a result line; the `type` tag is framing and dropped.
+  fn from_line(mut line: serde_json::Value) -> Result<Self, BackendError> {
+    if let Some(map) = line.as_object_mut() {
+      map.remove("type");
+    }
+    serde_json::from_value(line).map_err(|e| {
+      BackendError::with_code("bad_result", format!("backend sent a malformed query result: {}", e))
     })
   }
 }
 
-#[tauri::command]
-fn spawn_backend_build(
-  app: tauri::AppHandle,
-  talker_id: String,
-  config_overrides: Option<String>,
-) -> Result<(), String> {
-  use std::process::{Command, Stdio};
-  let (cwd, _) = get_backend_cwd_and_db(Some(&app));
-  #[cfg(debug_assertions)]
-  {
-    let mut args = vec![
-      "run".to_string(),
-      "python".to_string(),
-      "-m".to_string(),
-      "narrative_mirror.cli_json".to_string(),
-      "--db".to_string(),
-      "data/mirror.db".to_string(),
-      "build".to_string(),
-      "--talker".to_string(),
-      talker_id.clone(),
-      "--config".to_string(),
-      "config.yml".to_string(),
-    ];
-    if let Some(ref overrides) = config_overrides {
-      if !overrides.is_empty() {
-        args.push("--config-overrides".to_string());
-        args.push(overrides.clone());
-      }
-    }
-    args.push("--debug".to_string());
-    let _child = Command::new("uv")
-      .args(&args)
-      .env("PYTHONUNBUFFERED", "1")
-      .env("PYTHONIOENCODING", "utf-8")
-      .current_dir(&cwd)
-      .stdin(Stdio::null())
-      .stdout(Stdio::inherit())
-      .stderr(Stdio::inherit())
-      .spawn()
-      .map_err(|e| format!("Failed to spawn backend build: {}", e))?;
+impl SessionStats {
+  /// Count one finished query and add its `usage`, if any: `total_tokens` (or `prompt_tokens` +
+  /// `completion_tokens`) and `cost`. Missing fields and negative or non-finite costs add nothing. Only called
+  /// with results QueryResult::from_line accepted.
+  fn record(&mut self, usage: Option<&QueryUsage>) {
+    self.query_count += 1;
+    let Some(usage) = usage else {
+      return;
+    };
+    let tokens = usage.total_tokens.or_else(|| {
+      let (prompt, completion) = (usage.prompt_tokens, usage.completion_tokens);
+      (prompt.is_some() || completion.is_some())
+        .then(|| prompt.unwrap_or(0) + completion.unwrap_or(0))
+    });
+    self.total_tokens += tokens.unwrap_or(0);
+    self.total_cost += usage
+      .cost
+      .filter(|c| c.is_finite() && *c >= 0.0)
+      .unwrap_or(0.0);
+  }
+}
+
+/// The most recently started `build` child; kept so it can be cancelled (or killed on exit) and so two
+/// builds never run against the same db at once.
+#[derive(Default)]
+struct BuildProcess(Mutex<Option<RunningBuild>>);
+
+/// The most recently started `reindex` child, tracked like BuildProcess: one at a time, and never for a
+/// talker that is being built.
+#[derive(Default)]
+struct ReindexProcess(Mutex<Option<RunningBuild>>);
+
+/// A detached backend CLI job; selects its tracked child and the prefix of the events it emits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum JobKind {
+  Build,
+  Reindex,
+}
+
+impl JobKind {
+  fn name(self) -> &'static str {
+    match self {
+      JobKind::Build => "build",
+      JobKind::Reindex => "reindex",
+    }
+  }
+
+  /// `<name>://<event>`, e.g. `reindex://progress`.
+  fn event(self, event: &str) -> String {
+    format!("{}://{}", self.name(), event)
+  }
+
+  /// The slot its running child is tracked in.
+  fn slot(self, app: &tauri::AppHandle) -> Option<&Mutex<Option<RunningBuild>>> {
+    match self {
+      JobKind::Build => app.try_state::<BuildProcess>().map(|s| &s.inner().0),
+      JobKind::Reindex => app.try_state::<ReindexProcess>().map(|s| &s.inner().0),
+    }
+  }
+}
+
+/// Stdout lines of the current or last build, so a build panel opened mid-build can show what it missed
+/// before following `build://progress`.
+#[derive(Default)]
+struct BuildLog(Mutex<BuildLogLines>);
+
+#[derive(Default)]
+struct BuildLogLines {
+  lines: VecDeque<String>,
+  /// Lines dropped from the front once BUILD_LOG_LINES were kept.
+  dropped: usize,
+}
+
+impl BuildLogLines {
+  fn push(&mut self, line: String) {
+    if self.lines.len() == BUILD_LOG_LINES {
+      self.lines.pop_front();
+      self.dropped += 1;
+    }
+    self.lines.push_back(line);
+  }
+
+  /// The kept lines, oldest first, led by a marker line if earlier ones were dropped.
+  fn snapshot(&self) -> Vec<String> {
+    let marker = (self.dropped > 0).then(|| format!("[{} earlier lines truncated]", self.dropped));
+    marker.into_iter().chain(self.lines.iter().cloned()).collect()
+  }
+}
+
+struct RunningBuild {
+  child: Child,
+  talker_id: String,
+  /// Wall-clock start, reported by build_status.
+  started_at: std::time::SystemTime,
+}
+
+/// FIFO of requests waiting to be written to the backend. A single worker thread drains it, so callers
+/// never contend on the BackendProcess lock and requests reach stdin in the order they were issued.
+#[derive(Clone)]
+struct RequestQueue(std::sync::mpsc::Sender<QueuedRequest>);
+
+struct QueuedRequest {
+  payload: serde_json::Value,
+  /// Receives the registered request (or the spawn/write error) once the worker has written it.
+  reply: tokio::sync::oneshot::Sender<Result<PendingRequest, String>>,
+}
+
+/// Cancellation handles for streaming queries started with a query_id; cancel_query notifies them. On app
+/// exit every stream is cancelled through `shutdown`, so none keeps the exit waiting on the backend.
+#[derive(Default)]
+struct QueryCancels {
+  by_id: Mutex<HashMap<String, Arc<Notify>>>,
+  /// Set by shutdown_all_backends; streams started afterwards are refused.
+  shutting_down: AtomicBool,
+  /// Woken (notify_waiters) when shutting_down is set.
+  shutdown: Notify,
+}
+
+impl QueryCancels {
+  /// Cancel every running stream and refuse new ones.
+  fn shut_down(&self) {
+    self.shutting_down.store(true, Ordering::SeqCst);
+    self.shutdown.notify_waiters();
+  }
+}
+
+impl BackendProcess {
+  /// Take over a freshly spawned child: keep stdin, hand stdout to the dispatcher thread
+  /// and stderr to the forwarder thread. With `spawn.http`, requests are written to the backend's HTTP
+  /// server instead, once stdout has announced it; stdin is only held open.
+  fn from_child(
+    mut child: Child,
+    spawn: BackendSpawnConfig,
+    app: Option<&tauri::AppHandle>,
+  ) -> Self {
+    let pending = PendingMap::default();
+    let poisoned = Arc::new(AtomicBool::new(false));
+    let stdout = child.stdout.take().map(|stdout| {
+      Box::new(PipeReader::new(stdout, spawn.max_line_bytes, stdout_buffer_bytes())) as Box<dyn BackendReader>
+    });
+    let stdin = match stdout {
+      Some(stdout) if spawn.http => {
+        let endpoint = http_transport::spawn_endpoint_reader(stdout, pending.clone(), poisoned.clone());
+        Some(Box::new(HttpWriter::new(
+          endpoint,
+          pending.clone(),
+          spawn.max_line_bytes,
+          child.stdin.take(),
+        )) as Box<dyn BackendWriter>)
+      }
+      stdout => {
+        if let Some(stdout) = stdout {
+          spawn_stdout_dispatcher(stdout, pending.clone(), poisoned.clone());
+        }
+        child
+          .stdin
+          .take()
+          .map(|stdin| Box::new(FrameWriter::new(stdin)) as Box<dyn BackendWriter>)
+      }
+    };
+    if let Some(stderr) = child.stderr.take() {
+      spawn_stderr_forwarder(stderr, app.cloned());
+    }
+    BackendProcess {
+      child,
+      stdin,
+      pending,
+      spawn,
+      poisoned,
+      ready: false,
+      shutting_down: false,
+      killed: false,
+      capabilities: None,
+      max_concurrent: None,
+      state: BackendState::Running,
+    }
+  }
+
+  /// Register a fresh req_id with the dispatcher, then write `payload` tagged with it to stdin.
+  fn write_request(&mut self, mut payload: serde_json::Value) -> Result<PendingRequest, String> {
+    let req_id = NEXT_REQ_ID.fetch_add(1, Ordering::Relaxed);
+    payload
+      .as_object_mut()
+      .ok_or("backend payload must be a JSON object")?
+      .insert("req_id".to_string(), serde_json::json!(req_id));
+    let pending = PendingRequest::register(&self.pending, req_id, self.child.id())?;
+    self.write_line(&payload)?;
+    Ok(pending)
+  }
+
+  /// Write one JSON line to backend stdin as-is (no req_id is assigned).
+  fn write_line(&mut self, payload: &serde_json::Value) -> Result<(), String> {
+    let request = serde_json::to_string(payload).map_err(|e| e.to_string())?;
+    if request.len() > LARGE_REQUEST_BYTES {
+      log::warn!(
+        "Writing a {} KiB {} request to the backend",
+        request.len() / 1024,
+        payload.get("cmd").and_then(|c| c.as_str()).unwrap_or("?")
+      );
+    }
+    ipc_log::record(ipc_log::Direction::Sent, &request);
+    let stdin = self.stdin.as_mut().ok_or("backend process stdin gone")?;
+    stdin.send_line(&request).map_err(|e| {
+      // The pipe is broken or holds half a message; either way this process can't be used again.
+      self.poisoned.store(true, Ordering::SeqCst);
+      e.to_string()
+    })
+  }
+
+  /// Readiness handshake: send {"cmd":"ping"} and wait for {"type":"pong"} so the first real request
+  /// doesn't race interpreter startup. If the pong advertises length-prefixed framing, switch to it.
+  /// No-op once ready.
+  ///
+  /// With an app handle the wait is observable for a splash screen: `backend://starting` {instance_id, pid,
+  /// timeout_ms}, then `backend://startup_progress` {instance_id, pid, elapsed_ms} every
+  /// STARTUP_PROGRESS_INTERVAL, then `backend://ready` or `backend://startup_timeout` (with `error`).
+  /// It also gives up as soon as the app starts quitting (QueryCancels::shut_down), killing the half-started
+  /// child so it is not left behind.
+  fn wait_ready(&mut self, app: Option<&tauri::AppHandle>, timeout: Duration) -> Result<(), String> {
+    if self.ready {
+      return Ok(());
+    }
+    let started = std::time::Instant::now();
+    let instance_id = self.spawn.instance_id.clone();
+    let pid = self.child.id();
+    if let Some(app) = app {
+      emit_recorded(
+        app,
+        "backend://starting",
+        serde_json::json!({ "instance_id": instance_id, "pid": pid, "timeout_ms": timeout.as_millis() as u64 }),
+      );
+    }
+    let cancels = app.and_then(|app| app.try_state::<QueryCancels>());
+    let quitting = || cancels.as_ref().is_some_and(|c| c.shutting_down.load(Ordering::SeqCst));
+    let mut last_progress = started;
+    let mut progress = || {
+      if quitting() {
+        return Err("backend startup cancelled: the app is shutting down".to_string());
+      }
+      let Some(app) = app else { return Ok(()) };
+      if last_progress.elapsed() >= STARTUP_PROGRESS_INTERVAL {
+        last_progress = std::time::Instant::now();
+        let _ = app.emit(
+          "backend://startup_progress",
+          serde_json::json!({
+            "instance_id": instance_id,
+            "pid": pid,
+            "elapsed_ms": started.elapsed().as_millis() as u64,
+          }),
+        );
+      }
+      Ok(())
+    };
+    let result = self.handshake(started + timeout, timeout, &mut progress);
+    if result.is_err() && quitting() {
+      self.shutting_down = true;
+      self.killed = true;
+      let status = kill_child_tree(&mut self.child);
+      log::info!(
+        "App quit while backend (pid {}) was starting; killed it: {}",
+        pid,
+        describe_exit(status.as_ref())
+      );
+      return result;
+    }
+    if let Some(app) = app {
+      if result.is_ok() {
+        self.report_db_status(app, timeout, &mut progress);
+      }
+      let elapsed_ms = started.elapsed().as_millis() as u64;
+      match &result {
+        Ok(()) => emit_recorded(
+          app,
+          "backend://ready",
+          serde_json::json!({ "instance_id": instance_id, "pid": pid, "elapsed_ms": elapsed_ms }),
+        ),
+        Err(e) => emit_recorded(
+          app,
+          "backend://startup_timeout",
+          serde_json::json!({ "instance_id": instance_id, "pid": pid, "elapsed_ms": elapsed_ms, "error": e }),
+        ),
+      }
+    }
+    result
+  }
+
+  /// Ask the backend for {"cmd":"db_status"}, which creates the database if it is missing and migrates an old
+  /// schema, and emit its answer as `backend://db_initialized` {instance_id, path, status ("created",
+  /// "migrated", "current" or "newer"), created, migrated, schema_version, previous_version, talker_count,
+  /// empty} before `backend://ready`, so the UI can tell a first launch from a returning user. Best-effort: a
+  /// backend without the command or a failed check only logs.
+  fn report_db_status(
+    &mut self,
+    app: &tauri::AppHandle,
+    timeout: Duration,
+    progress: &mut dyn FnMut() -> Result<(), String>,
+  ) {
+    let deadline = std::time::Instant::now() + timeout;
+    let status = self
+      .write_request(serde_json::json!({ "cmd": "db_status" }))
+      .and_then(|pending| handshake_reply(pending, deadline, timeout, progress));
+    match status {
+      Ok(mut status) if status.get("type").and_then(|t| t.as_str()) == Some("db_status") => {
+        match status.get("status").and_then(|s| s.as_str()) {
+          Some("newer") => log::warn!(
+            "Database {} is from a newer version of the app; it was not migrated",
+            self.spawn.db_arg.display()
+          ),
+          Some(kind @ ("created" | "migrated")) => {
+            log::info!("Database {} {}", self.spawn.db_arg.display(), kind)
+          }
+          _ => {}
+        }
+        if let Some(obj) = status.as_object_mut() {
+          obj.remove("type");
+          obj.insert("instance_id".to_string(), serde_json::json!(self.spawn.instance_id));
+        }
+        emit_recorded(app, "backend://db_initialized", status);
+      }
+      Ok(other) => log::debug!("Backend did not report its database status: {}", other),
+      Err(e) => log::warn!("Could not check the backend database: {}", e),
+    }
+  }
+
+  /// The readiness ping and framing negotiation; `progress` is called while waiting for each reply, and an
+  /// error from it aborts the wait.
+  fn handshake(
+    &mut self,
+    deadline: std::time::Instant,
+    timeout: Duration,
+    progress: &mut dyn FnMut() -> Result<(), String>,
+  ) -> Result<(), String> {
+    let pending = self.write_request(serde_json::json!({
+      "cmd": "ping",
+      "accept_encoding": [encoding::GZIP],
+    }))?;
+    let pong = handshake_reply(pending, deadline, timeout, progress).map_err(|e| match self.child.try_wait() {
+      Ok(Some(status)) => format!("backend exited during startup ({}): {}", status, e),
+      _ => e,
+    })?;
+    if pong.get("type").and_then(|t| t.as_str()) != Some("pong") {
+      return Err(format!("unexpected backend handshake response: {}", pong));
+    }
+    self.max_concurrent = pong
+      .get("max_concurrent")
+      .and_then(|n| n.as_u64())
+      .map(|n| n as usize);
+    match pong.get("protocol_version").and_then(|v| v.as_u64()) {
+      Some(version) => check_protocol_version(version)?,
+      None => log::warn!("Backend did not report a protocol version; assuming it is compatible"),
+    }
+    if let Some(encoding) = pong.get("encoding").and_then(|e| e.as_str()) {
+      log::debug!("Backend sends large responses as {}", encoding);
+    }
+    let supports_frames = pong
+      .get("framing")
+      .and_then(|f| f.as_array())
+      .is_some_and(|modes| modes.iter().any(|m| m == Framing::LENGTH_PREFIXED));
+    if supports_frames {
+      self.negotiate_framing(deadline, timeout, progress)?;
+    }
+    self.ready = true;
+    Ok(())
+  }
+
+  /// Ask the backend to switch to length-prefixed frames. It acknowledges in line mode, and the dispatcher
+  /// switches its reader on that acknowledgement; stdin switches here. A refusal keeps line mode.
+  fn negotiate_framing(
+    &mut self,
+    deadline: std::time::Instant,
+    timeout: Duration,
+    progress: &mut dyn FnMut() -> Result<(), String>,
+  ) -> Result<(), String> {
+    let pending = self.write_request(serde_json::json!({
+      "cmd": "set_framing",
+      "mode": Framing::LENGTH_PREFIXED,
+    }))?;
+    let ack = handshake_reply(pending, deadline, timeout, progress)?;
+    if ack.get("type").and_then(|t| t.as_str()) != Some("framing") {
+      log::warn!("Backend refused length-prefixed framing, staying on lines: {}", ack);
+      return Ok(());
+    }
+    let stdin = self.stdin.as_mut().ok_or("backend process stdin gone")?;
+    stdin
+      .set_framing(Framing::LengthPrefixed)
+      .map_err(|e| e.to_string())?;
+    log::debug!("Backend switched to length-prefixed framing");
+    Ok(())
+  }
+
+  /// Respawn the backend in place if the child has exited (crash, OOM kill, etc.) or was poisoned.
+  /// Emits the restart events (see restart_backend_inner) so the frontend can warn the user.
+  fn ensure_alive(&mut self, app: &tauri::AppHandle) -> Result<(), String> {
+    if self.state == BackendState::Stopped {
+      return Err(BACKEND_STOPPED.to_string());
+    }
+    if self.state == BackendState::Suspended {
+      self.restart_backend_inner(app, "resumed after idle".to_string(), false)?;
+      emit_recorded(
+        app,
+        "backend://resumed",
+        serde_json::json!({ "instance_id": self.spawn.instance_id, "pid": self.child.id() }),
+      );
+      return Ok(());
+    }
+    let status = if self.poisoned.load(Ordering::SeqCst) {
+      self.killed = true;
+      let exit = kill_child_tree(&mut self.child);
+      format!(
+        "backend was poisoned (request timed out, stdin write failed, stdout closed or oversized output); killed: {}",
+        describe_exit(exit.as_ref())
+      )
+    } else {
+      match self.child.try_wait() {
+        Ok(None) => return Ok(()),
+        Ok(Some(status)) => status.to_string(),
+        Err(e) => e.to_string(),
+      }
+    };
+    log::warn!("Backend exited ({}), respawning", status);
+    self.restart_backend_inner(app, status, false)
+  }
+
+  /// Replace this process with a fresh one from the same spawn config; every restart (crash, request,
+  /// config reload, database switch) goes through here. Emits `backend://restarting` {instance_id, reason,
+  /// pid} before anything happens, then `backend://restarted` {instance_id, reason, pid} with the new pid
+  /// or `backend://restart_failed` {instance_id, reason, error}. With `graceful`, the old process is shut
+  /// down first (see shutdown_backend); otherwise it is assumed gone. Requests still waiting on the old
+  /// process see their channel close once its stdout hits EOF.
+  fn restart_backend_inner(
+    &mut self,
+    app: &tauri::AppHandle,
+    reason: String,
+    graceful: bool,
+  ) -> Result<(), String> {
+    let instance_id = self.spawn.instance_id.clone();
+    emit_recorded(
+      app,
+      "backend://restarting",
+      serde_json::json!({
+        "instance_id": instance_id,
+        "reason": reason,
+        "pid": self.child.id(),
+      }),
+    );
+    if graceful {
+      shutdown_backend(self, shutdown_grace(app, ShutdownReason::Restart));
+    }
+    match spawn_backend_process_in(Some(app), self.spawn.clone()) {
+      Ok(process) => {
+        *self = process;
+        emit_recorded(
+          app,
+          "backend://restarted",
+          serde_json::json!({
+            "instance_id": instance_id,
+            "reason": reason,
+            "pid": self.child.id(),
+          }),
+        );
+        Ok(())
+      }
+      Err(e) => {
+        let error = e.to_string();
+        self.state = BackendState::Failed(error.clone());
+        emit_recorded(
+          app,
+          "backend://restart_failed",
+          serde_json::json!({
+            "instance_id": instance_id,
+            "reason": reason,
+            "error": error,
+          }),
+        );
+        Err(error)
+      }
+    }
+  }
+}
+
+/// Spawn backend: dev uses uv run python, release uses bundled sidecar via std::process::Command.
+/// `db_path` overrides the database for this instance; otherwise the one saved by set_database is used,
+/// falling back to the default.
+fn spawn_backend_process(
+  app: Option<&tauri::AppHandle>,
+  instance_id: &str,
+  db_path: Option<PathBuf>,
+) -> Result<BackendProcess, SpawnError> {
+  let (cwd, default_db) = get_backend_cwd_and_db(app).map_err(SpawnError::BackendNotFound)?;
+  let db_arg = db_path.unwrap_or_else(|| active_db_path(app, default_db));
+  let spawn = BackendSpawnConfig {
+    instance_id: instance_id.to_string(),
+    cwd,
+    db_arg,
+    config_arg: DEFAULT_CONFIG_PATH.to_string(),
+    env: HashMap::new(),
+    max_line_bytes: DEFAULT_MAX_LINE_BYTES,
+    log_level: app.map_or_else(|| DEFAULT_LOG_LEVEL.to_string(), log_level),
+    http: app.is_some_and(|app| backend_transport(app) == "http"),
+    source: app.map(backend_source).unwrap_or_default(),
+  };
+  spawn_backend_process_in(app, spawn)
+}
+
+/// Spawn backend from an explicit spawn config (used for the initial spawn and for respawns), retrying with
+/// exponential backoff up to SPAWN_ATTEMPTS times. The outcome is recorded in LastSpawnError for get_spawn_error.
+/// Refused without retrying while another running app instance holds the database lock (db_lock::acquire);
+/// that emits `backend://db_locked` {instance_id, db_path, pid}.
+fn spawn_backend_process_in(
+  app: Option<&tauri::AppHandle>,
+  spawn: BackendSpawnConfig,
+) -> Result<BackendProcess, SpawnError> {
+  let mut delay = SPAWN_RETRY_BASE_DELAY;
+  let mut attempt = 1;
+  let locked = db_lock::acquire(&spawn.db_arg).err();
+  let result = loop {
+    if let Some(pid) = locked {
+      log::error!("Database {} is locked by app pid {}", spawn.db_arg.display(), pid);
+      if let Some(app) = app {
+        emit_recorded(
+          app,
+          "backend://db_locked",
+          serde_json::json!({
+            "instance_id": spawn.instance_id,
+            "db_path": spawn.db_arg.to_string_lossy(),
+            "pid": pid,
+          }),
+        );
+      }
+      break Err(SpawnError::DbLocked {
+        db_path: spawn.db_arg.clone(),
+        pid,
+      });
+    }
+    match launch_backend(app, spawn.clone()) {
+      Ok(process) => break Ok(process),
+      // Crashing on start (bad config, missing module) happens again on every attempt.
+      Err(e @ SpawnError::ExitedImmediately { .. }) if attempt == 1 => break Err(e),
+      Err(e) if attempt < SPAWN_ATTEMPTS => {
+        log::warn!(
+          "Backend spawn attempt {}/{} failed: {}; retrying in {:?}",
+          attempt,
+          SPAWN_ATTEMPTS,
+          e,
+          delay
+        );
+        std::thread::sleep(delay);
+        delay *= SPAWN_BACKOFF_FACTOR;
+        attempt += 1;
+      }
+      Err(e) => {
+        log::error!("Backend spawn attempt {}/{} failed: {}", attempt, SPAWN_ATTEMPTS, e);
+        break Err(SpawnError::GaveUp {
+          attempts: attempt,
+          last: Box::new(e),
+        });
+      }
+    }
+  };
+  if let Some(last) = app.and_then(|app| app.try_state::<LastSpawnError>()) {
+    if let Ok(mut last) = last.0.lock() {
+      *last = result.as_ref().err().map(|e| SpawnFailure {
+        message: e.to_string(),
+        details: e.details(),
+      });
+    }
+  }
+  result
+}
+
+/// Backend arguments after the program (and, in dev, `-m narrative_mirror.cli_json`). The database path is
+/// passed as an OsStr, so spaces, non-ASCII and non-UTF-8 bytes reach the backend exactly.
+fn backend_args(spawn: &BackendSpawnConfig) -> Vec<OsString> {
+  let mut args = vec![OsString::from("--db"), spawn.db_arg.clone().into_os_string()];
+  args.extend(
+    ["--log-level", &spawn.log_level, "stdio", "--config", &spawn.config_arg].map(OsString::from),
+  );
+  if spawn.http {
+    args.push(OsString::from("--http"));
+  }
+  args
+}
+
+fn launch_backend(
+  app: Option<&tauri::AppHandle>,
+  spawn: BackendSpawnConfig,
+) -> Result<BackendProcess, SpawnError> {
+  if let BackendSource::External { cmd, cwd } = spawn.source.clone() {
+    return launch_external(app, &cmd, &cwd, spawn);
+  }
+
+  #[cfg(debug_assertions)]
+  {
+    let python = dev_python_command().map_err(SpawnError::BadCommand)?;
+    let uses_uv = python[0] == "uv";
+    let mut child = new_process_group(&mut Command::new(&python[0]))
+      .args(&python[1..])
+      .args(["-m", "narrative_mirror.cli_json"])
+      .args(backend_args(&spawn))
+      .envs(&spawn.env)
+      .env("PYTHONUNBUFFERED", "1")
+      .env("PYTHONIOENCODING", "utf-8")
+      .current_dir(&spawn.cwd)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .map_err(|e| {
+        if uses_uv && e.kind() == std::io::ErrorKind::NotFound && spawn.cwd.is_dir() {
+          SpawnError::UvNotFound
+        } else {
+          SpawnError::Io(e)
+        }
+      })?;
+    check_started(&mut child)?;
+    Ok(BackendProcess::from_child(child, spawn, app))
+  }
+
+  #[cfg(not(debug_assertions))]
+  {
+    let app = app
+      .ok_or_else(|| SpawnError::Io(std::io::Error::other("AppHandle required for sidecar")))?;
+    let resource_dir = app
+      .path()
+      .resource_dir()
+      .map_err(|e| SpawnError::Io(std::io::Error::other(format!("resource_dir: {}", e))))?;
+    let diag = sidecar::diagnose(&resource_dir, APP_TARGET);
+    if !diag.usable() {
+      log::error!(
+        "Sidecar unusable: {}",
+        serde_json::to_string(&diag).unwrap_or_default()
+      );
+      return Err(SpawnError::SidecarUnusable(Box::new(diag)));
+    }
+    let sidecar_path = diag.expected_path;
+    #[cfg(feature = "verify-sidecar")]
+    sidecar::verify_checksum(&sidecar_path).map_err(SpawnError::SidecarChecksum)?;
+    let mut child = new_process_group(&mut Command::new(&sidecar_path))
+      .args(backend_args(&spawn))
+      .envs(&spawn.env)
+      .current_dir(&spawn.cwd)
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .map_err(SpawnError::Io)?;
+    check_started(&mut child)?;
+    Ok(BackendProcess::from_child(child, spawn, Some(app)))
+  }
+}
+
+/// Run a BackendSource::External backend in its own `cwd`. The database and config paths are made absolute
+/// against the app's backend dir first, so it works on the same data as the bundled one would.
+fn launch_external(
+  app: Option<&tauri::AppHandle>,
+  cmd: &[String],
+  cwd: &Path,
+  spawn: BackendSpawnConfig,
+) -> Result<BackendProcess, SpawnError> {
+  validate_external_backend(cmd, cwd).map_err(SpawnError::BadCommand)?;
+  let args = backend_args(&BackendSpawnConfig {
+    db_arg: spawn.cwd.join(&spawn.db_arg),
+    config_arg: spawn.cwd.join(&spawn.config_arg).to_string_lossy().into_owned(),
+    ..spawn.clone()
+  });
+  log::info!("Spawning external backend {:?} in {}", cmd, cwd.display());
+  let mut child = new_process_group(&mut Command::new(&cmd[0]))
+    .args(&cmd[1..])
+    .args(args)
+    .envs(&spawn.env)
+    .env("PYTHONUNBUFFERED", "1")
+    .env("PYTHONIOENCODING", "utf-8")
+    .current_dir(cwd)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(SpawnError::Io)?;
+  check_started(&mut child)?;
+  Ok(BackendProcess::from_child(child, spawn, app))
+}
+
+/// Tell "launched and crashed at once" apart from a slow start: if `child` has exited STARTUP_EXIT_CHECK
+/// after spawning, fail with its exit status and the last EXIT_STDERR_TAIL_LINES of its stderr.
+fn check_started(child: &mut Child) -> Result<(), SpawnError> {
+  std::thread::sleep(STARTUP_EXIT_CHECK);
+  let status = match child.try_wait() {
+    Ok(Some(status)) => status,
+    _ => return Ok(()),
+  };
+  // Anything left in the process group could hold stderr open; make sure reading it reaches EOF.
+  let _ = kill_process_tree(child.id());
+  let mut stderr_tail = VecDeque::new();
+  if let Some(stderr) = child.stderr.take() {
+    for_each_line_lossy(stderr, |line| {
+      if stderr_tail.len() == EXIT_STDERR_TAIL_LINES {
+        stderr_tail.pop_front();
+      }
+      stderr_tail.push_back(line);
+    });
+  }
+  log::error!("Backend exited right after spawning ({}): {:?}", status, stderr_tail);
+  Err(SpawnError::ExitedImmediately {
+    status: status.to_string(),
+    exit_code: status.code(),
+    stderr_tail: stderr_tail.into(),
+  })
+}
+
+/// Dev interpreter command: NARRARC_BACKEND_CMD split into words, or `uv run python`.
+#[cfg(debug_assertions)]
+fn dev_python_command() -> Result<Vec<String>, String> {
+  let Ok(cmd) = std::env::var(BACKEND_CMD_ENV) else {
+    return Ok(vec!["uv".to_string(), "run".to_string(), "python".to_string()]);
+  };
+  let words = shlex::split(&cmd)
+    .ok_or_else(|| format!("{} is not a valid command line: {}", BACKEND_CMD_ENV, cmd))?;
+  if words.is_empty() {
+    return Err(format!("{} is set but empty", BACKEND_CMD_ENV));
+  }
+  Ok(words)
+}
+
+/// Why a backend is shut down; picks the grace period it gets (see shutdown_grace).
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ShutdownReason {
+  /// The app is quitting.
+  AppExit,
+  /// restart_backend_inner: config reload, database switch, watchdog, restart_backend.
+  Restart,
+  /// Idle suspend or stop_backend.
+  Stop,
+}
+
+/// Grace period for a shutdown for `reason`, longer for restarts while a build is running.
+fn shutdown_grace(app: &tauri::AppHandle, reason: ShutdownReason) -> Duration {
+  grace_for(reason, reason == ShutdownReason::Restart && build_running(app))
+}
+
+fn grace_for(reason: ShutdownReason, build_running: bool) -> Duration {
+  match reason {
+    ShutdownReason::AppExit => APP_EXIT_GRACE,
+    ShutdownReason::Restart if build_running => BUILD_RESTART_GRACE,
+    ShutdownReason::Restart | ShutdownReason::Stop => SHUTDOWN_GRACE,
+  }
+}
+
+/// Whether a build child is still running.
+fn build_running(app: &tauri::AppHandle) -> bool {
+  let Some(build) = app.try_state::<BuildProcess>() else {
+    return false;
+  };
+  let Ok(mut running) = build.0.lock() else {
+    return false;
+  };
+  running
+    .as_mut()
+    .is_some_and(|b| matches!(b.child.try_wait(), Ok(None)))
+}
+
+/// Ask the backend to exit cleanly ({"cmd":"shutdown"} then close stdin) so SQLite writes and WAL
+/// checkpoints can finish; force-kill only if it is still running after `grace`.
+fn shutdown_backend(process: &mut BackendProcess, grace: Duration) {
+  process.shutting_down = true;
+  if let Some(mut stdin) = process.stdin.take() {
+    let _ = stdin.send_line(&serde_json::json!({ "cmd": "shutdown" }).to_string());
+  }
+  let pid = process.child.id();
+  let deadline = std::time::Instant::now() + grace;
+  while std::time::Instant::now() < deadline {
+    match process.child.try_wait() {
+      Ok(Some(status)) => {
+        log::info!("Backend (pid {}) shut down: {}", pid, describe_exit(Some(&status)));
+        return;
+      }
+      Err(e) => {
+        log::warn!("Could not wait for backend (pid {}) to shut down: {}", pid, e);
+        return;
+      }
+      Ok(None) => std::thread::sleep(Duration::from_millis(50)),
+    }
+  }
+  process.killed = true;
+  let status = kill_child_tree(&mut process.child);
+  log::warn!(
+    "Backend (pid {}) did not exit within {:?}; killed it: {}",
+    pid,
+    grace,
+    describe_exit(status.as_ref())
+  );
+}
+
+/// An exit status for logs: the exit code, or on Unix the signal that ended the process.
+fn describe_exit(status: Option<&std::process::ExitStatus>) -> String {
+  match status {
+    None => "exit status unknown".to_string(),
+    Some(status) => match (status.code(), exit_signal(status)) {
+      (Some(code), _) => format!("exit code {}", code),
+      (None, Some(signal)) => format!("signal {}", signal),
+      (None, None) => status.to_string(),
+    },
+  }
+}
+
+/// Watch a backend for exits nobody asked for (crash, OOM kill) and emit `backend://exited`
+/// with the exit code, the terminating signal (Unix), whether the app killed it and the tail of stderr,
+/// once per process. Intentional shutdowns are skipped; the next request respawns via ensure_alive as
+/// usual. Stops once the instance has been dropped.
+fn spawn_exit_monitor(app: tauri::AppHandle, state: Weak<Mutex<BackendProcess>>) {
+  std::thread::spawn(move || {
+    let mut reported_pid = None;
+    loop {
+      std::thread::sleep(EXIT_POLL_INTERVAL);
+      let Some(state) = state.upgrade() else {
+        return;
+      };
+      let exited = {
+        let mut guard = lock_process(&state);
+        let pid = guard.child.id();
+        if guard.shutting_down || reported_pid == Some(pid) {
+          continue;
+        }
+        match guard.child.try_wait() {
+          Ok(Some(status)) => Some((guard.spawn.instance_id.clone(), pid, status, guard.killed)),
+          _ => None,
+        }
+      };
+      let Some((instance_id, pid, status, killed)) = exited else {
+        continue;
+      };
+      reported_pid = Some(pid);
+      let stderr_tail = recent_stderr(&app, EXIT_STDERR_TAIL_LINES);
+      if killed {
+        log::warn!("Backend (pid {}) was killed by the app: {}", pid, describe_exit(Some(&status)));
+      } else {
+        log::error!("Backend (pid {}) exited unexpectedly: {}", pid, describe_exit(Some(&status)));
+      }
+      emit_recorded(
+        &app,
+        "backend://exited",
+        serde_json::json!({
+          "instance_id": instance_id,
+          "pid": pid,
+          "exit_code": status.code(),
+          "signal": exit_signal(&status),
+          "killed": killed,
+          "stderr_tail": stderr_tail,
+        }),
+      );
+    }
+  });
+}
+
+/// Every IDLE_CHECK_INTERVAL, gracefully shut down each backend that has had nothing in flight for the
+/// IdleTimeout, mark it suspended and emit `backend://suspended` {instance_id, pid, idle_ms}. The next
+/// request resumes it through ensure_alive.
+fn spawn_idle_monitor(app: tauri::AppHandle) {
+  std::thread::spawn(move || loop {
+    std::thread::sleep(IDLE_CHECK_INTERVAL);
+    let timeout_ms = app.state::<IdleTimeout>().0.load(Ordering::SeqCst);
+    if timeout_ms == 0 {
+      continue;
+    }
+    let Some(backends) = app.try_state::<Backends>() else {
+      continue;
+    };
+    let instances: Vec<BackendInstance> = match backends.0.lock() {
+      Ok(map) => map.values().cloned().collect(),
+      Err(_) => continue,
+    };
+    for instance in instances {
+      if !idle_past(instance.limiter.idle_for(), timeout_ms) {
+        continue;
+      }
+      let mut guard = lock_process(&instance.process);
+      // Re-check under the lock: a request may have started while we waited for it.
+      let idle = instance.limiter.idle_for();
+      let running = guard.state == BackendState::Running && matches!(guard.child.try_wait(), Ok(None));
+      if !running || !idle_past(idle, timeout_ms) {
+        continue;
+      }
+      let pid = guard.child.id();
+      log::info!("Backend {} idle for {:?}, shutting it down", guard.spawn.instance_id, idle);
+      shutdown_backend(&mut guard, shutdown_grace(&app, ShutdownReason::Stop));
+      guard.state = BackendState::Suspended;
+      emit_recorded(
+        &app,
+        "backend://suspended",
+        serde_json::json!({
+          "instance_id": guard.spawn.instance_id,
+          "pid": pid,
+          "idle_ms": idle.map_or(0, |d| d.as_millis() as u64),
+        }),
+      );
+    }
+  });
+}
+
+/// Whether a backend idle for `idle` (None while busy) has passed an idle timeout of `timeout_ms` (0 = never).
+fn idle_past(idle: Option<Duration>, timeout_ms: u64) -> bool {
+  timeout_ms > 0 && idle.is_some_and(|d| d >= Duration::from_millis(timeout_ms))
+}
+
+/// Store the idle timeout in minutes (0 disables it) and apply it to the running idle monitor. A backend
+/// already suspended stays so until its next request.
+#[tauri::command]
+fn set_idle_timeout(
+  app: tauri::AppHandle,
+  idle: tauri::State<'_, IdleTimeout>,
+  minutes: u64,
+) -> Result<u64, String> {
+  let app_data = app.path().app_data_dir().map_err(|e| e.to_string())?;
+  let mut settings = load_settings(&app_data);
+  settings["idle_timeout_minutes"] = serde_json::json!(minutes);
+  save_settings(&app_data, &settings)?;
+  idle.0.store(minutes.saturating_mul(60_000), Ordering::SeqCst);
+  Ok(minutes)
+}
+
+/// Turn read-only mode on or off: while on, spawn_backend_build, set_config, set_database and
+/// open_database_from_path fail with "read-only mode" before touching the backend. Refuses to turn it off
+/// when READ_ONLY_ENV turned it on. Returns the new state.
+#[tauri::command]
+fn set_read_only(read_only: tauri::State<'_, ReadOnly>, enabled: bool) -> Result<bool, String> {
+  if !enabled && read_only.locked {
+    return Err(format!("read-only mode was set by {} and can't be turned off", READ_ONLY_ENV));
+  }
+  read_only.enabled.store(enabled, Ordering::SeqCst);
+  log::info!("Read-only mode {}", if enabled { "on" } else { "off" });
+  Ok(enabled)
+}
+
+/// Whether read-only mode is on.
+#[tauri::command]
+fn get_read_only(read_only: tauri::State<'_, ReadOnly>) -> bool {
+  read_only.enabled.load(Ordering::SeqCst)
+}
+
+/// The last `n` captured backend stderr lines, oldest first.
+fn recent_stderr(app: &tauri::AppHandle, n: usize) -> Vec<String> {
+  let Some(buffer) = app.try_state::<BackendStderr>() else {
+    return Vec::new();
+  };
+  let Ok(lines) = buffer.0.lock() else {
+    return Vec::new();
+  };
+  let skip = lines.len().saturating_sub(n);
+  lines.iter().skip(skip).cloned().collect()
+}
+
+/// Emit `event` with a fresh sequence number (added as "seq" when the payload is an object) and record it
+/// in RecentEvents. Emitting under the lock keeps delivery order equal to seq order.
+fn emit_recorded(app: &tauri::AppHandle, event: &str, mut payload: serde_json::Value) {
+  let Some(recent) = app.try_state::<RecentEvents>() else {
+    let _ = app.emit(event, &payload);
+    return;
+  };
+  let Ok(mut recorded) = recent.0.lock() else {
+    let _ = app.emit(event, &payload);
+    return;
+  };
+  recorded.last_seq += 1;
+  let seq = recorded.last_seq;
+  if let Some(obj) = payload.as_object_mut() {
+    obj.insert("seq".to_string(), serde_json::json!(seq));
+  }
+  if recorded.events.len() == RECENT_EVENTS_CAPACITY {
+    recorded.events.pop_front();
+  }
+  recorded.events.push_back(serde_json::json!({ "seq": seq, "event": event, "payload": payload }));
+  let _ = app.emit(event, &payload);
+}
+
+/// Read backend stderr line-by-line: echo to our stderr, emit `backend://stderr`, and keep the last
+/// STDERR_BUFFER_LINES lines in BackendStderr.
+fn spawn_stderr_forwarder(stderr: std::process::ChildStderr, app: Option<tauri::AppHandle>) {
+  std::thread::spawn(move || {
+    for_each_line_lossy(stderr, |line| {
+      eprintln!("{}", line);
+      let Some(ref app) = app else {
+        return;
+      };
+      if let Some(buffer) = app.try_state::<BackendStderr>() {
+        if let Ok(mut lines) = buffer.0.lock() {
+          if lines.len() == STDERR_BUFFER_LINES {
+            lines.pop_front();
+          }
+          lines.push_back(line.clone());
+        }
+      }
+      let _ = app.emit("backend://stderr", &line);
+    });
+  });
+}
+
+/// Call `f` for each line (without trailing newline) until EOF; invalid UTF-8 is replaced rather than
+/// ending the read, so console output in a non-UTF-8 codepage never stalls the pipe.
+fn for_each_line_lossy(reader: impl Read, mut f: impl FnMut(String)) {
+  let mut reader = BufReader::new(reader);
+  let mut buf = Vec::new();
+  loop {
+    buf.clear();
+    match reader.read_until(b'\n', &mut buf) {
+      Ok(0) | Err(_) => break,
+      Ok(_) => f(String::from_utf8_lossy(&buf).trim_end().to_string()),
+    }
+  }
+}
+
+/// Lock a backend process. A lock poisoned by a panic while it was held is recovered rather than failing
+/// every later command, and the process is marked poisoned so the next request restarts it (its state may
+/// be half-updated).
+fn lock_process(state: &Mutex<BackendProcess>) -> MutexGuard<'_, BackendProcess> {
+  state.lock().unwrap_or_else(|e| {
+    log::error!("Backend lock was poisoned by a panic; the backend will be restarted");
+    let guard = e.into_inner();
+    guard.poisoned.store(true, Ordering::SeqCst);
+    state.clear_poison();
+    guard
+  })
+}
+
+/// Start the worker that owns writing to the backend: for each queued request, respawn if needed, wait for
+/// readiness (so requests issued right after a (re)spawn don't race startup), then tag it with a fresh
+/// req_id, register it with the dispatcher and write it to stdin.
+fn spawn_request_worker(app: tauri::AppHandle, state: Arc<Mutex<BackendProcess>>) -> RequestQueue {
+  let (tx, rx) = std::sync::mpsc::channel::<QueuedRequest>();
+  std::thread::spawn(move || {
+    for job in rx {
+      let result = {
+        let mut guard = lock_process(&state);
+        let process = guard.deref_mut();
+        process
+          .ensure_alive(&app)
+          .and_then(|()| process.wait_ready(Some(&app), READY_TIMEOUT))
+          .and_then(|()| process.write_request(job.payload))
+      };
+      let _ = job.reply.send(result);
+    }
+  });
+  RequestQueue(tx)
+}
+
+/// Put a spawned process under management: start its request worker and exit monitor.
+/// The limiter is sized from the handshake of `process` (see max_in_flight), or raised once a handshake
+/// still running finishes (spawn_startup_handshake); a later respawn keeps it.
+fn start_instance(app: &tauri::AppHandle, process: BackendProcess) -> BackendInstance {
+  let limit = max_in_flight(process.max_concurrent);
+  let process = Arc::new(Mutex::new(process));
+  let queue = spawn_request_worker(app.clone(), process.clone());
+  spawn_exit_monitor(app.clone(), Arc::downgrade(&process));
+  BackendInstance {
+    process,
+    queue,
+    limiter: RequestLimiter::new(limit),
+    watchdog: Watchdog::new(app),
+  }
+}
+
+/// Wait for the default backend's readiness handshake on a thread of its own, so a slow cold start doesn't
+/// hold up setup and quitting meanwhile kills the half-started child (see wait_ready). Requests issued
+/// before it is ready wait on the process lock. Once ready, the limiter is raised to what the backend
+/// advertised and the warmup, if enabled, is sent; a failure is kept for get_spawn_error.
+fn spawn_startup_handshake(app: &tauri::AppHandle, instance: BackendInstance) {
+  let app = app.clone();
+  std::thread::spawn(move || {
+    let ready = {
+      let mut guard = lock_process(&instance.process);
+      guard
+        .wait_ready(Some(&app), READY_TIMEOUT)
+        .map(|()| max_in_flight(guard.max_concurrent))
+    };
+    match ready {
+      Ok(limit) => {
+        instance.limiter.raise_to(limit);
+        if warmup_enabled(&app) {
+          spawn_warmup(&app, DEFAULT_INSTANCE, instance);
+        }
+      }
+      Err(e) if app.state::<QueryCancels>().shutting_down.load(Ordering::SeqCst) => {
+        log::info!("Backend startup abandoned: {}", e);
+      }
+      Err(e) => {
+        log::error!("Backend not ready: {}", e);
+        if let Ok(mut last) = app.state::<LastSpawnError>().0.lock() {
+          *last = Some(SpawnFailure {
+            message: e,
+            details: None,
+          });
+        }
+      }
+    }
+  });
+}
+
+/// Whether the startup warmup is enabled (the default); low-memory users can turn it off.
+fn warmup_enabled(app: &tauri::AppHandle) -> bool {
+  match app.path().app_config_dir() {
+    Ok(dir) => !dir.join(WARMUP_DISABLED_FILE).exists(),
+    Err(_) => true,
+  }
+}
+
+/// Send `{"cmd":"warmup"}` to `instance` in the background so the first real query doesn't pay for loading
+/// models and clients. Failure is only logged. Emits `backend://warm` {instance_id, ok, duration_ms | error}
+/// when done.
+fn spawn_warmup(app: &tauri::AppHandle, instance_id: &str, instance: BackendInstance) {
+  let app = app.clone();
+  let instance_id = instance_id.to_string();
+  tauri::async_runtime::spawn(async move {
+    let started = std::time::Instant::now();
+    let result = request(&instance, serde_json::json!({ "cmd": "warmup" }), WARMUP_TIMEOUT).await;
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    let payload = match result {
+      Ok(v) => {
+        log::info!("Backend {} warm in {} ms", instance_id, elapsed_ms);
+        serde_json::json!({
+          "instance_id": instance_id,
+          "ok": true,
+          "duration_ms": elapsed_ms,
+          "skipped": v.get("skipped").cloned().unwrap_or_default(),
+        })
+      }
+      Err(e) => {
+        log::warn!("Backend {} warmup failed: {}", instance_id, e);
+        serde_json::json!({ "instance_id": instance_id, "ok": false, "error": e })
+      }
+    };
+    emit_recorded(&app, "backend://warm", payload);
+  });
+}
+
+/// Refuse a backend whose protocol version is outside SUPPORTED_PROTOCOL_VERSIONS, saying which side to
+/// update rather than failing later on some unknown command.
+fn check_protocol_version(version: u64) -> Result<(), String> {
+  let (min, max) = SUPPORTED_PROTOCOL_VERSIONS.into_inner();
+  if version < min {
+    Err(format!(
+      "backend too old: it speaks protocol version {}, this app needs {}..={}; rebuild the backend",
+      version, min, max
+    ))
+  } else if version > max {
+    Err(format!(
+      "backend too new: it speaks protocol version {}, this app supports up to {}; update the app",
+      version, max
+    ))
+  } else {
+    Ok(())
+  }
+}
+
+/// Poll for the first reply to a handshake request until `deadline` (`timeout` is only for the message).
+fn handshake_reply(
+  mut pending: PendingRequest,
+  deadline: std::time::Instant,
+  timeout: Duration,
+  progress: &mut dyn FnMut() -> Result<(), String>,
+) -> Result<serde_json::Value, String> {
+  loop {
+    match pending.rx.try_recv() {
+      Ok(line) => return Ok(line.data),
+      Err(TryRecvError::Disconnected) => return Err("backend exited during startup".to_string()),
+      Err(TryRecvError::Empty) => {}
+    }
+    if std::time::Instant::now() >= deadline {
+      return Err(format!(
+        "backend not ready after {} ms",
+        timeout.as_millis()
+      ));
+    }
+    progress()?;
+    std::thread::sleep(Duration::from_millis(20));
+  }
+}
+
+/// Enqueue payload for the request worker and wait until it has been written to the backend.
+async fn send_request(
+  queue: &RequestQueue,
+  payload: serde_json::Value,
+) -> Result<PendingRequest, String> {
+  let (reply, written) = tokio::sync::oneshot::channel();
+  queue
+    .0
+    .send(QueuedRequest { payload, reply })
+    .map_err(|_| "backend request queue closed".to_string())?;
+  written
+    .await
+    .map_err(|_| "backend request worker stopped".to_string())?
+}
+
+/// Backend CLI command in a new process group: `python -m narrative_mirror.cli_json` in dev, the sidecar in
+/// release. stdin is null and stdout/stderr are piped.
+fn backend_cli_command(
+  #[cfg_attr(debug_assertions, allow(unused_variables))] app: &tauri::AppHandle,
+  cwd: &Path,
+  args: &[OsString],
+) -> Result<Command, String> {
+  #[cfg(debug_assertions)]
+  let mut cmd = {
+    let python = dev_python_command()?;
+    let mut cmd = Command::new(&python[0]);
+    new_process_group(&mut cmd)
+      .args(&python[1..])
+      .args(["-m", "narrative_mirror.cli_json"])
+      .env("PYTHONUNBUFFERED", "1")
+      .env("PYTHONIOENCODING", "utf-8");
+    cmd
+  };
+
+  #[cfg(not(debug_assertions))]
+  let mut cmd = {
+    let resource_dir = app
+      .path()
+      .resource_dir()
+      .map_err(|e| format!("resource_dir: {}", e))?;
+    let diag = sidecar::diagnose(&resource_dir, APP_TARGET);
+    if !diag.usable() {
+      return Err(SpawnError::SidecarUnusable(Box::new(diag)).to_string());
+    }
+    #[cfg(feature = "verify-sidecar")]
+    sidecar::verify_checksum(&diag.expected_path)
+      .map_err(|e| SpawnError::SidecarChecksum(e).to_string())?;
+    let mut cmd = Command::new(&diag.expected_path);
+    new_process_group(&mut cmd);
+    cmd
+  };
+  cmd
+    .args(args)
+    .current_dir(cwd)
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+  Ok(cmd)
+}
+
+/// The database builds and reindexes write to: the default backend's, so they land where queries read, whichever
+/// database set_database or open_database_from_path switched it to. Without a default backend (its spawn
+/// failed) it is resolved as that spawn does (active_db_path), so a relocated db_dir is honoured too.
+fn job_db_path(app: &tauri::AppHandle, backends: &Backends) -> Result<PathBuf, String> {
+  match backends.get(None) {
+    Ok(instance) => Ok(process_db_path(&instance.process)),
+    Err(_) => get_backend_cwd_and_db(Some(app)).map(|(_, db)| active_db_path(Some(app), db)),
+  }
+}
+
+/// The database `process` runs on, or will after a pending switch_db restart.
+fn process_db_path(process: &Mutex<BackendProcess>) -> PathBuf {
+  lock_process(process).spawn.db_arg.clone()
+}
+
+/// Arguments of a `subcommand` CLI run for `talker_id` (build, reindex) against `db`, which is passed as an
+/// OsStr like backend_args does for the daemon.
+fn job_args(db: &Path, log_level: &str, subcommand: &str, talker_id: &str, config: &str) -> Vec<OsString> {
+  let mut args = vec![OsString::from("--db"), db.as_os_str().to_os_string()];
+  args.extend(
+    ["--log-level", log_level, subcommand, "--talker", talker_id, "--config", config].map(OsString::from),
+  );
+  args
+}
+
+/// Start a build and return as soon as it is spawned. Progress arrives as `build://progress` events,
+/// followed by exactly one `build://done` or `build://error` (or `build://cancelled` via cancel_build).
+///
+/// `jobs` sets the number of parallel LLM/embedding workers for this build (default: see build_jobs).
+/// `seed` is passed as `--seed` to pin randomness, so repeated builds can be compared.
+///
+/// With `validate_only`, nothing is built: the backend runs `build --dry-run`, which checks the talker,
+/// config and overrides read-only. The result is emitted as `build://validation` and also returned.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn spawn_backend_build(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  build: tauri::State<'_, BuildProcess>,
+  reindex: tauri::State<'_, ReindexProcess>,
+  talker_id: String,
+  config_overrides: Option<String>,
+  config_path: Option<String>,
+  validate_only: Option<bool>,
+  jobs: Option<u32>,
+  seed: Option<u64>,
+) -> Result<Option<serde_json::Value>, String> {
+  ensure_writable(&app)?;
+  let config_overrides = config_overrides.filter(|o| !o.is_empty());
+  if let Some(ref overrides) = config_overrides {
+    let value: serde_json::Value = serde_json::from_str(overrides)
+      .map_err(|e| format!("config_overrides is not valid JSON: {}", e))?;
+    validate_overrides(&value)?;
+  }
+  let (cwd, _) = get_backend_cwd_and_db(Some(&app))?;
+  let config = resolve_config_path(&cwd, config_path)?;
+  let db = job_db_path(&app, &backends)?;
+  let mut args = job_args(&db, &log_level(&app), "build", &talker_id, &config);
+  if let Some(overrides) = config_overrides {
+    args.push(OsString::from("--config-overrides"));
+    args.push(OsString::from(overrides));
+  }
+  args.push(OsString::from("--jobs"));
+  args.push(OsString::from(build_jobs(&app, jobs).to_string()));
+  if let Some(seed) = seed {
+    args.push(OsString::from("--seed"));
+    args.push(OsString::from(seed.to_string()));
+  }
+
+  if validate_only.unwrap_or(false) {
+    args.push(OsString::from("--dry-run"));
+    let mut cmd = backend_cli_command(&app, &cwd, &args)?;
+    let output = tauri::async_runtime::spawn_blocking(move || cmd.output())
+      .await
+      .map_err(|e| e.to_string())?
+      .map_err(|e| format!("Failed to spawn backend build validation: {}", e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let validation = stdout
+      .lines()
+      .rev()
+      .filter_map(|line| serde_json::from_str::<serde_json::Value>(line.trim()).ok())
+      .find(|v| v.get("type").and_then(|t| t.as_str()) == Some("validation"))
+      .ok_or_else(|| {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        format!(
+          "Build validation produced no result ({}): {}",
+          output.status,
+          stderr.lines().last().unwrap_or("")
+        )
+      })?;
+    let _ = app.emit("build://validation", &validation);
+    return Ok(Some(validation));
+  }
+
+  let mut running = build.0.lock().map_err(|e| e.to_string())?;
+  if let Some(talker) = running_talker(&mut running) {
+    return Err(format!("A build is already running for {}", talker));
+  }
+  if let Some(talker) = running_talker(&mut *reindex.0.lock().map_err(|e| e.to_string())?) {
+    if talker == talker_id {
+      return Err(format!("A reindex is running for {}", talker));
+    }
+  }
+  let mut child = backend_cli_command(&app, &cwd, &args)?
+    .spawn()
+    .map_err(|e| format!("Failed to spawn backend build: {}", e))?;
+  if let Ok(mut log) = app.state::<BuildLog>().0.lock() {
+    *log = BuildLogLines::default();
+  }
+  if let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) {
+    spawn_build_monitor(app.clone(), JobKind::Build, child.id(), talker_id.clone(), stdout, stderr);
+  }
+  *running = Some(RunningBuild {
+    child,
+    talker_id,
+    started_at: std::time::SystemTime::now(),
+  });
+  Ok(None)
+}
+
+/// Start re-embedding `talker_id`'s nodes with the current (or overridden) embedding settings: the backend's
+/// `reindex`, far cheaper than a full build when only those changed. Returns once it is spawned; it reports
+/// like a build, as `reindex://progress` events followed by one `reindex://done` or `reindex://error` (or
+/// `reindex://cancelled` via cancel_reindex). Refused while another reindex, or a build of the same talker,
+/// is running.
+#[tauri::command]
+async fn spawn_reindex(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  build: tauri::State<'_, BuildProcess>,
+  reindex: tauri::State<'_, ReindexProcess>,
+  talker_id: String,
+  config_overrides: Option<serde_json::Value>,
+  config_path: Option<String>,
+) -> Result<(), String> {
+  ensure_writable(&app)?;
+  let config_overrides = config_overrides.filter(|o| !o.is_null());
+  if let Some(ref overrides) = config_overrides {
+    validate_overrides(overrides)?;
+  }
+  let (cwd, _) = get_backend_cwd_and_db(Some(&app))?;
+  let config = resolve_config_path(&cwd, config_path)?;
+  let db = job_db_path(&app, &backends)?;
+  let mut args = job_args(&db, &log_level(&app), "reindex", &talker_id, &config);
+  if let Some(overrides) = config_overrides {
+    args.push(OsString::from("--config-overrides"));
+    args.push(OsString::from(overrides.to_string()));
+  }
+
+  // Same lock order as spawn_backend_build: build, then reindex.
+  let mut building = build.0.lock().map_err(|e| e.to_string())?;
+  if running_talker(&mut building).as_deref() == Some(talker_id.as_str()) {
+    return Err(format!("A build is running for {}", talker_id));
+  }
+  let mut running = reindex.0.lock().map_err(|e| e.to_string())?;
+  if let Some(talker) = running_talker(&mut running) {
+    return Err(format!("A reindex is already running for {}", talker));
+  }
+  let mut child = backend_cli_command(&app, &cwd, &args)?
+    .spawn()
+    .map_err(|e| format!("Failed to spawn backend reindex: {}", e))?;
+  if let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) {
+    spawn_build_monitor(app.clone(), JobKind::Reindex, child.id(), talker_id.clone(), stdout, stderr);
+  }
+  *running = Some(RunningBuild {
+    child,
+    talker_id,
+    started_at: std::time::SystemTime::now(),
+  });
+  Ok(())
+}
+
+/// The talker of the job tracked in `slot`, if its child is still running.
+fn running_talker(slot: &mut Option<RunningBuild>) -> Option<String> {
+  let job = slot.as_mut()?;
+  matches!(job.child.try_wait(), Ok(None)).then(|| job.talker_id.clone())
+}
+
+/// Follow a build or reindex child: forward stdout lines as `<kind>://progress` (JSON lines as-is, anything
+/// else as a raw string; a build's also go to BuildLog), then emit `<kind>://done` or `<kind>://error` once
+/// it exits. Nothing is emitted if kill_job took it.
+fn spawn_build_monitor(
+  app: tauri::AppHandle,
+  kind: JobKind,
+  pid: u32,
+  talker_id: String,
+  stdout: std::process::ChildStdout,
+  stderr: std::process::ChildStderr,
+) {
+  let stderr_handle = std::thread::spawn(move || {
+    let mut last = None;
+    for_each_line_lossy(stderr, |line| {
+      eprintln!("{}", line);
+      if !line.trim().is_empty() {
+        last = Some(line);
+      }
+    });
+    last
+  });
+  std::thread::spawn(move || {
+    let mut result = serde_json::Value::Null;
+    for_each_line_lossy(stdout, |line| {
+      let trimmed = line.trim();
+      if trimmed.is_empty() {
+        return;
+      }
+      if kind == JobKind::Build {
+        if let Ok(mut log) = app.state::<BuildLog>().0.lock() {
+          log.push(trimmed.to_string());
+        }
+      }
+      match serde_json::from_str::<serde_json::Value>(trimmed) {
+        Ok(v) if v.get("status").and_then(|s| s.as_str()) == Some("complete") => result = v,
+        Ok(v) => {
+          let _ = app.emit(&kind.event("progress"), &v);
+        }
+        Err(_) => {
+          let _ = app.emit(&kind.event("progress"), trimmed);
+        }
+      }
+    });
+    let last_stderr = stderr_handle.join().ok().flatten();
+    let Some(status) = wait_for_build_exit(&app, kind, pid) else {
+      return;
+    };
+    if status.success() {
+      let _ = app.emit(
+        &kind.event("done"),
+        serde_json::json!({ "talker_id": talker_id, "result": result }),
+      );
+    } else {
+      let message = last_stderr.unwrap_or_else(|| format!("{} exited with {}", kind.name(), status));
+      let _ = app.emit(
+        &kind.event("error"),
+        serde_json::json!({ "talker_id": talker_id, "message": message }),
+      );
+    }
+  });
+}
+
+/// Poll until the tracked `kind` child with `pid` exits. None once it is no longer tracked (cancelled).
+fn wait_for_build_exit(app: &tauri::AppHandle, kind: JobKind, pid: u32) -> Option<ExitStatus> {
+  loop {
+    {
+      let mut running = kind.slot(app)?.lock().ok()?;
+      let current = running.as_mut().filter(|b| b.child.id() == pid)?;
+      match current.child.try_wait() {
+        Ok(Some(status)) => return Some(status),
+        Ok(None) => {}
+        Err(_) => return None,
+      }
+    }
+    std::thread::sleep(Duration::from_millis(100));
+  }
+}
+
+/// Kill the running build, if any. Returns whether a build was actually running; emits `build://cancelled`.
+#[tauri::command]
+fn cancel_build(app: tauri::AppHandle, build: tauri::State<'_, BuildProcess>) -> Result<bool, String> {
+  let Some(talker_id) = kill_job(&build.0, JobKind::Build)? else {
+    return Ok(false);
+  };
+  let _ = app.emit(
+    "build://cancelled",
+    serde_json::json!({ "talker_id": talker_id }),
+  );
+  Ok(true)
+}
+
+/// Kill the running reindex, if any. Returns whether one was actually running; emits `reindex://cancelled`.
+#[tauri::command]
+fn cancel_reindex(
+  app: tauri::AppHandle,
+  reindex: tauri::State<'_, ReindexProcess>,
+) -> Result<bool, String> {
+  let Some(talker_id) = kill_job(&reindex.0, JobKind::Reindex)? else {
+    return Ok(false);
+  };
+  let _ = app.emit(
+    "reindex://cancelled",
+    serde_json::json!({ "talker_id": talker_id }),
+  );
+  Ok(true)
+}
+
+/// Whether a build is running, so a reloaded window can restore its indicator and not start a second
+/// one: {running, talker, started_at (ms since the epoch), elapsed_ms}; talker and times are null when idle.
+#[tauri::command]
+fn build_status(build: tauri::State<'_, BuildProcess>) -> Result<serde_json::Value, String> {
+  let mut running = build.0.lock().map_err(|e| e.to_string())?;
+  let alive = running.as_mut().is_some_and(|b| matches!(b.child.try_wait(), Ok(None)));
+  let Some(current) = running.as_mut().filter(|_| alive) else {
+    return Ok(serde_json::json!({
+      "running": false,
+      "talker": null,
+      "started_at": null,
+      "elapsed_ms": null,
+    }));
+  };
+  let started_at = current
+    .started_at
+    .duration_since(std::time::UNIX_EPOCH)
+    .map(|d| d.as_millis() as u64)
+    .unwrap_or(0);
+  let elapsed_ms = current
+    .started_at
+    .elapsed()
+    .map(|d| d.as_millis() as u64)
+    .unwrap_or(0);
+  Ok(serde_json::json!({
+    "running": true,
+    "talker": current.talker_id,
+    "started_at": started_at,
+    "elapsed_ms": elapsed_ms,
+  }))
+}
+
+/// Stdout lines of the current or last build so far (JSON progress lines as sent), oldest first. If more
+/// than BUILD_LOG_LINES were written, the first line says how many earlier ones were dropped. Follow with
+/// `build://progress` for new lines.
+#[tauri::command]
+fn get_build_log(build_log: tauri::State<'_, BuildLog>) -> Result<Vec<String>, String> {
+  let log = build_log.0.lock().map_err(|e| e.to_string())?;
+  Ok(log.snapshot())
+}
+
+/// Kill the `kind` child tracked in `slot` (process tree) and reap it. Returns its talker, or None if none
+/// was running. Taking it out of the slot keeps its monitor from reporting done/error.
+fn kill_job(slot: &Mutex<Option<RunningBuild>>, kind: JobKind) -> Result<Option<String>, String> {
+  let mut running = slot.lock().map_err(|e| e.to_string())?;
+  let Some(mut prev) = running.take() else {
+    return Ok(None);
+  };
+  if !matches!(prev.child.try_wait(), Ok(None)) {
+    return Ok(None);
+  }
+  kill_process_tree(prev.child.id())
+    .map_err(|e| format!("Failed to kill {}: {}", kind.name(), e))?;
+  let _ = prev.child.wait();
+  Ok(Some(prev.talker_id))
+}
+
+/// Resolve the config file the backend should load: `config_path` or DEFAULT_CONFIG_PATH, relative to the
+/// backend cwd. Errors if the file does not exist so a bad pick in settings is reported up front.
+fn resolve_config_path(cwd: &Path, config_path: Option<String>) -> Result<String, String> {
+  let config = config_path
+    .filter(|p| !p.is_empty())
+    .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+  if !cwd.join(&config).is_file() {
+    return Err(format!(
+      "Config file not found: {} (relative to {})",
+      config,
+      cwd.display()
+    ));
+  }
+  Ok(config)
+}
+
+/// Resolve a database path chosen by the user (relative paths are taken from `data_dir`) and make sure it
+/// stays inside `data_dir`, so the backend cannot be pointed at arbitrary files. The file itself may not
+/// exist yet; its directory must.
+fn validate_db_path(data_dir: &Path, path: &str) -> Result<PathBuf, String> {
+  let path = data_dir.join(path.trim());
+  let file_name = path
+    .file_name()
+    .filter(|_| path.extension().is_some_and(|ext| ext == "db"))
+    .ok_or_else(|| format!("Database must be a .db file: {}", path.display()))?;
+  let dir = path
+    .parent()
+    .and_then(|p| p.canonicalize().ok())
+    .ok_or_else(|| format!("Database directory does not exist: {}", path.display()))?;
+  let allowed = data_dir
+    .canonicalize()
+    .map_err(|e| format!("{}: {}", data_dir.display(), e))?;
+  if !dir.starts_with(&allowed) {
+    return Err(format!(
+      "Database must be inside {}: {}",
+      allowed.display(),
+      path.display()
+    ));
+  }
+  Ok(dir.join(file_name))
+}
+
+/// Canonical path of `path` if it is an existing SQLite database we can read and write: not a directory,
+/// starting with the SQLite header, and in a directory that accepts the journal/WAL files.
+fn check_sqlite_file(path: &Path) -> Result<PathBuf, String> {
+  let path = path
+    .canonicalize()
+    .map_err(|e| format!("Database not found: {} ({})", path.display(), e))?;
+  if path.is_dir() {
+    return Err(format!("{} is a directory, not a database file", path.display()));
+  }
+  let mut file = std::fs::OpenOptions::new()
+    .read(true)
+    .write(true)
+    .open(&path)
+    .map_err(|e| format!("Database is not readable and writable: {} ({})", path.display(), e))?;
+  let mut header = [0u8; SQLITE_HEADER.len()];
+  if file.read_exact(&mut header).is_err() || header != *SQLITE_HEADER {
+    return Err(format!("Not a SQLite database: {}", path.display()));
+  }
+  if let Some(dir) = path.parent() {
+    ensure_writable_dir(dir)
+      .map_err(|e| format!("Database directory is not writable: {} ({})", dir.display(), e))?;
+  }
+  Ok(path)
+}
+
+/// The `db_dir` preference from SETTINGS_FILE in the app data dir, if set.
+#[cfg_attr(debug_assertions, allow(dead_code))]
+fn configured_db_dir(app_data: &Path) -> Option<PathBuf> {
+  let raw = std::fs::read_to_string(app_data.join(SETTINGS_FILE)).ok()?;
+  let settings: serde_json::Value = serde_json::from_str(&raw)
+    .map_err(|e| log::warn!("Ignoring malformed {}: {}", SETTINGS_FILE, e))
+    .ok()?;
+  settings
+    .get("db_dir")
+    .and_then(|d| d.as_str())
+    .filter(|d| !d.trim().is_empty())
+    .map(PathBuf::from)
+}
+
+/// Create `dir` if needed and check that a file can be written in it.
+fn ensure_writable_dir(dir: &Path) -> Result<(), String> {
+  std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+  let probe = dir.join(".narrarc-write-test");
+  std::fs::write(&probe, b"").map_err(|e| e.to_string())?;
+  let _ = std::fs::remove_file(&probe);
+  Ok(())
+}
+
+/// Directory that databases must live in: the one holding the default database.
+fn db_data_dir(default_db: &Path) -> PathBuf {
+  default_db
+    .parent()
+    .map(Path::to_path_buf)
+    .unwrap_or_else(|| PathBuf::from("data"))
+}
+
+/// The database a backend without an explicit one opens: the one saved by set_database or
+/// open_database_from_path, else `default_db` (which already honours the db_dir setting).
+fn active_db_path(app: Option<&tauri::AppHandle>, default_db: PathBuf) -> PathBuf {
+  app
+    .and_then(|app| saved_db_path(app, &default_db))
+    .unwrap_or(default_db)
+}
+
+/// The database saved by set_database or open_database_from_path, if it is still valid: inside the data
+/// directory, or an existing SQLite file picked by the user elsewhere.
+fn saved_db_path(app: &tauri::AppHandle, default_db: &Path) -> Option<PathBuf> {
+  let file = app.path().app_config_dir().ok()?.join(ACTIVE_DB_FILE);
+  let saved = std::fs::read_to_string(file).ok()?;
+  let path = validate_db_path(&db_data_dir(default_db), &saved)
+    .or_else(|e| check_sqlite_file(Path::new(saved.trim())).map_err(|_| e));
+  match path {
+    Ok(path) => Some(path),
+    Err(e) => {
+      log::warn!("Ignoring saved database: {}", e);
+      None
+    }
+  }
+}
+
+/// Check config_overrides before it reaches the backend: must be an object whose keys are in
+/// OVERRIDE_SECTIONS, each mapping to an object (or null).
+fn validate_overrides(v: &serde_json::Value) -> Result<(), String> {
+  let sections = v.as_object().ok_or_else(|| {
+    format!(
+      "config_overrides must be a JSON object, got {}",
+      json_kind(v)
+    )
+  })?;
+  for (key, section) in sections {
+    if !OVERRIDE_SECTIONS.contains(&key.as_str()) {
+      return Err(format!(
+        "config_overrides has unknown key \"{}\" (expected one of: {})",
+        key,
+        OVERRIDE_SECTIONS.join(", ")
+      ));
+    }
+    if !(section.is_object() || section.is_null()) {
+      return Err(format!(
+        "config_overrides.{} must be a JSON object, got {}",
+        key,
+        json_kind(section)
+      ));
+    }
+  }
+  Ok(())
+}
+
+/// Reject environment keys outside ALLOWED_BACKEND_ENV.
+fn validate_backend_env(env: &HashMap<String, String>) -> Result<(), String> {
+  for key in env.keys() {
+    if !ALLOWED_BACKEND_ENV.contains(&key.as_str()) {
+      return Err(format!(
+        "backend env var \"{}\" is not allowed (allowed: {})",
+        key,
+        ALLOWED_BACKEND_ENV.join(", ")
+      ));
+    }
+  }
+  Ok(())
+}
+
+pub(crate) fn json_kind(v: &serde_json::Value) -> &'static str {
+  match v {
+    serde_json::Value::Null => "null",
+    serde_json::Value::Bool(_) => "boolean",
+    serde_json::Value::Number(_) => "number",
+    serde_json::Value::String(_) => "string",
+    serde_json::Value::Array(_) => "array",
+    serde_json::Value::Object(_) => "object",
+  }
+}
+
+#[tauri::command]
+fn log_frontend_error(message: String) {
+  eprintln!("[Frontend Error] {}", message);
+}
+
+/// Why the backend failed to start, if it did; the UI shows this instead of waiting forever.
+#[tauri::command]
+fn get_spawn_error(last: tauri::State<'_, LastSpawnError>) -> Option<String> {
+  last.0.lock().ok().and_then(|last| last.as_ref().map(|f| f.message.clone()))
+}
+
+/// get_spawn_error plus structured details ({message, details}), e.g. where the sidecar was looked for and
+/// what was found instead, for the frontend to log with a startup failure report.
+#[tauri::command]
+fn get_spawn_diagnostics(last: tauri::State<'_, LastSpawnError>) -> Option<SpawnFailure> {
+  last.0.lock().ok().and_then(|last| last.clone())
+}
+
+/// Recorded events (oldest first) with seq greater than `since`, or all of them; see emit_recorded.
+#[tauri::command]
+fn get_recent_events(
+  recent: tauri::State<'_, RecentEvents>,
+  since: Option<u64>,
+) -> Vec<serde_json::Value> {
+  let since = since.unwrap_or(0);
+  recent
+    .0
+    .lock()
+    .map(|recorded| {
+      recorded
+        .events
+        .iter()
+        .filter(|e| e.get("seq").and_then(|s| s.as_u64()).unwrap_or(0) > since)
+        .cloned()
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Recent lines of the app's own log (oldest first), in dev and release; shown with get_backend_stderr in
+/// the diagnostics view.
+#[tauri::command]
+fn get_app_log(app_log: tauri::State<'_, AppLog>) -> Vec<String> {
+  app_log.lines()
+}
+
+/// Recent backend stderr lines (oldest first), for a "copy diagnostics" button.
+#[tauri::command]
+fn get_backend_stderr(buffer: tauri::State<'_, BackendStderr>) -> Vec<String> {
+  buffer
+    .0
+    .lock()
+    .map(|lines| lines.iter().cloned().collect())
+    .unwrap_or_default()
+}
+
+#[tauri::command]
+fn get_backend_dir(app: tauri::AppHandle) -> Result<String, String> {
+  let (cwd, _) = get_backend_cwd_and_db(Some(&app))?;
+  Ok(cwd.to_string_lossy().into_owned())
+}
+
+/// Whether `dir` looks like the Python backend.
+#[cfg(debug_assertions)]
+fn is_backend_dir(dir: &Path) -> bool {
+  dir.join("pyproject.toml").exists() || dir.join("src").join("narrative_mirror").exists()
+}
+
+/// Dev mode backend directory: `override_dir` (NARRARC_BACKEND_DIR) if set, else `../backend` or
+/// `../../backend` from `cwd`, else a `backend/pyproject.toml` in `manifest_dir` or up to
+/// BACKEND_SEARCH_DEPTH of its ancestors. The error lists where it looked.
+#[cfg(debug_assertions)]
+fn find_backend_dir(
+  override_dir: Option<PathBuf>,
+  cwd: &Path,
+  manifest_dir: &Path,
+) -> Result<PathBuf, String> {
+  if let Some(dir) = override_dir {
+    if is_backend_dir(&dir) {
+      return Ok(dir.canonicalize().unwrap_or(dir));
+    }
+    return Err(format!(
+      "{} is set to {}, which has no pyproject.toml or src/narrative_mirror",
+      BACKEND_DIR_ENV,
+      dir.display()
+    ));
+  }
+  let mut searched = Vec::new();
+  let near_cwd = ["../backend", "../../backend"].map(|rel| cwd.join(rel));
+  let near_manifest = manifest_dir
+    .ancestors()
+    .take(BACKEND_SEARCH_DEPTH + 1)
+    .map(|dir| dir.join("backend"));
+  for candidate in near_cwd.into_iter().chain(near_manifest) {
+    if is_backend_dir(&candidate) {
+      return Ok(candidate.canonicalize().unwrap_or(candidate));
+    }
+    searched.push(candidate.display().to_string());
+  }
+  Err(format!(
+    "Backend directory not found (looked in {}). Set {} to the directory containing pyproject.toml.",
+    searched.join(", "),
+    BACKEND_DIR_ENV
+  ))
+}
+
+/// Returns (backend_cwd, db_path). In release, ensures app_data dir exists with config.
+/// In dev, fails if no backend directory can be found.
+fn get_backend_cwd_and_db(app: Option<&tauri::AppHandle>) -> Result<(PathBuf, PathBuf), String> {
+  #[cfg(debug_assertions)]
+  {
+    let _ = app;
+    let cwd = std::env::current_dir().unwrap_or_else(|_| Path::new(".").to_path_buf());
+    let path = find_backend_dir(
+      std::env::var_os(BACKEND_DIR_ENV).map(PathBuf::from),
+      &cwd,
+      Path::new(env!("CARGO_MANIFEST_DIR")),
+    )?;
+    let db = path.join("data").join("mirror.db");
+    Ok((path, db))
+  }
+
+  #[cfg(not(debug_assertions))]
+  {
+    let app = app.expect("AppHandle required in release");
+    let app_data = app
+      .path()
+      .app_data_dir()
+      .expect("app_data_dir");
+    let backend_dir = app_data.join("narrarc").join("backend");
+    let data_dir = backend_dir.join("data");
+    let config_path = backend_dir.join("config.yml");
+    let res_dir = app.path().resource_dir().ok();
+    let config_example = res_dir.as_ref().and_then(|r| {
+      let p = r.join("config.yml.example");
+      if p.exists() {
+        Some(p)
+      } else {
+        let p2 = r.join("backend").join("config.yml.example");
+        p2.exists().then_some(p2)
+      }
+    });
+    if let Some(ref ex) = config_example {
+      if ex.exists() && !config_path.exists() {
+        let _ = std::fs::create_dir_all(&backend_dir);
+        let _ = std::fs::copy(ex, &config_path);
+      }
+    }
+    let data_dir = match configured_db_dir(&app_data) {
+      Some(dir) => match ensure_writable_dir(&dir) {
+        Ok(()) => dir,
+        Err(e) => {
+          log::warn!("Ignoring db_dir {}: {}; using {}", dir.display(), e, data_dir.display());
+          data_dir
+        }
+      },
+      None => data_dir,
+    };
+    let _ = std::fs::create_dir_all(&data_dir);
+    Ok((backend_dir, data_dir.join("mirror.db")))
+  }
+}
+
+/// Single request/response: write one JSON line, await the line tagged with its req_id, return parsed value or error from {"type":"error","message":"..."}.
+/// Errors reach the frontend as a BackendError object ({message, code?, details?}) rather than a bare string.
+/// Gives up after timeout_ms (default DEFAULT_REQUEST_TIMEOUT_MS) and poisons the process so it is respawned.
+/// If the pipe broke (the backend died between requests), the request is sent once more to the respawned
+/// backend: by default only for IDEMPOTENT_COMMANDS, or as `retry` says. DB_WRITE_COMMANDS are refused in
+/// read-only mode.
+#[tauri::command]
+async fn backend_request(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  payload: serde_json::Value,
+  timeout_ms: Option<u64>,
+  instance_id: Option<String>,
+  retry: Option<bool>,
+) -> Result<serde_json::Value, BackendError> {
+  ensure_payload_writable(&app, &payload)?;
+  let timeout = Duration::from_millis(timeout_ms.unwrap_or(DEFAULT_REQUEST_TIMEOUT_MS));
+  let instance = backends.get(instance_id.as_deref())?;
+  match request(&instance, payload.clone(), timeout).await {
+    Err(e) if should_retry(&payload, retry, &e, pipe_broken(&instance)) => {
+      log::warn!("Backend pipe failed ({}); retrying once on a fresh process", e);
+      request(&instance, payload, timeout).await
+    }
+    result => result,
+  }
+}
+
+/// Whether the backend process is gone or poisoned, so the next request respawns it.
+fn pipe_broken(instance: &BackendInstance) -> bool {
+  let mut guard = lock_process(&instance.process);
+  guard.poisoned.load(Ordering::SeqCst) || !matches!(guard.child.try_wait(), Ok(None))
+}
+
+/// Retry a failed request at most once, and only when it failed because the process died or its pipe broke
+/// (not a timeout, which already took the full wait, nor an error the backend answered with) and it is safe
+/// to send twice: `retry` if given, else whether its cmd is in IDEMPOTENT_COMMANDS.
+fn should_retry(
+  payload: &serde_json::Value,
+  retry: Option<bool>,
+  error: &BackendError,
+  pipe_broken: bool,
+) -> bool {
+  let safe = retry.unwrap_or_else(|| {
+    payload
+      .get("cmd")
+      .and_then(|c| c.as_str())
+      .is_some_and(|cmd| IDEMPOTENT_COMMANDS.contains(&cmd))
+  });
+  safe && pipe_broken && error.code.as_deref() != Some("timeout")
+}
+
+/// Several independent requests in one round trip (a single `{"cmd":"batch","items":[...]}` line), e.g. the
+/// dashboard's startup calls. Returns one entry per request, in order: {ok: true, value} or
+/// {ok: false, error: BackendError}; a failing item doesn't fail the batch. Streaming queries are rejected,
+/// and in read-only mode so is a batch with any of DB_WRITE_COMMANDS.
+#[tauri::command]
+async fn backend_batch(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  requests: Vec<serde_json::Value>,
+  timeout_ms: Option<u64>,
+  instance_id: Option<String>,
+) -> Result<Vec<serde_json::Value>, String> {
+  if requests.is_empty() {
+    return Ok(Vec::new());
+  }
+  if let Some(i) = requests.iter().position(|r| !r.is_object()) {
+    return Err(format!("batch item {} must be a JSON object", i));
+  }
+  if requests.iter().any(writes_database) {
+    ensure_writable(&app)?;
+  }
+  let count = requests.len();
+  let timeout_ms = timeout_ms.unwrap_or(DEFAULT_REQUEST_TIMEOUT_MS);
+  let instance = backends.get(instance_id.as_deref())?;
+  let response = request(
+    &instance,
+    serde_json::json!({ "cmd": "batch", "items": requests }),
+    Duration::from_millis(timeout_ms),
+  )
+  .await?;
+  let results = match response.get("results").and_then(|r| r.as_array()) {
+    Some(results) if results.len() == count => results,
+    _ => return Err(format!("Unexpected batch response: {}", json_kind(&response))),
+  };
+  Ok(
+    results
+      .iter()
+      .map(|item| {
+        if item.get("type").and_then(|t| t.as_str()) == Some("error") {
+          serde_json::json!({ "ok": false, "error": BackendError::from_error_value(item) })
+        } else {
+          serde_json::json!({ "ok": true, "value": item })
+        }
+      })
+      .collect(),
+  )
+}
+
+/// One request/response round-trip on `instance` (see backend_request).
+async fn request(
+  instance: &BackendInstance,
+  payload: serde_json::Value,
+  timeout: Duration,
+) -> Result<serde_json::Value, BackendError> {
+  let _permit = instance.limiter.acquire().await?;
+  with_timing(payload, |payload| async move {
+    let mut pending = send_request(&instance.queue, payload).await?;
+    let response = await_response(&mut pending, timeout).await;
+    match response {
+      Ok(_) => instance.watchdog.observe(&instance.process, true),
+      Err(ResponseError::Backend(_)) => instance.watchdog.observe(&instance.process, false),
+      Err(_) => {}
+    }
+    response.map_err(|e| {
+      // A timeout means the process is wedged; EOF means it died. Either way poison it so the next request
+      // goes through ensure_alive and respawns (the exit monitor reports the exit itself).
+      if matches!(e, ResponseError::TimedOut(_) | ResponseError::Closed) {
+        let guard = lock_process(&instance.process);
+        if guard.child.id() == pending.pid {
+          guard.poisoned.store(true, Ordering::SeqCst);
+        }
+      }
+      BackendError::from(e)
+    })
+  })
+  .await
+}
+
+/// Make `talker` the current conversation of the instance: the backend loads it ({"cmd":"select_talker"})
+/// and later backend_query_stream calls without a talker use it. Returns the talker's metadata; an unknown
+/// talker is an error and leaves the previous one active.
+#[tauri::command]
+async fn set_active_talker(
+  backends: tauri::State<'_, Backends>,
+  active: tauri::State<'_, ActiveTalkers>,
+  talker: String,
+  instance_id: Option<String>,
+) -> Result<Talker, BackendError> {
+  let instance_id = instance_id.unwrap_or_else(|| DEFAULT_INSTANCE.to_string());
+  let instance = backends.get(Some(&instance_id))?;
+  let value = request(
+    &instance,
+    serde_json::json!({ "cmd": "select_talker", "talker": talker }),
+    Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS),
+  )
+  .await?;
+  let info: Talker = serde_json::from_value(value["talker"].clone())
+    .map_err(|e| format!("Unexpected select_talker response: {}", e))?;
+  active.0.lock().map_err(|e| e.to_string())?.insert(instance_id, talker);
+  Ok(info)
+}
+
+/// Talkers in the backend's database for the talker picker. With `cached: true` the previous result for the
+/// instance is returned without a round-trip, if there is one. An empty database yields an empty list.
+#[tauri::command]
+async fn list_talkers(
+  backends: tauri::State<'_, Backends>,
+  cache: tauri::State<'_, TalkerCache>,
+  cached: Option<bool>,
+  instance_id: Option<String>,
+) -> Result<Vec<Talker>, String> {
+  let instance_id = instance_id.unwrap_or_else(|| DEFAULT_INSTANCE.to_string());
+  if cached.unwrap_or(false) {
+    if let Some(talkers) = cache.0.lock().ok().and_then(|c| c.get(&instance_id).cloned()) {
+      return Ok(talkers);
+    }
+  }
+  let instance = backends.get(Some(&instance_id))?;
+  let value = request(
+    &instance,
+    serde_json::json!({ "cmd": "list_talkers" }),
+    Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS),
+  )
+  .await?;
+  let talkers: Vec<Talker> = if value.is_null() {
+    Vec::new()
+  } else {
+    serde_json::from_value(value).map_err(|e| format!("Unexpected list_talkers response: {}", e))?
+  };
+  if let Ok(mut cache) = cache.0.lock() {
+    cache.insert(instance_id, talkers.clone());
+  }
+  Ok(talkers)
+}
+
+/// Delete everything stored for `talker` ({"cmd":"delete_talker"}) and return the backend's confirmation,
+/// {"status": "deleted", "talker_id", "rows_deleted"}. Refused in read-only mode, while a build or reindex
+/// of the talker runs, and unless the backend is up and answering (see ensure_healthy), so a deletion never
+/// lands on a backend that is being respawned. The talker picker cache and active talker are updated.
+#[tauri::command]
+async fn delete_talker(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  cache: tauri::State<'_, TalkerCache>,
+  active: tauri::State<'_, ActiveTalkers>,
+  talker: String,
+  instance_id: Option<String>,
+) -> Result<serde_json::Value, BackendError> {
+  ensure_writable(&app)?;
+  for kind in [JobKind::Build, JobKind::Reindex] {
+    let Some(slot) = kind.slot(&app) else {
+      continue;
+    };
+    if running_talker(&mut *slot.lock().map_err(|e| e.to_string())?).as_deref() == Some(talker.as_str()) {
+      return Err(BackendError::with_code(
+        "busy",
+        format!("A {} of {} is running; cancel it first", kind.name(), talker),
+      ));
+    }
+  }
+  let instance_id = instance_id.unwrap_or_else(|| DEFAULT_INSTANCE.to_string());
+  let instance = backends.get(Some(&instance_id))?;
+  let process = instance.process.clone();
+  tauri::async_runtime::spawn_blocking(move || ensure_healthy(&mut lock_process(&process)))
+    .await
+    .map_err(|e| e.to_string())??;
+  let deleted = request(
+    &instance,
+    serde_json::json!({ "cmd": "delete_talker", "talker": talker }),
+    Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS),
+  )
+  .await?;
+  if let Ok(mut cache) = cache.0.lock() {
+    if let Some(talkers) = cache.get_mut(&instance_id) {
+      talkers.retain(|t| t.id != talker);
+    }
+  }
+  if let Ok(mut active) = active.0.lock() {
+    if active.get(&instance_id) == Some(&talker) {
+      active.remove(&instance_id);
+    }
+  }
+  Ok(deleted)
+}
+
+/// Fail with code "backend_unhealthy" unless the backend is running, has passed its handshake, isn't
+/// poisoned and its process hasn't exited; request() would otherwise respawn it first.
+fn ensure_healthy(process: &mut BackendProcess) -> Result<(), BackendError> {
+  let problem = if process.state != BackendState::Running {
+    format!("the backend is {}", process.state.name())
+  } else if !matches!(process.child.try_wait(), Ok(None)) {
+    "the backend has exited".to_string()
+  } else if process.poisoned.load(Ordering::SeqCst) {
+    "the backend is about to restart after an error".to_string()
+  } else if !process.ready {
+    "the backend is still starting".to_string()
+  } else {
+    return Ok(());
+  };
+  Err(BackendError::with_code(
+    "backend_unhealthy",
+    format!("Not changing the database: {}", problem),
+  ))
+}
+
+/// Questions answered in this backend session ({"cmd":"history"}), newest first, at most `limit` (default
+/// DEFAULT_HISTORY_LIMIT), for the history sidebar. Empty before the first query and after a restart.
+#[tauri::command]
+async fn get_query_history(
+  backends: tauri::State<'_, Backends>,
+  limit: Option<u32>,
+  instance_id: Option<String>,
+) -> Result<Vec<HistoryItem>, String> {
+  let instance = backends.get(instance_id.as_deref())?;
+  let value = request(
+    &instance,
+    serde_json::json!({ "cmd": "history", "limit": limit.unwrap_or(DEFAULT_HISTORY_LIMIT) }),
+    Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS),
+  )
+  .await?;
+  parse_history(value)
+}
+
+/// The items of a history response; a missing or null list is an empty history.
+fn parse_history(mut value: serde_json::Value) -> Result<Vec<HistoryItem>, String> {
+  match value.get_mut("items").map(serde_json::Value::take) {
+    None | Some(serde_json::Value::Null) => Ok(Vec::new()),
+    Some(items) => serde_json::from_value(items).map_err(|e| format!("Unexpected history response: {}", e)),
+  }
+}
+
+/// Stream query: send the question, emit each progress line to the frontend as it arrives (normalized to a
+/// ProgressEvent, and partial answers as `backend://partial`) and return the typed result. See QueryOptions
+/// for the knobs; with a query_id the query can be stopped via cancel_query.
+#[tauri::command]
+async fn backend_query_stream(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  cancels: tauri::State<'_, QueryCancels>,
+  usage: tauri::State<'_, SessionUsage>,
+  active: tauri::State<'_, ActiveTalkers>,
+  question: String,
+  options: Option<QueryOptions>,
+) -> Result<QueryResult, BackendError> {
+  let options = options.unwrap_or_default();
+  let instance_id = options.instance_id;
+  let talker = match options.talker {
+    Some(talker) => talker,
+    None => active
+      .0
+      .lock()
+      .map_err(|e| e.to_string())?
+      .get(instance_id.as_deref().unwrap_or(DEFAULT_INSTANCE))
+      .cloned()
+      .ok_or_else(|| "No talker given and none is active; call set_active_talker first".to_string())?,
+  };
+  let mut watch = StreamWatch::new(
+    options.stall_ms,
+    options.silence_timeout_ms,
+    options.progress_window_ms,
+  );
+  watch.progress_policy = ProgressPolicy::parse(options.progress_policy.as_deref())?;
+  let (cwd, _) = get_backend_cwd_and_db(Some(&app))?;
+  let config = resolve_config_path(&cwd, options.config_path)?;
+  let mut payload = serde_json::json!({
+    "cmd": "query",
+    "talker": talker,
+    "question": question,
+    "stream": true,
+    "config": config,
+  });
+  if let Some(ref overrides) = options.config_overrides {
+    validate_overrides(overrides)?;
+    payload["config_overrides"] = overrides.clone();
+  }
+  if let Some(seed) = options.seed {
+    payload["seed"] = seed.into();
+  }
+  if options.verbose.unwrap_or(false) {
+    payload["verbose"] = true.into();
+  }
+  if let Some(paths) = options.attachments.filter(|paths| !paths.is_empty()) {
+    let allowed: Vec<PathBuf> = [app.path().home_dir(), app.path().app_data_dir()]
+      .into_iter()
+      .filter_map(Result::ok)
+      .collect();
+    let allow_binary = options.allow_binary_attachments.unwrap_or(false);
+    payload["attachments"] =
+      tauri::async_runtime::spawn_blocking(move || read_attachments(&paths, &allowed, allow_binary))
+        .await
+        .map_err(|e| e.to_string())??
+        .into();
+  }
+  let result = run_stream(&app, &backends, &cancels, payload, options.query_id, instance_id, watch).await?;
+  let result = QueryResult::from_line(result)?;
+  if let Ok(mut stats) = usage.0.lock() {
+    stats.record(result.usage.as_ref());
+  }
+  Ok(result)
+}
+
+/// Read the files attached to a query: [{"name", "path", "content"}] for text files, [{"name", "path",
+/// "binary": true, "size"}] for binary ones when `allow_binary` is set. Each path must be an existing file
+/// inside one of `allowed`, of at most MAX_ATTACHMENT_BYTES, and MAX_ATTACHMENTS_TOTAL_BYTES in all. A file
+/// is binary if it has a NUL byte or isn't UTF-8.
+fn read_attachments(
+  paths: &[String],
+  allowed: &[PathBuf],
+  allow_binary: bool,
+) -> Result<Vec<serde_json::Value>, BackendError> {
+  let bad = |message: String| BackendError::with_code("bad_attachment", message);
+  let allowed: Vec<PathBuf> = allowed.iter().filter_map(|dir| dir.canonicalize().ok()).collect();
+  let mut total = 0;
+  let mut out = Vec::new();
+  for path in paths {
+    let resolved = Path::new(path)
+      .canonicalize()
+      .map_err(|e| bad(format!("Attachment not found: {} ({})", path, e)))?;
+    if !allowed.iter().any(|dir| resolved.starts_with(dir)) {
+      return Err(bad(format!("Attachment is outside the allowed folders: {}", path)));
+    }
+    let size = std::fs::metadata(&resolved)
+      .ok()
+      .filter(|meta| meta.is_file())
+      .ok_or_else(|| bad(format!("Attachment is not a file: {}", path)))?
+      .len();
+    if size > MAX_ATTACHMENT_BYTES {
+      return Err(bad(format!(
+        "Attachment {} is {} bytes; the limit is {}",
+        path, size, MAX_ATTACHMENT_BYTES
+      )));
+    }
+    total += size;
+    if total > MAX_ATTACHMENTS_TOTAL_BYTES {
+      return Err(bad(format!(
+        "Attachments exceed {} bytes together",
+        MAX_ATTACHMENTS_TOTAL_BYTES
+      )));
+    }
+    let bytes = std::fs::read(&resolved).map_err(|e| bad(format!("{}: {}", path, e)))?;
+    let name = resolved.file_name().map(|n| n.to_string_lossy().into_owned());
+    let resolved = resolved.to_string_lossy();
+    let text = Some(bytes).filter(|b| !b.contains(&0)).and_then(|b| String::from_utf8(b).ok());
+    out.push(match text {
+      Some(content) => serde_json::json!({ "name": name, "path": resolved, "content": content }),
+      None if allow_binary => {
+        serde_json::json!({ "name": name, "path": resolved, "binary": true, "size": size })
+      }
+      None => return Err(bad(format!("Attachment is a binary file: {}", path))),
+    });
+  }
+  Ok(out)
+}
+
+/// Any streaming request: `payload` (a JSON object with a "cmd"; `"stream": true` is added) is sent to the
+/// backend, each progress line is emitted as `progress_event` (default `backend://progress`) so different
+/// operations can use their own channel, e.g. `export://progress`, and the first other line is returned as
+/// the result (an error line becomes the error). query_id, stall/silence handling, progress_window_ms and
+/// progress_policy work as in backend_query_stream.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn backend_stream(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  cancels: tauri::State<'_, QueryCancels>,
+  mut payload: serde_json::Value,
+  progress_event: Option<String>,
+  query_id: Option<String>,
+  instance_id: Option<String>,
+  stall_ms: Option<u64>,
+  silence_timeout_ms: Option<u64>,
+  progress_window_ms: Option<u64>,
+  progress_policy: Option<String>,
+) -> Result<serde_json::Value, BackendError> {
+  let Some(obj) = payload.as_object_mut() else {
+    return Err("payload must be a JSON object".to_string().into());
+  };
+  if !obj.get("cmd").is_some_and(|c| c.is_string()) {
+    return Err("payload must have a string \"cmd\"".to_string().into());
+  }
+  obj.insert("stream".to_string(), serde_json::json!(true));
+  let progress_event = progress_event.unwrap_or_else(|| PROGRESS_EVENT.to_string());
+  validate_event_name(&progress_event)?;
+  let mut watch = StreamWatch::new(stall_ms, silence_timeout_ms, progress_window_ms);
+  watch.progress_event = progress_event;
+  watch.progress_policy = ProgressPolicy::parse(progress_policy.as_deref())?;
+  run_stream(&app, &backends, &cancels, payload, query_id, instance_id, watch).await
+}
+
+/// Tauri event names may only contain alphanumerics and `-`, `/`, `:`, `_`; check up front so a bad name
+/// fails the command instead of every emit.
+fn validate_event_name(name: &str) -> Result<(), String> {
+  let valid = !name.is_empty()
+    && name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '/' | ':' | '_'));
+  if valid {
+    Ok(())
+  } else {
+    Err(format!("Invalid event name: {:?}", name))
+  }
+}
+
+/// Shared body of backend_query_stream and backend_stream: register query_id for cancel_query, stream the
+/// request on the chosen instance, then unregister. DB_WRITE_COMMANDS are refused in read-only mode.
+async fn run_stream(
+  app: &tauri::AppHandle,
+  backends: &Backends,
+  cancels: &QueryCancels,
+  payload: serde_json::Value,
+  query_id: Option<String>,
+  instance_id: Option<String>,
+  watch: StreamWatch,
+) -> Result<serde_json::Value, BackendError> {
+  ensure_payload_writable(app, &payload)?;
+  let instance_id = instance_id.unwrap_or_else(|| DEFAULT_INSTANCE.to_string());
+  let instance = backends.get(Some(&instance_id))?;
+  let cancel = Arc::new(Notify::new());
+  if let Some(ref id) = query_id {
+    // The id becomes part of the query's event names.
+    validate_event_name(id).map_err(|_| {
+      BackendError::with_code(
+        "bad_query_id",
+        format!(
+          "Invalid query_id {:?}: use only alphanumerics and -, /, :, _",
+          id
+        ),
+      )
+    })?;
+    cancels
+      .by_id
+      .lock()
+      .map_err(|e| e.to_string())?
+      .insert(id.clone(), cancel.clone());
+  }
+  let result = with_timing(payload, |payload| {
+    stream_request(
+      app,
+      &instance_id,
+      query_id.as_deref(),
+      &instance,
+      payload,
+      StreamCancel {
+        query: &cancel,
+        cancels,
+      },
+      &watch,
+    )
+  })
+  .await;
+  if let Some(ref id) = query_id {
+    if let Ok(mut map) = cancels.by_id.lock() {
+      map.remove(id);
+    }
+  }
+  result
+}
+
+/// Run one backend round-trip and log, at debug level, its cmd and truncated payload, then the duration and
+/// the response type (or error), so slowness can be pinned on the pipe, the agent or the model from the log.
+async fn with_timing<F, Fut>(
+  payload: serde_json::Value,
+  f: F,
+) -> Result<serde_json::Value, BackendError>
+where
+  F: FnOnce(serde_json::Value) -> Fut,
+  Fut: std::future::Future<Output = Result<serde_json::Value, BackendError>>,
+{
+  let cmd = payload
+    .get("cmd")
+    .and_then(|c| c.as_str())
+    .unwrap_or("?")
+    .to_string();
+  log::debug!(
+    "backend -> {} {}",
+    cmd,
+    payload_preview(&payload, LOG_PAYLOAD_CHARS)
+  );
+  let started = std::time::Instant::now();
+  let result = f(payload).await;
+  let elapsed = started.elapsed().as_millis();
+  match &result {
+    Ok(v) => {
+      let kind = v.get("type").and_then(|t| t.as_str()).unwrap_or(json_kind(v));
+      log::debug!("backend <- {} {} in {} ms", cmd, kind, elapsed);
+    }
+    Err(e) => log::debug!("backend <- {} error in {} ms: {}", cmd, elapsed, e),
+  }
+  result
+}
+
+/// truncate_for_log of `payload` as JSON. Serialization stops once enough is written, so logging a
+/// multi-megabyte request doesn't serialize all of it on the async runtime.
+fn payload_preview(payload: &serde_json::Value, max: usize) -> String {
+  struct Capped(Vec<u8>, usize);
+
+  impl std::io::Write for Capped {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+      let room = self.1 - self.0.len();
+      if room == 0 {
+        return Err(std::io::Error::other("preview is full"));
+      }
+      let n = buf.len().min(room);
+      self.0.extend_from_slice(&buf[..n]);
+      Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok(())
+    }
+  }
+
+  // A char takes at most 4 bytes: enough for `max` of them plus one to tell that something was cut.
+  let mut capped = Capped(Vec::new(), (max + 1) * 4);
+  let _ = serde_json::to_writer(&mut capped, payload);
+  truncate_for_log(&String::from_utf8_lossy(&capped.0), max)
+}
+
+/// First `max` characters of `s`, with an ellipsis if anything was cut.
+fn truncate_for_log(s: &str, max: usize) -> String {
+  match s.char_indices().nth(max) {
+    Some((end, _)) => format!("{}…", &s[..end]),
+    None => s.to_string(),
+  }
+}
+
+/// Silence thresholds, progress coalescing and the progress event name for a streaming request; see
+/// backend_query_stream.
+struct StreamWatch {
+  stall_after: Duration,
+  silence_timeout: Duration,
+  /// Progress received within this window is coalesced into one event; zero emits every line.
+  progress_window: Duration,
+  /// Event that progress is emitted as.
+  progress_event: String,
+  progress_policy: ProgressPolicy,
+}
+
+/// What stream_request does with progress and partial answers when more lines are already queued behind
+/// them, i.e. the app is falling behind the backend.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ProgressPolicy {
+  /// Emit every update, however far behind.
+  Block,
+  /// Hold updates back until the queue is drained, then emit them merged (progress::coalesce: the latest
+  /// trace, joined tokens) followed by the latest partial answer.
+  Latest,
+}
+
+impl ProgressPolicy {
+  fn parse(name: Option<&str>) -> Result<Self, String> {
+    match name {
+      None | Some("block") => Ok(ProgressPolicy::Block),
+      Some("latest") => Ok(ProgressPolicy::Latest),
+      Some(other) => Err(format!(
+        "Unknown progress policy {:?} (expected block or latest)",
+        other
+      )),
+    }
+  }
+}
+
+impl StreamWatch {
+  /// Thresholds from the optional command arguments (defaults where absent), emitting on PROGRESS_EVENT.
+  fn new(
+    stall_ms: Option<u64>,
+    silence_timeout_ms: Option<u64>,
+    progress_window_ms: Option<u64>,
+  ) -> Self {
+    StreamWatch {
+      stall_after: Duration::from_millis(stall_ms.unwrap_or(DEFAULT_STREAM_STALL_MS)),
+      silence_timeout: Duration::from_millis(
+        silence_timeout_ms.unwrap_or(DEFAULT_STREAM_SILENCE_TIMEOUT_MS),
+      ),
+      progress_window: Duration::from_millis(progress_window_ms.unwrap_or(0)),
+      progress_event: PROGRESS_EVENT.to_string(),
+      progress_policy: ProgressPolicy::Block,
+    }
+  }
+}
+
+/// How stream_request treats one line of a streaming response; only Error and Final end the stream.
+#[derive(Debug, PartialEq)]
+enum StreamLineKind {
+  Progress,
+  /// A provisional answer, emitted as PARTIAL_EVENT.
+  Partial,
+  Error,
+  /// {"type":"result"} for queries; commands without a streaming mode answer with their usual line.
+  Final,
+}
+
+fn stream_line_kind(line: &serde_json::Value) -> StreamLineKind {
+  match line.get("type").and_then(|t| t.as_str()) {
+    Some("progress") => StreamLineKind::Progress,
+    Some("partial") => StreamLineKind::Partial,
+    Some("error") => StreamLineKind::Error,
+    _ => StreamLineKind::Final,
+  }
+}
+
+/// Tell the backend to stop working on request `req_id`; a failed write is ignored.
+async fn send_cancel(instance: &BackendInstance, req_id: u64) {
+  let state = instance.process.clone();
+  let _ = tauri::async_runtime::spawn_blocking(move || {
+    let mut guard = lock_process(&state);
+    guard.write_line(&serde_json::json!({ "cmd": "cancel", "req_id": req_id }))
+  })
+  .await;
+}
+
+fn shutting_down_error() -> BackendError {
+  BackendError::with_code("cancelled", "cancelled: the app is shutting down")
+}
+
+/// What stops a stream early: cancel_query on its own query_id, or the app shutting down.
+struct StreamCancel<'a> {
+  query: &'a Notify,
+  cancels: &'a QueryCancels,
+}
+
+async fn stream_request(
+  app: &tauri::AppHandle,
+  instance_id: &str,
+  query_id: Option<&str>,
+  instance: &BackendInstance,
+  payload: serde_json::Value,
+  cancel: StreamCancel<'_>,
+  watch: &StreamWatch,
+) -> Result<serde_json::Value, BackendError> {
+  let StreamCancel { query: cancel, cancels } = cancel;
+  // Registered before the flag is checked, so a shutdown in between still wakes this stream.
+  let shutdown = cancels.shutdown.notified();
+  tokio::pin!(shutdown);
+  shutdown.as_mut().enable();
+  if cancels.shutting_down.load(Ordering::SeqCst) {
+    return Err(shutting_down_error());
+  }
+  let _permit = tokio::select! {
+    permit = instance.limiter.acquire() => permit?,
+    _ = cancel.notified() => return Err(BackendError::with_code("cancelled", "cancelled")),
+    _ = &mut shutdown => return Err(shutting_down_error()),
+  };
+  let mut pending = send_request(&instance.queue, payload).await?;
+  let mut last_line = std::time::Instant::now();
+  let mut stalled = false;
+  let mut buffered = BufferedProgress::default();
+  let mut flush_at: Option<std::time::Instant> = None;
+  // Latest partial answer held back while behind (ProgressPolicy::Latest).
+  let mut held_partial: Option<(u64, serde_json::Value)> = None;
+  loop {
+    if flush_at.is_some_and(|at| std::time::Instant::now() >= at) {
+      flush_progress(app, &watch.progress_event, instance_id, query_id, &mut buffered);
+      flush_at = None;
+    }
+    let silent = last_line.elapsed();
+    let wait = if stalled {
+      watch.silence_timeout.saturating_sub(silent)
+    } else {
+      watch
+        .stall_after
+        .min(watch.silence_timeout)
+        .saturating_sub(silent)
+    };
+    let wait = match flush_at {
+      Some(at) => wait.min(at.saturating_duration_since(std::time::Instant::now())),
+      None => wait,
+    };
+    let line = tokio::select! {
+      line = next_stream_line(&mut pending, wait) => match line {
+        Ok(Some(line)) => line,
+        Err(ResponseError::Crashed { .. }) => {
+          // Stdout closed before a result/error line: the backend died mid-query. Poison it so the next
+          // request respawns even if the exit hasn't been reaped yet.
+          let mut exit_code = None;
+          {
+            let mut guard = lock_process(&instance.process);
+            if guard.child.id() == pending.pid {
+              exit_code = guard.child.try_wait().ok().flatten().and_then(|s| s.code());
+              guard.poisoned.store(true, Ordering::SeqCst);
+            }
+          }
+          let error = ResponseError::Crashed { exit_code };
+          log::error!("Backend {}: {}", instance_id, error);
+          flush_progress(app, &watch.progress_event, instance_id, query_id, &mut buffered);
+          return Err(error.into());
+        }
+        Err(e) => return Err(e.into()),
+        Ok(None) => {
+          if flush_at.is_some_and(|at| std::time::Instant::now() >= at) {
+            // Woken for the progress window, not by silence.
+            continue;
+          }
+          let silent = last_line.elapsed();
+          if silent >= watch.silence_timeout {
+            {
+              let guard = lock_process(&instance.process);
+              if guard.child.id() == pending.pid {
+                guard.poisoned.store(true, Ordering::SeqCst);
+              }
+            }
+            return Err(BackendError::with_code(
+              "timeout",
+              format!(
+                "backend produced no output for {} ms; it will be restarted",
+                silent.as_millis()
+              ),
+            ));
+          }
+          stalled = true;
+          let _ = app.emit(
+            "backend://stalled",
+            serde_json::json!({
+              "instance_id": instance_id,
+              "query_id": query_id,
+              "silent_ms": silent.as_millis() as u64,
+            }),
+          );
+          continue;
+        }
+      },
+      _ = cancel.notified() => {
+        send_cancel(instance, pending.req_id).await;
+        return Err(BackendError::with_code("cancelled", "cancelled"));
+      }
+      _ = &mut shutdown => {
+        // Ask the backend to drop the query too, so its own graceful exit isn't held up by it.
+        send_cancel(instance, pending.req_id).await;
+        flush_progress(app, &watch.progress_event, instance_id, query_id, &mut buffered);
+        return Err(shutting_down_error());
+      }
+    };
+    last_line = std::time::Instant::now();
+    stalled = false;
+    let StreamLine { seq, data: v } = line;
+    let kind = stream_line_kind(&v);
+    let behind = watch.progress_policy == ProgressPolicy::Latest && !pending.rx.is_empty();
+    if behind && matches!(kind, StreamLineKind::Progress | StreamLineKind::Partial) {
+      if kind == StreamLineKind::Progress {
+        buffered.events.push(ProgressEvent::from_backend(v));
+        buffered.last_seq = seq;
+      } else {
+        held_partial = Some((seq, v));
+      }
+      continue;
+    }
+    if let Some((partial_seq, partial)) = held_partial.take() {
+      // Caught up: emit what was held back, in the order the backend wrote it.
+      flush_progress(app, &watch.progress_event, instance_id, query_id, &mut buffered);
+      emit_partial(app, instance_id, query_id, partial_seq, &partial);
+    }
+    match kind {
+      StreamLineKind::Progress if watch.progress_window.is_zero() => {
+        flush_progress(app, &watch.progress_event, instance_id, query_id, &mut buffered);
+        let event = ProgressEvent::from_backend(v);
+        emit_progress(app, &watch.progress_event, instance_id, query_id, seq, event);
+      }
+      StreamLineKind::Progress => {
+        buffered.events.push(ProgressEvent::from_backend(v));
+        buffered.last_seq = seq;
+        flush_at.get_or_insert_with(|| std::time::Instant::now() + watch.progress_window);
+      }
+      StreamLineKind::Partial => {
+        // Keep progress ahead of a later draft in the order the backend wrote them.
+        flush_progress(app, &watch.progress_event, instance_id, query_id, &mut buffered);
+        emit_partial(app, instance_id, query_id, seq, &v);
+      }
+      StreamLineKind::Error => {
+        flush_progress(app, &watch.progress_event, instance_id, query_id, &mut buffered);
+        instance.watchdog.observe(&instance.process, false);
+        return Err(BackendError::from_error_value(&v));
+      }
+      StreamLineKind::Final => {
+        flush_progress(app, &watch.progress_event, instance_id, query_id, &mut buffered);
+        instance.watchdog.observe(&instance.process, true);
+        return Ok(v);
+      }
+    }
+  }
+}
+
+/// Emit a {"type":"partial"} line as PARTIAL_EVENT, and on the query's own channel if it has an id.
+fn emit_partial(
+  app: &tauri::AppHandle,
+  instance_id: &str,
+  query_id: Option<&str>,
+  stream_seq: u64,
+  line: &serde_json::Value,
+) {
+  let payload = serde_json::json!({
+    "instance_id": instance_id,
+    "query_id": query_id,
+    "stream_seq": stream_seq,
+    "text": line.get("text").and_then(|t| t.as_str()).unwrap_or_default(),
+  });
+  if let Some(id) = query_id {
+    let _ = app.emit(&query_event(PARTIAL_EVENT, id), &payload);
+  }
+  let _ = app.emit(PARTIAL_EVENT, payload);
+}
+
+/// The channel of one query's `name` events: `<name>/<query_id>`.
+fn query_event(name: &str, query_id: &str) -> String {
+  format!("{}/{}", name, query_id)
+}
+
+/// Progress held back during the current coalescing window.
+#[derive(Default)]
+struct BufferedProgress {
+  events: Vec<ProgressEvent>,
+  /// Stream seq of the newest buffered line.
+  last_seq: u64,
+}
+
+/// Emit one progress update as `name`, tagged with the instance it came from and `stream_seq`, the
+/// position of its (last) line in the request's output. stream_seq only grows within a stream, so the UI
+/// can drop out-of-order or duplicate updates; it is separate from the global `seq` of emit_recorded.
+/// A query with an id also gets the update on its own channel (query_event).
+fn emit_progress(
+  app: &tauri::AppHandle,
+  name: &str,
+  instance_id: &str,
+  query_id: Option<&str>,
+  stream_seq: u64,
+  event: ProgressEvent,
+) {
+  let mut event = serde_json::to_value(event).unwrap_or(serde_json::Value::Null);
+  if let Some(obj) = event.as_object_mut() {
+    obj.insert("instance_id".to_string(), serde_json::json!(instance_id));
+    obj.insert("query_id".to_string(), serde_json::json!(query_id));
+    obj.insert("stream_seq".to_string(), serde_json::json!(stream_seq));
+  }
+  if let Some(id) = query_id {
+    let _ = app.emit(&query_event(name, id), &event);
+  }
+  emit_recorded(app, name, event);
+}
+
+/// Emit the progress buffered during the current window as one coalesced event, if there is any.
+fn flush_progress(
+  app: &tauri::AppHandle,
+  name: &str,
+  instance_id: &str,
+  query_id: Option<&str>,
+  buffered: &mut BufferedProgress,
+) {
+  if let Some(event) = progress::coalesce(std::mem::take(&mut buffered.events)) {
+    emit_progress(app, name, instance_id, query_id, buffered.last_seq, event);
+  }
+}
+
+/// Save the questions answered by the backend this session: the backend renders them as `format` ("md" or
+/// "json", see EXPORT_FORMATS), the user picks a destination in a save dialog and the file is written there.
+/// Returns the chosen path, or None if the dialog was cancelled.
+#[tauri::command]
+async fn export_transcript(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  format: String,
+  instance_id: Option<String>,
+) -> Result<Option<String>, BackendError> {
+  use tauri_plugin_dialog::DialogExt;
+  let Some(&(_, filter_name)) = EXPORT_FORMATS.iter().find(|(ext, _)| *ext == format) else {
+    let supported: Vec<&str> = EXPORT_FORMATS.iter().map(|(ext, _)| *ext).collect();
+    return Err(BackendError::with_code(
+      "unsupported_format",
+      format!(
+        "Unsupported export format: {} (expected one of {})",
+        format,
+        supported.join(", ")
+      ),
+    ));
+  };
+  let instance = backends.get(instance_id.as_deref())?;
+  let export = serde_json::json!({ "cmd": "export", "format": format });
+  let timeout = Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS);
+  let exported = request(&instance, export, timeout).await?;
+  let content = exported
+    .get("content")
+    .and_then(|c| c.as_str())
+    .ok_or_else(|| "Unexpected export response: no content".to_string())?
+    .to_string();
+  tauri::async_runtime::spawn_blocking(move || {
+    let picked = app
+      .dialog()
+      .file()
+      .add_filter(filter_name, &[format.as_str()])
+      .set_file_name(format!("transcript.{}", format))
+      .blocking_save_file();
+    let Some(picked) = picked else {
+      return Ok(None);
+    };
+    let path = picked.into_path().map_err(|e| e.to_string())?;
+    std::fs::write(&path, content)
+      .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok::<_, String>(Some(path.to_string_lossy().into_owned()))
+  })
+  .await
+  .map_err(|e| e.to_string())?
+  .map_err(BackendError::from)
+}
+
+/// Usage totals since the app started: {total_tokens, total_cost, query_count}. Only streaming queries are
+/// counted, and only results that report a `usage` object add tokens or cost.
+#[tauri::command]
+fn get_session_stats(usage: tauri::State<'_, SessionUsage>) -> Result<SessionStats, String> {
+  usage.0.lock().map(|stats| stats.clone()).map_err(|e| e.to_string())
+}
+
+/// Channels of one streaming query, from start_query.
+#[derive(serde::Serialize)]
+struct QueryChannels {
+  query_id: String,
+  progress_event: String,
+  partial_event: String,
+}
+
+/// Reserve a query id before starting a stream, so the caller can listen on the query's own progress and
+/// partial channels first and then pass the id as `query_id` to backend_query_stream (or backend_stream,
+/// whose progress goes to `<progress_event>/<query_id>`). Ids are unique for the app's lifetime.
+#[tauri::command]
+fn start_query() -> QueryChannels {
+  let query_id = format!("q{}", NEXT_QUERY_ID.fetch_add(1, Ordering::Relaxed));
+  QueryChannels {
+    progress_event: query_event(PROGRESS_EVENT, &query_id),
+    partial_event: query_event(PARTIAL_EVENT, &query_id),
+    query_id,
+  }
+}
+
+/// Cancel a streaming query started with this query_id. Returns whether such a query was running.
+#[tauri::command]
+fn cancel_query(cancels: tauri::State<'_, QueryCancels>, query_id: String) -> Result<bool, String> {
+  let map = cancels.by_id.lock().map_err(|e| e.to_string())?;
+  match map.get(&query_id) {
+    Some(cancel) => {
+      cancel.notify_one();
+      Ok(true)
+    }
+    None => Ok(false),
+  }
+}
+
+/// Kill the current backend and spawn a fresh one (e.g. after config.yml changed). Returns the new PID.
+/// `env` replaces the extra environment for this and later spawns; keys must be in ALLOWED_BACKEND_ENV.
+/// The lock is only held by writers, so this waits for in-flight writes; requests awaiting a response
+/// on the old process are aborted with "backend closed the connection (process exited)".
+#[tauri::command]
+async fn restart_backend(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  env: Option<HashMap<String, String>>,
+  instance_id: Option<String>,
+) -> Result<u32, String> {
+  if let Some(ref env) = env {
+    validate_backend_env(env)?;
+  }
+  let state = backends.get(instance_id.as_deref())?.process;
+  tauri::async_runtime::spawn_blocking(move || {
+    let mut guard = lock_process(&state);
+    let process = guard.deref_mut();
+    if let Some(env) = env {
+      process.spawn.env = env;
+    }
+    process.restart_backend_inner(&app, "requested".to_string(), true)?;
+    Ok::<_, String>(process.child.id())
+  })
+  .await
+  .map_err(|e| e.to_string())?
+}
+
+/// Shut the backend down gracefully (see shutdown_backend) and leave it stopped, e.g. while its config is
+/// edited by hand: requests fail with "backend is stopped" instead of respawning it, until start_backend.
+/// Emits `backend://stopped` {instance_id, pid}. Stopping a stopped backend does nothing.
+#[tauri::command]
+async fn stop_backend(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  instance_id: Option<String>,
+) -> Result<(), String> {
+  let state = backends.get(instance_id.as_deref())?.process;
+  tauri::async_runtime::spawn_blocking(move || {
+    let mut guard = lock_process(&state);
+    if guard.state == BackendState::Stopped {
+      return;
+    }
+    shutdown_backend(&mut guard, shutdown_grace(&app, ShutdownReason::Stop));
+    let _ = guard.child.wait();
+    guard.state = BackendState::Stopped;
+    emit_recorded(
+      &app,
+      "backend://stopped",
+      serde_json::json!({ "instance_id": guard.spawn.instance_id, "pid": guard.child.id() }),
+    );
+  })
+  .await
+  .map_err(|e| e.to_string())
+}
+
+/// Start a backend left stopped by stop_backend (or suspended, or whose last spawn failed) with its previous
+/// spawn config, and wait until it is ready. A running backend is left as it is. Returns the PID.
+#[tauri::command]
+async fn start_backend(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  instance_id: Option<String>,
+) -> Result<u32, String> {
+  let state = backends.get(instance_id.as_deref())?.process;
+  tauri::async_runtime::spawn_blocking(move || {
+    let mut guard = lock_process(&state);
+    let process = guard.deref_mut();
+    let alive = matches!(process.child.try_wait(), Ok(None));
+    if process.state != BackendState::Running || !alive {
+      process.restart_backend_inner(&app, "started".to_string(), false)?;
+    }
+    process.wait_ready(Some(&app), READY_TIMEOUT)?;
+    Ok(process.child.id())
+  })
+  .await
+  .map_err(|e| e.to_string())?
+}
+
+/// Pick up edits made to config.yml (or `config_path`) outside the app: check the file is valid YAML, make
+/// it the backend's default config and return its summary (the get_config shape). A backend without live
+/// reload is restarted gracefully with the new config instead. Emits `backend://config_reloaded`
+/// {instance_id, path, config}.
+#[tauri::command]
+async fn reload_config(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  config_path: Option<String>,
+  instance_id: Option<String>,
+) -> Result<serde_json::Value, BackendError> {
+  ensure_writable(&app)?;
+  let (cwd, _) = get_backend_cwd_and_db(Some(&app))?;
+  let config = resolve_config_path(&cwd, config_path)?;
+  let text = std::fs::read_to_string(cwd.join(&config))
+    .map_err(|e| format!("Failed to read {}: {}", config, e))?;
+  serde_yaml::from_str::<serde_yaml::Value>(&text)
+    .map_err(|e| format!("{} is not valid YAML: {}", config, e))?;
+  let instance_id = instance_id.unwrap_or_else(|| DEFAULT_INSTANCE.to_string());
+  let instance = backends.get(Some(&instance_id))?;
+  let timeout = Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS);
+  let reload = serde_json::json!({ "cmd": "reload_config", "path": config });
+  let summary = match request(&instance, reload, timeout).await {
+    Ok(v) => v.get("config").cloned().unwrap_or(serde_json::Value::Null),
+    Err(e) if is_unknown_cmd(&e) => {
+      log::info!("Backend {} cannot reload config live; restarting it", instance_id);
+      let state = instance.process.clone();
+      let (handle, config_arg) = (app.clone(), config.clone());
+      tauri::async_runtime::spawn_blocking(move || {
+        let mut guard = lock_process(&state);
+        let process = guard.deref_mut();
+        process.spawn.config_arg = config_arg;
+        process.restart_backend_inner(&handle, "config reloaded".to_string(), true)
+      })
+      .await
+      .map_err(|e| e.to_string())??;
+      let get_config = serde_json::json!({ "cmd": "get_config", "config": config });
+      request(&instance, get_config, timeout).await?
+    }
+    Err(e) => return Err(e),
+  };
+  emit_recorded(
+    &app,
+    "backend://config_reloaded",
+    serde_json::json!({ "instance_id": instance_id, "path": config, "config": summary }),
+  );
+  Ok(summary)
+}
+
+/// The config the backend loads from config.yml (or `config_path`), as structured JSON for a settings form
+/// ({"cmd":"get_config"}).
+#[tauri::command]
+async fn get_config(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  config_path: Option<String>,
+  instance_id: Option<String>,
+) -> Result<serde_json::Value, BackendError> {
+  let (cwd, _) = get_backend_cwd_and_db(Some(&app))?;
+  let config = resolve_config_path(&cwd, config_path)?;
+  let instance = backends.get(instance_id.as_deref())?;
+  let get_config = serde_json::json!({ "cmd": "get_config", "config": config });
+  request(&instance, get_config, Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS)).await
+}
+
+/// Whether the backend refused a request because it doesn't know its cmd (code "unknown_cmd"), i.e. it
+/// predates the command and the caller should fall back.
+fn is_unknown_cmd(e: &BackendError) -> bool {
+  e.code.as_deref() == Some("unknown_cmd")
+}
+
+/// Save settings-form changes: `patch` is {section: {key: value}} limited to config_file::CONFIG_KEYS (null
+/// removes a key). They are written into config.yml (or `config_path`) keeping its comments and key order,
+/// then pushed to the backend as reload_config does. Returns the new summary.
+#[tauri::command]
+async fn set_config(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  patch: serde_json::Value,
+  config_path: Option<String>,
+  instance_id: Option<String>,
+) -> Result<serde_json::Value, BackendError> {
+  ensure_writable(&app)?;
+  config_file::validate_patch(&patch)?;
+  let (cwd, _) = get_backend_cwd_and_db(Some(&app))?;
+  let config = resolve_config_path(&cwd, config_path)?;
+  let path = cwd.join(&config);
+  let text =
+    std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", config, e))?;
+  let patched = config_file::apply_patch(&text, &patch).map_err(|e| format!("{}: {}", config, e))?;
+  std::fs::write(&path, patched).map_err(|e| format!("Failed to write {}: {}", config, e))?;
+  log::info!("Updated {} from the settings form", config);
+  reload_config(app, backends, Some(config), instance_id).await
+}
+
+/// Check config.yml (or `config_path`) before building or querying ({"cmd":"validate_config"}): returns
+/// {type: "config_validation", path, valid, issues: [{severity, message, key, line}]}. An unreadable file is
+/// reported here without asking the backend; a backend without the command only gets a YAML syntax check.
+#[tauri::command]
+async fn validate_config(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  config_path: Option<String>,
+  instance_id: Option<String>,
+) -> Result<serde_json::Value, BackendError> {
+  let (cwd, _) = get_backend_cwd_and_db(Some(&app))?;
+  let config = resolve_config_path(&cwd, config_path)?;
+  let text = std::fs::read_to_string(cwd.join(&config))
+    .map_err(|e| format!("Failed to read {}: {}", config, e))?;
+  let instance = backends.get(instance_id.as_deref())?;
+  let validate = serde_json::json!({ "cmd": "validate_config", "path": config });
+  match request(&instance, validate, Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS)).await {
+    Err(e) if is_unknown_cmd(&e) => Ok(yaml_syntax_report(&config, &text)),
+    result => result,
+  }
+}
+
+/// Pre-flight check of the model provider credentials ({"cmd":"check_credentials"}): the backend sends a
+/// one-token completion with the configured LLM (plus `config_overrides`, as in a query) and answers
+/// {type: "credentials", ok: true, provider, model, latency_ms}. Failures carry code "auth_failed" (key
+/// rejected), "no_network" (provider unreachable) or "check_failed".
+#[tauri::command]
+async fn check_credentials(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  config_overrides: Option<serde_json::Value>,
+  config_path: Option<String>,
+  instance_id: Option<String>,
+) -> Result<serde_json::Value, BackendError> {
+  if let Some(ref overrides) = config_overrides {
+    validate_overrides(overrides)?;
+  }
+  let (cwd, _) = get_backend_cwd_and_db(Some(&app))?;
+  let config = resolve_config_path(&cwd, config_path)?;
+  let instance = backends.get(instance_id.as_deref())?;
+  let check = serde_json::json!({
+    "cmd": "check_credentials",
+    "config": config,
+    "config_overrides": config_overrides,
+  });
+  request(&instance, check, Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS)).await
+}
+
+/// validate_config's answer from a YAML parse alone, for backends that can't check the schema.
+fn yaml_syntax_report(path: &str, text: &str) -> serde_json::Value {
+  let issues = match serde_yaml::from_str::<serde_yaml::Value>(text) {
+    Ok(_) => Vec::new(),
+    Err(e) => vec![serde_json::json!({
+      "severity": "error",
+      "message": format!("Not valid YAML: {}", e),
+      "key": null,
+      "line": e.location().map(|l| l.line()),
+    })],
+  };
+  serde_json::json!({
+    "type": "config_validation",
+    "path": path,
+    "valid": issues.is_empty(),
+    "issues": issues,
+  })
+}
+
+/// Result of reset_conversation.
+#[derive(serde::Serialize)]
+struct ResetOutcome {
+  /// Whether the backend forgot the conversation in place.
+  reset: bool,
+  talker: Option<String>,
+  /// Answered questions it forgot.
+  cleared: u64,
+  /// The backend cannot reset in place; restart it (restart_backend) to start over.
+  restart_required: bool,
+}
+
+/// "New chat": make the backend forget the conversation with `talker` (every talker without one) without
+/// restarting it ({"cmd":"reset"}). A backend without the command is left alone and the outcome says
+/// `restart_required`, so the frontend can offer restart_backend instead.
+#[tauri::command]
+async fn reset_conversation(
+  backends: tauri::State<'_, Backends>,
+  talker: Option<String>,
+  instance_id: Option<String>,
+) -> Result<ResetOutcome, BackendError> {
+  let instance = backends.get(instance_id.as_deref())?;
+  let reset = serde_json::json!({ "cmd": "reset", "talker": talker });
+  match request(&instance, reset, Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS)).await {
+    Ok(v) => Ok(ResetOutcome {
+      reset: true,
+      talker,
+      cleared: v.get("cleared").and_then(|c| c.as_u64()).unwrap_or(0),
+      restart_required: false,
+    }),
+    Err(e) if is_unknown_cmd(&e) => Ok(ResetOutcome {
+      reset: false,
+      talker,
+      cleared: 0,
+      restart_required: true,
+    }),
+    Err(e) => Err(e),
+  }
+}
+
+/// What the backend supports, for feature detection: {protocol_version, commands, features}. Asked once per
+/// backend process and cached; `refresh: true` asks again.
+#[tauri::command]
+async fn backend_capabilities(
+  backends: tauri::State<'_, Backends>,
+  instance_id: Option<String>,
+  refresh: Option<bool>,
+) -> Result<serde_json::Value, BackendError> {
+  let instance = backends.get(instance_id.as_deref())?;
+  if !refresh.unwrap_or(false) {
+    let cached = lock_process(&instance.process).capabilities.clone();
+    if let Some(capabilities) = cached {
+      return Ok(capabilities);
+    }
+  }
+  let mut capabilities = request(
+    &instance,
+    serde_json::json!({ "cmd": "capabilities" }),
+    Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS),
+  )
+  .await?;
+  if let Some(obj) = capabilities.as_object_mut() {
+    obj.remove("type");
+  }
+  lock_process(&instance.process).capabilities = Some(capabilities.clone());
+  Ok(capabilities)
+}
+
+/// Spawn metadata for diagnostics / issue triage; no round-trip to the backend.
+#[tauri::command]
+async fn get_backend_info(
+  backends: tauri::State<'_, Backends>,
+  instance_id: Option<String>,
+) -> Result<serde_json::Value, String> {
+  let state = backends.get(instance_id.as_deref())?.process;
+  tauri::async_runtime::spawn_blocking(move || {
+    let guard = lock_process(&state);
+    Ok::<_, String>(serde_json::json!({
+      "instance_id": guard.spawn.instance_id,
+      "pid": guard.child.id(),
+      "state": guard.state.name(),
+      "error": match &guard.state {
+        BackendState::Failed(error) => Some(error),
+        _ => None,
+      },
+      "cwd": guard.spawn.cwd.to_string_lossy(),
+      "db_path": guard.spawn.db_arg.to_string_lossy(),
+      "config_path": guard.spawn.config_arg,
+      "db_dir": guard.spawn.db_arg.parent().map(|d| d.to_string_lossy()),
+      "mode": if cfg!(debug_assertions) { "dev" } else { "release" },
+      "target": APP_TARGET,
+    }))
+  })
+  .await
+  .map_err(|e| e.to_string())?
+}
+
+/// One JSON blob for a bug report: app and backend info, the last spawn failure, config.yml with its secrets
+/// redacted, recent backend stderr and app log lines (with those secrets scrubbed) and session stats. Works
+/// with a dead backend too: live fields (pid) are left out and its exit status is given instead.
+#[tauri::command]
+async fn submit_diagnostics(app: tauri::AppHandle) -> Result<serde_json::Value, String> {
+  tauri::async_runtime::spawn_blocking(move || diagnostics(&app))
+    .await
+    .map_err(|e| e.to_string())
+}
+
+fn diagnostics(app: &tauri::AppHandle) -> serde_json::Value {
+  let instances: Vec<BackendInstance> = app
+    .state::<Backends>()
+    .0
+    .lock()
+    .map(|map| map.values().cloned().collect())
+    .unwrap_or_default();
+  let backends: Vec<serde_json::Value> = instances.iter().map(backend_diagnostics).collect();
+  let (config, secrets) = match redacted_config(app) {
+    Ok((config, secrets)) => (config, secrets),
+    Err(e) => (serde_json::json!({ "error": e }), Vec::new()),
+  };
+  let scrub = |lines: Vec<String>| -> Vec<String> {
+    lines
+      .into_iter()
+      .map(|line| {
+        secrets
+          .iter()
+          .fold(line, |line, secret| line.replace(secret.as_str(), ipc_log::REDACTED))
+      })
+      .collect()
+  };
+  let stderr: Vec<String> = app
+    .state::<BackendStderr>()
+    .0
+    .lock()
+    .map(|lines| lines.iter().cloned().collect())
+    .unwrap_or_default();
+  let spawn_error = app.state::<LastSpawnError>().0.lock().ok().and_then(|last| last.clone());
+  let session_stats = app.state::<SessionUsage>().0.lock().ok().map(|stats| stats.clone());
+  serde_json::json!({
+    "app": {
+      "version": app.package_info().version.to_string(),
+      "mode": if cfg!(debug_assertions) { "dev" } else { "release" },
+      "target": APP_TARGET,
+      "os": std::env::consts::OS,
+    },
+    "backends": backends,
+    "spawn_error": spawn_error,
+    "config": config,
+    "backend_stderr": scrub(stderr),
+    "app_log": scrub(app.state::<AppLog>().lines()),
+    "session_stats": session_stats,
+  })
+}
+
+/// get_backend_info for one instance, with `pid` only while the process runs and `exit` once it has exited.
+fn backend_diagnostics(instance: &BackendInstance) -> serde_json::Value {
+  let mut guard = lock_process(&instance.process);
+  let mut info = serde_json::json!({
+    "instance_id": guard.spawn.instance_id,
+    "state": guard.state.name(),
+    "cwd": guard.spawn.cwd.to_string_lossy(),
+    "db_path": guard.spawn.db_arg.to_string_lossy(),
+    "config_path": guard.spawn.config_arg,
+    "source": guard.spawn.source,
+    "transport": if guard.spawn.http { "http" } else { "stdio" },
+  });
+  match guard.child.try_wait() {
+    Ok(None) => info["pid"] = serde_json::json!(guard.child.id()),
+    Ok(Some(status)) => info["exit"] = serde_json::json!(describe_exit(Some(&status))),
+    Err(e) => info["exit"] = serde_json::json!(e.to_string()),
+  }
+  info
+}
+
+/// config.yml of the default backend dir, parsed, with config_file::redact_secrets applied; also returns
+/// the secrets.
+fn redacted_config(app: &tauri::AppHandle) -> Result<(serde_json::Value, Vec<String>), String> {
+  let (cwd, _) = get_backend_cwd_and_db(Some(app))?;
+  let path = cwd.join(DEFAULT_CONFIG_PATH);
+  let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+  let mut config: serde_json::Value =
+    serde_yaml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+  let secrets = config_file::redact_secrets(&mut config);
+  Ok((config, secrets))
+}
+
+/// Load on a backend for a "3 requests pending" readout: {limit, in_flight, queued}, where queued counts
+/// requests and streaming queries waiting for one of the `limit` slots.
+#[tauri::command]
+fn backend_queue_depth(
+  backends: tauri::State<'_, Backends>,
+  instance_id: Option<String>,
+) -> Result<serde_json::Value, String> {
+  Ok(backends.get(instance_id.as_deref())?.limiter.depth())
+}
+
+/// Lightweight liveness probe for the UI: ping the backend without respawning it.
+/// Returns {alive: true, latency_ms, pid}, or {alive: false, exit_code} if the process has exited.
+#[tauri::command]
+async fn backend_health(
+  backends: tauri::State<'_, Backends>,
+  instance_id: Option<String>,
+) -> Result<serde_json::Value, String> {
+  let state = backends.get(instance_id.as_deref())?.process;
+  let started = std::time::Instant::now();
+  let probe = tauri::async_runtime::spawn_blocking(move || {
+    let mut guard = lock_process(&state);
+    let process = guard.deref_mut();
+    if let Ok(Some(status)) = process.child.try_wait() {
+      return Ok::<_, String>(Err(status));
+    }
+    process
+      .write_request(serde_json::json!({ "cmd": "ping" }))
+      .map(Ok)
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+  let mut pending = match probe {
+    Ok(pending) => pending,
+    Err(status) => {
+      return Ok(serde_json::json!({ "alive": false, "exit_code": status.code() }));
+    }
+  };
+  match tokio::time::timeout(HEALTH_TIMEOUT, pending.rx.recv()).await {
+    Ok(Some(_)) => Ok(serde_json::json!({
+      "alive": true,
+      "latency_ms": started.elapsed().as_millis() as u64,
+      "pid": pending.pid,
+    })),
+    Ok(None) => Ok(serde_json::json!({ "alive": false, "pid": pending.pid })),
+    Err(_) => Ok(serde_json::json!({
+      "alive": false,
+      "pid": pending.pid,
+      "error": format!("no response within {} ms", HEALTH_TIMEOUT.as_millis()),
+    })),
+  }
+}
+
+/// Switch the default backend to another database under the data directory: the backend is restarted with
+/// the new --db (it cannot reopen a database in place) and the choice is saved for the next launch.
+/// Returns the resolved database path.
+#[tauri::command]
+async fn set_database(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  path: String,
+) -> Result<String, String> {
+  ensure_writable(&app)?;
+  let (_, default_db) = get_backend_cwd_and_db(Some(&app))?;
+  let db_path = validate_db_path(&db_data_dir(&default_db), &path)?;
+  switch_database(app, &backends, db_path).await
+}
+
+/// Switch the default backend to a database the user picked in an open-file dialog, which may be anywhere:
+/// the path must be an existing, readable and writable SQLite file (see check_sqlite_file). Otherwise as
+/// set_database. Returns the canonical path.
+#[tauri::command]
+async fn open_database_from_path(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  path: String,
+) -> Result<String, String> {
+  ensure_writable(&app)?;
+  let db_path = check_sqlite_file(Path::new(path.trim()))?;
+  switch_database(app, &backends, db_path).await
+}
+
+/// `db_path` as it is written to ACTIVE_DB_FILE. saved_db_path reads that file back as text, so a path that
+/// is not valid UTF-8 is refused here rather than saved mangled and ignored on the next launch.
+fn saved_db_text(db_path: &Path) -> Result<&str, String> {
+  db_path.to_str().ok_or_else(|| {
+    format!(
+      "Database path is not valid UTF-8 and cannot be saved: {}",
+      db_path.display()
+    )
+  })
+}
+
+/// Save `db_path` as the active database and restart the default backend on it.
+async fn switch_database(
+  app: tauri::AppHandle,
+  backends: &Backends,
+  db_path: PathBuf,
+) -> Result<String, String> {
+  let config_dir = app.path().app_config_dir().map_err(|e| e.to_string())?;
+  std::fs::create_dir_all(&config_dir).map_err(|e| e.to_string())?;
+  let display = saved_db_text(&db_path)?.to_string();
+  std::fs::write(config_dir.join(ACTIVE_DB_FILE), &display).map_err(|e| e.to_string())?;
+  // Without a running default backend (spawn failed) the saved path is picked up on the next launch.
+  let Ok(instance) = backends.get(None) else {
+    return Ok(display);
+  };
+  let db_arg = db_path;
+  tauri::async_runtime::spawn_blocking(move || {
+    let mut guard = lock_process(&instance.process);
+    let process = guard.deref_mut();
+    process.spawn.switch_db(db_arg);
+    process.restart_backend_inner(&app, "database changed".to_string(), true)
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+  Ok(display)
+}
+
+/// Memory and CPU of the backend for a diagnostics readout: {pid, rss_bytes, cpu_percent}.
+/// Errors if the process has exited.
+#[tauri::command]
+async fn backend_resource_usage(
+  backends: tauri::State<'_, Backends>,
+  instance_id: Option<String>,
+) -> Result<serde_json::Value, String> {
+  let state = backends.get(instance_id.as_deref())?.process;
+  tauri::async_runtime::spawn_blocking(move || {
+    let pid = {
+      let mut guard = lock_process(&state);
+      if let Ok(Some(status)) = guard.child.try_wait() {
+        return Err(format!("backend process has exited ({})", status));
+      }
+      guard.child.id()
+    };
+    let usage = resource_usage(pid)?;
+    Ok(serde_json::json!({
+      "pid": pid,
+      "rss_bytes": usage.rss_bytes,
+      "cpu_percent": usage.cpu_percent,
+    }))
+  })
+  .await
+  .map_err(|e| e.to_string())?
+}
+
+/// Spawn an additional backend under `instance_id` (optionally on its own database) and wait until it is
+/// ready. Target it by passing the same instance_id to backend_request / backend_query_stream. Returns the PID.
+#[tauri::command]
+async fn spawn_named_backend(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  instance_id: String,
+  db_path: Option<String>,
+) -> Result<u32, String> {
+  ensure_writable(&app)?;
+  if instance_id.trim().is_empty() {
+    return Err("instance_id must not be empty".to_string());
+  }
+  if backends.get(Some(&instance_id)).is_ok() {
+    return Err(format!("Backend instance {} already exists", instance_id));
+  }
+  let db_path = match db_path {
+    Some(path) => {
+      let (_, default_db) = get_backend_cwd_and_db(Some(&app))?;
+      Some(validate_db_path(&db_data_dir(&default_db), &path)?)
+    }
+    None => None,
+  };
+  let handle = app.clone();
+  let id = instance_id.clone();
+  let mut process = tauri::async_runtime::spawn_blocking(move || {
+    let mut process = spawn_backend_process(Some(&handle), &id, db_path)?;
+    if let Err(e) = process.wait_ready(Some(&handle), READY_TIMEOUT) {
+      shutdown_backend(&mut process, Duration::ZERO);
+      return Err(e);
+    }
+    Ok::<_, String>(process)
+  })
+  .await
+  .map_err(|e| e.to_string())??;
+  let pid = process.child.id();
+  let mut map = backends.0.lock().map_err(|e| e.to_string())?;
+  if map.contains_key(&instance_id) {
+    shutdown_backend(&mut process, Duration::ZERO);
+    return Err(format!("Backend instance {} already exists", instance_id));
+  }
+  map.insert(instance_id, start_instance(&app, process));
+  Ok(pid)
+}
+
+/// Store (or with None clear) the directory release builds keep the database in; takes effect on the next
+/// launch. The directory must be writable. Returns the stored path.
+#[tauri::command]
+fn set_db_dir(app: tauri::AppHandle, path: Option<String>) -> Result<Option<String>, String> {
+  ensure_writable(&app)?;
+  let app_data = app.path().app_data_dir().map_err(|e| e.to_string())?;
+  let mut settings = load_settings(&app_data);
+  let path = path.filter(|p| !p.trim().is_empty());
+  match path {
+    Some(ref dir) => {
+      ensure_writable_dir(Path::new(dir))?;
+      settings["db_dir"] = serde_json::json!(dir);
+    }
+    None => {
+      if let Some(obj) = settings.as_object_mut() {
+        obj.remove("db_dir");
+      }
+    }
+  }
+  save_settings(&app_data, &settings)?;
+  Ok(path)
+}
+
+/// Store (or with None clear) the number of parallel workers builds use; see build_jobs. Returns the
+/// value builds will now get, after clamping to BUILD_JOBS_RANGE.
+#[tauri::command]
+fn set_build_jobs(app: tauri::AppHandle, jobs: Option<u32>) -> Result<u32, String> {
+  let app_data = app.path().app_data_dir().map_err(|e| e.to_string())?;
+  let mut settings = load_settings(&app_data);
+  match jobs {
+    Some(jobs) => settings["build_jobs"] = serde_json::json!(jobs),
+    None => {
+      if let Some(obj) = settings.as_object_mut() {
+        obj.remove("build_jobs");
+      }
+    }
+  }
+  save_settings(&app_data, &settings)?;
+  Ok(build_jobs(&app, None))
+}
+
+/// SETTINGS_FILE in `app_data` as a JSON object; empty if missing or malformed.
+fn load_settings(app_data: &Path) -> serde_json::Value {
+  std::fs::read_to_string(app_data.join(SETTINGS_FILE))
+    .ok()
+    .and_then(|raw| serde_json::from_str::<serde_json::Value>(&raw).ok())
+    .filter(|v| v.is_object())
+    .unwrap_or_else(|| serde_json::json!({}))
+}
+
+fn save_settings(app_data: &Path, settings: &serde_json::Value) -> Result<(), String> {
+  std::fs::create_dir_all(app_data).map_err(|e| e.to_string())?;
+  std::fs::write(app_data.join(SETTINGS_FILE), settings.to_string()).map_err(|e| e.to_string())
+}
+
+/// Store (or with None clear) the backend's stderr verbosity, one of LOG_LEVELS. Builds use it from the next
+/// one on; running backends pick it up when they are next restarted. Returns the level now in effect.
+#[tauri::command]
+fn set_log_level(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  level: Option<String>,
+) -> Result<String, String> {
+  if let Some(ref level) = level {
+    validate_log_level(level)?;
+  }
+  let app_data = app.path().app_data_dir().map_err(|e| e.to_string())?;
+  let mut settings = load_settings(&app_data);
+  match level {
+    Some(level) => settings["log_level"] = serde_json::json!(level),
+    None => {
+      if let Some(obj) = settings.as_object_mut() {
+        obj.remove("log_level");
+      }
+    }
+  }
+  save_settings(&app_data, &settings)?;
+  let level = log_level(&app);
+  let instances: Vec<BackendInstance> =
+    backends.0.lock().map_err(|e| e.to_string())?.values().cloned().collect();
+  for instance in instances {
+    lock_process(&instance.process).spawn.log_level = level.clone();
+  }
+  Ok(level)
+}
+
+/// The `log_level` setting, or DEFAULT_LOG_LEVEL if unset or invalid.
+#[tauri::command]
+fn get_log_level(app: tauri::AppHandle) -> String {
+  log_level(&app)
+}
+
+fn log_level(app: &tauri::AppHandle) -> String {
+  app
+    .path()
+    .app_data_dir()
+    .ok()
+    .and_then(|dir| load_settings(&dir).get("log_level").and_then(|l| l.as_str()).map(str::to_string))
+    .filter(|level| validate_log_level(level).is_ok())
+    .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string())
+}
+
+/// Store (or with None clear) how requests reach the backend, one of BACKEND_TRANSPORTS. Running backends
+/// switch when they are next restarted. Returns the transport now in effect.
+#[tauri::command]
+fn set_backend_transport(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  transport: Option<String>,
+) -> Result<String, String> {
+  if let Some(ref transport) = transport {
+    validate_backend_transport(transport)?;
+  }
+  let app_data = app.path().app_data_dir().map_err(|e| e.to_string())?;
+  let mut settings = load_settings(&app_data);
+  match transport {
+    Some(transport) => settings["backend_transport"] = serde_json::json!(transport),
+    None => {
+      if let Some(obj) = settings.as_object_mut() {
+        obj.remove("backend_transport");
+      }
+    }
+  }
+  save_settings(&app_data, &settings)?;
+  let transport = backend_transport(&app);
+  let instances: Vec<BackendInstance> =
+    backends.0.lock().map_err(|e| e.to_string())?.values().cloned().collect();
+  for instance in instances {
+    lock_process(&instance.process).spawn.http = transport == "http";
+  }
+  Ok(transport)
+}
+
+/// The `backend_transport` setting, or DEFAULT_BACKEND_TRANSPORT if unset or invalid.
+#[tauri::command]
+fn get_backend_transport(app: tauri::AppHandle) -> String {
+  backend_transport(&app)
+}
+
+fn backend_transport(app: &tauri::AppHandle) -> String {
+  app
+    .path()
+    .app_data_dir()
+    .ok()
+    .and_then(|dir| {
+      load_settings(&dir)
+        .get("backend_transport")
+        .and_then(|t| t.as_str())
+        .map(str::to_string)
+    })
+    .filter(|transport| validate_backend_transport(transport).is_ok())
+    .unwrap_or_else(|| DEFAULT_BACKEND_TRANSPORT.to_string())
+}
+
+fn validate_backend_transport(transport: &str) -> Result<(), String> {
+  if BACKEND_TRANSPORTS.contains(&transport) {
+    Ok(())
+  } else {
+    Err(format!(
+      "Unknown backend transport {:?} (expected one of {})",
+      transport,
+      BACKEND_TRANSPORTS.join(", ")
+    ))
+  }
+}
+
+/// Store where the backend comes from (see BackendSource); "bundled" clears the setting. An external command
+/// and its cwd must exist. Running backends switch when they are next (re)spawned. Returns the source now in
+/// effect.
+#[tauri::command]
+fn set_backend_source(
+  app: tauri::AppHandle,
+  backends: tauri::State<'_, Backends>,
+  source: BackendSource,
+) -> Result<BackendSource, String> {
+  ensure_writable(&app)?;
+  validate_backend_source(&source)?;
+  let app_data = app.path().app_data_dir().map_err(|e| e.to_string())?;
+  let mut settings = load_settings(&app_data);
+  match source {
+    BackendSource::Bundled => {
+      if let Some(obj) = settings.as_object_mut() {
+        obj.remove("backend_source");
+      }
+    }
+    ref external => {
+      settings["backend_source"] = serde_json::to_value(external).map_err(|e| e.to_string())?
+    }
+  }
+  save_settings(&app_data, &settings)?;
+  let source = backend_source(&app);
+  let instances: Vec<BackendInstance> =
+    backends.0.lock().map_err(|e| e.to_string())?.values().cloned().collect();
+  for instance in instances {
+    lock_process(&instance.process).spawn.source = source.clone();
+  }
+  Ok(source)
+}
+
+/// The `backend_source` setting, or BackendSource::Bundled if unset or malformed.
+#[tauri::command]
+fn get_backend_source(app: tauri::AppHandle) -> BackendSource {
+  backend_source(&app)
+}
+
+fn backend_source(app: &tauri::AppHandle) -> BackendSource {
+  app
+    .path()
+    .app_data_dir()
+    .ok()
+    .and_then(|dir| {
+      let source = load_settings(&dir).get("backend_source")?.clone();
+      serde_json::from_value(source).ok()
+    })
+    .unwrap_or_default()
+}
+
+fn validate_backend_source(source: &BackendSource) -> Result<(), String> {
+  match source {
+    BackendSource::Bundled => Ok(()),
+    BackendSource::External { cmd, cwd } => validate_external_backend(cmd, cwd),
+  }
+}
+
+/// `cwd` must be a directory and `cmd[0]` a file: relative to `cwd` when it has a directory part, otherwise
+/// found on PATH.
+fn validate_external_backend(cmd: &[String], cwd: &Path) -> Result<(), String> {
+  let program = cmd
+    .first()
+    .filter(|program| !program.is_empty())
+    .ok_or("External backend command is empty")?;
+  if !cwd.is_dir() {
+    return Err(format!("External backend cwd {} is not a directory", cwd.display()));
+  }
+  let path = Path::new(program);
+  let found = if path.components().count() > 1 {
+    cwd.join(path).is_file()
+  } else {
+    let suffixes: &[&str] = if cfg!(windows) { &["", ".exe", ".cmd", ".bat"] } else { &[""] };
+    std::env::var_os("PATH").is_some_and(|paths| {
+      std::env::split_paths(&paths)
+        .any(|dir| suffixes.iter().any(|suffix| dir.join(format!("{}{}", program, suffix)).is_file()))
+    })
+  };
+  if found {
+    Ok(())
+  } else {
+    Err(format!("External backend command {} not found", program))
   }
+}
 
-  #[cfg(not(debug_assertions))]
-  {
-    let resource_dir = app
-      .path()
-      .resource_dir()
-      .map_err(|e| format!("resource_dir: {}", e))?;
-    let target = env!("APP_TARGET");
-    let sidecar_name = format!(
-      "backend-{}{}",
-      target,
-      if cfg!(windows) { ".exe" } else { "" }
-    );
-    let sidecar_path = resource_dir
-      .join("bin")
-      .join("backend")
-      .join(&sidecar_name);
-    let mut args: Vec<&str> = vec![
-      "--db",
-      "data/mirror.db",
-      "build",
-      "--talker",
-      &talker_id,
-      "--config",
-      "config.yml",
-      "--debug",
-    ];
-    if let Some(ref overrides) = config_overrides {
-      if !overrides.is_empty() {
-        args = vec![
-          "--db",
-          "data/mirror.db",
-          "build",
-          "--talker",
-          &talker_id,
-          "--config",
-          "config.yml",
-          "--config-overrides",
-          overrides,
-          "--debug",
-        ];
-      }
-    }
-    let _child = Command::new(&sidecar_path)
-      .args(args)
-      .current_dir(&cwd)
-      .stdin(Stdio::null())
-      .stdout(Stdio::inherit())
-      .stderr(Stdio::inherit())
-      .spawn()
-      .map_err(|e| format!("Failed to spawn sidecar build: {}", e))?;
+fn validate_log_level(level: &str) -> Result<(), String> {
+  if LOG_LEVELS.contains(&level) {
+    Ok(())
+  } else {
+    Err(format!("Unknown log level {:?} (expected one of {})", level, LOG_LEVELS.join(", ")))
   }
-  Ok(())
 }
 
-#[tauri::command]
-fn log_frontend_error(message: String) {
-  eprintln!("[Frontend Error] {}", message);
+/// Parallel workers for a build (`--jobs`): `requested`, else the `build_jobs` setting, else JOBS_ENV, else
+/// the number of CPUs; clamped to BUILD_JOBS_RANGE.
+fn build_jobs(app: &tauri::AppHandle, requested: Option<u32>) -> u32 {
+  let setting = app
+    .path()
+    .app_data_dir()
+    .ok()
+    .and_then(|dir| load_settings(&dir).get("build_jobs").and_then(|j| j.as_u64()));
+  resolve_build_jobs(requested, setting, std::env::var(JOBS_ENV).ok())
 }
 
-#[tauri::command]
-fn get_backend_dir(app: tauri::AppHandle) -> String {
-  let (cwd, _) = get_backend_cwd_and_db(Some(&app));
-  cwd.to_string_lossy().into_owned()
+fn resolve_build_jobs(requested: Option<u32>, setting: Option<u64>, env: Option<String>) -> u32 {
+  let jobs = requested
+    .map(u64::from)
+    .or(setting)
+    .or_else(|| env.and_then(|v| v.trim().parse().ok()))
+    .unwrap_or_else(|| {
+      std::thread::available_parallelism()
+        .map(|n| n.get() as u64)
+        .unwrap_or(1)
+    });
+  let (min, max) = BUILD_JOBS_RANGE.into_inner();
+  jobs.clamp(u64::from(min), u64::from(max)) as u32
 }
 
-/// Returns (backend_cwd, db_path_for_args). In release, ensures app_data dir exists with config.
-fn get_backend_cwd_and_db(app: Option<&tauri::AppHandle>) -> (PathBuf, String) {
-  #[cfg(debug_assertions)]
-  {
-    use std::path::Path;
-    let cwd = std::env::current_dir().unwrap_or_else(|_| Path::new(".").to_path_buf());
-    for rel in ["../backend", "../../backend"] {
-      let p = cwd.join(rel);
-      if p.join("pyproject.toml").exists() || p.join("src").join("narrative_mirror").exists() {
-        let path = p.canonicalize().unwrap_or(p);
-        let db = path.join("data").join("mirror.db");
-        return (
-          path,
-          db.to_str().unwrap_or("data/mirror.db").to_string(),
-        );
-      }
+/// Turn the IPC transcript (every line to/from the backend, secrets in config_overrides redacted) on or off.
+/// Off at every launch. Returns the transcript path.
+#[tauri::command]
+fn set_ipc_logging(app: tauri::AppHandle, enabled: bool) -> Result<String, String> {
+  let path = app
+    .path()
+    .app_data_dir()
+    .map_err(|e| e.to_string())?
+    .join(IPC_LOG_FILE);
+  if enabled {
+    if !ipc_log::is_enabled() {
+      ipc_log::enable(&path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
     }
-    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
-    let path = manifest
-      .parent()
-      .and_then(|p| p.parent())
-      .map(|p| p.join("backend"))
-      .unwrap_or_else(|| manifest.join("../../backend"));
-    let db = path.join("data").join("mirror.db");
-    (
-      path,
-      db.to_str().unwrap_or("data/mirror.db").to_string(),
-    )
+  } else {
+    ipc_log::disable();
   }
+  Ok(path.to_string_lossy().into_owned())
+}
 
-  #[cfg(not(debug_assertions))]
-  {
-    let app = app.expect("AppHandle required in release");
-    let app_data = app
-      .path()
-      .app_data_dir()
-      .expect("app_data_dir");
-    let backend_dir = app_data.join("narrarc").join("backend");
-    let data_dir = backend_dir.join("data");
-    let config_path = backend_dir.join("config.yml");
-    let res_dir = app.path().resource_dir().ok();
-    let config_example = res_dir.as_ref().and_then(|r| {
-      let p = r.join("config.yml.example");
-      if p.exists() {
-        Some(p)
-      } else {
-        let p2 = r.join("backend").join("config.yml.example");
-        p2.exists().then_some(p2)
-      }
-    });
-    if let Some(ref ex) = config_example {
-      if ex.exists() && !config_path.exists() {
-        let _ = std::fs::create_dir_all(&backend_dir);
-        let _ = std::fs::copy(ex, &config_path);
-      }
-    }
-    let _ = std::fs::create_dir_all(&data_dir);
-    let db_path = data_dir.join("mirror.db");
-    (
-      backend_dir,
-      db_path.to_str().unwrap_or("data/mirror.db").to_string(),
-    )
-  }
+/// Whether a warmup runs after the backend starts.
+#[tauri::command]
+fn get_warmup_enabled(app: tauri::AppHandle) -> bool {
+  warmup_enabled(&app)
 }
 
-/// Single request/response: write one JSON line, read one line, return parsed value or error from {"type":"error","message":"..."}.
+/// Turn the startup warmup on or off; takes effect on the next launch.
 #[tauri::command]
-async fn backend_request(
-  state: tauri::State<'_, Arc<Mutex<BackendProcess>>>,
-  payload: serde_json::Value,
-) -> Result<serde_json::Value, String> {
-  let request = serde_json::to_string(&payload).map_err(|e| e.to_string())?;
-  let state = state.inner().clone();
-  let line = tauri::async_runtime::spawn_blocking(move || {
-    let mut guard = state.lock().map_err(|e| e.to_string())?;
-    let process = guard.deref_mut();
-    let stdin = process
-      .stdin
-      .as_mut()
-      .ok_or("backend process stdin gone")?;
-    writeln!(stdin, "{}", request).map_err(|e| e.to_string())?;
-    stdin.flush().map_err(|e| e.to_string())?;
-    let stdout = process
-      .stdout
-      .as_mut()
-      .ok_or("backend process stdout gone")?;
-    let mut line = String::new();
-    stdout.read_line(&mut line).map_err(|e| e.to_string())?;
-    Ok::<_, String>(line)
-  })
-  .await
-  .map_err(|e| e.to_string())??;
-  let value: serde_json::Value =
-    serde_json::from_str(line.trim()).map_err(|e| format!("backend invalid JSON: {}", e))?;
-  if let Some(msg) = value.get("type").and_then(|t| t.as_str()) {
-    if msg == "error" {
-      let message = value
-        .get("message")
-        .and_then(|m| m.as_str())
-        .unwrap_or("unknown error");
-      return Err(message.to_string());
+fn set_warmup_enabled(app: tauri::AppHandle, enabled: bool) -> Result<(), String> {
+  let config_dir = app.path().app_config_dir().map_err(|e| e.to_string())?;
+  let marker = config_dir.join(WARMUP_DISABLED_FILE);
+  if enabled {
+    match std::fs::remove_file(&marker) {
+      Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+      _ => Ok(()),
     }
+  } else {
+    std::fs::create_dir_all(&config_dir).map_err(|e| e.to_string())?;
+    std::fs::write(&marker, "").map_err(|e| e.to_string())
   }
-  Ok(value)
 }
 
-/// Stream query: write request then read stdout line-by-line; emit each progress line to frontend
-/// in real time (so agent steps appear incrementally), then return the result line.
+/// Shut down and forget a backend started with spawn_named_backend, releasing its database lock unless
+/// another instance runs on the same database. Returns whether it existed. The default instance cannot be
+/// dropped.
 #[tauri::command]
-async fn backend_query_stream(
+async fn drop_named_backend(
   app: tauri::AppHandle,
-  state: tauri::State<'_, Arc<Mutex<BackendProcess>>>,
-  talker: String,
-  question: String,
-  config_overrides: Option<serde_json::Value>,
-) -> Result<serde_json::Value, String> {
-  let mut payload = serde_json::json!({
-    "cmd": "query",
-    "talker": talker,
-    "question": question,
-    "stream": true,
-    "config": "config.yml",
-  });
-  if let Some(ref overrides) = config_overrides {
-    payload["config_overrides"] = overrides.clone();
+  backends: tauri::State<'_, Backends>,
+  instance_id: String,
+) -> Result<bool, String> {
+  if instance_id == DEFAULT_INSTANCE {
+    return Err("The default backend instance cannot be dropped".to_string());
   }
-  let request = serde_json::to_string(&payload).map_err(|e| e.to_string())?;
-  let (tx, mut rx) = tokio::sync::mpsc::channel::<String>(64);
-  let result_cell = Arc::new(Mutex::new(None::<serde_json::Value>));
-  let error_cell = Arc::new(Mutex::new(None::<String>));
-  let result_cell_r = result_cell.clone();
-  let error_cell_r = error_cell.clone();
-  let app_handle = app.clone();
-
-  let recv_handle = tauri::async_runtime::spawn(async move {
-    while let Some(line) = rx.recv().await {
-      let trimmed = line.trim();
-      if trimmed.is_empty() {
-        continue;
-      }
-      if let Ok(v) = serde_json::from_str::<serde_json::Value>(trimmed) {
-        match v.get("type").and_then(|t| t.as_str()) {
-          Some("progress") => {
-            let _ = app_handle.emit("backend://progress", &v);
-          }
-          Some("result") => {
-            if let Ok(mut g) = result_cell_r.lock() {
-              *g = Some(v);
-            }
-            break;
-          }
-          Some("error") => {
-            if let Ok(mut g) = error_cell_r.lock() {
-              *g = v
-                .get("message")
-                .and_then(|m| m.as_str())
-                .map(|s| s.to_string());
-            }
-            break;
-          }
-          _ => {}
-        }
-      }
-    }
-  });
-
-  let tx_block = tx.clone();
-  let state = state.inner().clone();
+  let (removed, remaining) = {
+    let mut map = backends.0.lock().map_err(|e| e.to_string())?;
+    let removed = map.remove(&instance_id);
+    (removed, map.values().cloned().collect::<Vec<_>>())
+  };
+  let Some(instance) = removed else {
+    return Ok(false);
+  };
   tauri::async_runtime::spawn_blocking(move || {
-    let mut guard = state.lock().map_err(|e| e.to_string())?;
-    let process = guard.deref_mut();
-    let stdin = process
-      .stdin
-      .as_mut()
-      .ok_or("backend process stdin gone")?;
-    writeln!(stdin, "{}", request).map_err(|e| e.to_string())?;
-    stdin.flush().map_err(|e| e.to_string())?;
-    let stdout = process
-      .stdout
-      .as_mut()
-      .ok_or("backend process stdout gone")?;
-    loop {
-      let mut line = String::new();
-      if stdout.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
-        break;
-      }
-      let trimmed = line.trim();
-      let stop = !trimmed.is_empty()
-        && serde_json::from_str::<serde_json::Value>(trimmed)
-          .map(|v| {
-            let t = v.get("type").and_then(|t| t.as_str());
-            t == Some("result") || t == Some("error")
-          })
-          .unwrap_or(false);
-      tx_block.blocking_send(line).map_err(|e| e.to_string())?;
-      if stop {
-        break;
-      }
-    }
-    Ok::<_, String>(())
+    let mut guard = lock_process(&instance.process);
+    shutdown_backend(guard.deref_mut(), shutdown_grace(&app, ShutdownReason::Stop));
+    if !db_in_use(&remaining, &guard.spawn.db_arg) {
+      db_lock::release(&guard.spawn.db_arg);
+    }
+    Ok::<_, String>(true)
   })
   .await
-  .map_err(|e| e.to_string())??;
+  .map_err(|e| e.to_string())?
+}
 
-  drop(tx);
-  let _ = recv_handle.await;
-  if let Ok(mut g) = error_cell.lock() {
-    if let Some(msg) = g.take() {
-      return Err(msg);
-    }
-  }
-  let out = result_cell
-    .lock()
-    .map_err(|e| e.to_string())?
-    .take()
-    .ok_or_else(|| "backend stream did not return result".to_string());
-  out
+/// Whether any of `instances` runs on `db`.
+fn db_in_use(instances: &[BackendInstance], db: &Path) -> bool {
+  instances
+    .iter()
+    .any(|instance| lock_process(&instance.process).spawn.db_arg == db)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -418,39 +4842,901 @@ pub fn run() {
     .invoke_handler(tauri::generate_handler![
       get_backend_dir,
       spawn_backend_build,
+      cancel_build,
+      build_status,
+      spawn_reindex,
+      cancel_reindex,
+      get_build_log,
+      set_read_only,
+      get_read_only,
       log_frontend_error,
       backend_request,
+      backend_batch,
       backend_query_stream,
+      backend_stream,
+      get_session_stats,
+      export_transcript,
+      cancel_query,
+      start_query,
+      restart_backend,
+      stop_backend,
+      start_backend,
+      get_backend_stderr,
+      get_app_log,
+      backend_health,
+      backend_queue_depth,
+      get_backend_info,
+      submit_diagnostics,
+      get_recent_events,
+      spawn_named_backend,
+      drop_named_backend,
+      get_spawn_error,
+      get_spawn_diagnostics,
+      backend_resource_usage,
+      set_database,
+      open_database_from_path,
+      list_talkers,
+      delete_talker,
+      get_query_history,
+      set_active_talker,
+      get_warmup_enabled,
+      set_warmup_enabled,
+      set_db_dir,
+      set_build_jobs,
+      set_idle_timeout,
+      set_log_level,
+      get_log_level,
+      set_backend_transport,
+      get_backend_transport,
+      set_backend_source,
+      get_backend_source,
+      set_ipc_logging,
+      reload_config,
+      get_config,
+      set_config,
+      validate_config,
+      check_credentials,
+      reset_conversation,
+      backend_capabilities,
     ])
     .plugin(tauri_plugin_shell::init())
     .plugin(tauri_plugin_dialog::init())
-    .on_window_event(|window, event| {
-      if let tauri::WindowEvent::CloseRequested { .. } = event {
-        if let Some(state) = window.try_state::<Arc<Mutex<BackendProcess>>>() {
-          if let Ok(mut guard) = state.inner().lock() {
-            let _ = guard.child.kill();
-          }
+    .setup(|app| {
+      let app_log = AppLog::default();
+      let mut log_builder = tauri_plugin_log::Builder::default().level(log::LevelFilter::Info);
+      if !cfg!(debug_assertions) {
+        // No stdout or log file in release; get_app_log is the only way to read the log.
+        log_builder = log_builder.clear_targets();
+      }
+      app
+        .handle()
+        .plugin(log_builder.target(app_log.target()).build())?;
+      app.manage(app_log);
+      app.manage(BackendStderr::default());
+      app.manage(RecentEvents::default());
+      app.manage(BuildProcess::default());
+      app.manage(ReindexProcess::default());
+      app.manage(BuildLog::default());
+      app.manage(QueryCancels::default());
+      app.manage(TalkerCache::default());
+      app.manage(ActiveTalkers::default());
+      app.manage(SessionUsage::default());
+      let idle_minutes = app
+        .path()
+        .app_data_dir()
+        .ok()
+        .and_then(|dir| load_settings(&dir).get("idle_timeout_minutes").and_then(|m| m.as_u64()))
+        .unwrap_or(0);
+      app.manage(IdleTimeout(AtomicU64::new(idle_minutes.saturating_mul(60_000))));
+      let locked = read_only_flag(std::env::var(READ_ONLY_ENV).ok().as_deref());
+      app.manage(ReadOnly {
+        enabled: AtomicBool::new(locked),
+        locked,
+      });
+      app.manage(LastSpawnError::default());
+      // A failed spawn must not abort setup: the window still opens and shows get_spawn_error.
+      let mut instances = HashMap::new();
+      match spawn_backend_process(Some(app.handle()), DEFAULT_INSTANCE, None) {
+        Ok(backend) => {
+          let instance = start_instance(app.handle(), backend);
+          spawn_startup_handshake(app.handle(), instance.clone());
+          instances.insert(DEFAULT_INSTANCE.to_string(), instance);
         }
+        Err(e) => log::error!("Backend spawn failed: {}", e),
       }
+      app.manage(Backends(Mutex::new(instances)));
+      spawn_idle_monitor(app.handle().clone());
+      Ok(())
     })
-    .setup(|app| {
-      if cfg!(debug_assertions) {
-        app.handle().plugin(
-          tauri_plugin_log::Builder::default()
-            .level(log::LevelFilter::Info)
-            .build(),
-        )?;
-      }
-      let backend = match spawn_backend_process(Some(app.handle())) {
-        Ok(p) => Arc::new(Mutex::new(p)),
-        Err(e) => {
-          log::error!("Backend spawn failed: {}", e);
-          return Err(e.into());
-        }
+    .build(tauri::generate_context!())
+    .expect("error while building tauri application")
+    .run(|app, event| {
+      // Only when the app is actually exiting: shutting down drains Backends and refuses new streams for
+      // good, and on macOS closing the window leaves the app running to be reopened. Closing the last
+      // window elsewhere ends in ExitRequested; ⌘Q and app.exit() too.
+      if let tauri::RunEvent::ExitRequested { .. } | tauri::RunEvent::Exit = event {
+        shutdown_all_backends(app);
+      }
+    });
+}
+
+/// Cancel running streams, kill a running build or reindex, then shut down every backend instance (and
+/// forget them, so a second call is a no-op). The jobs go first: left running they would keep writing to the
+/// database after the app is gone.
+fn shutdown_all_backends(app: &tauri::AppHandle) {
+  if let Some(cancels) = app.try_state::<QueryCancels>() {
+    cancels.shut_down();
+  }
+  for kind in [JobKind::Build, JobKind::Reindex] {
+    let Some(slot) = kind.slot(app) else {
+      continue;
+    };
+    match kill_job(slot, kind) {
+      Ok(Some(talker_id)) => log::warn!("Killed {} for {} on shutdown", kind.name(), talker_id),
+      Ok(None) => {}
+      Err(e) => log::error!("Could not stop {} on shutdown: {}", kind.name(), e),
+    }
+  }
+  let Some(backends) = app.try_state::<Backends>() else {
+    return;
+  };
+  let instances: Vec<BackendInstance> = match backends.0.lock() {
+    Ok(mut map) => map.drain().map(|(_, instance)| instance).collect(),
+    Err(_) => Vec::new(),
+  };
+  for instance in instances {
+    let mut guard = lock_process(&instance.process);
+    shutdown_backend(guard.deref_mut(), shutdown_grace(app, ShutdownReason::AppExit));
+    db_lock::release(&guard.spawn.db_arg);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[cfg(unix)]
+  #[test]
+  fn saved_db_text_refuses_non_utf8_paths() {
+    use std::os::unix::ffi::OsStrExt;
+    assert_eq!(saved_db_text(Path::new("/data/a.db")).unwrap(), "/data/a.db");
+    let bad = Path::new(std::ffi::OsStr::from_bytes(b"/data/\xff.db"));
+    assert!(saved_db_text(bad).unwrap_err().contains("not valid UTF-8"));
+  }
+
+  #[test]
+  fn validate_db_path_stays_inside_data_dir() {
+    let root = std::env::temp_dir().join(format!("narrarc-db-test-{}", std::process::id()));
+    let data = root.join("data");
+    std::fs::create_dir_all(data.join("projects")).unwrap();
+    let data_canon = data.canonicalize().unwrap();
+    assert_eq!(
+      validate_db_path(&data, "other.db").unwrap(),
+      data_canon.join("other.db")
+    );
+    assert_eq!(
+      validate_db_path(&data, "projects/a.db").unwrap(),
+      data_canon.join("projects").join("a.db")
+    );
+    assert!(validate_db_path(&data, "../escape.db").is_err());
+    assert!(validate_db_path(&data, "notes.txt").is_err());
+    assert!(validate_db_path(&data, "missing/x.db").is_err());
+    let outside = root.join("outside.db");
+    assert!(validate_db_path(&data, outside.to_str().unwrap()).is_err());
+    let _ = std::fs::remove_dir_all(&root);
+  }
+
+  #[test]
+  fn shut_down_wakes_streams_and_refuses_new_ones() {
+    let rt = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+    rt.block_on(async {
+      let cancels = QueryCancels::default();
+      let shutdown = cancels.shutdown.notified();
+      tokio::pin!(shutdown);
+      shutdown.as_mut().enable();
+      cancels.shut_down();
+      tokio::time::timeout(Duration::from_secs(1), shutdown).await.unwrap();
+      assert!(cancels.shutting_down.load(Ordering::SeqCst));
+    });
+  }
+
+  #[test]
+  fn request_limiter_reports_queued_callers() {
+    let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    rt.block_on(async {
+      let limiter = RequestLimiter::new(1);
+      let first = limiter.acquire().await.unwrap();
+      let second = {
+        let limiter = limiter.clone();
+        tokio::spawn(async move { drop(limiter.acquire().await.unwrap()) })
       };
-      app.manage(backend);
+      tokio::task::yield_now().await;
+      assert_eq!(
+        limiter.depth(),
+        serde_json::json!({ "limit": 1, "in_flight": 1, "queued": 1 })
+      );
+      drop(first);
+      second.await.unwrap();
+      assert_eq!(
+        limiter.depth(),
+        serde_json::json!({ "limit": 1, "in_flight": 0, "queued": 0 })
+      );
+      let held = limiter.acquire().await.unwrap();
+      limiter.raise_to(3);
+      limiter.raise_to(2);
+      assert_eq!(
+        limiter.depth(),
+        serde_json::json!({ "limit": 3, "in_flight": 1, "queued": 0 })
+      );
+      drop(held);
+    });
+  }
+
+  #[test]
+  fn idle_timeout_waits_for_in_flight_requests() {
+    let rt = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    rt.block_on(async {
+      let limiter = RequestLimiter::new(1);
+      let permit = limiter.acquire().await.unwrap();
+      assert_eq!(limiter.idle_for(), None);
+      assert!(!idle_past(limiter.idle_for(), 1));
+      drop(permit);
+      assert!(limiter.idle_for().is_some());
+    });
+    assert!(idle_past(Some(Duration::from_secs(60)), 60_000));
+    assert!(!idle_past(Some(Duration::from_secs(59)), 60_000));
+    assert!(!idle_past(Some(Duration::from_secs(3600)), 0));
+  }
+
+  #[test]
+  fn watchdog_backs_off_when_restarts_do_not_help() {
+    let now = std::time::Instant::now();
+    let mut state = WatchdogState::default();
+    assert!(!state.observe(false, 3, now));
+    assert!(!state.observe(true, 3, now));
+    assert!(!state.observe(false, 3, now));
+    assert!(!state.observe(false, 3, now));
+    assert!(state.observe(false, 3, now));
+    state.restarting = true;
+    assert!(!state.observe(false, 3, now));
+    state.restart_finished(true, now);
+    // Three more errors before any success: the restart didn't help, so wait before the next one.
+    for _ in 0..2 {
+      assert!(!state.observe(false, 3, now));
+    }
+    assert!(!state.observe(false, 3, now));
+    assert_eq!(state.failed_restarts, 1);
+    assert!(state.observe(false, 3, now + WATCHDOG_BASE_BACKOFF));
+    state.restart_finished(false, now);
+    assert_eq!(state.backoff_until, Some(now + WATCHDOG_BASE_BACKOFF * 2));
+    assert!(!state.observe(true, 3, now));
+    assert_eq!(state.failed_restarts, 0);
+    assert!(!WatchdogState::default().observe(false, 0, now));
+  }
+
+  #[test]
+  fn stream_lines_interleave_partial_progress_and_result() {
+    let lines: String = [
+      serde_json::json!({ "type": "progress", "trace_steps": [] }),
+      serde_json::json!({ "type": "partial", "text": "Draft" }),
+      serde_json::json!({ "type": "progress", "trace_steps": [{ "node_name": "answer" }] }),
+      // A verbose progress line: its state may mention results, but only the top-level type counts.
+      serde_json::json!({
+        "type": "progress",
+        "trace_steps": [{ "node_name": "answer" }],
+        "state": { "evaluation": "sufficient", "last": { "type": "result" } },
+      }),
+      serde_json::json!({ "type": "partial", "text": "Draft answer" }),
+      serde_json::json!({ "type": "result", "answer": "Final answer" }),
+    ]
+    .iter()
+    .map(|data| format!("{}\n", serde_json::json!({ "req_id": 1, "data": data })))
+    .collect();
+    let pending = PendingMap::default();
+    let mut request = PendingRequest::register(&pending, 1, 0).unwrap();
+    let mut reader = PipeReader::new(std::io::Cursor::new(lines), 1024, 1024);
+    transport::dispatch_lines(&mut reader, &pending, &AtomicBool::new(false));
+
+    let rt = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+    let mut kinds = Vec::new();
+    let last = rt.block_on(async {
+      loop {
+        let line = next_stream_line(&mut request, Duration::from_secs(1)).await.unwrap().unwrap();
+        let kind = stream_line_kind(&line.data);
+        let done = matches!(kind, StreamLineKind::Error | StreamLineKind::Final);
+        kinds.push(kind);
+        if done {
+          return line.data;
+        }
+      }
+    });
+    assert_eq!(
+      kinds,
+      [
+        StreamLineKind::Progress,
+        StreamLineKind::Partial,
+        StreamLineKind::Progress,
+        StreamLineKind::Progress,
+        StreamLineKind::Partial,
+        StreamLineKind::Final,
+      ]
+    );
+    assert_eq!(last["answer"], "Final answer");
+  }
+
+  #[test]
+  fn log_level_must_be_a_known_name() {
+    for level in LOG_LEVELS {
+      assert!(validate_log_level(level).is_ok());
+    }
+    assert!(validate_log_level(DEFAULT_LOG_LEVEL).is_ok());
+    let err = validate_log_level("verbose").unwrap_err();
+    assert!(err.contains("error, warn, info, debug, trace"), "{}", err);
+  }
+
+  #[test]
+  fn backend_source_round_trips_and_checks_the_external_command() {
+    assert_eq!(serde_json::to_value(BackendSource::Bundled).unwrap(), "bundled");
+    let source: BackendSource = serde_json::from_value(serde_json::json!({
+      "external": { "cmd": ["uv", "run", "python", "-m", "narrative_mirror.cli_json"], "cwd": "/src/backend" }
+    }))
+    .unwrap();
+    assert_eq!(
+      source,
+      BackendSource::External {
+        cmd: ["uv", "run", "python", "-m", "narrative_mirror.cli_json"].map(String::from).to_vec(),
+        cwd: PathBuf::from("/src/backend"),
+      }
+    );
+    assert!(validate_backend_source(&BackendSource::Bundled).is_ok());
+
+    let exe = std::env::current_exe().unwrap();
+    let dir = exe.parent().unwrap().to_path_buf();
+    let name = exe.file_name().unwrap().to_string_lossy().into_owned();
+    let external = |cmd: &[&str], cwd: &Path| BackendSource::External {
+      cmd: cmd.iter().map(|s| s.to_string()).collect(),
+      cwd: cwd.to_path_buf(),
+    };
+    assert_eq!(validate_backend_source(&external(&[exe.to_str().unwrap()], &dir)), Ok(()));
+    assert_eq!(validate_backend_source(&external(&[&format!("./{}", name)], &dir)), Ok(()));
+    assert!(validate_backend_source(&external(&[], &dir)).unwrap_err().contains("empty"));
+    assert!(validate_backend_source(&external(&[&name], &dir.join("missing")))
+      .unwrap_err()
+      .contains("not a directory"));
+    assert!(validate_backend_source(&external(&["./no-such-backend"], &dir))
+      .unwrap_err()
+      .contains("not found"));
+    assert!(validate_backend_source(&external(&["no-such-backend-on-path"], &dir))
+      .unwrap_err()
+      .contains("not found"));
+  }
+
+  #[test]
+  fn read_attachments_checks_location_size_and_binary_content() {
+    let root = std::env::temp_dir().join(format!("narrarc-attach-test-{}", std::process::id()));
+    let (allowed, outside) = (root.join("allowed"), root.join("outside"));
+    std::fs::create_dir_all(&allowed).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    let file = |dir: &Path, name: &str, bytes: &[u8]| {
+      let path = dir.join(name);
+      std::fs::write(&path, bytes).unwrap();
+      path.to_string_lossy().into_owned()
+    };
+    let notes = file(&allowed, "notes.txt", "聊天记录\nline 2".as_bytes());
+    let image = file(&allowed, "photo.png", b"\x89PNG\0\x01");
+    let elsewhere = file(&outside, "secret.txt", b"no");
+    let big = file(&allowed, "big.txt", &vec![b'a'; MAX_ATTACHMENT_BYTES as usize + 1]);
+    let dirs = [allowed.clone()];
+
+    let read = read_attachments(std::slice::from_ref(&notes), &dirs, false).unwrap();
+    assert_eq!(read[0]["name"], "notes.txt");
+    assert_eq!(read[0]["content"], "聊天记录\nline 2");
+    let code = |paths: &[String], allow_binary| read_attachments(paths, &dirs, allow_binary).unwrap_err();
+    assert_eq!(code(std::slice::from_ref(&image), false).code.as_deref(), Some("bad_attachment"));
+    assert!(code(std::slice::from_ref(&image), false).message.contains("binary"));
+    let binary = read_attachments(&[image], &dirs, true).unwrap();
+    assert_eq!((binary[0]["binary"].clone(), binary[0]["size"].clone()), (true.into(), 6.into()));
+    assert!(binary[0].get("content").is_none());
+    assert!(code(&[elsewhere], false).message.contains("outside"));
+    assert!(code(&[allowed.join("missing.txt").to_string_lossy().into_owned()], false)
+      .message
+      .contains("not found"));
+    assert!(code(&[allowed.to_string_lossy().into_owned()], false).message.contains("not a file"));
+    assert!(code(&[big], false).message.contains("the limit is"));
+    let chunk = vec![b'a'; MAX_ATTACHMENT_BYTES as usize];
+    let many: Vec<String> = (0..=MAX_ATTACHMENTS_TOTAL_BYTES / MAX_ATTACHMENT_BYTES)
+      .map(|i| file(&allowed, &format!("part{}.txt", i), &chunk))
+      .collect();
+    assert!(code(&many, false).message.contains("together"));
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn payload_preview_matches_truncating_the_whole_payload() {
+    for text in ["x".repeat(4 * 1024 * 1024), "聊天".repeat(1000), "short".to_string()] {
+      let payload = serde_json::json!({ "cmd": "query", "question": text });
+      assert_eq!(
+        payload_preview(&payload, LOG_PAYLOAD_CHARS),
+        truncate_for_log(&payload.to_string(), LOG_PAYLOAD_CHARS)
+      );
+    }
+  }
+
+  #[test]
+  fn app_target_is_a_target_triple() {
+    assert!(looks_like_triple(APP_TARGET), "{}", APP_TARGET);
+    assert!(looks_like_triple("x86_64-pc-windows-msvc"));
+    assert!(looks_like_triple("aarch64-apple-darwin"));
+    assert!(!looks_like_triple("unknown"));
+    assert!(!looks_like_triple(""));
+    assert!(!looks_like_triple("x86_64--linux"));
+    assert!(!looks_like_triple("x86_64-unknown-linux-gnu\n"));
+  }
+
+  #[test]
+  fn shutdown_grace_is_short_on_exit_and_long_for_restarts_during_builds() {
+    assert_eq!(grace_for(ShutdownReason::AppExit, true), APP_EXIT_GRACE);
+    assert_eq!(grace_for(ShutdownReason::Restart, false), SHUTDOWN_GRACE);
+    assert_eq!(grace_for(ShutdownReason::Restart, true), BUILD_RESTART_GRACE);
+    assert_eq!(grace_for(ShutdownReason::Stop, true), SHUTDOWN_GRACE);
+  }
+
+  #[test]
+  fn read_only_flag_accepts_common_truthy_values() {
+    assert!(!read_only_flag(None));
+    assert!(read_only_flag(Some("1")));
+    assert!(read_only_flag(Some(" True ")));
+    assert!(read_only_flag(Some("on")));
+    assert!(!read_only_flag(Some("0")));
+    assert!(!read_only_flag(Some("")));
+  }
+
+  #[test]
+  fn in_flight_limit_follows_the_backend_unless_overridden() {
+    assert_eq!(in_flight_limit(None, None), MAX_IN_FLIGHT_REQUESTS);
+    assert_eq!(in_flight_limit(None, Some(4)), 4);
+    assert_eq!(in_flight_limit(None, Some(0)), MAX_IN_FLIGHT_REQUESTS);
+    assert_eq!(in_flight_limit(Some(" 2 "), Some(4)), 2);
+    assert_eq!(in_flight_limit(Some("0"), Some(4)), 4);
+    assert_eq!(in_flight_limit(Some("many"), None), MAX_IN_FLIGHT_REQUESTS);
+  }
+
+  /// Spawn config of the default instance on data/mirror.db, as spawn_backend_process would build it.
+  fn test_spawn_config() -> BackendSpawnConfig {
+    BackendSpawnConfig {
+      instance_id: DEFAULT_INSTANCE.to_string(),
+      cwd: std::env::temp_dir(),
+      db_arg: PathBuf::from("data/mirror.db"),
+      config_arg: DEFAULT_CONFIG_PATH.to_string(),
+      env: HashMap::new(),
+      max_line_bytes: DEFAULT_MAX_LINE_BYTES,
+      log_level: DEFAULT_LOG_LEVEL.to_string(),
+      http: false,
+      source: BackendSource::Bundled,
+    }
+  }
+
+  /// A BackendProcess around `sleep 30` with piped stdio; kill its child when done.
+  #[cfg(unix)]
+  fn sleeping_process() -> BackendProcess {
+    let child = Command::new("sleep")
+      .arg("30")
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .unwrap();
+    BackendProcess::from_child(child, test_spawn_config(), None)
+  }
+
+  #[test]
+  fn backend_args_pass_the_db_path_unchanged() {
+    let mut spawn = test_spawn_config();
+    spawn.db_arg = std::env::temp_dir().join("Narrarc 数据").join("mirror.db");
+    let args = backend_args(&spawn);
+    assert_eq!(args[0], "--db");
+    assert_eq!(args[1], spawn.db_arg.as_os_str());
+    assert_eq!(args[2..], ["--log-level", DEFAULT_LOG_LEVEL, "stdio", "--config", DEFAULT_CONFIG_PATH]);
+    spawn.http = true;
+    assert_eq!(backend_args(&spawn).last().unwrap(), "--http");
+    spawn.http = false;
+
+    #[cfg(unix)]
+    {
+      use std::os::unix::ffi::{OsStrExt, OsStringExt};
+      // Not valid UTF-8: the old to_str() fallback would have replaced the whole path with data/mirror.db.
+      spawn.db_arg = PathBuf::from(OsString::from_vec(b"/tmp/narrarc \xff d\xc3\xa9j\xc3\xa0/mirror.db".to_vec()));
+      let output = Command::new("sh")
+        .args(["-c", "printf %s \"$2\"", "sh"])
+        .args(backend_args(&spawn))
+        .output()
+        .unwrap();
+      assert_eq!(output.stdout, spawn.db_arg.as_os_str().as_bytes());
+    }
+  }
+
+  #[test]
+  fn progress_policy_defaults_to_block() {
+    assert_eq!(ProgressPolicy::parse(None), Ok(ProgressPolicy::Block));
+    assert_eq!(ProgressPolicy::parse(Some("latest")), Ok(ProgressPolicy::Latest));
+    assert!(ProgressPolicy::parse(Some("drop")).unwrap_err().contains("block or latest"));
+  }
+
+  #[test]
+  fn yaml_syntax_report_points_at_the_bad_line() {
+    let ok = yaml_syntax_report("config.yml", "llm:\n  model: m\n");
+    assert_eq!(ok["valid"], true);
+    assert_eq!(ok["issues"], serde_json::json!([]));
+    let bad = yaml_syntax_report("config.yml", "llm:\n  model: [unclosed\n");
+    assert_eq!(bad["valid"], false);
+    assert_eq!(bad["issues"][0]["severity"], "error");
+    assert!(bad["issues"][0]["line"].as_u64().is_some());
+  }
+
+  #[test]
+  fn parse_history_accepts_missing_and_partial_items() {
+    assert_eq!(parse_history(serde_json::json!({ "type": "history" })), Ok(Vec::new()));
+    assert_eq!(parse_history(serde_json::Value::Null), Ok(Vec::new()));
+    let items = parse_history(serde_json::json!({
+      "type": "history",
+      "items": [
+        { "id": 2, "talker": "t1", "question": "why?", "timestamp": 1700000000000i64 },
+        { "id": 1, "talker": null, "question": "what?" },
+      ],
+    }))
+    .unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].talker.as_deref(), Some("t1"));
+    assert_eq!((items[1].id, items[1].talker.clone(), items[1].timestamp), (1, None, 0));
+    assert!(parse_history(serde_json::json!({ "items": [{ "question": "no id" }] })).is_err());
+  }
+
+  #[test]
+  fn handshake_reply_reports_progress_until_the_deadline() {
+    let pending = PendingMap::default();
+    let request = PendingRequest::register(&pending, 1, 0).unwrap();
+    let timeout = Duration::from_millis(100);
+    let mut ticks = 0;
+    let err = handshake_reply(request, std::time::Instant::now() + timeout, timeout, &mut || {
+      ticks += 1;
       Ok(())
     })
-    .run(tauri::generate_context!())
-    .expect("error while running tauri application");
+    .unwrap_err();
+    assert_eq!(err, "backend not ready after 100 ms");
+    assert!(ticks >= 2, "progress called {} times", ticks);
+
+    let request = PendingRequest::register(&pending, 2, 0).unwrap();
+    let deadline = std::time::Instant::now() + Duration::from_secs(60);
+    let err = handshake_reply(request, deadline, timeout, &mut || Err("quitting".to_string())).unwrap_err();
+    assert_eq!(err, "quitting", "an error from progress aborts the wait at once");
+  }
+
+  #[test]
+  fn unknown_commands_are_recognized_by_their_code() {
+    let unknown = serde_json::json!({ "type": "error", "message": "Unknown cmd: reset", "code": "unknown_cmd" });
+    assert!(is_unknown_cmd(&BackendError::from_error_value(&unknown)));
+    let failed = serde_json::json!({ "type": "error", "message": "Unknown cmd: reset" });
+    assert!(!is_unknown_cmd(&BackendError::from_error_value(&failed)));
+  }
+
+  #[test]
+  fn writes_database_looks_inside_batches() {
+    assert!(writes_database(&serde_json::json!({ "cmd": "import", "path": "a.json" })));
+    assert!(writes_database(&serde_json::json!({ "cmd": "delete_session", "talker": "t" })));
+    assert!(!writes_database(&serde_json::json!({ "cmd": "list_talkers" })));
+    assert!(!writes_database(&serde_json::json!({ "question": "no cmd" })));
+    let batch = |cmd: &str| serde_json::json!({ "cmd": "batch", "items": [{ "cmd": "ping" }, { "cmd": cmd }] });
+    assert!(writes_database(&batch("reset")));
+    assert!(!writes_database(&batch("get_config")));
+  }
+
+  #[test]
+  fn should_retry_only_safe_requests_after_a_broken_pipe() {
+    let read = serde_json::json!({ "cmd": "list_talkers" });
+    let write = serde_json::json!({ "cmd": "delete_session", "talker": "t" });
+    let exited = BackendError::with_code("backend_exited", "backend closed the connection");
+    let broken = BackendError::from("Broken pipe (os error 32)".to_string());
+    assert!(should_retry(&read, None, &exited, true));
+    assert!(should_retry(&read, None, &broken, true));
+    assert!(!should_retry(&read, None, &broken, false));
+    assert!(!should_retry(&read, Some(false), &broken, true));
+    assert!(!should_retry(&write, None, &broken, true));
+    assert!(should_retry(&write, Some(true), &broken, true));
+    let timeout = BackendError::with_code("timeout", "backend request timed out");
+    assert!(!should_retry(&read, None, &timeout, true));
+  }
+
+  #[test]
+  fn check_protocol_version_names_the_stale_side() {
+    let (min, max) = SUPPORTED_PROTOCOL_VERSIONS.into_inner();
+    assert!(check_protocol_version(min).is_ok());
+    assert!(check_protocol_version(max).is_ok());
+    assert!(check_protocol_version(max + 1).unwrap_err().starts_with("backend too new"));
+    if min > 0 {
+      assert!(check_protocol_version(min - 1).unwrap_err().starts_with("backend too old"));
+    }
+  }
+
+  #[test]
+  fn check_sqlite_file_rejects_dirs_and_other_files() {
+    let root = std::env::temp_dir().join(format!("narrarc-sqlite-test-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let db = root.join("picked.db");
+    let mut bytes = SQLITE_HEADER.to_vec();
+    bytes.extend([0u8; 84]);
+    std::fs::write(&db, &bytes).unwrap();
+    assert_eq!(check_sqlite_file(&db).unwrap(), db.canonicalize().unwrap());
+    let err = check_sqlite_file(&root).unwrap_err();
+    assert!(err.contains("is a directory"), "{}", err);
+    std::fs::write(root.join("notes.db"), "just text, not sqlite").unwrap();
+    let err = check_sqlite_file(&root.join("notes.db")).unwrap_err();
+    assert!(err.starts_with("Not a SQLite database"), "{}", err);
+    std::fs::write(root.join("empty.db"), "").unwrap();
+    assert!(check_sqlite_file(&root.join("empty.db")).is_err());
+    let err = check_sqlite_file(&root.join("missing.db")).unwrap_err();
+    assert!(err.starts_with("Database not found"), "{}", err);
+    let _ = std::fs::remove_dir_all(&root);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn check_started_reports_a_child_that_exits_at_once() {
+    let mut crashed = new_process_group(&mut Command::new("sh"))
+      .args(["-c", "echo 'ModuleNotFoundError: no module named x' >&2; exit 3"])
+      .stderr(Stdio::piped())
+      .spawn()
+      .unwrap();
+    match check_started(&mut crashed) {
+      Err(SpawnError::ExitedImmediately {
+        exit_code,
+        stderr_tail,
+        ..
+      }) => {
+        assert_eq!(exit_code, Some(3));
+        assert_eq!(stderr_tail, ["ModuleNotFoundError: no module named x"]);
+      }
+      other => panic!("expected ExitedImmediately, got {:?}", other),
+    }
+
+    let mut running = new_process_group(&mut Command::new("sleep"))
+      .arg("30")
+      .stderr(Stdio::piped())
+      .spawn()
+      .unwrap();
+    assert!(check_started(&mut running).is_ok());
+    kill_child_tree(&mut running);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn poisoned_process_lock_recovers_and_marks_restart() {
+    let state = Arc::new(Mutex::new(sleeping_process()));
+    let holder = state.clone();
+    let _ = std::thread::spawn(move || {
+      let _guard = holder.lock().unwrap();
+      panic!("write failed while holding the backend lock");
+    })
+    .join();
+    assert!(state.is_poisoned());
+    {
+      let guard = lock_process(&state);
+      // ensure_alive restarts a poisoned process on the next request.
+      assert!(guard.poisoned.load(Ordering::SeqCst));
+    }
+    assert!(!state.is_poisoned());
+    kill_child_tree(&mut lock_process(&state).child);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn builds_after_a_database_switch_use_the_new_database() {
+    let state = Arc::new(Mutex::new(sleeping_process()));
+    let picked = std::env::temp_dir().join("Narrarc elsewhere").join("picked.db");
+    lock_process(&state).spawn.switch_db(picked.clone());
+    let args = job_args(&process_db_path(&state), DEFAULT_LOG_LEVEL, "build", "t1", DEFAULT_CONFIG_PATH);
+    assert_eq!(args[0], "--db");
+    assert_eq!(args[1], picked.as_os_str());
+    assert_eq!(
+      args[2..],
+      ["--log-level", DEFAULT_LOG_LEVEL, "build", "--talker", "t1", "--config", DEFAULT_CONFIG_PATH]
+    );
+    kill_child_tree(&mut lock_process(&state).child);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn ensure_healthy_refuses_a_backend_that_is_not_up_and_ready() {
+    let mut process = sleeping_process();
+    let problem = |process: &mut BackendProcess| {
+      let err = ensure_healthy(process).unwrap_err();
+      assert_eq!(err.code.as_deref(), Some("backend_unhealthy"));
+      err.message
+    };
+    assert!(problem(&mut process).contains("still starting"));
+    process.ready = true;
+    assert!(ensure_healthy(&mut process).is_ok());
+    process.poisoned.store(true, Ordering::SeqCst);
+    assert!(problem(&mut process).contains("restart"));
+    process.poisoned.store(false, Ordering::SeqCst);
+    process.state = BackendState::Suspended;
+    assert!(problem(&mut process).contains("suspended"));
+    process.state = BackendState::Running;
+    kill_child_tree(&mut process.child);
+    assert!(problem(&mut process).contains("exited"));
+  }
+
+  #[test]
+  fn query_options_take_camel_case_keys() {
+    let options: QueryOptions = serde_json::from_value(serde_json::json!({
+      "talker": "t1",
+      "queryId": "q-1",
+      "configOverrides": { "llm": { "model": "m" } },
+      "progressWindowMs": 50,
+      "allowBinaryAttachments": true,
+    }))
+    .unwrap();
+    assert_eq!(options.query_id.as_deref(), Some("q-1"));
+    assert_eq!(options.progress_window_ms, Some(50));
+    assert_eq!(options.allow_binary_attachments, Some(true));
+    assert!(serde_json::from_value::<QueryOptions>(serde_json::json!({ "query_id": "q" })).is_err());
+  }
+
+  #[test]
+  fn query_result_matches_the_backend_response_exactly() {
+    let line = serde_json::json!({
+      "type": "result",
+      "conversation_id": "t1",
+      "question": "q",
+      "answer_mode": "full_narrative",
+      "factual_answer": null,
+      "phases": [{ "phase_index": 1 }],
+      "agent_trace": { "steps": [], "total_llm_calls": 2, "total_duration_ms": 40 },
+      "all_messages": [],
+      "usage": { "total_tokens": 9 },
+    });
+    let result = QueryResult::from_line(line.clone()).unwrap();
+    assert_eq!(result.conversation_id, "t1");
+    assert_eq!(result.usage.as_ref().and_then(|u| u.total_tokens), Some(9));
+    let mut expected = line.clone();
+    expected.as_object_mut().unwrap().remove("type");
+    assert_eq!(serde_json::to_value(&result).unwrap(), expected);
+
+    let mut missing = line.clone();
+    missing.as_object_mut().unwrap().remove("phases");
+    let err = QueryResult::from_line(missing).unwrap_err();
+    assert_eq!(err.code.as_deref(), Some("bad_result"));
+    assert!(err.message.contains("missing field `phases`"), "{}", err.message);
+
+    let mut unknown = line;
+    unknown["answer"] = "a".into();
+    let err = QueryResult::from_line(unknown).unwrap_err();
+    assert!(err.message.contains("unknown field `answer`"), "{}", err.message);
+  }
+
+  #[test]
+  fn session_stats_add_usage_when_reported() {
+    let mut stats = SessionStats::default();
+    stats.record(Some(&QueryUsage {
+      total_tokens: Some(120),
+      cost: Some(0.01),
+      ..Default::default()
+    }));
+    stats.record(Some(&QueryUsage {
+      prompt_tokens: Some(30),
+      completion_tokens: Some(5),
+      ..Default::default()
+    }));
+    stats.record(None);
+    stats.record(Some(&QueryUsage {
+      cost: Some(-1.0),
+      ..Default::default()
+    }));
+    assert_eq!(stats.query_count, 4);
+    assert_eq!(stats.total_tokens, 155);
+    assert!((stats.total_cost - 0.01).abs() < 1e-12);
+  }
+
+  #[test]
+  fn jobs_report_under_their_own_prefix_while_their_child_runs() {
+    assert_eq!(JobKind::Build.event("progress"), "build://progress");
+    assert_eq!(JobKind::Reindex.event("cancelled"), "reindex://cancelled");
+
+    #[cfg(unix)]
+    {
+      let mut slot = Some(RunningBuild {
+        child: new_process_group(Command::new("sleep").arg("30")).spawn().unwrap(),
+        talker_id: "t1".to_string(),
+        started_at: std::time::SystemTime::now(),
+      });
+      assert_eq!(running_talker(&mut slot), Some("t1".to_string()));
+      let slot = Mutex::new(slot);
+      assert_eq!(kill_job(&slot, JobKind::Reindex), Ok(Some("t1".to_string())));
+      assert!(slot.lock().unwrap().is_none());
+      assert_eq!(kill_job(&slot, JobKind::Reindex), Ok(None));
+    }
+  }
+
+  #[test]
+  fn build_log_keeps_the_latest_lines_and_counts_dropped_ones() {
+    let mut log = BuildLogLines::default();
+    log.push("first".to_string());
+    assert_eq!(log.snapshot(), vec!["first".to_string()]);
+    for i in 0..BUILD_LOG_LINES {
+      log.push(format!("line {}", i));
+    }
+    let lines = log.snapshot();
+    assert_eq!(lines.len(), BUILD_LOG_LINES + 1);
+    assert_eq!(lines[0], "[1 earlier lines truncated]");
+    assert_eq!(lines[1], "line 0");
+    assert_eq!(lines.last(), Some(&format!("line {}", BUILD_LOG_LINES - 1)));
+  }
+
+  #[test]
+  fn build_jobs_prefers_request_then_setting_then_env() {
+    assert_eq!(resolve_build_jobs(Some(3), Some(5), Some("7".to_string())), 3);
+    assert_eq!(resolve_build_jobs(None, Some(5), Some("7".to_string())), 5);
+    assert_eq!(resolve_build_jobs(None, None, Some(" 7 ".to_string())), 7);
+    assert_eq!(resolve_build_jobs(Some(0), None, None), 1);
+    assert_eq!(resolve_build_jobs(None, Some(1000), None), 64);
+    let default = resolve_build_jobs(None, None, Some("many".to_string()));
+    assert!(BUILD_JOBS_RANGE.contains(&default));
+  }
+
+  #[test]
+  fn configured_db_dir_reads_settings() {
+    let root = std::env::temp_dir().join(format!("narrarc-settings-test-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    assert_eq!(configured_db_dir(&root), None);
+    std::fs::write(root.join(SETTINGS_FILE), r#"{"db_dir": "/mnt/ext/narrarc"}"#).unwrap();
+    assert_eq!(configured_db_dir(&root), Some(PathBuf::from("/mnt/ext/narrarc")));
+    std::fs::write(root.join(SETTINGS_FILE), r#"{"db_dir": ""}"#).unwrap();
+    assert_eq!(configured_db_dir(&root), None);
+    std::fs::write(root.join(SETTINGS_FILE), "not json").unwrap();
+    assert_eq!(configured_db_dir(&root), None);
+    assert!(ensure_writable_dir(&root.join("data")).is_ok());
+    let _ = std::fs::remove_dir_all(&root);
+  }
+
+  #[cfg(debug_assertions)]
+  #[test]
+  fn find_backend_dir_walks_up_from_manifest() {
+    let root = std::env::temp_dir().join(format!("narrarc-backend-dir-test-{}", std::process::id()));
+    let backend = root.join("repo").join("backend");
+    let manifest = root.join("repo").join("client").join("src-tauri");
+    let elsewhere = root.join("ide").join("workspace");
+    std::fs::create_dir_all(&backend).unwrap();
+    std::fs::create_dir_all(&manifest).unwrap();
+    std::fs::create_dir_all(&elsewhere).unwrap();
+
+    let err = find_backend_dir(None, &elsewhere, &manifest).unwrap_err();
+    assert!(err.contains(BACKEND_DIR_ENV), "{}", err);
+
+    std::fs::write(backend.join("pyproject.toml"), "").unwrap();
+    let found = find_backend_dir(None, &elsewhere, &manifest).unwrap();
+    assert_eq!(found, backend.canonicalize().unwrap());
+    assert_eq!(
+      find_backend_dir(None, &root.join("repo").join("client"), &elsewhere).unwrap(),
+      found
+    );
+
+    let custom = root.join("custom");
+    std::fs::create_dir_all(custom.join("src").join("narrative_mirror")).unwrap();
+    assert_eq!(
+      find_backend_dir(Some(custom.clone()), &elsewhere, &manifest).unwrap(),
+      custom.canonicalize().unwrap()
+    );
+    assert!(find_backend_dir(Some(elsewhere.clone()), &elsewhere, &manifest).is_err());
+    let _ = std::fs::remove_dir_all(&root);
+  }
+
+  #[test]
+  fn start_query_hands_out_unique_ids_with_their_own_channels() {
+    let first = start_query();
+    let second = start_query();
+    assert_ne!(first.query_id, second.query_id);
+    assert_eq!(first.progress_event, format!("backend://progress/{}", first.query_id));
+    assert_eq!(first.partial_event, format!("backend://partial/{}", first.query_id));
+    assert_eq!(validate_event_name(&first.progress_event), Ok(()));
+    assert!(validate_event_name(&query_event(PROGRESS_EVENT, "pane 1")).is_err());
+  }
 }
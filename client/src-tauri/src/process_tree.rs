@@ -0,0 +1,146 @@
+//! Killing a backend together with everything it started. In dev the backend is `uv run python ...`, so
+//! killing only the direct child orphans the interpreter that actually holds the database open.
+
+use std::process::{Child, Command, ExitStatus};
+
+/// Start `cmd` as the leader of a new process group (Unix) so kill_process_tree can signal the whole
+/// group. Windows needs nothing here: taskkill /T walks the parent/child links instead.
+pub(crate) fn new_process_group(cmd: &mut Command) -> &mut Command {
+  #[cfg(unix)]
+  {
+    use std::os::unix::process::CommandExt;
+    cmd.process_group(0);
+  }
+  cmd
+}
+
+/// Kill the process `pid` and all of its descendants. On Unix `pid` must have been spawned through
+/// new_process_group.
+pub(crate) fn kill_process_tree(pid: u32) -> std::io::Result<()> {
+  #[cfg(unix)]
+  {
+    let pgid = libc::pid_t::try_from(pid)
+      .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "pid out of range"))?;
+    // SAFETY: kill(2) has no memory-safety preconditions; a negative pid addresses the process group.
+    if unsafe { libc::kill(-pgid, libc::SIGKILL) } != 0 {
+      return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+  }
+
+  #[cfg(windows)]
+  {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    let status = Command::new("taskkill")
+      .args(["/T", "/F", "/PID", &pid.to_string()])
+      .creation_flags(CREATE_NO_WINDOW)
+      .stdout(std::process::Stdio::null())
+      .stderr(std::process::Stdio::null())
+      .status()?;
+    if !status.success() {
+      return Err(std::io::Error::other(format!("taskkill exited with {}", status)));
+    }
+    Ok(())
+  }
+}
+
+/// Kill `child` and its descendants, falling back to killing just `child`, then reap it. Returns its exit
+/// status, or None if it could not be reaped.
+pub(crate) fn kill_child_tree(child: &mut Child) -> Option<ExitStatus> {
+  if let Err(e) = kill_process_tree(child.id()) {
+    log::warn!("Failed to kill process tree of {}: {}", child.id(), e);
+    let _ = child.kill();
+  }
+  child.wait().ok()
+}
+
+/// The signal that terminated a process, on Unix; None if it exited on its own (or on Windows, which has
+/// no signals).
+pub(crate) fn exit_signal(status: &ExitStatus) -> Option<i32> {
+  #[cfg(unix)]
+  {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+  }
+
+  #[cfg(windows)]
+  {
+    let _ = status;
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{BufRead, BufReader};
+  use std::process::Stdio;
+  use std::time::{Duration, Instant};
+
+  /// Spawn a shell that starts a long-running grandchild and prints its pid.
+  fn spawn_parent_with_grandchild() -> (Child, u32) {
+    #[cfg(unix)]
+    let mut cmd = {
+      let mut cmd = Command::new("sh");
+      cmd.args(["-c", "sleep 30 & echo $!; wait"]);
+      cmd
+    };
+    #[cfg(windows)]
+    let mut cmd = {
+      let mut cmd = Command::new("powershell");
+      cmd.args([
+        "-NoProfile",
+        "-Command",
+        "$p = Start-Process ping -ArgumentList '-n','30','127.0.0.1' -PassThru -NoNewWindow; \
+         $p.Id; Wait-Process -Id $p.Id",
+      ]);
+      cmd
+    };
+    let mut child = new_process_group(&mut cmd)
+      .stdout(Stdio::piped())
+      .spawn()
+      .unwrap();
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+      .read_line(&mut line)
+      .unwrap();
+    (child, line.trim().parse().unwrap())
+  }
+
+  fn is_running(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+      // Zombies (killed but not yet reaped by init) count as gone.
+      let out = Command::new("ps")
+        .args(["-o", "stat=", "-p", &pid.to_string()])
+        .output()
+        .unwrap();
+      let stat = String::from_utf8_lossy(&out.stdout);
+      !stat.trim().is_empty() && !stat.trim_start().starts_with('Z')
+    }
+    #[cfg(windows)]
+    {
+      let out = Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .unwrap();
+      String::from_utf8_lossy(&out.stdout).contains(&pid.to_string())
+    }
+  }
+
+  #[test]
+  fn kill_process_tree_leaves_no_orphans() {
+    let (mut parent, grandchild) = spawn_parent_with_grandchild();
+    assert!(is_running(grandchild));
+    let status = kill_child_tree(&mut parent).expect("killed parent is reaped");
+    assert!(!status.success());
+    #[cfg(unix)]
+    assert_eq!(exit_signal(&status), Some(libc::SIGKILL));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while is_running(grandchild) && Instant::now() < deadline {
+      std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(!is_running(grandchild), "grandchild {} survived", grandchild);
+  }
+}
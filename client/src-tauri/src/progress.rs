@@ -0,0 +1,198 @@
+//! Normalized `backend://progress` payloads, so the UI codes against one tagged shape instead of whatever
+//! the agent currently prints.
+
+use serde::{Deserialize, Serialize};
+
+/// One progress update of a streaming query, tagged by `kind`. Kinds the frontend doesn't know yet are
+/// passed through untouched as Unknown.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum ProgressEvent {
+  /// The agent trace so far (the backend's {"trace_steps": [...]}). Verbose queries add the workflow
+  /// `state` behind it, passed through as the backend wrote it.
+  Trace {
+    trace_steps: Vec<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state: Option<serde_json::Value>,
+  },
+  /// A named step started or finished.
+  Step {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+  },
+  /// A chunk of generated answer text.
+  Token { text: String },
+  /// The agent invoked a tool.
+  ToolCall {
+    name: String,
+    #[serde(default)]
+    arguments: serde_json::Value,
+  },
+  /// Several updates received within one progress window, in order (see coalesce).
+  Batch { events: Vec<ProgressEvent> },
+  #[serde(untagged)]
+  Unknown(serde_json::Value),
+}
+
+impl ProgressEvent {
+  /// Normalize a raw backend progress line. Lines carrying a `kind` are parsed as-is; the legacy shapes
+  /// ({"trace_steps": ...}, {"step": ..., "detail": ...}) are mapped onto their variants.
+  pub(crate) fn from_backend(mut raw: serde_json::Value) -> Self {
+    if let Some(obj) = raw.as_object_mut() {
+      obj.remove("type");
+      if !obj.contains_key("kind") {
+        if let Some(steps) = obj.get("trace_steps").and_then(|s| s.as_array()) {
+          return ProgressEvent::Trace {
+            trace_steps: steps.clone(),
+            state: obj.get("state").cloned(),
+          };
+        }
+        if let Some(name) = obj.get("step").and_then(|s| s.as_str()) {
+          return ProgressEvent::Step {
+            name: name.to_string(),
+            detail: obj
+              .get("detail")
+              .and_then(|d| d.as_str())
+              .map(str::to_string),
+          };
+        }
+      }
+    }
+    serde_json::from_value(raw.clone()).unwrap_or(ProgressEvent::Unknown(raw))
+  }
+}
+
+/// Merge the updates of one progress window: adjacent Token chunks are joined and every Trace but the
+/// last is dropped, since each carries the whole trace so far. One remaining update is returned as-is,
+/// several as a Batch; None for an empty window.
+pub(crate) fn coalesce(events: Vec<ProgressEvent>) -> Option<ProgressEvent> {
+  let last_trace = events
+    .iter()
+    .rposition(|e| matches!(e, ProgressEvent::Trace { .. }));
+  let mut merged: Vec<ProgressEvent> = Vec::with_capacity(events.len());
+  for (i, event) in events.into_iter().enumerate() {
+    match (event, merged.last_mut()) {
+      (ProgressEvent::Trace { .. }, _) if Some(i) != last_trace => {}
+      (ProgressEvent::Token { text }, Some(ProgressEvent::Token { text: joined })) => joined.push_str(&text),
+      (event, _) => merged.push(event),
+    }
+  }
+  match merged.len() {
+    0 => None,
+    1 => merged.pop(),
+    _ => Some(ProgressEvent::Batch { events: merged }),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn round_trip(event: ProgressEvent, wire: serde_json::Value) {
+    assert_eq!(serde_json::to_value(&event).unwrap(), wire);
+    assert_eq!(
+      serde_json::from_value::<ProgressEvent>(wire).unwrap(),
+      event
+    );
+  }
+
+  #[test]
+  fn variants_round_trip() {
+    round_trip(
+      ProgressEvent::Trace {
+        trace_steps: vec![json!({ "node_name": "planner" })],
+        state: None,
+      },
+      json!({ "kind": "trace", "trace_steps": [{ "node_name": "planner" }] }),
+    );
+    round_trip(
+      ProgressEvent::Step {
+        name: "retrieve".to_string(),
+        detail: Some("12 hits".to_string()),
+      },
+      json!({ "kind": "step", "name": "retrieve", "detail": "12 hits" }),
+    );
+    round_trip(
+      ProgressEvent::Token {
+        text: "Hel".to_string(),
+      },
+      json!({ "kind": "token", "text": "Hel" }),
+    );
+    round_trip(
+      ProgressEvent::ToolCall {
+        name: "search".to_string(),
+        arguments: json!({ "q": "trip" }),
+      },
+      json!({ "kind": "tool_call", "name": "search", "arguments": { "q": "trip" } }),
+    );
+    round_trip(
+      ProgressEvent::Unknown(json!({ "kind": "reasoning", "text": "..." })),
+      json!({ "kind": "reasoning", "text": "..." }),
+    );
+  }
+
+  #[test]
+  fn from_backend_maps_legacy_shapes() {
+    assert_eq!(
+      ProgressEvent::from_backend(json!({ "type": "progress", "trace_steps": [] })),
+      ProgressEvent::Trace {
+        trace_steps: vec![],
+        state: None
+      }
+    );
+    let state = json!({ "search_queries": ["trip"], "iterations": 1 });
+    assert_eq!(
+      ProgressEvent::from_backend(json!({ "type": "progress", "trace_steps": [], "state": state })),
+      ProgressEvent::Trace {
+        trace_steps: vec![],
+        state: Some(state.clone())
+      },
+      "a verbose query's state is kept"
+    );
+    assert_eq!(
+      ProgressEvent::from_backend(json!({ "type": "progress", "stage": "layer1", "step": "embed" })),
+      ProgressEvent::Step {
+        name: "embed".to_string(),
+        detail: None
+      }
+    );
+    assert_eq!(
+      ProgressEvent::from_backend(json!({ "type": "progress", "kind": "token", "text": "a" })),
+      ProgressEvent::Token {
+        text: "a".to_string()
+      }
+    );
+    assert_eq!(
+      ProgressEvent::from_backend(json!({ "type": "progress", "mystery": 1 })),
+      ProgressEvent::Unknown(json!({ "mystery": 1 }))
+    );
+  }
+
+  #[test]
+  fn coalesce_joins_tokens_and_keeps_latest_trace() {
+    let token = |t: &str| ProgressEvent::Token {
+      text: t.to_string(),
+    };
+    let trace = |n: usize| ProgressEvent::Trace {
+      trace_steps: vec![json!({}); n],
+      state: None,
+    };
+    assert_eq!(coalesce(vec![]), None);
+    assert_eq!(coalesce(vec![token("a"), token("b"), token("c")]), Some(token("abc")));
+    assert_eq!(
+      coalesce(vec![trace(1), token("a"), trace(2), token("b")]),
+      Some(ProgressEvent::Batch {
+        events: vec![token("a"), trace(2), token("b")]
+      })
+    );
+    assert_eq!(
+      serde_json::to_value(ProgressEvent::Batch {
+        events: vec![token("x")]
+      })
+      .unwrap(),
+      json!({ "kind": "batch", "events": [{ "kind": "token", "text": "x" }] })
+    );
+  }
+}
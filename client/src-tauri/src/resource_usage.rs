@@ -0,0 +1,101 @@
+//! Memory and CPU usage of a backend process, read from the OS (`ps` on Unix, PowerShell on Windows).
+
+use std::process::Command;
+
+/// Point-in-time usage. On Unix it covers the whole process group, so dev mode counts the Python
+/// interpreter under `uv run` rather than just `uv`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ResourceUsage {
+  pub(crate) rss_bytes: u64,
+  /// CPU time over wall time since the process started, as a percentage of one core.
+  pub(crate) cpu_percent: f64,
+}
+
+/// Usage of process `pid` (and its process group on Unix). Errors if no such process is running.
+pub(crate) fn resource_usage(pid: u32) -> Result<ResourceUsage, String> {
+  #[cfg(unix)]
+  {
+    let out = Command::new("ps")
+      .args(["-A", "-o", "pgid=,rss=,%cpu="])
+      .output()
+      .map_err(|e| format!("Failed to run ps: {}", e))?;
+    parse_ps_group(&String::from_utf8_lossy(&out.stdout), pid)
+      .ok_or_else(|| format!("No running process with pid {}", pid))
+  }
+
+  #[cfg(windows)]
+  {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    let script = format!(
+      "$p = Get-Process -Id {} -ErrorAction Stop; \
+       \"$($p.WorkingSet64) $($p.TotalProcessorTime.TotalSeconds) $(((Get-Date) - $p.StartTime).TotalSeconds)\"",
+      pid
+    );
+    let out = Command::new("powershell")
+      .args(["-NoProfile", "-Command", &script])
+      .creation_flags(CREATE_NO_WINDOW)
+      .output()
+      .map_err(|e| format!("Failed to run powershell: {}", e))?;
+    if !out.status.success() {
+      return Err(format!("No running process with pid {}", pid));
+    }
+    let text = String::from_utf8_lossy(&out.stdout);
+    let fields: Vec<f64> = text
+      .split_whitespace()
+      .filter_map(|f| f.parse().ok())
+      .collect();
+    let [rss, cpu_secs, elapsed_secs] = fields[..] else {
+      return Err(format!("Unexpected Get-Process output: {}", text.trim()));
+    };
+    Ok(ResourceUsage {
+      rss_bytes: rss as u64,
+      cpu_percent: if elapsed_secs > 0.0 {
+        cpu_secs / elapsed_secs * 100.0
+      } else {
+        0.0
+      },
+    })
+  }
+}
+
+/// Sum the `pgid rss_kib %cpu` rows of `ps` output belonging to process group `pgid`.
+/// None when the group has no members.
+#[cfg_attr(windows, allow(dead_code))]
+fn parse_ps_group(output: &str, pgid: u32) -> Option<ResourceUsage> {
+  let mut usage = None;
+  for line in output.lines() {
+    let mut fields = line.split_whitespace();
+    let (Some(group), Some(rss), Some(cpu)) = (fields.next(), fields.next(), fields.next()) else {
+      continue;
+    };
+    if group.parse::<u32>().ok() != Some(pgid) {
+      continue;
+    }
+    let total = usage.get_or_insert(ResourceUsage {
+      rss_bytes: 0,
+      cpu_percent: 0.0,
+    });
+    total.rss_bytes += rss.parse::<u64>().unwrap_or(0) * 1024;
+    total.cpu_percent += cpu.parse::<f64>().unwrap_or(0.0);
+  }
+  usage
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_ps_group_sums_group_members() {
+    let output = "  100  2048  1.5\n  100 1024 0.5\n  200 9999 50.0\n";
+    assert_eq!(
+      parse_ps_group(output, 100),
+      Some(ResourceUsage {
+        rss_bytes: 3072 * 1024,
+        cpu_percent: 2.0,
+      })
+    );
+    assert_eq!(parse_ps_group(output, 300), None);
+  }
+}
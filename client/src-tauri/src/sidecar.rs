@@ -0,0 +1,285 @@
+//! Locating the bundled backend binary in release builds, and explaining what was found when it can't be
+//! used: missing resources, a missing `bin/backend` directory, or a sidecar built for another target. With
+//! the `verify-sidecar` feature the binary is also checked against the SHA-256 in MANIFEST_FILE.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// What was looked for and what was there; logged and returned by get_spawn_diagnostics so a packaging bug
+/// can be told apart from a broken install.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub(crate) struct SidecarDiagnostics {
+  pub(crate) resource_dir: PathBuf,
+  /// Target triple the app was built for (APP_TARGET).
+  pub(crate) target: String,
+  pub(crate) expected_path: PathBuf,
+  pub(crate) expected_exists: bool,
+  /// Whether `<resource_dir>/bin/backend` exists at all.
+  pub(crate) bin_dir_exists: bool,
+  /// `backend-*` files found in that directory, sorted.
+  pub(crate) found: Vec<String>,
+  /// Set when a sidecar is present but built for another architecture.
+  pub(crate) mismatch: Option<TargetMismatch>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub(crate) struct TargetMismatch {
+  pub(crate) file: String,
+  /// Architecture read from the file's header, if recognised.
+  pub(crate) arch: Option<String>,
+}
+
+impl SidecarDiagnostics {
+  /// The expected binary exists and was not built for another architecture.
+  pub(crate) fn usable(&self) -> bool {
+    self.expected_exists && self.mismatch.is_none()
+  }
+}
+
+/// Written next to the sidecars by `npm run build:sidecar-manifest` (scripts/sidecar-manifest.mjs):
+/// {"sha256": {"<file name>": "<hex>"}}. Only bundled by `npm run tauri:build:verified`.
+pub(crate) const MANIFEST_FILE: &str = "manifest.json";
+
+/// `<resource_dir>/bin/backend/backend-<target>[.exe]`, where the bundler puts the sidecar.
+pub(crate) fn sidecar_path(resource_dir: &Path, target: &str) -> PathBuf {
+  resource_dir
+    .join("bin")
+    .join("backend")
+    .join(format!("backend-{}{}", target, if cfg!(windows) { ".exe" } else { "" }))
+}
+
+/// Look for the sidecar of `target` under `resource_dir`. A present binary whose header names another
+/// architecture is a mismatch; so, when the expected one is missing, is any other `backend-*` file.
+pub(crate) fn diagnose(resource_dir: &Path, target: &str) -> SidecarDiagnostics {
+  let expected_path = sidecar_path(resource_dir, target);
+  let bin_dir = expected_path.parent().unwrap_or(resource_dir);
+  let mut found: Vec<String> = std::fs::read_dir(bin_dir)
+    .map(|entries| {
+      entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("backend-"))
+        .collect()
+    })
+    .unwrap_or_default();
+  found.sort();
+  let expected_exists = expected_path.is_file();
+  let mismatch = if expected_exists {
+    binary_arch(&expected_path)
+      .filter(|arch| !arch_matches(arch, target))
+      .map(|arch| TargetMismatch {
+        file: expected_path
+          .file_name()
+          .map(|n| n.to_string_lossy().into_owned())
+          .unwrap_or_default(),
+        arch: Some(arch.to_string()),
+      })
+  } else {
+    found.first().map(|file| TargetMismatch {
+      file: file.clone(),
+      arch: binary_arch(&bin_dir.join(file)).map(str::to_string),
+    })
+  };
+  SidecarDiagnostics {
+    resource_dir: resource_dir.to_path_buf(),
+    target: target.to_string(),
+    bin_dir_exists: bin_dir.is_dir(),
+    expected_path,
+    expected_exists,
+    found,
+    mismatch,
+  }
+}
+
+/// Refuse a sidecar whose SHA-256 differs from the one MANIFEST_FILE (in the same directory) lists for it, or
+/// that the manifest doesn't list: it was tampered with or only partly written.
+#[cfg(feature = "verify-sidecar")]
+pub(crate) fn verify_checksum(path: &Path) -> Result<(), String> {
+  let name = path
+    .file_name()
+    .map(|n| n.to_string_lossy().into_owned())
+    .unwrap_or_default();
+  let manifest_path = path.with_file_name(MANIFEST_FILE);
+  let manifest = std::fs::read_to_string(&manifest_path)
+    .map_err(|e| format!("cannot read {}: {}", manifest_path.display(), e))?;
+  let expected = expected_sha256(&manifest, &name)?;
+  let file = std::fs::File::open(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+  let actual = sha256_hex(file).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+  if !actual.eq_ignore_ascii_case(&expected) {
+    return Err(format!(
+      "SHA-256 of {} is {} but the manifest expects {}",
+      name, actual, expected
+    ));
+  }
+  Ok(())
+}
+
+/// The hash MANIFEST_FILE content `manifest` lists for `file`.
+#[cfg(feature = "verify-sidecar")]
+fn expected_sha256(manifest: &str, file: &str) -> Result<String, String> {
+  let manifest: serde_json::Value =
+    serde_json::from_str(manifest).map_err(|e| format!("{} is not valid JSON: {}", MANIFEST_FILE, e))?;
+  manifest["sha256"][file]
+    .as_str()
+    .map(str::to_string)
+    .ok_or_else(|| format!("{} has no SHA-256 for {}", MANIFEST_FILE, file))
+}
+
+#[cfg(feature = "verify-sidecar")]
+fn sha256_hex(mut reader: impl Read) -> std::io::Result<String> {
+  use sha2::{Digest, Sha256};
+  let mut hasher = Sha256::new();
+  std::io::copy(&mut reader, &mut hasher)?;
+  Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Architecture of an executable from its ELF, Mach-O or PE header: "x86_64", "aarch64", "x86", "arm" or
+/// "universal" (a Mach-O fat binary). None if unreadable or unrecognised.
+pub(crate) fn binary_arch(path: &Path) -> Option<&'static str> {
+  let mut header = Vec::with_capacity(4096);
+  std::fs::File::open(path)
+    .ok()?
+    .take(4096)
+    .read_to_end(&mut header)
+    .ok()?;
+  parse_arch(&header)
+}
+
+fn parse_arch(header: &[u8]) -> Option<&'static str> {
+  let u16_le = |at: usize| Some(u16::from_le_bytes(header.get(at..at + 2)?.try_into().ok()?));
+  let u32_le = |at: usize| Some(u32::from_le_bytes(header.get(at..at + 4)?.try_into().ok()?));
+  if header.starts_with(b"\x7fELF") {
+    // e_machine; every target we ship is little-endian.
+    return match u16_le(18)? {
+      0x3e => Some("x86_64"),
+      0xb7 => Some("aarch64"),
+      0x03 => Some("x86"),
+      0x28 => Some("arm"),
+      _ => None,
+    };
+  }
+  if header.starts_with(&[0xca, 0xfe, 0xba, 0xbe]) {
+    return Some("universal");
+  }
+  if header.starts_with(&[0xcf, 0xfa, 0xed, 0xfe]) || header.starts_with(&[0xce, 0xfa, 0xed, 0xfe]) {
+    return match u32_le(4)? {
+      0x0100_0007 => Some("x86_64"),
+      0x0100_000c => Some("aarch64"),
+      7 => Some("x86"),
+      12 => Some("arm"),
+      _ => None,
+    };
+  }
+  if header.starts_with(b"MZ") {
+    let pe = u32_le(0x3c)? as usize;
+    if header.get(pe..pe + 4)? != b"PE\0\0" {
+      return None;
+    }
+    return match u16_le(pe + 4)? {
+      0x8664 => Some("x86_64"),
+      0xaa64 => Some("aarch64"),
+      0x014c => Some("x86"),
+      0x01c4 => Some("arm"),
+      _ => None,
+    };
+  }
+  None
+}
+
+/// Whether a binary for `arch` runs on `target` (a triple such as `aarch64-apple-darwin`).
+fn arch_matches(arch: &str, target: &str) -> bool {
+  let target_arch = match target.split('-').next().unwrap_or("") {
+    "i586" | "i686" => "x86",
+    a if a.starts_with("arm") || a.starts_with("thumb") => "arm",
+    a => a,
+  };
+  arch == "universal" || arch == target_arch
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn diagnose_reports_a_sidecar_for_another_target() {
+    let dir = std::env::temp_dir().join(format!("narrarc-sidecar-test-{}", std::process::id()));
+    let bin = dir.join("bin").join("backend");
+    std::fs::create_dir_all(&bin).unwrap();
+    let mut elf = vec![0u8; 64];
+    elf[..4].copy_from_slice(b"\x7fELF");
+    elf[18] = 0xb7;
+    std::fs::write(bin.join("backend-aarch64-unknown-linux-gnu"), &elf).unwrap();
+
+    let missing = diagnose(&dir, "x86_64-unknown-linux-gnu");
+    assert!(missing.bin_dir_exists && !missing.expected_exists && !missing.usable());
+    assert_eq!(
+      missing.mismatch,
+      Some(TargetMismatch {
+        file: "backend-aarch64-unknown-linux-gnu".to_string(),
+        arch: Some("aarch64".to_string()),
+      })
+    );
+    if !cfg!(windows) {
+      assert!(diagnose(&dir, "aarch64-unknown-linux-gnu").usable());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let empty = diagnose(&dir, "x86_64-unknown-linux-gnu");
+    assert!(!empty.bin_dir_exists && empty.found.is_empty() && empty.mismatch.is_none());
+  }
+
+  #[cfg(feature = "verify-sidecar")]
+  #[test]
+  fn verify_checksum_compares_against_the_manifest() {
+    let dir = std::env::temp_dir().join(format!("narrarc-checksum-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let sidecar = dir.join("backend-test");
+    std::fs::write(&sidecar, b"abc").unwrap();
+    let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    let manifest = |hash: &str| serde_json::json!({ "sha256": { "backend-test": hash } }).to_string();
+
+    std::fs::write(dir.join(MANIFEST_FILE), manifest(abc)).unwrap();
+    assert_eq!(verify_checksum(&sidecar), Ok(()));
+    std::fs::write(&sidecar, b"ab").unwrap();
+    assert!(verify_checksum(&sidecar).unwrap_err().contains("manifest expects"));
+    std::fs::write(dir.join(MANIFEST_FILE), "{}").unwrap();
+    assert!(verify_checksum(&sidecar).unwrap_err().contains("no SHA-256 for backend-test"));
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  /// The manifest scripts/sidecar-manifest.mjs writes is one verify_checksum accepts, and the script refuses
+  /// to hash a sidecar the bundler would re-sign afterwards. Skipped where node is not installed.
+  #[cfg(feature = "verify-sidecar")]
+  #[test]
+  fn manifest_script_output_passes_verify_checksum() {
+    let script = Path::new(env!("CARGO_MANIFEST_DIR")).join("../scripts/sidecar-manifest.mjs");
+    let run = |dir: &Path, signing: bool| {
+      let mut cmd = std::process::Command::new("node");
+      cmd
+        .arg(&script)
+        .arg(dir)
+        .env_remove("APPLE_SIGNING_IDENTITY")
+        .env_remove("APPLE_CERTIFICATE");
+      if signing {
+        cmd.env("APPLE_SIGNING_IDENTITY", "Developer ID Application: Test");
+      }
+      cmd.status()
+    };
+    let dir = std::env::temp_dir().join(format!("narrarc-manifest-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let sidecar = dir.join("backend-x86_64-unknown-linux-gnu");
+    std::fs::write(&sidecar, b"sidecar bytes").unwrap();
+
+    let Ok(status) = run(&dir, false) else {
+      std::fs::remove_dir_all(&dir).unwrap();
+      return;
+    };
+    assert!(status.success());
+    assert_eq!(verify_checksum(&sidecar), Ok(()));
+
+    std::fs::remove_file(dir.join(MANIFEST_FILE)).unwrap();
+    assert!(!run(&dir, true).unwrap().success());
+    assert!(!dir.join(MANIFEST_FILE).exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
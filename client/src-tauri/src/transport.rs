@@ -0,0 +1,893 @@
+//! Transport to the backend: the write/read halves behind traits (so tests can script a fake backend),
+//! line or length-prefixed framing, the stdout dispatcher that routes messages by req_id, and response
+//! parsing.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use crate::ipc_log::{self, Direction};
+
+/// Error text sent to every pending request when the backend prints a line over the size cap.
+const LINE_TOO_LONG: &str = "backend response exceeded max line size";
+
+/// Largest single write() of a message to the backend. A multi-megabyte request goes out in pieces no
+/// bigger than a pipe's default capacity, so each write returns as the backend drains it.
+const WRITE_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Consecutive non-JSON stdout lines logged before the rest of the run is suppressed, so a chatty `print()`
+/// in the backend cannot flood the app log.
+const NON_JSON_LOG_LIMIT: usize = 20;
+
+/// Requests waiting for output, keyed by req_id; the stdout dispatcher routes each line to its route.
+pub(crate) type PendingMap = Arc<Mutex<HashMap<u64, Route>>>;
+
+/// One line routed to a request, numbered in the order the dispatcher read it (0, 1, 2, ... per request),
+/// so consumers further down (coalescing, events) can keep or check token order.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StreamLine {
+  pub(crate) seq: u64,
+  pub(crate) data: serde_json::Value,
+}
+
+/// Sending side of a registered request; numbers lines as they go into the channel.
+#[derive(Clone)]
+pub(crate) struct Route {
+  tx: UnboundedSender<StreamLine>,
+  next_seq: u64,
+}
+
+impl Route {
+  pub(crate) fn send(&mut self, data: serde_json::Value) {
+    let seq = self.next_seq;
+    self.next_seq += 1;
+    let _ = self.tx.send(StreamLine { seq, data });
+  }
+}
+
+/// How messages are delimited on the pipe. Every backend starts in Lines; LengthPrefixed (a 4-byte
+/// big-endian length, then the JSON bytes) is switched to after the handshake when the backend advertises
+/// it, so embedded newlines or a split write can't corrupt a message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Framing {
+  Lines,
+  LengthPrefixed,
+}
+
+impl Framing {
+  /// Name of the length-prefixed mode in the handshake (`"framing": ["length"]` in the pong).
+  pub(crate) const LENGTH_PREFIXED: &'static str = "length";
+}
+
+/// Write half of the transport: one JSON message per call. Real pipes use FrameWriter over ChildStdin.
+pub(crate) trait BackendWriter: Send {
+  fn send_line(&mut self, line: &str) -> std::io::Result<()>;
+
+  /// Delimit later messages with `framing`. Plain writers only speak Lines.
+  fn set_framing(&mut self, framing: Framing) -> std::io::Result<()> {
+    match framing {
+      Framing::Lines => Ok(()),
+      Framing::LengthPrefixed => Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "writer only supports line framing",
+      )),
+    }
+  }
+}
+
+impl<W: Write + Send> BackendWriter for W {
+  fn send_line(&mut self, line: &str) -> std::io::Result<()> {
+    write_chunked(self, line.as_bytes())?;
+    self.write_all(b"\n")?;
+    self.flush()
+  }
+}
+
+/// BackendWriter that can switch from lines to length-prefixed frames.
+pub(crate) struct FrameWriter<W> {
+  inner: W,
+  framing: Framing,
+}
+
+impl<W: Write + Send> FrameWriter<W> {
+  pub(crate) fn new(inner: W) -> Self {
+    FrameWriter {
+      inner,
+      framing: Framing::Lines,
+    }
+  }
+}
+
+impl<W: Write + Send> BackendWriter for FrameWriter<W> {
+  fn send_line(&mut self, line: &str) -> std::io::Result<()> {
+    match self.framing {
+      Framing::Lines => {
+        write_chunked(&mut self.inner, line.as_bytes())?;
+        self.inner.write_all(b"\n")?;
+      }
+      Framing::LengthPrefixed => {
+        self.inner.write_all(&frame_header(line.len())?)?;
+        write_chunked(&mut self.inner, line.as_bytes())?;
+      }
+    }
+    self.inner.flush()
+  }
+
+  fn set_framing(&mut self, framing: Framing) -> std::io::Result<()> {
+    self.framing = framing;
+    Ok(())
+  }
+}
+
+/// The 4 big-endian length bytes that start a frame of `len` bytes.
+pub(crate) fn frame_header(len: usize) -> std::io::Result<[u8; 4]> {
+  let len = u32::try_from(len).map_err(|_| {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, "message too large for a frame")
+  })?;
+  Ok(len.to_be_bytes())
+}
+
+/// Write `bytes` in WRITE_CHUNK_BYTES pieces rather than with one write() of the whole message.
+fn write_chunked(writer: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+  for chunk in bytes.chunks(WRITE_CHUNK_BYTES) {
+    writer.write_all(chunk)?;
+  }
+  Ok(())
+}
+
+/// Read half of the transport: the next message (a line without its newline, or a frame's payload), None
+/// at EOF. An oversized message is reported as an InvalidData error; the reader stays usable afterwards.
+pub(crate) trait BackendReader: Send {
+  fn read_line(&mut self) -> std::io::Result<Option<String>>;
+
+  /// Parse later messages with `framing`. Takes effect from the next read_line.
+  fn set_framing(&mut self, _framing: Framing) {}
+}
+
+/// BackendReader over a byte stream (backend stdout) with a per-message size cap.
+pub(crate) struct PipeReader<R> {
+  inner: BufReader<R>,
+  max_line_bytes: usize,
+  framing: Framing,
+}
+
+impl<R: Read> PipeReader<R> {
+  /// `buffer_bytes` is the read buffer size; each refill is one read() on the pipe.
+  pub(crate) fn new(inner: R, max_line_bytes: usize, buffer_bytes: usize) -> Self {
+    PipeReader {
+      inner: BufReader::with_capacity(buffer_bytes, inner),
+      max_line_bytes,
+      framing: Framing::Lines,
+    }
+  }
+}
+
+impl<R: Read + Send> BackendReader for PipeReader<R> {
+  fn read_line(&mut self) -> std::io::Result<Option<String>> {
+    let message = match self.framing {
+      Framing::Lines => read_bounded_line(&mut self.inner, self.max_line_bytes)?,
+      Framing::LengthPrefixed => read_frame(&mut self.inner, self.max_line_bytes)?,
+    };
+    match message {
+      BoundedLine::Eof => Ok(None),
+      BoundedLine::Line(bytes) => Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+      BoundedLine::TooLong => Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        LINE_TOO_LONG,
+      )),
+    }
+  }
+
+  fn set_framing(&mut self, framing: Framing) {
+    self.framing = framing;
+  }
+}
+
+/// Result of read_bounded_line.
+#[derive(Debug, PartialEq)]
+pub(crate) enum BoundedLine {
+  Eof,
+  /// One line without its trailing newline.
+  Line(Vec<u8>),
+  /// The line was longer than the cap; it has been consumed and discarded.
+  TooLong,
+}
+
+/// Read one newline-terminated line of at most `max` bytes. An oversized line is skipped through its
+/// newline without being buffered, so memory stays bounded whatever the backend prints. Lines are taken
+/// from the buffered data as soon as their newline arrives, however the backend's flushes cut them: a read
+/// holding several lines yields each in turn, and a fragment without its newline waits for the next read.
+pub(crate) fn read_bounded_line(
+  reader: &mut impl BufRead,
+  max: usize,
+) -> std::io::Result<BoundedLine> {
+  let mut line = Vec::new();
+  let mut too_long = false;
+  loop {
+    let available = reader.fill_buf()?;
+    if available.is_empty() {
+      return Ok(if too_long {
+        BoundedLine::TooLong
+      } else if line.is_empty() {
+        BoundedLine::Eof
+      } else {
+        BoundedLine::Line(line)
+      });
+    }
+    let newline = available.iter().position(|&b| b == b'\n');
+    let chunk = &available[..newline.unwrap_or(available.len())];
+    if !too_long {
+      if line.len() + chunk.len() > max {
+        too_long = true;
+        line = Vec::new();
+      } else {
+        line.extend_from_slice(chunk);
+      }
+    }
+    let used = chunk.len() + usize::from(newline.is_some());
+    reader.consume(used);
+    if newline.is_some() {
+      return Ok(if too_long {
+        BoundedLine::TooLong
+      } else {
+        BoundedLine::Line(line)
+      });
+    }
+  }
+}
+
+/// Read one length-prefixed frame with a payload of at most `max` bytes; an oversized one is skipped
+/// without being buffered. EOF inside a frame is an UnexpectedEof error.
+pub(crate) fn read_frame(reader: &mut impl BufRead, max: usize) -> std::io::Result<BoundedLine> {
+  let mut header = [0u8; 4];
+  if reader.fill_buf()?.is_empty() {
+    return Ok(BoundedLine::Eof);
+  }
+  reader.read_exact(&mut header)?;
+  let len = u32::from_be_bytes(header) as usize;
+  if len > max {
+    let skipped = std::io::copy(&mut reader.take(len as u64), &mut std::io::sink())?;
+    if skipped < len as u64 {
+      return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    return Ok(BoundedLine::TooLong);
+  }
+  let mut payload = vec![0u8; len];
+  reader.read_exact(&mut payload)?;
+  Ok(BoundedLine::Line(payload))
+}
+
+/// A registered request: receives every backend line tagged with its req_id.
+/// Dropping it unregisters the id so late lines are discarded.
+pub(crate) struct PendingRequest {
+  pub(crate) req_id: u64,
+  pub(crate) pid: u32,
+  pub(crate) rx: UnboundedReceiver<StreamLine>,
+  pending: PendingMap,
+}
+
+impl PendingRequest {
+  /// Register req_id in `pending` before its payload is written, so no response can slip past.
+  pub(crate) fn register(pending: &PendingMap, req_id: u64, pid: u32) -> Result<Self, String> {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    pending
+      .lock()
+      .map_err(|e| e.to_string())?
+      .insert(req_id, Route { tx, next_seq: 0 });
+    Ok(PendingRequest {
+      req_id,
+      pid,
+      rx,
+      pending: pending.clone(),
+    })
+  }
+}
+
+impl Drop for PendingRequest {
+  fn drop(&mut self) {
+    if let Ok(mut map) = self.pending.lock() {
+      map.remove(&self.req_id);
+    }
+  }
+}
+
+/// Own backend stdout on a dedicated thread; see dispatch_lines.
+pub(crate) fn spawn_stdout_dispatcher(
+  mut reader: Box<dyn BackendReader>,
+  pending: PendingMap,
+  poisoned: Arc<AtomicBool>,
+) {
+  std::thread::spawn(move || dispatch_lines(reader.as_mut(), &pending, &poisoned));
+}
+
+/// Each message is {"req_id": ..., "data": ...}; route `data` to the pending request with that req_id.
+/// A {"type":"framing","mode":"length"} acknowledgement switches the reader to length-prefixed frames.
+/// Non-JSON lines (a stray `print()` in the backend) are skipped and logged, so the protocol stays in sync;
+/// untagged or unmatched lines are discarded. An oversized line fails every pending request and poisons
+/// the process. On EOF all waiters are dropped.
+pub(crate) fn dispatch_lines(
+  reader: &mut dyn BackendReader,
+  pending: &PendingMap,
+  poisoned: &AtomicBool,
+) {
+  let mut non_json_run = 0;
+  loop {
+    let line = match reader.read_line() {
+      Ok(Some(line)) => line,
+      Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+        log::error!("{}; failing pending requests", e);
+        poisoned.store(true, Ordering::SeqCst);
+        if let Ok(mut map) = pending.lock() {
+          for (_, mut route) in map.drain() {
+            route.send(serde_json::json!({ "type": "error", "message": LINE_TOO_LONG }));
+          }
+        }
+        continue;
+      }
+      Ok(None) | Err(_) => break,
+    };
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+      continue;
+    }
+    ipc_log::record(Direction::Received, trimmed);
+    let mut value = match serde_json::from_str::<serde_json::Value>(trimmed) {
+      Ok(v) => v,
+      Err(_) => {
+        non_json_run += 1;
+        if non_json_run <= NON_JSON_LOG_LIMIT {
+          log::warn!("Skipping non-JSON backend stdout line: {}", trimmed);
+        } else if non_json_run == NON_JSON_LOG_LIMIT + 1 {
+          log::warn!("Suppressing further non-JSON backend stdout lines until valid output resumes");
+        }
+        continue;
+      }
+    };
+    non_json_run = 0;
+    let Some(req_id) = value.get("req_id").and_then(|id| id.as_u64()) else {
+      log::debug!("Discarding untagged backend line: {}", trimmed);
+      continue;
+    };
+    let data = crate::encoding::decode(
+      value
+        .get_mut("data")
+        .map(serde_json::Value::take)
+        .unwrap_or(serde_json::Value::Null),
+    );
+    // The backend acknowledges set_framing in the old framing and switches right after, so switch before
+    // reading on.
+    if data.get("type").and_then(|t| t.as_str()) == Some("framing")
+      && data.get("mode").and_then(|m| m.as_str()) == Some(Framing::LENGTH_PREFIXED)
+    {
+      reader.set_framing(Framing::LengthPrefixed);
+    }
+    if let Ok(mut map) = pending.lock() {
+      match map.get_mut(&req_id) {
+        Some(route) => route.send(data),
+        None => log::debug!("Discarding backend line for stale req_id {}", req_id),
+      }
+    }
+  }
+  if let Ok(mut map) = pending.lock() {
+    map.clear();
+  }
+}
+
+/// Error payload returned to the frontend by backend commands. `code` lets the UI branch (e.g.
+/// "rate_limited" vs "auth_failed"); `details` carries anything else the backend attached.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub(crate) struct BackendError {
+  pub(crate) message: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) code: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub(crate) details: Option<serde_json::Value>,
+}
+
+impl BackendError {
+  pub(crate) fn with_code(code: &str, message: impl Into<String>) -> Self {
+    BackendError {
+      message: message.into(),
+      code: Some(code.to_string()),
+      details: None,
+    }
+  }
+
+  /// Build from a backend {"type":"error","message":...,"code":...} line. `details` is the backend's
+  /// own `details` field, or else every other field it sent (kind, traceback, ...).
+  pub(crate) fn from_error_value(value: &serde_json::Value) -> Self {
+    let message = value
+      .get("message")
+      .and_then(|m| m.as_str())
+      .unwrap_or("unknown error")
+      .to_string();
+    let code = value.get("code").and_then(|c| c.as_str()).map(str::to_string);
+    let details = match value.get("details") {
+      Some(details) => Some(details.clone()),
+      None => value.as_object().and_then(|obj| {
+        let rest: serde_json::Map<_, _> = obj
+          .iter()
+          .filter(|(k, _)| !matches!(k.as_str(), "type" | "message" | "code"))
+          .map(|(k, v)| (k.clone(), v.clone()))
+          .collect();
+        (!rest.is_empty()).then_some(serde_json::Value::Object(rest))
+      }),
+    };
+    BackendError {
+      message,
+      code,
+      details,
+    }
+  }
+}
+
+impl std::fmt::Display for BackendError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.message)
+  }
+}
+
+/// Errors raised on the Rust side (validation, spawn, ...) carry only a message.
+impl From<String> for BackendError {
+  fn from(message: String) -> Self {
+    BackendError {
+      message,
+      code: None,
+      details: None,
+    }
+  }
+}
+
+impl From<BackendError> for String {
+  fn from(e: BackendError) -> String {
+    e.message
+  }
+}
+
+/// Why a plain request produced no value.
+#[derive(Debug, PartialEq)]
+pub(crate) enum ResponseError {
+  /// No response within the timeout; the process should be treated as wedged.
+  TimedOut(Duration),
+  /// Backend stdout hit EOF (the process exited) before the response arrived.
+  Closed,
+  /// The backend answered {"type":"error",...}.
+  Backend(BackendError),
+  /// Backend stdout hit EOF partway through a streaming response, before its result/error line.
+  Crashed { exit_code: Option<i32> },
+}
+
+impl std::fmt::Display for ResponseError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ResponseError::TimedOut(t) => write!(f, "backend request timed out after {} ms", t.as_millis()),
+      ResponseError::Closed => write!(f, "backend closed the connection (process exited)"),
+      ResponseError::Backend(e) => write!(f, "{}", e),
+      ResponseError::Crashed { exit_code } => {
+        write!(f, "backend crashed during query (no result/error received)")?;
+        match exit_code {
+          Some(code) => write!(f, "; exit code {}", code),
+          None => Ok(()),
+        }
+      }
+    }
+  }
+}
+
+impl From<ResponseError> for BackendError {
+  fn from(e: ResponseError) -> Self {
+    match e {
+      ResponseError::TimedOut(_) => BackendError::with_code("timeout", e.to_string()),
+      ResponseError::Closed => BackendError::with_code("backend_exited", e.to_string()),
+      ResponseError::Backend(e) => e,
+      ResponseError::Crashed { exit_code } => BackendError {
+        message: e.to_string(),
+        code: Some("backend_crashed".to_string()),
+        details: exit_code.map(|code| serde_json::json!({ "exit_code": code })),
+      },
+    }
+  }
+}
+
+/// Wait for the single response of a plain request; {"type":"error"} becomes ResponseError::Backend.
+pub(crate) async fn await_response(
+  pending: &mut PendingRequest,
+  timeout: Duration,
+) -> Result<serde_json::Value, ResponseError> {
+  let value = match tokio::time::timeout(timeout, pending.rx.recv()).await {
+    Ok(Some(line)) => line.data,
+    Ok(None) => return Err(ResponseError::Closed),
+    Err(_) => return Err(ResponseError::TimedOut(timeout)),
+  };
+  if value.get("type").and_then(|t| t.as_str()) == Some("error") {
+    return Err(ResponseError::Backend(BackendError::from_error_value(&value)));
+  }
+  Ok(value)
+}
+
+/// Next line of a streaming response, with its seq, or None if `wait` passes without one. EOF is
+/// ResponseError::Crashed (without an exit code; the caller knows the process): a stream only ends
+/// legitimately with a result/error line, which the caller stops at.
+pub(crate) async fn next_stream_line(
+  pending: &mut PendingRequest,
+  wait: Duration,
+) -> Result<Option<StreamLine>, ResponseError> {
+  match tokio::time::timeout(wait, pending.rx.recv()).await {
+    Ok(Some(line)) => Ok(Some(line)),
+    Ok(None) => Err(ResponseError::Crashed { exit_code: None }),
+    Err(_) => Ok(None),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::VecDeque;
+
+  /// `payload` prefixed with its length as 4 big-endian bytes.
+  fn encode_frame(payload: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut frame = frame_header(payload.len())?.to_vec();
+    frame.extend_from_slice(payload);
+    Ok(frame)
+  }
+
+  /// Scripted backend stdout: hands out canned lines, then EOF.
+  struct ScriptedReader(VecDeque<std::io::Result<Option<String>>>);
+
+  impl ScriptedReader {
+    fn lines(lines: &[&str]) -> Self {
+      ScriptedReader(lines.iter().map(|l| Ok(Some(l.to_string()))).collect())
+    }
+  }
+
+  impl BackendReader for ScriptedReader {
+    fn read_line(&mut self) -> std::io::Result<Option<String>> {
+      self.0.pop_front().unwrap_or(Ok(None))
+    }
+  }
+
+  fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+      .enable_time()
+      .build()
+      .unwrap()
+  }
+
+  #[test]
+  fn read_bounded_line_skips_oversized_line() {
+    let mut input = vec![b'x'; 64 * 1024];
+    input.extend_from_slice(b"\n{\"ok\":true}\n");
+    let mut reader = BufReader::with_capacity(1024, input.as_slice());
+    assert_eq!(
+      read_bounded_line(&mut reader, 4096).unwrap(),
+      BoundedLine::TooLong
+    );
+    assert_eq!(
+      read_bounded_line(&mut reader, 4096).unwrap(),
+      BoundedLine::Line(b"{\"ok\":true}".to_vec())
+    );
+    assert_eq!(read_bounded_line(&mut reader, 4096).unwrap(), BoundedLine::Eof);
+  }
+
+  #[test]
+  fn read_bounded_line_returns_unterminated_last_line() {
+    let mut reader = BufReader::new(&b"a\nbc"[..]);
+    assert_eq!(
+      read_bounded_line(&mut reader, 16).unwrap(),
+      BoundedLine::Line(b"a".to_vec())
+    );
+    assert_eq!(
+      read_bounded_line(&mut reader, 16).unwrap(),
+      BoundedLine::Line(b"bc".to_vec())
+    );
+  }
+
+  #[test]
+  fn send_line_appends_newline() {
+    let mut out = Vec::new();
+    out.send_line("{\"cmd\":\"ping\"}").unwrap();
+    assert_eq!(out, b"{\"cmd\":\"ping\"}\n");
+  }
+
+  #[test]
+  fn dispatch_routes_lines_by_req_id() {
+    let pending = PendingMap::default();
+    let poisoned = AtomicBool::new(false);
+    let mut first = PendingRequest::register(&pending, 1, 0).unwrap();
+    let mut second = PendingRequest::register(&pending, 2, 0).unwrap();
+    let mut reader = ScriptedReader::lines(&[
+      "not json",
+      r#"{"type":"untagged"}"#,
+      r#"{"req_id":2,"data":{"type":"progress"}}"#,
+      r#"{"req_id":1,"data":[1,2]}"#,
+      r#"{"req_id":99,"data":"stale"}"#,
+      r#"{"req_id":2,"data":{"type":"result"}}"#,
+    ]);
+    dispatch_lines(&mut reader, &pending, &poisoned);
+    assert_eq!(first.rx.try_recv().unwrap().data, serde_json::json!([1, 2]));
+    assert_eq!(
+      second.rx.try_recv().unwrap().data,
+      serde_json::json!({ "type": "progress" })
+    );
+    assert_eq!(
+      second.rx.try_recv().unwrap().data,
+      serde_json::json!({ "type": "result" })
+    );
+    // EOF drops every waiter so nobody blocks on a dead process.
+    assert!(second.rx.try_recv().is_err());
+    assert!(pending.lock().unwrap().is_empty());
+    assert!(!poisoned.load(Ordering::SeqCst));
+  }
+
+  #[test]
+  fn dispatch_numbers_lines_per_request_in_order() {
+    let pending = PendingMap::default();
+    let mut tokens = PendingRequest::register(&pending, 1, 0).unwrap();
+    let mut other = PendingRequest::register(&pending, 2, 0).unwrap();
+    let lines: Vec<String> = (0..50)
+      .map(|i| {
+        let req_id = if i % 3 == 0 { 2 } else { 1 };
+        format!(r#"{{"req_id":{},"data":{{"type":"progress","kind":"token","text":"{}"}}}}"#, req_id, i)
+      })
+      .collect();
+    let refs: Vec<&str> = lines.iter().map(String::as_str).collect();
+    dispatch_lines(&mut ScriptedReader::lines(&refs), &pending, &AtomicBool::new(false));
+    for (request, want) in [(&mut tokens, 1), (&mut other, 2)] {
+      let mut expected_seq = 0;
+      let mut last_text = -1;
+      while let Ok(line) = request.rx.try_recv() {
+        assert_eq!(line.seq, expected_seq, "gap or reorder for req {}", want);
+        let text: i64 = line.data["text"].as_str().unwrap().parse().unwrap();
+        assert!(text > last_text);
+        assert_eq!(text % 3 == 0, want == 2);
+        last_text = text;
+        expected_seq += 1;
+      }
+      assert!(expected_seq > 0);
+    }
+  }
+
+  /// Backend stdout that returns one chunk per read(), blocking until the test sends the next.
+  struct ChunkedPipe(std::sync::mpsc::Receiver<&'static [u8]>);
+
+  impl Read for ChunkedPipe {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      let Ok(chunk) = self.0.recv() else {
+        return Ok(0);
+      };
+      buf[..chunk.len()].copy_from_slice(chunk);
+      Ok(chunk.len())
+    }
+  }
+
+  #[test]
+  fn dispatch_forwards_complete_lines_and_holds_a_partial_one_across_reads() {
+    let pending = PendingMap::default();
+    let mut request = PendingRequest::register(&pending, 1, 0).unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let dispatcher = {
+      let pending = pending.clone();
+      std::thread::spawn(move || {
+        let mut reader = PipeReader::new(ChunkedPipe(rx), 1024, 1024);
+        dispatch_lines(&mut reader, &pending, &AtomicBool::new(false));
+      })
+    };
+    tx.send(b"{\"req_id\":1,\"data\":{\"type\":\"progress\"}}\n{\"req_id\":1,\"data\":{\"type\":\"resul")
+      .unwrap();
+    let rt = runtime();
+    let first = rt.block_on(async { tokio::time::timeout(Duration::from_secs(5), request.rx.recv()).await });
+    assert_eq!(
+      first.unwrap().unwrap().data["type"],
+      "progress",
+      "a complete line must not wait for the rest of the chunk"
+    );
+    assert!(request.rx.try_recv().is_err(), "the fragment must not be forwarded before its newline");
+    tx.send(b"t\"}}\n").unwrap();
+    let second = rt.block_on(async { tokio::time::timeout(Duration::from_secs(5), request.rx.recv()).await });
+    assert_eq!(second.unwrap().unwrap().data["type"], "result");
+    drop(tx);
+    dispatcher.join().unwrap();
+  }
+
+  #[test]
+  fn dispatch_fails_pending_on_oversized_line() {
+    let pending = PendingMap::default();
+    let poisoned = AtomicBool::new(false);
+    let mut request = PendingRequest::register(&pending, 7, 0).unwrap();
+    let mut input = vec![b'x'; 1024];
+    input.push(b'\n');
+    let mut reader = PipeReader::new(input.as_slice(), 64, 16);
+    dispatch_lines(&mut reader, &pending, &poisoned);
+    assert!(poisoned.load(Ordering::SeqCst));
+    let rt = runtime();
+    assert_eq!(
+      rt.block_on(await_response(&mut request, Duration::from_secs(1))),
+      Err(ResponseError::Backend(LINE_TOO_LONG.to_string().into()))
+    );
+  }
+
+  #[test]
+  fn dispatch_skips_non_json_lines() {
+    let pending = PendingMap::default();
+    let mut request = PendingRequest::register(&pending, 1, 0).unwrap();
+    let input = "garbage\n{\"req_id\":1,\"data\":{\"ok\":true}}\n";
+    let mut reader = PipeReader::new(input.as_bytes(), 1024, 8);
+    dispatch_lines(&mut reader, &pending, &AtomicBool::new(false));
+    assert_eq!(
+      runtime().block_on(await_response(&mut request, Duration::from_secs(1))),
+      Ok(serde_json::json!({ "ok": true }))
+    );
+  }
+
+  #[test]
+  fn await_response_reports_eof_as_closed() {
+    let pending = PendingMap::default();
+    let mut request = PendingRequest::register(&pending, 1, 0).unwrap();
+    let mut reader = ScriptedReader::lines(&[]);
+    dispatch_lines(&mut reader, &pending, &AtomicBool::new(false));
+    let result = runtime().block_on(await_response(&mut request, Duration::from_secs(1)));
+    assert_eq!(result, Err(ResponseError::Closed));
+    assert_eq!(
+      result.unwrap_err().to_string(),
+      "backend closed the connection (process exited)"
+    );
+  }
+
+  #[test]
+  fn await_response_parses_errors_and_times_out() {
+    let rt = runtime();
+    let pending = PendingMap::default();
+    let mut ok = PendingRequest::register(&pending, 1, 0).unwrap();
+    let mut failed = PendingRequest::register(&pending, 2, 0).unwrap();
+    let mut silent = PendingRequest::register(&pending, 3, 0).unwrap();
+    let mut reader = ScriptedReader::lines(&[
+      r#"{"req_id":1,"data":{"status":"deleted"}}"#,
+      r#"{"req_id":2,"data":{"type":"error","message":"Unknown cmd: x","code":"bad_cmd","trace":"t"}}"#,
+    ]);
+    // Keep request 3 registered past EOF by dispatching into a copy of the map.
+    let routed = PendingMap::default();
+    for id in [1, 2] {
+      let tx = pending.lock().unwrap().get(&id).unwrap().clone();
+      routed.lock().unwrap().insert(id, tx);
+    }
+    dispatch_lines(&mut reader, &routed, &AtomicBool::new(false));
+    let timeout = Duration::from_millis(20);
+    assert_eq!(
+      rt.block_on(await_response(&mut ok, timeout)),
+      Ok(serde_json::json!({ "status": "deleted" }))
+    );
+    assert_eq!(
+      rt.block_on(await_response(&mut failed, timeout)),
+      Err(ResponseError::Backend(BackendError {
+        message: "Unknown cmd: x".to_string(),
+        code: Some("bad_cmd".to_string()),
+        details: Some(serde_json::json!({ "trace": "t" })),
+      }))
+    );
+    assert_eq!(
+      rt.block_on(await_response(&mut silent, timeout)),
+      Err(ResponseError::TimedOut(timeout))
+    );
+  }
+
+  #[test]
+  fn stream_ending_without_terminal_is_a_crash() {
+    let pending = PendingMap::default();
+    let mut request = PendingRequest::register(&pending, 5, 0).unwrap();
+    let mut reader =
+      ScriptedReader::lines(&[r#"{"req_id":5,"data":{"type":"progress","trace_steps":[]}}"#]);
+    dispatch_lines(&mut reader, &pending, &AtomicBool::new(false));
+    let rt = runtime();
+    let wait = Duration::from_secs(1);
+    assert_eq!(
+      rt.block_on(next_stream_line(&mut request, wait)),
+      Ok(Some(StreamLine {
+        seq: 0,
+        data: serde_json::json!({ "type": "progress", "trace_steps": [] }),
+      }))
+    );
+    let crashed = rt.block_on(next_stream_line(&mut request, wait)).unwrap_err();
+    assert_eq!(crashed, ResponseError::Crashed { exit_code: None });
+    let error = BackendError::from(ResponseError::Crashed { exit_code: Some(-9) });
+    assert_eq!(error.code.as_deref(), Some("backend_crashed"));
+    assert_eq!(
+      error.message,
+      "backend crashed during query (no result/error received); exit code -9"
+    );
+    assert_eq!(error.details, Some(serde_json::json!({ "exit_code": -9 })));
+  }
+
+  #[test]
+  fn frames_round_trip_embedded_newlines() {
+    let message = "{\"text\":\"line one\nline two\"}";
+    let mut wire = encode_frame(message.as_bytes()).unwrap();
+    assert_eq!(&wire[..4], &(message.len() as u32).to_be_bytes());
+    wire.extend(encode_frame(b"{}").unwrap());
+    let mut reader = BufReader::new(wire.as_slice());
+    assert_eq!(
+      read_frame(&mut reader, 1024).unwrap(),
+      BoundedLine::Line(message.as_bytes().to_vec())
+    );
+    assert_eq!(read_frame(&mut reader, 1024).unwrap(), BoundedLine::Line(b"{}".to_vec()));
+    assert_eq!(read_frame(&mut reader, 1024).unwrap(), BoundedLine::Eof);
+  }
+
+  #[test]
+  fn read_frame_skips_oversized_and_rejects_truncated_frames() {
+    let mut wire = encode_frame(&[b'x'; 100]).unwrap();
+    wire.extend(encode_frame(b"ok").unwrap());
+    let mut reader = BufReader::with_capacity(16, wire.as_slice());
+    assert_eq!(read_frame(&mut reader, 10).unwrap(), BoundedLine::TooLong);
+    assert_eq!(read_frame(&mut reader, 10).unwrap(), BoundedLine::Line(b"ok".to_vec()));
+    let truncated = &encode_frame(b"abcdef").unwrap()[..7];
+    let err = read_frame(&mut BufReader::new(truncated), 10).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+  }
+
+  /// Records the size of every write() it gets.
+  #[derive(Default)]
+  struct WriteSizes {
+    bytes: Vec<u8>,
+    writes: Vec<usize>,
+  }
+
+  impl Write for WriteSizes {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+      self.writes.push(buf.len());
+      self.bytes.extend_from_slice(buf);
+      Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn large_messages_are_written_in_chunks() {
+    let message = format!("{{\"text\":\"{}\"}}", "x".repeat(3 * WRITE_CHUNK_BYTES));
+    let mut writer = FrameWriter::new(WriteSizes::default());
+    writer.send_line(&message).unwrap();
+    assert_eq!(writer.inner.bytes, format!("{}\n", message).into_bytes());
+    assert!(writer.inner.writes.len() > 3);
+    assert!(writer.inner.writes.iter().all(|&n| n <= WRITE_CHUNK_BYTES));
+
+    writer.inner = WriteSizes::default();
+    writer.set_framing(Framing::LengthPrefixed).unwrap();
+    writer.send_line(&message).unwrap();
+    assert_eq!(writer.inner.bytes, encode_frame(message.as_bytes()).unwrap());
+    assert!(writer.inner.writes.iter().all(|&n| n <= WRITE_CHUNK_BYTES));
+  }
+
+  #[test]
+  fn frame_writer_switches_framing() {
+    let mut writer = FrameWriter::new(Vec::new());
+    writer.send_line("{\"cmd\":\"ping\"}").unwrap();
+    writer.set_framing(Framing::LengthPrefixed).unwrap();
+    writer.send_line("{\"q\":\"a\nb\"}").unwrap();
+    let mut expected = b"{\"cmd\":\"ping\"}\n".to_vec();
+    expected.extend(encode_frame(b"{\"q\":\"a\nb\"}").unwrap());
+    assert_eq!(writer.inner, expected);
+    assert!(Vec::new().set_framing(Framing::LengthPrefixed).is_err());
+  }
+
+  #[test]
+  fn dispatch_switches_to_frames_after_ack() {
+    let pending = PendingMap::default();
+    let mut negotiate = PendingRequest::register(&pending, 1, 0).unwrap();
+    let mut query = PendingRequest::register(&pending, 2, 0).unwrap();
+    let mut wire = b"{\"req_id\":1,\"data\":{\"type\":\"framing\",\"mode\":\"length\"}}\n".to_vec();
+    // A raw newline between tokens would split the message in line mode.
+    wire.extend(encode_frame(b"{\"req_id\":2,\n\"data\":{\"answer\":\"a\\nb\"}}").unwrap());
+    let mut reader = PipeReader::new(wire.as_slice(), 1024, 4);
+    dispatch_lines(&mut reader, &pending, &AtomicBool::new(false));
+    assert_eq!(
+      negotiate.rx.try_recv().unwrap().data,
+      serde_json::json!({ "type": "framing", "mode": "length" })
+    );
+    assert_eq!(query.rx.try_recv().unwrap().data, serde_json::json!({ "answer": "a\nb" }));
+  }
+}
@@ -99,6 +99,91 @@ describe('error handling', () => {
 
     await expect(api.listSessions()).rejects.toThrow('Database file not found');
   });
+
+  it('surfaces code and details of a typed backend error', async () => {
+    mockInvoke.mockRejectedValueOnce({ message: 'no such talker', code: 'not_found', details: { talker: 'x' } });
+
+    const err = await api.listSessions().catch((e) => e);
+    expect(err).toBeInstanceOf(api.BackendError);
+    expect(err.message).toBe('no such talker');
+    expect(err.code).toBe('not_found');
+    expect(err.details).toEqual({ talker: 'x' });
+  });
+});
+
+describe('backendBatch', () => {
+  it('sends all requests in one backend_batch call and keeps per-item errors', async () => {
+    mockInvoke.mockResolvedValueOnce([
+      { ok: true, value: [] },
+      { ok: false, error: { message: 'Unknown cmd: x', code: 'bad_cmd' } },
+    ]);
+
+    const results = await api.backendBatch([{ cmd: 'list_talkers' }, { cmd: 'x' }]);
+
+    expect(mockInvoke).toHaveBeenCalledWith('backend_batch', {
+      requests: [{ cmd: 'list_talkers' }, { cmd: 'x' }],
+    });
+    expect(results[0]).toEqual({ ok: true, value: [] });
+    expect(results[1].ok).toBe(false);
+    if (!results[1].ok) {
+      expect(results[1].error).toBeInstanceOf(api.BackendError);
+      expect(results[1].error.code).toBe('bad_cmd');
+    }
+  });
+});
+
+describe('backendStream', () => {
+  it('listens on the given event and passes it to backend_stream', async () => {
+    let handler: ((e: { payload: Record<string, unknown> }) => void) | undefined;
+    const unlisten = jest.fn();
+    mockListen.mockImplementationOnce((_name: unknown, cb: unknown) => {
+      handler = cb as typeof handler;
+      return Promise.resolve(unlisten);
+    });
+    mockInvoke.mockImplementationOnce(() => {
+      handler?.({ payload: { type: 'progress', step: 'export' } });
+      return Promise.resolve({ type: 'result', path: 'out.md' });
+    });
+    const seen: Record<string, unknown>[] = [];
+
+    const result = await api.backendStream({ cmd: 'export' }, (p) => seen.push(p), 'export://progress');
+
+    expect(mockListen).toHaveBeenCalledWith('export://progress', expect.any(Function));
+    expect(mockInvoke).toHaveBeenCalledWith('backend_stream', {
+      payload: { cmd: 'export' },
+      progressEvent: 'export://progress',
+    });
+    expect(seen).toEqual([{ type: 'progress', step: 'export' }]);
+    expect(result).toEqual({ type: 'result', path: 'out.md' });
+    expect(unlisten).toHaveBeenCalled();
+  });
+});
+
+describe('queryNarrativeStream', () => {
+  it('passes interleaved drafts and progress to their callbacks before completing', async () => {
+    const handlers: Record<string, (e: { payload: Record<string, unknown> }) => void> = {};
+    mockListen.mockImplementation((name: unknown, cb: unknown) => {
+      handlers[name as string] = cb as (e: { payload: Record<string, unknown> }) => void;
+      return Promise.resolve(() => {});
+    });
+    mockInvoke.mockImplementationOnce(() => {
+      handlers['backend://progress']({ payload: { trace_steps: [], stream_seq: 0 } });
+      handlers['backend://partial']({ payload: { text: 'Draft', stream_seq: 1 } });
+      handlers['backend://progress']({ payload: { trace_steps: [], stream_seq: 2 } });
+      handlers['backend://partial']({ payload: { text: 'Draft answer', stream_seq: 3 } });
+      return Promise.resolve({ type: 'result', answer: 'Final answer' });
+    });
+    const events: string[] = [];
+
+    await api.queryNarrativeStream('wxid_xxx', 'q', {
+      onProgress: () => events.push('progress'),
+      onPartial: (text) => events.push(`partial:${text}`),
+      onComplete: () => events.push('complete'),
+      onError: (err) => events.push(`error:${err.message}`),
+    });
+
+    expect(events).toEqual(['progress', 'partial:Draft', 'progress', 'partial:Draft answer', 'complete']);
+  });
 });
 
 describe('deleteSession', () => {
@@ -1,6 +1,14 @@
 import { invoke } from '@tauri-apps/api/core';
 import { listen } from '@tauri-apps/api/event';
-import type { Session, Message, QueryResponse, AgentStep, BackendConfig, ConfigOverrides } from './types';
+import type {
+  Session,
+  Message,
+  QueryResponse,
+  AgentStep,
+  BackendConfig,
+  ConfigOverrides,
+  ConfigPatch,
+} from './types';
 
 const CONFIG_PATH = 'config.yml';
 const STORAGE_KEY_OVERRIDES = 'narrarc_config_overrides';
@@ -42,6 +50,30 @@ export function setConfigOverrides(overrides: ConfigOverrides | null): void {
   localStorage.setItem(STORAGE_KEY_OVERRIDES, JSON.stringify(overrides));
 }
 
+/** Error returned by a backend command: message plus the backend's machine-readable code and details. */
+export class BackendError extends Error {
+  code?: string;
+  details?: unknown;
+
+  constructor(message: string, code?: string, details?: unknown) {
+    super(message);
+    this.name = 'BackendError';
+    this.code = code;
+    this.details = details;
+  }
+}
+
+/** Convert whatever invoke rejected with ({message, code?, details?}, Error or string) into a BackendError. */
+export function toBackendError(err: unknown): BackendError {
+  if (err instanceof BackendError) return err;
+  if (err instanceof Error) return new BackendError(err.message);
+  if (err && typeof err === 'object' && typeof (err as { message?: unknown }).message === 'string') {
+    const { message, code, details } = err as { message: string; code?: string; details?: unknown };
+    return new BackendError(message, code, details);
+  }
+  return new BackendError(String(err));
+}
+
 /** Single request to the long-lived backend process (stdio daemon). */
 async function backendRequest<T>(payload: Record<string, unknown>): Promise<T> {
   if (!isTauriContext()) {
@@ -49,14 +81,82 @@ async function backendRequest<T>(payload: Record<string, unknown>): Promise<T> {
       'Tauri API 不可用。请通过 npm run tauri:dev 或运行构建后的应用启动。'
     );
   }
-  const result = await invoke<unknown>('backend_request', { payload });
-  return result as T;
+  try {
+    const result = await invoke<unknown>('backend_request', { payload });
+    return result as T;
+  } catch (err) {
+    throw toBackendError(err);
+  }
+}
+
+/** Outcome of one item of backendBatch. */
+export type BatchResult<T = unknown> = { ok: true; value: T } | { ok: false; error: BackendError };
+
+/** Send several independent requests in one round trip; results come back in order, one per request. */
+export async function backendBatch(requests: Record<string, unknown>[]): Promise<BatchResult[]> {
+  if (!isTauriContext()) {
+    throw new Error(
+      'Tauri API 不可用。请通过 npm run tauri:dev 或运行构建后的应用启动。'
+    );
+  }
+  let results: Array<{ ok: boolean; value?: unknown; error?: unknown }>;
+  try {
+    results = await invoke('backend_batch', { requests });
+  } catch (err) {
+    throw toBackendError(err);
+  }
+  return results.map((r) =>
+    r.ok ? { ok: true as const, value: r.value } : { ok: false as const, error: toBackendError(r.error) }
+  );
+}
+
+/**
+ * Streaming request for any long-running cmd: progress lines arrive on progressEvent (default
+ * backend://progress) and are passed to onProgress; resolves with the final line.
+ */
+export async function backendStream<T = unknown>(
+  payload: Record<string, unknown>,
+  onProgress: (progress: Record<string, unknown>) => void,
+  progressEvent = 'backend://progress'
+): Promise<T> {
+  const unlisten = await listen<Record<string, unknown>>(progressEvent, (event) => {
+    onProgress(event.payload);
+  });
+  try {
+    return await invoke<T>('backend_stream', { payload, progressEvent });
+  } catch (err) {
+    throw toBackendError(err);
+  } finally {
+    unlisten();
+  }
 }
 
 export interface QueryStreamCallbacks {
-  onProgress: (steps: AgentStep[]) => void;
+  /** `state` is the workflow state behind the trace, only sent for verbose queries. */
+  onProgress: (steps: AgentStep[], state?: Record<string, unknown>) => void;
+  /** Provisional answer so far; each call replaces the previous text, onComplete supersedes it. */
+  onPartial?: (text: string) => void;
   onComplete: (result: QueryResponse) => void;
   onError: (err: Error) => void;
+  /** Called with the query's id before it is sent, e.g. to cancel it with cancelQuery. */
+  onStart?: (queryId: string) => void;
+}
+
+/** A query id reserved with start_query, and the events only that query's updates are emitted on. */
+export interface QueryChannels {
+  query_id: string;
+  progress_event: string;
+  partial_event: string;
+}
+
+/** Reserve a query id, so its own progress channels can be listened on before the query starts. */
+export async function startQuery(): Promise<QueryChannels> {
+  return invoke<QueryChannels>('start_query');
+}
+
+/** Stop a streaming query started with this id; resolves with whether it was still running. */
+export async function cancelQuery(queryId: string): Promise<boolean> {
+  return invoke<boolean>('cancel_query', { queryId });
 }
 
 export async function listSessions(): Promise<Session[]> {
@@ -68,6 +168,67 @@ export async function getConfig(): Promise<BackendConfig> {
   return backendRequest<BackendConfig>({ cmd: 'get_config', config: CONFIG_PATH });
 }
 
+export interface ConfigIssue {
+  severity: 'error' | 'warning';
+  message: string;
+  /** Setting it concerns, e.g. "llm.max_workers"; null for the whole file. */
+  key: string | null;
+  /** 1-based line in the config file, if known. */
+  line: number | null;
+}
+
+export interface ConfigValidation {
+  type: 'config_validation';
+  path: string;
+  /** False when any issue is an error. */
+  valid: boolean;
+  issues: ConfigIssue[];
+}
+
+/** Check config.yml against the backend's schema before building or querying. */
+export async function validateConfig(): Promise<ConfigValidation> {
+  try {
+    return await invoke<ConfigValidation>('validate_config', { configPath: CONFIG_PATH });
+  } catch (err) {
+    throw toBackendError(err);
+  }
+}
+
+export interface CredentialCheck {
+  type: 'credentials';
+  ok: true;
+  provider: string;
+  model: string;
+  latency_ms: number;
+}
+
+/**
+ * Send a one-token completion with the LLM settings (config.yml plus `overrides`, or the saved overrides) to
+ * confirm the key works. Fails with code 'auth_failed', 'no_network' or 'check_failed'.
+ */
+export async function checkCredentials(overrides?: ConfigOverrides | null): Promise<CredentialCheck> {
+  try {
+    return await invoke<CredentialCheck>('check_credentials', {
+      configOverrides: overrides === undefined ? getConfigOverrides() : overrides,
+      configPath: CONFIG_PATH,
+    });
+  } catch (err) {
+    throw toBackendError(err);
+  }
+}
+
+/**
+ * Save settings-form changes into config.yml (keeping its comments) and apply them to the running backend.
+ * Resolves with the new config.
+ */
+export async function setConfig(patch: ConfigPatch): Promise<BackendConfig> {
+  try {
+    return await invoke<BackendConfig>('set_config', { patch, configPath: CONFIG_PATH });
+  } catch (err) {
+    throw toBackendError(err);
+  }
+}
+
 export async function getMessages(
   talkerId: string,
   limit?: number,
@@ -98,37 +259,90 @@ export async function queryNarrative(
   return backendRequest<QueryResponse>(payload);
 }
 
-/** Stream query via backend_query_stream; progress via backend://progress event. */
+/**
+ * What to do with progress while the app lags behind the backend: 'block' emits every update, 'latest'
+ * merges the backlog (latest trace, joined tokens). Results and errors are never dropped.
+ */
+export type ProgressPolicy = 'block' | 'latest';
+
+/**
+ * Stream query via backend_query_stream; progress arrives on the query's own backend://progress/<id>
+ * channel (see startQuery), so concurrent queries in other panes don't cross-talk. With a null talkerId the
+ * talker set with setActiveTalker is asked. A seed pins randomness so repeated runs are comparable.
+ * Attachments are paths of local text files (under the home or app data folder) the question refers to.
+ * Verbose asks the backend for its workflow state with every trace, for debugging the agent.
+ */
 export async function queryNarrativeStream(
-  talkerId: string,
+  talkerId: string | null,
   question: string,
-  callbacks: QueryStreamCallbacks
+  callbacks: QueryStreamCallbacks,
+  seed?: number,
+  progressPolicy?: ProgressPolicy,
+  attachments?: string[],
+  verbose?: boolean
 ): Promise<void> {
   if (!isTauriContext()) {
     callbacks.onError(new Error('Tauri API 不可用'));
     return;
   }
   const overrides = getConfigOverrides();
-  const unlisten = await listen<{ trace_steps?: AgentStep[] }>(
-    'backend://progress',
+  const channels = await startQuery();
+  let lastSeq = -1;
+  const unlisten = await listen<{
+    trace_steps?: AgentStep[];
+    state?: Record<string, unknown>;
+    events?: { trace_steps?: AgentStep[]; state?: Record<string, unknown> }[];
+    stream_seq?: number;
+  }>(channels.progress_event, (event) => {
+    // Each trace is the whole trace so far, so an older one arriving late is simply dropped.
+    const seq = event.payload?.stream_seq;
+    if (typeof seq === 'number') {
+      if (seq <= lastSeq) return;
+      lastSeq = seq;
+    }
+    // Merged updates arrive as a batch holding at most one (the latest) trace.
+    const latest = Array.isArray(event.payload?.trace_steps)
+      ? event.payload
+      : event.payload?.events?.find((e) => Array.isArray(e.trace_steps));
+    if (latest && Array.isArray(latest.trace_steps)) {
+      callbacks.onProgress(latest.trace_steps, latest.state);
+    }
+  });
+  let lastPartialSeq = -1;
+  const unlistenPartial = await listen<{ text?: string; stream_seq?: number }>(
+    channels.partial_event,
     (event) => {
-      if (Array.isArray(event.payload?.trace_steps)) {
-        callbacks.onProgress(event.payload.trace_steps);
+      const seq = event.payload?.stream_seq;
+      if (typeof seq === 'number') {
+        if (seq <= lastPartialSeq) return;
+        lastPartialSeq = seq;
+      }
+      if (typeof event.payload?.text === 'string') {
+        callbacks.onPartial?.(event.payload.text);
       }
     }
   );
   try {
-    const result = await invoke<Record<string, unknown>>('backend_query_stream', {
-      talker: talkerId,
+    callbacks.onStart?.(channels.query_id);
+    // Checked on the Rust side: a result missing a QueryResponse field fails with code "bad_result".
+    const result = await invoke<QueryResponse>('backend_query_stream', {
       question,
-      configOverrides: overrides ?? undefined,
+      options: {
+        talker: talkerId ?? undefined,
+        configOverrides: overrides ?? undefined,
+        seed,
+        progressPolicy,
+        attachments,
+        verbose,
+        queryId: channels.query_id,
+      },
     });
-    const { type: _, ...rest } = result as { type?: string; [k: string]: unknown };
-    callbacks.onComplete(rest as unknown as QueryResponse);
+    callbacks.onComplete(result);
   } catch (err) {
-    callbacks.onError(err instanceof Error ? err : new Error(String(err)));
+    callbacks.onError(toBackendError(err));
   } finally {
     unlisten();
+    unlistenPartial();
   }
 }
 
@@ -151,8 +365,248 @@ export async function deleteSession(talkerId: string): Promise<void> {
   await backendRequest<unknown>({ cmd: 'delete_session', talker: talkerId });
 }
 
-export async function triggerBuild(talkerId: string): Promise<void> {
+/** Start a build; a seed pins randomness so repeated builds are comparable. */
+export async function triggerBuild(talkerId: string, seed?: number): Promise<void> {
+  const overrides = getConfigOverrides();
+  const overridesJson = overrides ? JSON.stringify(overrides) : undefined;
+  await invoke('spawn_backend_build', { talkerId, configOverrides: overridesJson, seed });
+}
+
+/**
+ * Re-embed a built talker's nodes with the current embedding settings, without a full build. Reports as
+ * `reindex://progress` then `reindex://done` or `reindex://error`.
+ */
+export async function triggerReindex(talkerId: string): Promise<void> {
+  await invoke('spawn_reindex', { talkerId, configOverrides: getConfigOverrides() });
+}
+
+export async function cancelReindex(): Promise<boolean> {
+  return invoke<boolean>('cancel_reindex');
+}
+
+export interface BuildValidation {
+  ok: boolean;
+  talker_id: string;
+  message_count: number;
+  errors: string[];
+}
+
+/** Check that a build for talkerId would start (talker exists, config/overrides valid) without building. */
+export async function validateBuild(talkerId: string): Promise<BuildValidation> {
   const overrides = getConfigOverrides();
   const overridesJson = overrides ? JSON.stringify(overrides) : undefined;
-  await invoke('spawn_backend_build', { talkerId, configOverrides: overridesJson });
+  return invoke<BuildValidation>('spawn_backend_build', {
+    talkerId,
+    configOverrides: overridesJson,
+    validateOnly: true,
+  });
+}
+
+export interface BuildStatus {
+  running: boolean;
+  talker: string | null;
+  /** ms since the epoch */
+  started_at: number | null;
+  elapsed_ms: number | null;
+}
+
+/** Whether a build started earlier (possibly from another window or before a reload) is still running. */
+export async function getBuildStatus(): Promise<BuildStatus> {
+  return invoke<BuildStatus>('build_status');
+}
+
+/**
+ * Stdout lines of the current or last build so far, oldest first; the first line notes dropped lines if the
+ * log was capped. Show these, then append `build://progress` events.
+ */
+export async function getBuildLog(): Promise<string[]> {
+  return invoke<string[]>('get_build_log');
+}
+
+export interface SessionStats {
+  total_tokens: number;
+  total_cost: number;
+  query_count: number;
+}
+
+/** Token and cost totals of the streaming queries run since the app started. */
+export async function getSessionStats(): Promise<SessionStats> {
+  return invoke<SessionStats>('get_session_stats');
+}
+
+/** Save this session's answered questions via a save dialog; resolves to the chosen path, or null if cancelled. */
+export async function exportTranscript(format: 'md' | 'json'): Promise<string | null> {
+  try {
+    return await invoke<string | null>('export_transcript', { format });
+  } catch (err) {
+    throw toBackendError(err);
+  }
+}
+
+/**
+ * Shut the backend down after `minutes` without requests (0 = never) to free memory; the next request
+ * restarts it. Listen for backend://suspended and backend://resumed to show the state.
+ */
+export async function setIdleTimeout(minutes: number): Promise<number> {
+  return invoke<number>('set_idle_timeout', { minutes });
+}
+
+/** Recent lines of the app's own log (spawn errors, restarts), oldest first; also available in release. */
+export async function getAppLog(): Promise<string[]> {
+  return invoke<string[]>('get_app_log');
+}
+
+/** Everything for a bug report in one object (backend info, spawn error, redacted config, recent stderr and app log, session stats), to show or copy. Works with the backend down. */
+export async function submitDiagnostics(): Promise<Record<string, unknown>> {
+  return invoke<Record<string, unknown>>('submit_diagnostics');
+}
+
+export interface SpawnDiagnostics {
+  message: string;
+  /** e.g. {resource_dir, target, expected_path, expected_exists, bin_dir_exists, found, mismatch} */
+  details: Record<string, unknown> | null;
+}
+
+/** Why the backend failed to start, with structured details to log; null if it started. */
+export async function getSpawnDiagnostics(): Promise<SpawnDiagnostics | null> {
+  return invoke<SpawnDiagnostics | null>('get_spawn_diagnostics');
+}
+
+export interface TalkerInfo {
+  id: string;
+  name: string;
+  message_count: number;
+  /** ms since the epoch */
+  last_timestamp: number;
+}
+
+export interface HistoryItem {
+  id: number;
+  talker: string | null;
+  question: string;
+  /** ms since the epoch */
+  timestamp: number;
+}
+
+/** Questions answered since the backend started, newest first (default limit 50), for the history sidebar. */
+export async function getQueryHistory(limit?: number): Promise<HistoryItem[]> {
+  return invoke<HistoryItem[]>('get_query_history', { limit });
+}
+
+export interface ResetOutcome {
+  reset: boolean;
+  talker: string | null;
+  /** Answered questions the backend forgot. */
+  cleared: number;
+  /** The backend cannot reset in place; restart it (restart_backend) to start over. */
+  restart_required: boolean;
+}
+
+/** "New chat": forget the conversation with talkerId (every talker without one) without a restart. */
+export async function resetConversation(talkerId?: string): Promise<ResetOutcome> {
+  try {
+    return await invoke<ResetOutcome>('reset_conversation', { talker: talkerId });
+  } catch (err) {
+    throw toBackendError(err);
+  }
+}
+
+/** Make talkerId the current conversation so the backend keeps its context loaded between questions. */
+export async function setActiveTalker(talkerId: string): Promise<TalkerInfo> {
+  try {
+    return await invoke<TalkerInfo>('set_active_talker', { talker: talkerId });
+  } catch (err) {
+    throw toBackendError(err);
+  }
+}
+
+export interface DeletedTalker {
+  status: 'deleted';
+  talker_id: string;
+  /** Rows removed from the database across all tables. */
+  rows_deleted: number;
+}
+
+/** Delete everything stored for talkerId. Fails with code 'backend_unhealthy' or 'busy' rather than waiting. */
+export async function deleteTalker(talkerId: string): Promise<DeletedTalker> {
+  try {
+    return await invoke<DeletedTalker>('delete_talker', { talker: talkerId });
+  } catch (err) {
+    throw toBackendError(err);
+  }
+}
+
+export interface DbStatus {
+  instance_id?: string;
+  path: string;
+  /** 'newer' means the database comes from a newer app version and was left as it is. */
+  status: 'created' | 'migrated' | 'current' | 'newer';
+  created: boolean;
+  /** Whether the schema was created or brought up to date. */
+  migrated: boolean;
+  schema_version: number;
+  previous_version: number | null;
+  talker_count: number | null;
+  /** No talkers imported yet: show onboarding. */
+  empty: boolean;
+}
+
+/**
+ * Listen for backend://db_initialized, emitted once a started backend has created or checked its database
+ * and before backend://ready. Resolves with the function that stops listening.
+ */
+export async function onDbInitialized(callback: (status: DbStatus) => void): Promise<() => void> {
+  return listen<DbStatus>('backend://db_initialized', (event) => callback(event.payload));
+}
+
+export type LogLevel = 'error' | 'warn' | 'info' | 'debug' | 'trace';
+
+/** Backend stderr verbosity; applies to the next build and to the backend after its next restart. */
+export async function setLogLevel(level: LogLevel | null): Promise<LogLevel> {
+  return invoke<LogLevel>('set_log_level', { level });
+}
+
+export async function getLogLevel(): Promise<LogLevel> {
+  return invoke<LogLevel>('get_log_level');
+}
+
+export type BackendTransport = 'stdio' | 'http';
+
+/** How requests reach the backend: its stdin, or a local HTTP server where pipes are restricted. Applies after its next restart. */
+export async function setBackendTransport(transport: BackendTransport | null): Promise<BackendTransport> {
+  return invoke<BackendTransport>('set_backend_transport', { transport });
+}
+
+export async function getBackendTransport(): Promise<BackendTransport> {
+  return invoke<BackendTransport>('get_backend_transport');
+}
+
+export type BackendSource = 'bundled' | { external: { cmd: string[]; cwd: string } };
+
+/** Run the bundled backend, or a local checkout (`cmd` plus the backend args, in `cwd`). Applies when the backend is next (re)spawned. */
+export async function setBackendSource(source: BackendSource): Promise<BackendSource> {
+  return invoke<BackendSource>('set_backend_source', { source });
+}
+
+export async function getBackendSource(): Promise<BackendSource> {
+  return invoke<BackendSource>('get_backend_source');
+}
+
+/** Block builds, config edits and database switches (queries still work), e.g. on a shared demo machine. */
+export async function setReadOnly(enabled: boolean): Promise<boolean> {
+  return invoke<boolean>('set_read_only', { enabled });
+}
+
+export async function getReadOnly(): Promise<boolean> {
+  return invoke<boolean>('get_read_only');
+}
+
+/** Shut the backend down and keep it down (requests fail until startBackend), e.g. while editing its config. */
+export async function stopBackend(): Promise<void> {
+  await invoke('stop_backend');
+}
+
+/** Start a stopped backend and wait until it is ready; resolves to its PID. */
+export async function startBackend(): Promise<number> {
+  return invoke<number>('start_backend');
 }
@@ -1,5 +1,5 @@
 import React, { useState, useEffect } from 'react';
-import { X, Settings, Save, RotateCcw } from 'lucide-react';
+import { X, Settings, Save, RotateCcw, ShieldCheck, PlugZap } from 'lucide-react';
 import { motion, AnimatePresence } from 'motion/react';
 import type { BackendConfig, ConfigOverrides } from '../types';
 import * as api from '../api';
@@ -44,10 +44,16 @@ export function SettingsModal({ isOpen, onClose }: SettingsModalProps) {
   const [loading, setLoading] = useState(false);
   const [saving, setSaving] = useState(false);
   const [error, setError] = useState<string | null>(null);
+  const [checking, setChecking] = useState(false);
+  const [validation, setValidation] = useState<api.ConfigValidation | null>(null);
+  const [testing, setTesting] = useState(false);
+  const [connection, setConnection] = useState<{ ok: boolean; message: string } | null>(null);
 
   useEffect(() => {
     if (!isOpen) return;
     setError(null);
+    setValidation(null);
+    setConnection(null);
     setLoading(true);
     api
       .getConfig()
@@ -76,6 +82,32 @@ export function SettingsModal({ isOpen, onClose }: SettingsModalProps) {
     }
   };
 
+  const handleCheck = () => {
+    setChecking(true);
+    setError(null);
+    api
+      .validateConfig()
+      .then(setValidation)
+      .catch((e) => setError(e instanceof Error ? e.message : String(e)))
+      .finally(() => setChecking(false));
+  };
+
+  const handleTestConnection = () => {
+    if (!form || !baseConfig) return;
+    setTesting(true);
+    setConnection(null);
+    api
+      .checkCredentials(toOverrides(form, baseConfig))
+      .then((r) => setConnection({ ok: true, message: `连接成功：${r.provider} / ${r.model}（${r.latency_ms} ms）` }))
+      .catch((e) => {
+        const err = api.toBackendError(e);
+        const reason =
+          err.code === 'auth_failed' ? 'API Key 无效或无权限' : err.code === 'no_network' ? '无法连接到服务' : err.message;
+        setConnection({ ok: false, message: `连接失败：${reason}` });
+      })
+      .finally(() => setTesting(false));
+  };
+
   const handleReset = () => {
     if (!baseConfig) return;
     setForm({ ...baseConfig });
@@ -130,6 +162,31 @@ export function SettingsModal({ isOpen, onClose }: SettingsModalProps) {
                 {error}
               </div>
             )}
+            {connection && (
+              <p
+                className={`text-sm p-3 rounded-lg border border-zinc-200 dark:border-white/10 ${
+                  connection.ok ? 'text-emerald-600 dark:text-emerald-500' : 'text-red-600 dark:text-red-500'
+                }`}
+              >
+                {connection.message}
+              </p>
+            )}
+            {validation && (
+              <div className="text-sm p-3 rounded-lg border border-zinc-200 dark:border-white/10 space-y-1">
+                <p className={validation.valid ? 'text-emerald-600 dark:text-emerald-500' : 'text-red-600 dark:text-red-500'}>
+                  {validation.valid ? `${validation.path} 有效` : `${validation.path} 有错误`}
+                </p>
+                {validation.issues.map((issue, i) => (
+                  <p
+                    key={i}
+                    className={issue.severity === 'error' ? 'text-red-600 dark:text-red-500' : 'text-amber-600 dark:text-amber-500'}
+                  >
+                    {issue.line != null ? `第 ${issue.line} 行：` : ''}
+                    {issue.message}
+                  </p>
+                ))}
+              </div>
+            )}
             {form && (
               <>
                 <section>
@@ -260,14 +317,32 @@ export function SettingsModal({ isOpen, onClose }: SettingsModalProps) {
           </div>
 
           <div className="p-6 border-t border-zinc-200 dark:border-white/10 bg-zinc-50 dark:bg-[#050505] flex justify-between gap-3 flex-shrink-0">
-            <button
-              onClick={handleReset}
-              disabled={!form || loading}
-              className="px-4 py-2 text-sm font-medium text-zinc-600 dark:text-zinc-400 hover:text-zinc-900 dark:hover:text-zinc-200 transition-colors flex items-center gap-2 disabled:opacity-50"
-            >
-              <RotateCcw className="w-4 h-4" />
-              恢复文件默认
-            </button>
+            <div className="flex gap-1">
+              <button
+                onClick={handleReset}
+                disabled={!form || loading}
+                className="px-4 py-2 text-sm font-medium text-zinc-600 dark:text-zinc-400 hover:text-zinc-900 dark:hover:text-zinc-200 transition-colors flex items-center gap-2 disabled:opacity-50"
+              >
+                <RotateCcw className="w-4 h-4" />
+                恢复文件默认
+              </button>
+              <button
+                onClick={handleCheck}
+                disabled={checking}
+                className="px-4 py-2 text-sm font-medium text-zinc-600 dark:text-zinc-400 hover:text-zinc-900 dark:hover:text-zinc-200 transition-colors flex items-center gap-2 disabled:opacity-50"
+              >
+                <ShieldCheck className="w-4 h-4" />
+                {checking ? '检查中…' : '检查配置'}
+              </button>
+              <button
+                onClick={handleTestConnection}
+                disabled={!form || loading || testing}
+                className="px-4 py-2 text-sm font-medium text-zinc-600 dark:text-zinc-400 hover:text-zinc-900 dark:hover:text-zinc-200 transition-colors flex items-center gap-2 disabled:opacity-50"
+              >
+                <PlugZap className="w-4 h-4" />
+                {testing ? '测试中…' : '测试连接'}
+              </button>
+            </div>
             <div className="flex gap-3">
               <button
                 onClick={onClose}
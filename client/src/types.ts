@@ -77,10 +77,24 @@ export interface BackendConfig {
 /** Partial overrides applied on top of config.yml. */
 export type ConfigOverrides = Partial<BackendConfig>;
 
+/** Changes saved into config.yml by setConfig; null removes a key so the backend default applies. */
+export type ConfigPatch = {
+  [S in keyof BackendConfig]?: { [K in keyof BackendConfig[S]]?: BackendConfig[S][K] | null };
+} & { llm?: { seed?: number | null } };
+
 export interface QueryResponse {
   conversation_id: string;
   question: string;
   phases: Phase[];
   agent_trace: AgentTrace;
   all_messages: Message[];
+  /** Token and cost accounting, when the backend reports it. */
+  usage?: QueryUsage;
+}
+
+export interface QueryUsage {
+  prompt_tokens?: number;
+  completion_tokens?: number;
+  total_tokens?: number;
+  cost?: number;
 }